@@ -0,0 +1,797 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Generator for `generated.rs`
+//!
+//! Reads the server's OpenAPI document and writes one [`Client`] method per
+//! operation, named after its operation ID, plus a struct for the query
+//! parameters of each operation that has any. Schemas the document refers
+//! to by name are taken to be the `crs-common` types of the same name.
+//! Responses without a JSON body are handed back as the raw
+//! `reqwest::Response`.
+//!
+//! This stands in for a progenitor build step, which is what the other Oxide
+//! clients use: progenitor and its dependencies cannot be fetched in the
+//! offline builds this workspace has to support. It therefore only handles
+//! what `crs-server`'s document uses, and [`generate`] returns an error
+//! rather than guessing at anything else. In particular, it does not
+//! support:
+//!
+//! - header and cookie parameters, or parameters declared on a path item
+//!   rather than on each operation
+//! - inline schemas other than strings, booleans and the integer formats
+//!   `schema_type` knows: no inline objects, arrays or `oneOf`/`allOf`/
+//!   `anyOf`, and no `$ref`s to anything but `#/components/schemas`
+//! - parameters whose named schema is an object or array
+//! - request bodies that are optional or not `application/json`
+//! - operations with more than one success response, or whose success
+//!   response has a body other than `application/json`
+//! - methods other than GET, PUT, POST, DELETE, PATCH and HEAD
+//! - `$ref`s to other documents
+//!
+//! It also does not check that named schemas match the `crs-common` types;
+//! a mismatch shows up as a compile error in `generated.rs`.
+//!
+//! The generated file is committed, and a test fails if it is out of date.
+//! Regenerate it with:
+//!
+//! ```text
+//! CRS_UPDATE_API_CLIENT=1 cargo test -p crs-api-client codegen
+//! ```
+//!
+//! [`Client`]: crate::Client
+
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use thiserror::Error;
+
+/// Longest line the generator writes on purpose
+const MAX_WIDTH: usize = 80;
+
+/// Width doc comments are wrapped to
+const DOC_WIDTH: usize = 76;
+
+/// HTTP methods the generator supports, as path item fields
+const METHODS: &[&str] = &["get", "put", "post", "delete", "patch", "head"];
+
+/// Why the OpenAPI document could not be turned into a client
+#[derive(Debug, Error)]
+pub enum CodegenError {
+    /// The document is missing something OpenAPI requires
+    #[error("{at}: {what}")]
+    Invalid { at: String, what: String },
+
+    /// The document uses something the generator does not support
+    #[error("{at}: unsupported {what}")]
+    Unsupported { at: String, what: String },
+}
+
+fn invalid(at: &str, what: impl Into<String>) -> CodegenError {
+    CodegenError::Invalid {
+        at: at.to_string(),
+        what: what.into(),
+    }
+}
+
+fn unsupported(at: &str, what: impl Into<String>) -> CodegenError {
+    CodegenError::Unsupported {
+        at: at.to_string(),
+        what: what.into(),
+    }
+}
+
+/// One operation in the document
+struct Operation<'a> {
+    id: &'a str,
+    method: &'a str,
+    path: &'a str,
+    doc: &'a Value,
+    path_params: Vec<Param<'a>>,
+    query_params: Vec<Param<'a>>,
+    body: Option<String>,
+    response: Response,
+}
+
+/// A path or query parameter
+struct Param<'a> {
+    name: &'a str,
+    description: Option<&'a str>,
+    required: bool,
+    /// Rust type of the parameter's value
+    ty: String,
+}
+
+/// What an operation answers with
+enum Response {
+    /// A JSON body of the given type
+    Json(String),
+    /// No body
+    Empty,
+    /// Anything else, handed back undecoded
+    Raw,
+}
+
+/// Generate `generated.rs` from the OpenAPI document
+pub fn generate(document: &Value) -> Result<String, CodegenError> {
+    let operations = operations(document)?;
+    let mut imports = Imports::default();
+    for operation in &operations {
+        imports.add_operation(operation);
+    }
+
+    let mut items = String::new();
+    let mut methods = String::new();
+    for operation in &operations {
+        if !operation.query_params.is_empty() {
+            items.push('\n');
+            items.push_str(&query_struct(operation, &imports));
+        }
+        methods.push('\n');
+        methods.push_str(&method(operation, &imports));
+    }
+
+    let mut out = String::new();
+    out.push_str(
+        "// Copyright 2025 Oxide Computer Company\n\
+         \n\
+         // Generated from crs-server/openapi.json by codegen.rs. Do not edit\n\
+         // by hand; regenerate with\n\
+         // `CRS_UPDATE_API_CLIENT=1 cargo test -p crs-api-client codegen`.\n\
+         \n\
+         //! Methods for every operation in the OpenAPI document\n\
+         \n\
+         use super::{decode, encode_path_segment, Client, Error};\n",
+    );
+    if !imports.crs_common.is_empty() {
+        let names: Vec<_> =
+            imports.crs_common.iter().map(String::as_str).collect();
+        out.push_str(&use_list("crs_common", &names));
+    }
+    if !items.is_empty() {
+        out.push_str("use serde::Serialize;\n");
+    }
+    for path in &imports.other {
+        let _ = writeln!(out, "use {};", path);
+    }
+    out.push_str(&items);
+    out.push_str("\nimpl Client {\n");
+    out.push_str(&methods.replacen('\n', "", 1));
+    out.push_str("}\n");
+    Ok(out)
+}
+
+/// Collect the document's operations, ordered by path and method
+fn operations(document: &Value) -> Result<Vec<Operation<'_>>, CodegenError> {
+    let paths = document["paths"]
+        .as_object()
+        .ok_or_else(|| invalid("document", "no `paths`"))?;
+    let mut operations = Vec::new();
+    for (path, item) in paths {
+        let item = item
+            .as_object()
+            .ok_or_else(|| invalid(path, "path item is not an object"))?;
+        for (method, op) in item {
+            if !METHODS.contains(&method.as_str()) {
+                return Err(unsupported(
+                    path,
+                    format!("path item field `{}`", method),
+                ));
+            }
+            operations.push(operation(document, path, method, op)?);
+        }
+    }
+    Ok(operations)
+}
+
+/// Read one operation
+fn operation<'a>(
+    document: &'a Value,
+    path: &'a str,
+    method: &'a str,
+    operation: &'a Value,
+) -> Result<Operation<'a>, CodegenError> {
+    let at = format!("{} {}", method.to_uppercase(), path);
+    let id = operation["operationId"]
+        .as_str()
+        .ok_or_else(|| invalid(&at, "no `operationId`"))?;
+
+    let mut path_params = Vec::new();
+    let mut query_params = Vec::new();
+    for param in operation["parameters"].as_array().into_iter().flatten() {
+        let param = resolve(document, param)?;
+        let name = param["name"]
+            .as_str()
+            .ok_or_else(|| invalid(&at, "parameter without a `name`"))?;
+        let at = format!("parameter `{}` of {}", name, id);
+        let ty = schema_type(&param["schema"])
+            .ok_or_else(|| unsupported(&at, "schema"))?;
+        let schema = resolve(document, &param["schema"])?;
+        if matches!(schema["type"].as_str(), Some("object" | "array")) {
+            return Err(unsupported(&at, "non-scalar schema"));
+        }
+        let param_value = Param {
+            name,
+            description: param["description"].as_str(),
+            required: param["required"] == true,
+            ty,
+        };
+        match param["in"].as_str() {
+            Some("path") => path_params.push(param_value),
+            Some("query") => query_params.push(param_value),
+            Some(location) => {
+                return Err(unsupported(
+                    &at,
+                    format!("parameter location `{}`", location),
+                ))
+            }
+            None => return Err(invalid(&at, "no `in`")),
+        }
+    }
+    for param in &path_params {
+        if !path.contains(&format!("{{{}}}", param.name)) {
+            return Err(invalid(
+                &at,
+                format!("path parameter `{}` not in the path", param.name),
+            ));
+        }
+    }
+    if path.matches('{').count() != path_params.len() {
+        return Err(invalid(&at, "path template without a parameter"));
+    }
+
+    let body = match operation.get("requestBody") {
+        None => None,
+        Some(body) => {
+            let body = resolve(document, body)?;
+            if body["required"] != true {
+                return Err(unsupported(&at, "optional request body"));
+            }
+            let schema = body["content"]
+                .get("application/json")
+                .map(|content| &content["schema"])
+                .ok_or_else(|| unsupported(&at, "non-JSON request body"))?;
+            Some(
+                schema_type(schema)
+                    .ok_or_else(|| unsupported(&at, "request body schema"))?,
+            )
+        }
+    };
+
+    Ok(Operation {
+        id,
+        method,
+        path,
+        doc: operation,
+        path_params,
+        query_params,
+        body,
+        response: response(document, &at, operation)?,
+    })
+}
+
+/// Follow a `$ref` within the document
+fn resolve<'a>(
+    document: &'a Value,
+    value: &'a Value,
+) -> Result<&'a Value, CodegenError> {
+    let Some(reference) = value["$ref"].as_str() else {
+        return Ok(value);
+    };
+    let pointer = reference
+        .strip_prefix('#')
+        .ok_or_else(|| unsupported(reference, "`$ref` to another document"))?;
+    document
+        .pointer(pointer)
+        .ok_or_else(|| invalid(reference, "dangling `$ref`"))
+}
+
+/// Work out what an operation answers with when it succeeds
+///
+/// An operation with no 2xx response (only a `default` one, say) is handed
+/// back raw.
+fn response(
+    document: &Value,
+    at: &str,
+    operation: &Value,
+) -> Result<Response, CodegenError> {
+    let responses = operation["responses"]
+        .as_object()
+        .ok_or_else(|| invalid(at, "no `responses`"))?;
+    let mut successes =
+        responses.iter().filter(|(code, _)| code.starts_with('2'));
+    let Some((_, success)) = successes.next() else {
+        return Ok(Response::Raw);
+    };
+    if successes.next().is_some() {
+        return Err(unsupported(at, "second success response"));
+    }
+    let success = resolve(document, success)?;
+    let content = success["content"].as_object();
+    if content.is_none_or(|content| content.is_empty()) {
+        return Ok(Response::Empty);
+    }
+    let schema = success["content"]
+        .get("application/json")
+        .map(|content| &content["schema"])
+        .ok_or_else(|| unsupported(at, "non-JSON success response"))?;
+    schema_type(schema)
+        .map(Response::Json)
+        .ok_or_else(|| unsupported(at, "success response schema"))
+}
+
+/// Rust type for a schema
+///
+/// Named schemas are `crs-common` types, and an empty schema is any JSON.
+fn schema_type(schema: &Value) -> Option<String> {
+    if let Some(reference) = schema["$ref"].as_str() {
+        let name = reference.strip_prefix("#/components/schemas/")?;
+        return Some(name.to_string());
+    }
+    if schema.as_object().is_some_and(|o| o.is_empty()) {
+        return Some("serde_json::Value".to_string());
+    }
+    let format = schema["format"].as_str();
+    let ty = match (schema["type"].as_str()?, format) {
+        ("string", Some("uuid")) => "Uuid",
+        ("string", _) => "String",
+        ("boolean", _) => "bool",
+        ("integer", Some("uint32")) if schema["minimum"] == 1 => "NonZeroU32",
+        ("integer", Some("uint32")) => "u32",
+        ("integer", Some("uint64")) => "u64",
+        ("integer", Some("int64")) => "i64",
+        _ => return None,
+    };
+    Some(ty.to_string())
+}
+
+/// Types the generated code imports
+#[derive(Default)]
+struct Imports {
+    /// Names imported from `crs-common`
+    crs_common: BTreeSet<String>,
+    /// Other paths imported
+    other: BTreeSet<&'static str>,
+}
+
+impl Imports {
+    /// Import the types an operation takes and returns
+    fn add_operation(&mut self, operation: &Operation) {
+        let params = operation.path_params.iter();
+        for param in params.chain(&operation.query_params) {
+            self.add(&param.ty);
+        }
+        if let Some(body) = &operation.body {
+            self.add(body);
+        }
+        if let Response::Json(ty) = &operation.response {
+            self.add(ty);
+        }
+    }
+
+    /// Import a type, unless it needs no import
+    fn add(&mut self, ty: &str) {
+        match ty {
+            "Uuid" => {
+                self.other.insert("uuid::Uuid");
+            }
+            "NonZeroU32" => {
+                self.other.insert("std::num::NonZeroU32");
+            }
+            "String" | "bool" | "u32" | "u64" | "i64" | "serde_json::Value" => {
+            }
+            name => {
+                self.crs_common.insert(name.to_string());
+            }
+        }
+    }
+}
+
+/// The struct holding an operation's query parameters
+fn query_struct(operation: &Operation, imports: &Imports) -> String {
+    let mut out = String::new();
+    let _ =
+        writeln!(out, "/// Query parameters of [`Client::{}`]", operation.id);
+    out.push_str("#[derive(Debug, Clone, Default, Serialize)]\n");
+    let _ = writeln!(out, "pub struct {} {{", query_struct_name(operation.id));
+    for (i, param) in operation.query_params.iter().enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        if let Some(description) = param.description {
+            out.push_str(&doc_comment(description, 4, imports));
+        }
+        if param.required {
+            let _ = writeln!(out, "    pub {}: {},", param.name, param.ty);
+        } else {
+            out.push_str(
+                "    #[serde(skip_serializing_if = \"Option::is_none\")]\n",
+            );
+            let _ =
+                writeln!(out, "    pub {}: Option<{}>,", param.name, param.ty);
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// Name of the struct holding an operation's query parameters
+fn query_struct_name(id: &str) -> String {
+    let mut name: String = id
+        .split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_ascii_uppercase().to_string())
+                .unwrap_or_default()
+                + chars.as_str()
+        })
+        .collect();
+    name.push_str("Query");
+    name
+}
+
+/// The method calling an operation
+fn method(operation: &Operation, imports: &Imports) -> String {
+    let mut out = String::new();
+    let summary = operation.doc["summary"].as_str().unwrap_or(operation.id);
+    out.push_str(&doc_comment(summary, 4, imports));
+    if let Some(description) = operation.doc["description"].as_str() {
+        out.push_str("    ///\n");
+        out.push_str(&doc_comment(description, 4, imports));
+    }
+
+    // Arguments: path parameters, then query parameters, then the body
+    let mut args = vec!["&self".to_string()];
+    for param in &operation.path_params {
+        let ty = if param.ty == "String" {
+            "&str"
+        } else {
+            &param.ty
+        };
+        args.push(format!("{}: {}", param.name, ty));
+    }
+    if !operation.query_params.is_empty() {
+        args.push(format!("query: &{}", query_struct_name(operation.id)));
+    }
+    if let Some(body) = &operation.body {
+        args.push(format!("body: &{}", body));
+    }
+    let output = match &operation.response {
+        Response::Json(ty) => ty.clone(),
+        Response::Empty => "()".to_string(),
+        Response::Raw => "reqwest::Response".to_string(),
+    };
+    let one_line = format!(
+        "    pub async fn {}({}) -> Result<{}, Error> {{",
+        operation.id,
+        args.join(", "),
+        output
+    );
+    if one_line.len() <= MAX_WIDTH {
+        let _ = writeln!(out, "{}", one_line);
+    } else {
+        let _ = writeln!(out, "    pub async fn {}(", operation.id);
+        for arg in &args {
+            let _ = writeln!(out, "        {},", arg);
+        }
+        let _ = writeln!(out, "    ) -> Result<{}, Error> {{", output);
+    }
+
+    // Fill in the path parameters, each as one encoded segment
+    if operation.path_params.is_empty() {
+        let _ =
+            writeln!(out, "        let url = self.url({:?});", operation.path);
+    } else {
+        let mut template = operation.path.to_string();
+        let mut values = Vec::new();
+        for param in &operation.path_params {
+            template = template.replace(&format!("{{{}}}", param.name), "{}");
+            values.push(if param.ty == "String" {
+                format!("encode_path_segment({})", param.name)
+            } else {
+                format!("encode_path_segment(&{}.to_string())", param.name)
+            });
+        }
+        out.push_str("        let url = self.url(&format!(\n");
+        let _ = write!(out, "            {:?}", template);
+        for value in values {
+            let _ = write!(out, ",\n            {}", value);
+        }
+        out.push_str("\n        ));\n");
+    }
+
+    let _ = write!(
+        out,
+        "        let request = self.client.{}(url)",
+        operation.method
+    );
+    if !operation.query_params.is_empty() {
+        out.push_str(".query(query)");
+    }
+    if operation.body.is_some() {
+        out.push_str(".json(body)");
+    }
+    out.push_str(";\n");
+    out.push_str(match operation.response {
+        Response::Json(_) => {
+            "        decode(self.send(request).await?).await\n"
+        }
+        Response::Empty => {
+            "        self.send(request).await?;\n        Ok(())\n"
+        }
+        Response::Raw => "        self.send(request).await\n",
+    });
+    out.push_str("    }\n");
+    out
+}
+
+/// A `use` declaration of several names from one crate, wrapped the way
+/// rustfmt wraps them
+fn use_list(krate: &str, names: &[&str]) -> String {
+    let single = format!("use {}::{{{}}};\n", krate, names.join(", "));
+    if single.len() <= MAX_WIDTH + 1 {
+        return single;
+    }
+    let mut out = format!("use {}::{{\n", krate);
+    let mut line = String::new();
+    for name in names {
+        let item = format!("{},", name);
+        if !line.is_empty() && 4 + line.len() + 1 + item.len() >= MAX_WIDTH {
+            let _ = writeln!(out, "    {}", line);
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&item);
+    }
+    let _ = writeln!(out, "    {}", line);
+    out.push_str("};\n");
+    out
+}
+
+/// Doc comment lines for `text`, indented by `indent` spaces and wrapped
+fn doc_comment(text: &str, indent: usize, imports: &Imports) -> String {
+    let text = resolve_links(text, &imports.crs_common);
+    let prefix = format!("{}///", " ".repeat(indent));
+    let mut out = String::new();
+    for (i, paragraph) in text.split("\n\n").enumerate() {
+        if i > 0 {
+            let _ = writeln!(out, "{}", prefix);
+        }
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            if !line.is_empty()
+                && prefix.len() + 1 + line.len() + 1 + word.len() > DOC_WIDTH
+            {
+                let _ = writeln!(out, "{} {}", prefix, line);
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+        let _ = writeln!(out, "{} {}", prefix, line);
+    }
+    out
+}
+
+/// Point intra-doc links to `crs-common` types that are not imported at
+/// their full path
+fn resolve_links(text: &str, imported: &BTreeSet<String>) -> String {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("[`") {
+        let Some(len) = rest[start..].find("`]") else {
+            break;
+        };
+        let end = start + len + 2;
+        let name = &rest[start + 2..start + len];
+        out.push_str(&rest[..end]);
+        rest = &rest[end..];
+        let is_type = name.chars().next().is_some_and(char::is_uppercase)
+            && name.chars().all(char::is_alphanumeric);
+        if is_type && !imported.contains(name) && !rest.starts_with('(') {
+            let _ = write!(out, "(crs_common::{})", name);
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    /// Fail if `generated.rs` is out of date with the OpenAPI document
+    ///
+    /// With `CRS_UPDATE_API_CLIENT` set, rewrite it instead.
+    #[test]
+    fn test_codegen_up_to_date() {
+        let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let document_path = manifest_dir.join("../crs-server/openapi.json");
+        let document: Value = serde_json::from_str(
+            &std::fs::read_to_string(&document_path).unwrap(),
+        )
+        .unwrap();
+        let path = manifest_dir.join("src/generated.rs");
+        let generated = generate(&document).unwrap();
+
+        if std::env::var_os("CRS_UPDATE_API_CLIENT").is_some() {
+            std::fs::write(&path, &generated).unwrap();
+            return;
+        }
+
+        let committed = std::fs::read_to_string(&path).unwrap_or_default();
+        assert!(
+            committed == generated,
+            "{} is out of date with {}. Regenerate it with \
+             `CRS_UPDATE_API_CLIENT=1 cargo test -p crs-api-client codegen` \
+             and commit the result.",
+            path.display(),
+            document_path.display()
+        );
+    }
+
+    #[test]
+    fn test_schema_type() {
+        let ty = |schema: Value| schema_type(&schema);
+        assert_eq!(
+            ty(serde_json::json!({"$ref": "#/components/schemas/BanKind"})),
+            Some("BanKind".to_string())
+        );
+        assert_eq!(
+            ty(serde_json::json!({"type": "string", "format": "uuid"})),
+            Some("Uuid".to_string())
+        );
+        assert_eq!(
+            ty(serde_json::json!({
+                "type": "integer",
+                "format": "uint32",
+                "minimum": 1
+            })),
+            Some("NonZeroU32".to_string())
+        );
+        assert_eq!(
+            ty(serde_json::json!({})),
+            Some("serde_json::Value".to_string())
+        );
+        assert_eq!(ty(serde_json::json!({"type": "array"})), None);
+    }
+
+    /// A change to the operation in [`document`]
+    type Edit = fn(&mut Value);
+
+    /// A document with one operation, `GET /things/{id}`, changed by `edit`
+    fn document(edit: impl FnOnce(&mut Value)) -> Value {
+        let mut document = serde_json::json!({
+            "paths": {
+                "/things/{id}": {
+                    "get": {
+                        "operationId": "get_thing",
+                        "parameters": [{
+                            "in": "path",
+                            "name": "id",
+                            "required": true,
+                            "schema": {"type": "string"}
+                        }],
+                        "responses": {
+                            "200": {
+                                "content": {
+                                    "application/json": {
+                                        "schema": {"type": "string"}
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        });
+        edit(document.pointer_mut("/paths/~1things~1{id}/get").unwrap());
+        document
+    }
+
+    #[test]
+    fn test_generate_minimal_document() {
+        let generated = generate(&document(|_| ())).unwrap();
+        assert!(generated.contains(
+            "pub async fn get_thing(&self, id: &str) -> Result<String, Error>"
+        ));
+    }
+
+    #[test]
+    fn test_generate_rejects_unsupported_shapes() {
+        let unsupported: [(&str, Edit); 6] = [
+            ("header parameter", |op| {
+                op["parameters"][0]["in"] = "header".into()
+            }),
+            ("array parameter", |op| {
+                op["parameters"][0]["schema"] = serde_json::json!({
+                    "type": "array",
+                    "items": {"type": "string"}
+                })
+            }),
+            ("form body", |op| {
+                op["requestBody"] = serde_json::json!({
+                    "required": true,
+                    "content": {"application/x-www-form-urlencoded": {}}
+                })
+            }),
+            ("optional body", |op| {
+                op["requestBody"] = serde_json::json!({
+                    "content": {"application/json": {"schema": {}}}
+                })
+            }),
+            ("text response", |op| {
+                op["responses"]["200"]["content"] =
+                    serde_json::json!({"text/plain": {}})
+            }),
+            ("second success response", |op| {
+                op["responses"]["204"] = serde_json::json!({})
+            }),
+        ];
+        for (name, edit) in unsupported {
+            let result = generate(&document(edit));
+            assert!(
+                matches!(result, Err(CodegenError::Unsupported { .. })),
+                "{}: {:?}",
+                name,
+                result.map(|_| ())
+            );
+        }
+
+        let mut trace = document(|_| ());
+        trace["paths"]["/things/{id}"]["trace"] = serde_json::json!({});
+        assert!(matches!(
+            generate(&trace),
+            Err(CodegenError::Unsupported { .. })
+        ));
+    }
+
+    #[test]
+    fn test_generate_rejects_invalid_documents() {
+        let missing_id = document(|op| {
+            op.as_object_mut().unwrap().remove("operationId");
+        });
+        let unknown_path_param =
+            document(|op| op["parameters"][0]["name"] = "other".into());
+        let dangling_ref = document(|op| {
+            op["parameters"][0] =
+                serde_json::json!({"$ref": "#/components/parameters/Id"})
+        });
+        for document in [missing_id, unknown_path_param, dangling_ref] {
+            let result = generate(&document);
+            assert!(
+                matches!(result, Err(CodegenError::Invalid { .. })),
+                "{:?}",
+                result.map(|_| ())
+            );
+        }
+    }
+
+    #[test]
+    fn test_doc_comment() {
+        let imports = Imports::default();
+        assert_eq!(
+            doc_comment("Summary\n\nA second paragraph", 4, &imports),
+            "    /// Summary\n    ///\n    /// A second paragraph\n"
+        );
+        let long = "word ".repeat(30);
+        for line in doc_comment(&long, 4, &imports).lines() {
+            assert!(line.len() <= DOC_WIDTH, "{:?} is too long", line);
+        }
+    }
+
+    #[test]
+    fn test_resolve_links() {
+        let imported = BTreeSet::from(["Ban".to_string()]);
+        assert_eq!(
+            resolve_links("[`Ban`] and [`ClientEvent`], `code`", &imported),
+            "[`Ban`] and [`ClientEvent`](crs_common::ClientEvent), `code`"
+        );
+    }
+}
@@ -0,0 +1,429 @@
+// Copyright 2025 Oxide Computer Company
+
+// Generated from crs-server/openapi.json by codegen.rs. Do not edit
+// by hand; regenerate with
+// `CRS_UPDATE_API_CLIENT=1 cargo test -p crs-api-client codegen`.
+
+//! Methods for every operation in the OpenAPI document
+
+use super::{decode, encode_path_segment, Client, Error};
+use crs_common::{
+    AvailabilityWindow, Ban, BanKind, BanRequest, ClientHistoryResponse,
+    ClientId, ClientMetricsResponse, ClientSortKey, ClientStatus,
+    ClusterStatus, ClusterSync, DeregisterRequest, HealthResponse,
+    HeartbeatRequest, HeartbeatResponse, ListBansResponse, ListClientsResponse,
+    ListMaintenanceWindowsResponse, MaintenanceRequest, MaintenanceWindow,
+    MaintenanceWindowRequest, PaginationOrder, QuarantineRequest,
+    ReadinessResponse, RegisterRequest, RegisterResponse, ServerInfo,
+};
+use serde::Serialize;
+use std::num::NonZeroU32;
+use uuid::Uuid;
+
+/// Query parameters of [`Client::list_clients`]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ListClientsQuery {
+    /// Only list clients whose hostname matches this glob, ignoring case
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+
+    /// Maximum number of items returned by a single call
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<NonZeroU32>,
+
+    /// Sort order (ascending by default)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order: Option<PaginationOrder>,
+
+    /// Only list clients running this operating system, ignoring case
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub os: Option<String>,
+
+    /// Token returned by previous call to retrieve the subsequent page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page_token: Option<String>,
+
+    /// Only list clients whose tags match this selector, such as
+    /// `env=prod,region in (us-west,us-east),!decommissioned`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub selector: Option<String>,
+
+    /// What to sort clients by (IP address by default)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort: Option<ClientSortKey>,
+
+    /// Only list clients with this status
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<ClientStatus>,
+
+    /// Only list clients whose tags match this selector, given as
+    /// comma-separated `key=value` pairs or any other selector syntax
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+
+    /// Only list clients running this client version
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+
+    /// Only compute availability over this window (24h, 7d or 30d)
+    ///
+    /// Without it, availability is computed over every window.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub window: Option<AvailabilityWindow>,
+}
+
+/// Query parameters of [`Client::stream_events`]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StreamEventsQuery {
+    /// Only send events with a sequence number greater than this
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub since: Option<u64>,
+}
+
+impl Client {
+    /// List all bans
+    pub async fn list_bans(&self) -> Result<ListBansResponse, Error> {
+        let url = self.url("/api/bans");
+        let request = self.client.get(url);
+        decode(self.send(request).await?).await
+    }
+
+    /// Ban a client ID or hostname
+    ///
+    /// Matching clients are refused with 403 Forbidden when they next
+    /// register or heartbeat.
+    pub async fn create_ban(&self, body: &BanRequest) -> Result<Ban, Error> {
+        let url = self.url("/api/bans");
+        let request = self.client.post(url).json(body);
+        decode(self.send(request).await?).await
+    }
+
+    /// Remove a ban
+    pub async fn delete_ban(
+        &self,
+        kind: BanKind,
+        value: &str,
+    ) -> Result<(), Error> {
+        let url = self.url(&format!(
+            "/api/bans/{}/{}",
+            encode_path_segment(&kind.to_string()),
+            encode_path_segment(value)
+        ));
+        let request = self.client.delete(url);
+        self.send(request).await?;
+        Ok(())
+    }
+
+    /// List registered clients
+    ///
+    /// Returns registered clients with their current status, registration
+    /// time, last heartbeat time, and availability statistics. Clients can
+    /// be filtered by status, operating system, version, hostname glob and
+    /// tags, and are sorted by IP address unless another sort key is given.
+    ///
+    /// The list is paginated: when more clients match than fit on one page,
+    /// `next_page` holds a token that fetches the rest when passed back as
+    /// `page_token`.
+    pub async fn list_clients(
+        &self,
+        query: &ListClientsQuery,
+    ) -> Result<ListClientsResponse, Error> {
+        let url = self.url("/api/clients");
+        let request = self.client.get(url).query(query);
+        decode(self.send(request).await?).await
+    }
+
+    /// Remove a client from the registry
+    ///
+    /// The client disappears from the client list. If it is still running
+    /// it will reappear the next time it registers, unless it is also
+    /// banned.
+    pub async fn remove_client(
+        &self,
+        client_id: ClientId,
+    ) -> Result<(), Error> {
+        let url = self.url(&format!(
+            "/api/clients/{}",
+            encode_path_segment(&client_id.to_string())
+        ));
+        let request = self.client.delete(url);
+        self.send(request).await?;
+        Ok(())
+    }
+
+    /// Get a client's history
+    ///
+    /// Returns every recorded registration, status transition, information
+    /// change and quarantine for the client, oldest first.
+    pub async fn client_history(
+        &self,
+        client_id: ClientId,
+    ) -> Result<ClientHistoryResponse, Error> {
+        let url = self.url(&format!(
+            "/api/clients/{}/history",
+            encode_path_segment(&client_id.to_string())
+        ));
+        let request = self.client.get(url);
+        decode(self.send(request).await?).await
+    }
+
+    /// End a client's maintenance
+    ///
+    /// The client goes back to the status its heartbeats warrant. Fails
+    /// with 409 Conflict if the client is not in maintenance.
+    pub async fn end_maintenance(
+        &self,
+        client_id: ClientId,
+    ) -> Result<(), Error> {
+        let url = self.url(&format!(
+            "/api/clients/{}/maintenance",
+            encode_path_segment(&client_id.to_string())
+        ));
+        let request = self.client.delete(url);
+        self.send(request).await?;
+        Ok(())
+    }
+
+    /// Put a client in maintenance
+    ///
+    /// The client's status stays maintenance, whatever its heartbeats say,
+    /// until the maintenance is ended.
+    pub async fn start_maintenance(
+        &self,
+        client_id: ClientId,
+        body: &MaintenanceRequest,
+    ) -> Result<(), Error> {
+        let url = self.url(&format!(
+            "/api/clients/{}/maintenance",
+            encode_path_segment(&client_id.to_string())
+        ));
+        let request = self.client.put(url).json(body);
+        self.send(request).await?;
+        Ok(())
+    }
+
+    /// Get a client's recent system metrics
+    ///
+    /// Returns the metrics samples the client attached to its recent
+    /// heartbeats, oldest first. Samples are kept in memory only, so the
+    /// list starts out empty when the server restarts.
+    pub async fn client_metrics(
+        &self,
+        client_id: ClientId,
+    ) -> Result<ClientMetricsResponse, Error> {
+        let url = self.url(&format!(
+            "/api/clients/{}/metrics",
+            encode_path_segment(&client_id.to_string())
+        ));
+        let request = self.client.get(url);
+        decode(self.send(request).await?).await
+    }
+
+    /// Lift a client's quarantine
+    pub async fn release_client(
+        &self,
+        client_id: ClientId,
+    ) -> Result<(), Error> {
+        let url = self.url(&format!(
+            "/api/clients/{}/quarantine",
+            encode_path_segment(&client_id.to_string())
+        ));
+        let request = self.client.delete(url);
+        self.send(request).await?;
+        Ok(())
+    }
+
+    /// Quarantine a client
+    ///
+    /// The client stays listed and keeps heartbeating, but is flagged until
+    /// the quarantine is lifted.
+    pub async fn quarantine_client(
+        &self,
+        client_id: ClientId,
+        body: &QuarantineRequest,
+    ) -> Result<(), Error> {
+        let url = self.url(&format!(
+            "/api/clients/{}/quarantine",
+            encode_path_segment(&client_id.to_string())
+        ));
+        let request = self.client.put(url).json(body);
+        self.send(request).await?;
+        Ok(())
+    }
+
+    /// Fetch cluster membership
+    ///
+    /// Lists the peers this server syncs with, whether the most recent sync
+    /// with each succeeded, and when each was last synced. A server that is
+    /// not in a cluster reports itself as not enabled, with no members.
+    pub async fn get_cluster_status(&self) -> Result<ClusterStatus, Error> {
+        let url = self.url("/api/cluster");
+        let request = self.client.get(url);
+        decode(self.send(request).await?).await
+    }
+
+    /// Exchange registry state with another cluster node
+    ///
+    /// Merges the sender's state into this server's registry and answers
+    /// with this server's state, after the merge. The request must carry
+    /// the cluster's shared secret in the `X-CRS-Cluster-Secret` header.
+    /// Servers that are not in a cluster answer 404 Not Found.
+    pub async fn sync_cluster(
+        &self,
+        body: &ClusterSync,
+    ) -> Result<ClusterSync, Error> {
+        let url = self.url("/api/cluster/sync");
+        let request = self.client.post(url).json(body);
+        decode(self.send(request).await?).await
+    }
+
+    /// Deregister a client that is shutting down
+    ///
+    /// Marks the client as departed so that a clean shutdown is
+    /// distinguishable from a crash. Returns 401 if the client ID is not
+    /// found in the registry or the client secret does not match, without
+    /// saying which.
+    pub async fn deregister(
+        &self,
+        body: &DeregisterRequest,
+    ) -> Result<(), Error> {
+        let url = self.url("/api/deregister");
+        let request = self.client.post(url).json(body);
+        self.send(request).await?;
+        Ok(())
+    }
+
+    /// Stream client events as Server-Sent Events
+    ///
+    /// Each event's `id` is its sequence number, its `event` is the kind of
+    /// change, and its `data` is the JSON-encoded
+    /// [`ClientEvent`](crs_common::ClientEvent). Resume after a reconnect
+    /// with the `Last-Event-ID` header or `?since=`; if the requested
+    /// events are no longer available the server responds with 410 Gone.
+    pub async fn stream_events(
+        &self,
+        query: &StreamEventsQuery,
+    ) -> Result<reqwest::Response, Error> {
+        let url = self.url("/api/events");
+        let request = self.client.get(url).query(query);
+        self.send(request).await
+    }
+
+    /// Record a client heartbeat
+    ///
+    /// Updates the last heartbeat timestamp for a registered client, along
+    /// with any system metrics and health check results the heartbeat
+    /// carries. A client with a failing check is marked degraded. Returns
+    /// 401 if the client ID is not found in the registry or the client
+    /// secret does not match, without saying which.
+    pub async fn heartbeat(
+        &self,
+        body: &HeartbeatRequest,
+    ) -> Result<HeartbeatResponse, Error> {
+        let url = self.url("/api/heartbeat");
+        let request = self.client.post(url).json(body);
+        decode(self.send(request).await?).await
+    }
+
+    /// List all maintenance windows
+    ///
+    /// Windows are listed in order of when they start. One-off windows are
+    /// removed once they are over.
+    pub async fn list_maintenance_windows(
+        &self,
+    ) -> Result<ListMaintenanceWindowsResponse, Error> {
+        let url = self.url("/api/maintenance-windows");
+        let request = self.client.get(url);
+        decode(self.send(request).await?).await
+    }
+
+    /// Schedule a maintenance window
+    ///
+    /// The window covers a client or every client matching a tag selector,
+    /// for a duration or on a recurring cron schedule. Status changes of
+    /// covered clients are recorded as expected and not sent to webhooks.
+    pub async fn create_maintenance_window(
+        &self,
+        body: &MaintenanceWindowRequest,
+    ) -> Result<MaintenanceWindow, Error> {
+        let url = self.url("/api/maintenance-windows");
+        let request = self.client.post(url).json(body);
+        decode(self.send(request).await?).await
+    }
+
+    /// Cancel a maintenance window
+    pub async fn delete_maintenance_window(
+        &self,
+        window_id: Uuid,
+    ) -> Result<(), Error> {
+        let url = self.url(&format!(
+            "/api/maintenance-windows/{}",
+            encode_path_segment(&window_id.to_string())
+        ));
+        let request = self.client.delete(url);
+        self.send(request).await?;
+        Ok(())
+    }
+
+    /// Get the OpenAPI document describing this API
+    pub async fn get_openapi(&self) -> Result<serde_json::Value, Error> {
+        let url = self.url("/api/openapi.json");
+        let request = self.client.get(url);
+        decode(self.send(request).await?).await
+    }
+
+    /// Register a new client
+    ///
+    /// Accepts client information (hostname, OS, IP, version, tags) and
+    /// registers the client in the registry. If the server has an
+    /// enrollment token, the request must carry it. If client certificates
+    /// are required, the hostname must match the certificate, which ties
+    /// the deterministic client ID to the certificate subject. Returns the
+    /// client's ID, a new client secret, and the recommended heartbeat
+    /// interval.
+    pub async fn register(
+        &self,
+        body: &RegisterRequest,
+    ) -> Result<RegisterResponse, Error> {
+        let url = self.url("/api/register");
+        let request = self.client.post(url).json(body);
+        decode(self.send(request).await?).await
+    }
+
+    /// Get server information
+    ///
+    /// Returns the server's version, the git commit it was built from, its
+    /// start time and address, and the default heartbeat thresholds.
+    pub async fn get_server_info(&self) -> Result<ServerInfo, Error> {
+        let url = self.url("/api/server");
+        let request = self.client.get(url);
+        decode(self.send(request).await?).await
+    }
+
+    /// Liveness check
+    ///
+    /// Always succeeds while the server is able to answer requests.
+    pub async fn healthz(&self) -> Result<HealthResponse, Error> {
+        let url = self.url("/healthz");
+        let request = self.client.get(url);
+        decode(self.send(request).await?).await
+    }
+
+    /// Serve metrics for Prometheus to scrape
+    pub async fn serve_metrics(&self) -> Result<reqwest::Response, Error> {
+        let url = self.url("/metrics");
+        let request = self.client.get(url);
+        self.send(request).await
+    }
+
+    /// Readiness check
+    ///
+    /// Succeeds once the registry has been loaded and the status sweeper is
+    /// running. Otherwise answers 503 Service Unavailable, naming what is
+    /// not ready yet.
+    pub async fn readyz(&self) -> Result<ReadinessResponse, Error> {
+        let url = self.url("/readyz");
+        let request = self.client.get(url);
+        decode(self.send(request).await?).await
+    }
+}
@@ -0,0 +1,285 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Typed client for the Central Registry Service API
+//!
+//! [`Client`] has one method per operation in the server's OpenAPI document
+//! (`crs-server/openapi.json`), named after the operation ID, taking and
+//! returning the request and response types from `crs-common`. Both
+//! `crs-client` and `crs-check` talk to the server through it, so nothing
+//! else formats API URLs or decodes responses by hand.
+//!
+//! The methods are generated from the document into `src/generated.rs`,
+//! which is committed. A test fails if it is out of date, so adding an
+//! endpoint to the server means regenerating it with
+//! `CRS_UPDATE_API_CLIENT=1 cargo test -p crs-api-client codegen`.
+//! Operations with query parameters take them as a struct named after the
+//! operation, such as [`ListClientsQuery`].
+//!
+//! # Administration
+//!
+//! Servers with an admin token refuse to remove, ban, quarantine or put
+//! clients in maintenance without it. Give it to
+//! [`Client::with_admin_token`] to send it with every request. Cluster
+//! peers likewise present the cluster's secret, given to
+//! [`Client::with_cluster_secret`], to sync.
+//!
+//! # Errors
+//!
+//! Every method returns an [`Error`]. Responses other than 2xx become
+//! [`Error::Response`], carrying the status and the server's error message,
+//! so callers can react to particular statuses with [`Error::status`].
+//!
+//! # Usage
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), crs_api_client::Error> {
+//! let client = crs_api_client::Client::new("http://127.0.0.1:8081");
+//! let clients = client.list_all_clients(&Default::default()).await?;
+//! println!("{} client(s) registered", clients.clients.len());
+//! # Ok(())
+//! # }
+//! ```
+
+mod generated;
+
+#[cfg(test)]
+mod codegen;
+
+pub use generated::*;
+
+use crs_common::{
+    ClientScanParams, ListClientsResponse, ADMIN_TOKEN_HEADER,
+    CLUSTER_SECRET_HEADER,
+};
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::num::NonZeroU32;
+
+/// Errors talking to the CRS server
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The request could not be sent or no response arrived
+    #[error("failed to reach server: {0}")]
+    Communication(#[source] reqwest::Error),
+
+    /// The server answered with a status other than 2xx
+    #[error("server returned error: {}{}", .status, describe(.message))]
+    Response {
+        /// Response status
+        status: StatusCode,
+        /// Machine-readable error code, if the server gave one
+        error_code: Option<String>,
+        /// The server's error message, if it sent one
+        message: Option<String>,
+    },
+
+    /// The response body was not what the operation returns
+    #[error("invalid response from server: {0}")]
+    InvalidResponse(#[source] reqwest::Error),
+}
+
+impl Error {
+    /// Status of the server's response, if there was one
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Error::Response { status, .. } => Some(*status),
+            Error::Communication(_) | Error::InvalidResponse(_) => None,
+        }
+    }
+}
+
+fn describe(message: &Option<String>) -> String {
+    match message {
+        Some(message) => format!(" ({})", message),
+        None => String::new(),
+    }
+}
+
+/// Body of an error response
+#[derive(Deserialize)]
+struct ErrorBody {
+    error_code: Option<String>,
+    message: Option<String>,
+}
+
+/// Client for one CRS server
+#[derive(Debug, Clone)]
+pub struct Client {
+    baseurl: String,
+    client: reqwest::Client,
+    admin_token: Option<String>,
+    cluster_secret: Option<String>,
+}
+
+impl Client {
+    /// Create a client for the server at `baseurl`, such as
+    /// `http://127.0.0.1:8081`
+    pub fn new(baseurl: &str) -> Self {
+        Self::new_with_client(baseurl, reqwest::Client::new())
+    }
+
+    /// Create a client that sends requests with a preconfigured HTTP client
+    ///
+    /// Use this to set timeouts, trusted CA certificates or a client
+    /// certificate.
+    pub fn new_with_client(baseurl: &str, client: reqwest::Client) -> Self {
+        Self {
+            baseurl: baseurl.trim_end_matches('/').to_string(),
+            client,
+            admin_token: None,
+            cluster_secret: None,
+        }
+    }
+
+    /// Send the server's admin token with every request
+    ///
+    /// The server only looks at it for operations that change the registry
+    /// on an operator's behalf.
+    pub fn with_admin_token(mut self, token: String) -> Self {
+        self.admin_token = Some(token);
+        self
+    }
+
+    /// Send the cluster's shared secret with every request
+    ///
+    /// The server only looks at it for `sync_cluster`.
+    pub fn with_cluster_secret(mut self, secret: String) -> Self {
+        self.cluster_secret = Some(secret);
+        self
+    }
+
+    /// Base URL of the server
+    pub fn baseurl(&self) -> &str {
+        &self.baseurl
+    }
+
+    /// Fetch the page of clients that `page_token` points at
+    ///
+    /// The token carries the filters and sort order of the first page.
+    pub async fn list_clients_next_page(
+        &self,
+        page_token: &str,
+        limit: Option<NonZeroU32>,
+    ) -> Result<ListClientsResponse, Error> {
+        self.list_clients(&ListClientsQuery {
+            page_token: Some(page_token.to_string()),
+            limit,
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// Fetch every client matching `scan`, following pages to the end
+    ///
+    /// The clients of every page are returned together, and `next_page` is
+    /// cleared.
+    pub async fn list_all_clients(
+        &self,
+        scan: &ClientScanParams,
+    ) -> Result<ListClientsResponse, Error> {
+        let mut all = self.list_clients(&scan.clone().into()).await?;
+        let mut next_page = all.next_page.take();
+        while let Some(token) = next_page {
+            let page = self.list_clients_next_page(&token, None).await?;
+            next_page = page.next_page;
+            all.clients.extend(page.clients);
+        }
+        Ok(all)
+    }
+
+    /// URL of an API path on this server
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.baseurl, path)
+    }
+
+    /// Send a request, turning responses other than 2xx into errors
+    async fn send(
+        &self,
+        mut request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, Error> {
+        if let Some(token) = &self.admin_token {
+            request = request.header(ADMIN_TOKEN_HEADER, token);
+        }
+        if let Some(secret) = &self.cluster_secret {
+            request = request.header(CLUSTER_SECRET_HEADER, secret);
+        }
+        let response = request.send().await.map_err(Error::Communication)?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+
+        let body = response.json::<ErrorBody>().await.ok();
+        Err(Error::Response {
+            status,
+            error_code: body.as_ref().and_then(|b| b.error_code.clone()),
+            message: body.and_then(|b| b.message),
+        })
+    }
+}
+
+impl From<ClientScanParams> for ListClientsQuery {
+    fn from(scan: ClientScanParams) -> Self {
+        Self {
+            hostname: scan.hostname,
+            order: scan.order,
+            os: scan.os,
+            selector: scan.selector,
+            sort: scan.sort,
+            status: scan.status,
+            tag: scan.tag,
+            version: scan.version,
+            window: scan.window,
+            ..Default::default()
+        }
+    }
+}
+
+/// Decode a JSON response body
+async fn decode<T: DeserializeOwned>(
+    response: reqwest::Response,
+) -> Result<T, Error> {
+    response.json().await.map_err(Error::InvalidResponse)
+}
+
+/// Percent-encode a value for use as one segment of a URL path
+fn encode_path_segment(value: &str) -> String {
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_path_segment() {
+        assert_eq!(
+            encode_path_segment("web-01.example.com"),
+            "web-01.example.com"
+        );
+        assert_eq!(encode_path_segment("a b/c%"), "a%20b%2Fc%25");
+    }
+
+    #[test]
+    fn test_error_display() {
+        let error = Error::Response {
+            status: StatusCode::NOT_FOUND,
+            error_code: None,
+            message: Some("Client not found".to_string()),
+        };
+        assert_eq!(
+            error.to_string(),
+            "server returned error: 404 Not Found (Client not found)"
+        );
+        assert_eq!(error.status(), Some(StatusCode::NOT_FOUND));
+    }
+}
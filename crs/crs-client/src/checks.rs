@@ -0,0 +1,452 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Health checks reported with heartbeats
+//!
+//! Each check is configured as a `[[checks]]` table in the client's config
+//! file, with a `name` and exactly one of:
+//! - `command` - a shell command, which passes if it exits successfully
+//! - `tcp` - a `host:port` address, which passes if it accepts a connection
+//! - `file` - a path, which passes if it exists
+//!
+//! Commands and connections are given `timeout_secs` (5 by default) to
+//! finish; a command that runs longer is killed, along with any processes
+//! it started. A [`CheckRunner`] runs every check in the background, once per
+//! heartbeat interval, and heartbeats carry the latest result of each, so a
+//! slow check never delays a heartbeat.
+
+use anyhow::Result;
+use crs_common::CheckResult;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tokio::time::MissedTickBehavior;
+
+/// Time a command or connection is given unless the check says otherwise
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest command output included in a failed check's message
+const MAX_MESSAGE_LEN: usize = 200;
+
+/// A health check as written in the config file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HealthCheck {
+    /// Name the check is reported under
+    pub name: String,
+
+    /// What the check does
+    #[serde(flatten)]
+    pub kind: CheckKind,
+
+    /// Seconds a command or connection is given to finish
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+}
+
+/// What a health check does
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckKind {
+    /// Run a shell command, passing if it exits successfully
+    Command(String),
+
+    /// Connect to a `host:port` address, passing if the connection succeeds
+    Tcp(String),
+
+    /// Pass if the file exists
+    File(PathBuf),
+}
+
+impl HealthCheck {
+    fn timeout(&self) -> Duration {
+        self.timeout_secs
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_TIMEOUT)
+    }
+
+    /// Run the check
+    pub async fn run(&self) -> CheckResult {
+        let outcome = match &self.kind {
+            CheckKind::Command(command) => {
+                run_command(command, self.timeout()).await
+            }
+            CheckKind::Tcp(address) => connect(address, self.timeout()).await,
+            CheckKind::File(path) => file_exists(path).await,
+        };
+        CheckResult {
+            name: self.name.clone(),
+            passed: outcome.is_ok(),
+            message: outcome.err(),
+        }
+    }
+}
+
+/// Check that every check has a name and that no two share one
+pub fn validate(checks: &[HealthCheck]) -> Result<()> {
+    let mut names = HashSet::new();
+    for check in checks {
+        if check.name.is_empty() {
+            anyhow::bail!("health checks must have a name");
+        }
+        if !names.insert(check.name.as_str()) {
+            anyhow::bail!(
+                "more than one health check is named {:?}",
+                check.name
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Health checks running in the background
+///
+/// Each check runs on its own, every `period`, so that a slow check holds
+/// up neither the other checks nor the heartbeat. A check that takes longer
+/// than `period` simply runs less often. The checks stop when the runner is
+/// dropped.
+pub struct CheckRunner {
+    period: Duration,
+    results: Arc<Mutex<Vec<Option<CheckResult>>>>,
+    _tasks: JoinSet<()>,
+}
+
+impl CheckRunner {
+    /// Start running `checks` every `period`, the first time straight away
+    pub fn start(checks: &[HealthCheck], period: Duration) -> Self {
+        let results = Arc::new(Mutex::new(vec![None; checks.len()]));
+        let mut tasks = JoinSet::new();
+        for (index, check) in checks.iter().cloned().enumerate() {
+            let results = results.clone();
+            tasks.spawn(async move {
+                let mut interval = tokio::time::interval(period);
+                interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                loop {
+                    interval.tick().await;
+                    let result = check.run().await;
+                    results.lock().unwrap()[index] = Some(result);
+                }
+            });
+        }
+        Self {
+            period,
+            results,
+            _tasks: tasks,
+        }
+    }
+
+    /// How often each check runs
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// The latest result of each check, in the order the checks were given
+    ///
+    /// Checks that have not finished their first run yet are left out.
+    pub fn latest(&self) -> Vec<CheckResult> {
+        self.results
+            .lock()
+            .unwrap()
+            .iter()
+            .flatten()
+            .cloned()
+            .collect()
+    }
+}
+
+async fn run_command(command: &str, timeout: Duration) -> Result<(), String> {
+    #[cfg(unix)]
+    let mut cmd = tokio::process::Command::new("sh");
+    #[cfg(unix)]
+    cmd.arg("-c");
+    #[cfg(not(unix))]
+    let mut cmd = tokio::process::Command::new("cmd");
+    #[cfg(not(unix))]
+    cmd.arg("/C");
+
+    // The command is killed if it runs past the timeout. On Unix it gets a
+    // process group of its own, so that anything it started, such as the
+    // other side of a pipeline, is killed along with it.
+    #[cfg(unix)]
+    cmd.process_group(0);
+    let child = cmd
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("failed to run command: {}", e))?;
+    #[cfg(unix)]
+    let pid = child.id();
+    let output =
+        match tokio::time::timeout(timeout, child.wait_with_output()).await {
+            Ok(output) => {
+                output.map_err(|e| format!("failed to run command: {}", e))?
+            }
+            Err(_) => {
+                #[cfg(unix)]
+                if let Some(pid) = pid {
+                    kill_process_group(pid);
+                }
+                return Err(format!("timed out after {}s", timeout.as_secs()));
+            }
+        };
+    if output.status.success() {
+        return Ok(());
+    }
+
+    // Prefer what the command said about the failure
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let detail = [stderr.trim(), stdout.trim()]
+        .into_iter()
+        .find(|s| !s.is_empty());
+    Err(match detail {
+        Some(detail) => format!("{}: {}", output.status, truncate(detail)),
+        None => output.status.to_string(),
+    })
+}
+
+/// Kill every process in the process group led by `pid`
+#[cfg(unix)]
+fn kill_process_group(pid: u32) {
+    // SAFETY: kill takes no pointers. If the group is already gone the call
+    // fails with ESRCH, which is fine.
+    unsafe {
+        libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+async fn connect(address: &str, timeout: Duration) -> Result<(), String> {
+    match tokio::time::timeout(timeout, tokio::net::TcpStream::connect(address))
+        .await
+    {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(e)) => Err(format!("cannot connect to {}: {}", address, e)),
+        Err(_) => Err(format!(
+            "connecting to {} timed out after {}s",
+            address,
+            timeout.as_secs()
+        )),
+    }
+}
+
+async fn file_exists(path: &Path) -> Result<(), String> {
+    match tokio::fs::try_exists(path).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(format!("{} does not exist", path.display())),
+        Err(e) => Err(format!("cannot check {}: {}", path.display(), e)),
+    }
+}
+
+/// Shorten command output to at most `MAX_MESSAGE_LEN` characters
+fn truncate(s: &str) -> String {
+    match s.char_indices().nth(MAX_MESSAGE_LEN - 3) {
+        Some((end, _)) if s.chars().count() > MAX_MESSAGE_LEN => {
+            format!("{}...", &s[..end])
+        }
+        _ => s.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Config {
+        checks: Vec<HealthCheck>,
+    }
+
+    fn check(kind: CheckKind) -> HealthCheck {
+        HealthCheck {
+            name: "test".to_string(),
+            kind,
+            timeout_secs: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_checks() {
+        let config: Config = toml::from_str(
+            r#"
+            [[checks]]
+            name = "nginx"
+            command = "systemctl is-active nginx"
+            timeout_secs = 2
+
+            [[checks]]
+            name = "postgres"
+            tcp = "127.0.0.1:5432"
+
+            [[checks]]
+            name = "ready"
+            file = "/run/app.ready"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.checks[0].kind,
+            CheckKind::Command("systemctl is-active nginx".to_string())
+        );
+        assert_eq!(config.checks[0].timeout(), Duration::from_secs(2));
+        assert_eq!(
+            config.checks[1].kind,
+            CheckKind::Tcp("127.0.0.1:5432".to_string())
+        );
+        assert_eq!(config.checks[1].timeout(), DEFAULT_TIMEOUT);
+        assert_eq!(
+            config.checks[2].kind,
+            CheckKind::File(PathBuf::from("/run/app.ready"))
+        );
+        assert!(validate(&config.checks).is_ok());
+
+        // Every check needs something to do
+        assert!(toml::from_str::<Config>("[[checks]]\nname = \"nothing\"\n")
+            .is_err());
+
+        let mut duplicated = config.checks.clone();
+        duplicated[1].name = "nginx".to_string();
+        assert!(validate(&duplicated).is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_command_check() {
+        let result = check(CheckKind::Command("true".to_string())).run().await;
+        assert!(result.passed);
+        assert_eq!(result.message, None);
+
+        let result =
+            check(CheckKind::Command("echo down >&2; exit 3".to_string()))
+                .run()
+                .await;
+        assert!(!result.passed);
+        assert_eq!(result.message.as_deref(), Some("exit status: 3: down"));
+
+        let result = HealthCheck {
+            timeout_secs: Some(1),
+            ..check(CheckKind::Command("sleep 10".to_string()))
+        }
+        .run()
+        .await;
+        assert_eq!(result.message.as_deref(), Some("timed out after 1s"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_command_timeout_kills_children() {
+        // The check forks a child that would outlive it, and writes down
+        // its process ID
+        let dir = tempfile::tempdir().unwrap();
+        let pid_path = dir.path().join("pid");
+        let command = format!(
+            "sleep 600 & echo $! > {}; wait",
+            pid_path.to_str().unwrap()
+        );
+        let result = HealthCheck {
+            timeout_secs: Some(1),
+            ..check(CheckKind::Command(command))
+        }
+        .run()
+        .await;
+        assert_eq!(result.message.as_deref(), Some("timed out after 1s"));
+
+        // Killed processes may linger as zombies until they are reaped
+        let pid = std::fs::read_to_string(&pid_path).unwrap();
+        let running = || {
+            let output = std::process::Command::new("ps")
+                .args(["-o", "stat=", "-p", pid.trim()])
+                .output()
+                .unwrap();
+            let stat = String::from_utf8_lossy(&output.stdout);
+            !stat.trim().is_empty() && !stat.trim().starts_with('Z')
+        };
+        for _ in 0..50 {
+            if !running() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("the check's child outlived the timeout");
+    }
+
+    #[tokio::test]
+    async fn test_tcp_and_file_checks() {
+        let listener =
+            tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let tcp = check(CheckKind::Tcp(address.clone()));
+        assert!(tcp.run().await.passed);
+        drop(listener);
+        let result = tcp.run().await;
+        assert!(!result.passed);
+        assert!(result.message.unwrap().contains(&address));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ready");
+        let file = check(CheckKind::File(path.clone()));
+        assert!(!file.run().await.passed);
+        std::fs::write(&path, "").unwrap();
+        assert!(file.run().await.passed);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_check_runner_keeps_latest_results() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ready");
+        let checks = vec![
+            HealthCheck {
+                name: "slow".to_string(),
+                timeout_secs: Some(30),
+                ..check(CheckKind::Command("sleep 30".to_string()))
+            },
+            HealthCheck {
+                name: "ready".to_string(),
+                ..check(CheckKind::File(path.clone()))
+            },
+            HealthCheck {
+                name: "root".to_string(),
+                ..check(CheckKind::File(PathBuf::from("/")))
+            },
+        ];
+        async fn wait_for(
+            runner: &CheckRunner,
+            done: fn(&[CheckResult]) -> bool,
+        ) {
+            for _ in 0..100 {
+                if done(&runner.latest()) {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            panic!("checks did not finish: {:?}", runner.latest());
+        }
+
+        let runner = CheckRunner::start(&checks, Duration::from_millis(50));
+        // The slow check holds up neither of the others
+        wait_for(&runner, |results| results.len() == 2).await;
+        let results = runner.latest();
+        assert_eq!(results[0].name, "ready");
+        assert!(!results[0].passed);
+        assert_eq!(results[1].name, "root");
+        assert!(results[1].passed);
+
+        // Checks run again every period
+        std::fs::write(&path, "").unwrap();
+        wait_for(&runner, |results| results[0].passed).await;
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("short"), "short");
+        let long = "é".repeat(MAX_MESSAGE_LEN + 1);
+        let truncated = truncate(&long);
+        assert_eq!(truncated.chars().count(), MAX_MESSAGE_LEN);
+        assert!(truncated.ends_with("..."));
+    }
+}
@@ -11,7 +11,32 @@
 //! - Registration with the CRS server
 //! - Periodic heartbeat transmission
 //! - Automatic reconnection on failures
-//! - Graceful shutdown
+//! - Failing over to other servers when the primary cannot be reached
+//! - Graceful shutdown (deregistering from the server on Ctrl-C or SIGTERM)
+//!
+//! # Failover
+//!
+//! With [`CrsClient::with_failover_servers`], the client knows more than one
+//! server, in order of preference. When the server it is using cannot be
+//! reached at all, it moves on to the next one and registers there. Errors
+//! the server answers with do not cause a failover. While it is away from
+//! the primary, the client checks every so often (see
+//! [`CrsClient::with_failback_interval`]) whether the primary is ready
+//! again, and if so registers with it and goes back to using it.
+//!
+//! # System Metrics
+//!
+//! With [`CrsClient::with_system_metrics`], every heartbeat also carries
+//! the load average, memory and root filesystem use, and uptime, as
+//! collected by the [`metrics`] module. Servers that predate metrics ignore
+//! them.
+//!
+//! # Health Checks
+//!
+//! With [`CrsClient::with_health_checks`], the client runs a set of
+//! [`checks::HealthCheck`]s in the background, once per heartbeat interval,
+//! and sends the latest result of each with every heartbeat. The server
+//! marks a client whose checks fail as degraded.
 //!
 //! # Client ID Generation
 //!
@@ -21,6 +46,19 @@
 //!
 //! This ensures the same client receives the same ID across restarts.
 //!
+//! # Authentication
+//!
+//! To talk to a server whose certificate is signed by a private CA, pass the
+//! CA certificates with [`CrsClient::with_ca_bundle`]. If the server
+//! requires client certificates, pass one with
+//! [`CrsClient::with_client_certificate`]; the certificate must be issued for
+//! the client's hostname.
+//!
+//! If the server requires an enrollment token, pass it with
+//! [`CrsClient::with_enrollment_token`]. Each registration returns a client
+//! secret which the client presents with every heartbeat; if the server
+//! rejects the secret the client simply registers again.
+//!
 //! # Usage
 //!
 //! ```no_run
@@ -36,9 +74,19 @@
 //! # }
 //! ```
 
+pub mod checks;
+pub mod metrics;
+
 use anyhow::{Context, Result};
-use crs_common::{ClientId, ClientInfo, HeartbeatRequest, RegisterRequest};
+use checks::{CheckRunner, HealthCheck};
+use crs_api_client::Client as ApiClient;
+use crs_common::{
+    ClientId, ClientInfo, DeregisterRequest, HeartbeatRequest, RegisterRequest,
+};
+use reqwest::StatusCode;
 use std::collections::HashMap;
+use std::future::Future;
+use std::path::Path;
 use std::process::Command;
 use std::time::Duration;
 
@@ -46,11 +94,31 @@ use std::time::Duration;
 ///
 /// Handles registration and heartbeat communication with the CRS server.
 pub struct CrsClient {
-    server_url: String,
+    /// Server URLs in order of preference; the first is the primary
+    server_urls: Vec<String>,
+    /// Index of the server currently in use
+    active_server: usize,
+    failback_interval: Duration,
     client_info: ClientInfo,
     client_id: Option<ClientId>,
+    client_secret: Option<String>,
+    enrollment_token: Option<String>,
     heartbeat_interval: Duration,
-    http_client: reqwest::Client,
+    report_metrics: bool,
+    checks: Vec<HealthCheck>,
+    check_runner: Option<CheckRunner>,
+    http: reqwest::Client,
+    api: ApiClient,
+    tls: TlsOptions,
+}
+
+/// TLS settings for the HTTP client
+#[derive(Clone, Default)]
+struct TlsOptions {
+    /// CA certificates to trust instead of the system roots
+    ca_certs: Vec<reqwest::Certificate>,
+    /// Client certificate and key to present to the server
+    identity: Option<reqwest::Identity>,
 }
 
 impl CrsClient {
@@ -88,54 +156,175 @@ impl CrsClient {
             tags: HashMap::new(),
         };
 
-        let http_client = reqwest::Client::builder()
-            .timeout(Duration::from_secs(10))
-            .build()
-            .context("failed to create HTTP client")?;
+        let tls = TlsOptions::default();
+        let http = build_http_client(&tls)?;
+        let api = ApiClient::new_with_client(&server_url, http.clone());
 
         Ok(Self {
-            server_url,
+            server_urls: vec![server_url],
+            active_server: 0,
+            failback_interval: Duration::from_secs(60),
             client_info,
             client_id: None,
+            client_secret: None,
+            enrollment_token: None,
             heartbeat_interval: Duration::from_secs(10),
-            http_client,
+            report_metrics: false,
+            checks: Vec::new(),
+            check_runner: None,
+            http,
+            api,
+            tls,
         })
     }
 
-    /// Register with the CRS server
-    async fn register(&mut self) -> Result<()> {
-        let url = format!("{}/api/register", self.server_url);
+    /// Fail over to these servers, in order, when the primary (the server
+    /// given to [`CrsClient::new`]) cannot be reached
+    pub fn with_failover_servers(mut self, server_urls: Vec<String>) -> Self {
+        self.server_urls.truncate(1);
+        self.server_urls.extend(server_urls);
+        self
+    }
 
-        let request = RegisterRequest {
-            client_info: self.client_info.clone(),
-        };
+    /// Set how often to check whether the primary server is back while
+    /// failed over to another one (every 60 seconds by default)
+    pub fn with_failback_interval(mut self, interval: Duration) -> Self {
+        self.failback_interval = interval;
+        self
+    }
 
-        let response = self
-            .http_client
-            .post(&url)
-            .json(&request)
-            .send()
-            .await
-            .context("failed to send registration request")?;
+    /// Set the enrollment token to present when registering
+    pub fn with_enrollment_token(mut self, token: String) -> Self {
+        self.enrollment_token = Some(token);
+        self
+    }
 
-        if !response.status().is_success() {
-            anyhow::bail!(
-                "registration failed with status: {}",
-                response.status()
-            );
+    /// Attach system metrics to every heartbeat
+    pub fn with_system_metrics(mut self) -> Self {
+        self.report_metrics = true;
+        self
+    }
+
+    /// Run health checks once per heartbeat interval and report their
+    /// latest results with every heartbeat
+    ///
+    /// Returns an error if a check has no name or two checks share one.
+    pub fn with_health_checks(
+        mut self,
+        checks: Vec<HealthCheck>,
+    ) -> Result<Self> {
+        checks::validate(&checks)?;
+        self.checks = checks;
+        Ok(self)
+    }
+
+    /// Verify the server against the CA certificates in a PEM bundle
+    ///
+    /// Only the certificates in the bundle are trusted; the system's root
+    /// certificates are no longer consulted.
+    pub fn with_ca_bundle(mut self, path: &Path) -> Result<Self> {
+        let pem = std::fs::read(path)
+            .with_context(|| format!("failed to read CA bundle {:?}", path))?;
+        let certs = reqwest::Certificate::from_pem_bundle(&pem)
+            .with_context(|| format!("failed to parse CA bundle {:?}", path))?;
+        if certs.is_empty() {
+            anyhow::bail!("no certificates found in CA bundle {:?}", path);
         }
 
-        let register_response: crs_common::RegisterResponse = response
-            .json()
+        self.tls.ca_certs = certs;
+        self.http = build_http_client(&self.tls)?;
+        self.use_server(self.active_server);
+        Ok(self)
+    }
+
+    /// Present a client certificate to the server
+    ///
+    /// `cert` is a PEM certificate chain and `key` a PEM PKCS#8 private key.
+    pub fn with_client_certificate(
+        mut self,
+        cert: &Path,
+        key: &Path,
+    ) -> Result<Self> {
+        let cert_pem = std::fs::read(cert).with_context(|| {
+            format!("failed to read client certificate {:?}", cert)
+        })?;
+        let key_pem = std::fs::read(key)
+            .with_context(|| format!("failed to read client key {:?}", key))?;
+        let identity = reqwest::Identity::from_pkcs8_pem(&cert_pem, &key_pem)
+            .context("failed to load client certificate")?;
+
+        self.tls.identity = Some(identity);
+        self.http = build_http_client(&self.tls)?;
+        self.use_server(self.active_server);
+        Ok(self)
+    }
+
+    /// URL of the server currently in use
+    fn server_url(&self) -> &str {
+        &self.server_urls[self.active_server]
+    }
+
+    /// Switch to the server at `index` in the server list
+    ///
+    /// The client is registered with the old server, not the new one, so
+    /// its credentials are dropped.
+    fn use_server(&mut self, index: usize) {
+        if index != self.active_server {
+            self.client_id = None;
+            self.client_secret = None;
+        }
+        self.active_server = index;
+        self.api = ApiClient::new_with_client(
+            &self.server_urls[index],
+            self.http.clone(),
+        );
+    }
+
+    /// Move on to the next server in the list, wrapping around to the
+    /// primary after the last one
+    ///
+    /// Returns false if there is no other server to fail over to.
+    fn fail_over(&mut self) -> bool {
+        if self.server_urls.len() < 2 {
+            return false;
+        }
+        let next = (self.active_server + 1) % self.server_urls.len();
+        self.use_server(next);
+        eprintln!("Failing over to {}", self.server_url());
+        true
+    }
+
+    /// Whether the primary server is up and ready to take clients
+    async fn primary_ready(&self) -> bool {
+        ApiClient::new_with_client(&self.server_urls[0], self.http.clone())
+            .readyz()
             .await
-            .context("failed to parse registration response")?;
+            .is_ok()
+    }
+
+    /// Register with the CRS server
+    async fn register(&mut self) -> Result<()> {
+        let request = RegisterRequest {
+            client_info: self.client_info.clone(),
+            enrollment_token: self.enrollment_token.clone(),
+        };
+
+        let register_response = match self.api.register(&request).await {
+            Ok(response) => response,
+            Err(e) if e.status() == Some(StatusCode::UNAUTHORIZED) => {
+                anyhow::bail!("registration refused: invalid enrollment token")
+            }
+            Err(e) => return Err(e).context("registration failed"),
+        };
 
         self.client_id = Some(register_response.client_id);
+        self.client_secret = Some(register_response.client_secret);
         self.heartbeat_interval =
             Duration::from_secs(register_response.heartbeat_interval_secs);
 
         println!(
-            "Registered with CRS server, client ID: {}",
+            "Registered with CRS server {}, client ID: {}",
+            self.server_url(),
             register_response.client_id
         );
         println!(
@@ -149,62 +338,123 @@ impl CrsClient {
     /// Send a heartbeat to the CRS server
     /// Returns true if heartbeat succeeded, false if client needs to re-register
     async fn heartbeat(&self) -> Result<bool> {
-        let client_id = self.client_id.context("client not registered")?;
+        let (client_id, client_secret) = self.credentials()?;
+
+        let request = HeartbeatRequest {
+            client_id,
+            client_secret,
+            metrics: self
+                .report_metrics
+                .then(metrics::collect)
+                .filter(|m| !m.is_empty()),
+            checks: self
+                .check_runner
+                .as_ref()
+                .map(CheckRunner::latest)
+                .unwrap_or_default(),
+        };
 
-        let url = format!("{}/api/heartbeat", self.server_url);
+        match self.api.heartbeat(&request).await {
+            Ok(_) => Ok(true), // Heartbeat succeeded
+            // Check if the server doesn't know about this client or no
+            // longer accepts our secret
+            Err(e)
+                if matches!(
+                    e.status(),
+                    Some(StatusCode::NOT_FOUND | StatusCode::UNAUTHORIZED)
+                ) =>
+            {
+                Ok(false) // Need to re-register
+            }
+            Err(e) => Err(e).context("heartbeat failed"),
+        }
+    }
 
-        let request = HeartbeatRequest { client_id };
+    /// Tell the CRS server that this client is shutting down
+    async fn deregister(&self) -> Result<()> {
+        let (client_id, client_secret) = self.credentials()?;
 
-        let response = self
-            .http_client
-            .post(&url)
-            .json(&request)
-            .send()
-            .await
-            .context("failed to send heartbeat")?;
+        let request = DeregisterRequest {
+            client_id,
+            client_secret,
+        };
 
-        // Check if the server doesn't know about this client (404)
-        if response.status() == reqwest::StatusCode::NOT_FOUND {
-            return Ok(false); // Need to re-register
-        }
+        self.api
+            .deregister(&request)
+            .await
+            .context("deregistration failed")
+    }
 
-        if !response.status().is_success() {
-            anyhow::bail!(
-                "heartbeat failed with status: {}",
-                response.status()
-            );
+    /// Client ID and secret from the most recent registration
+    fn credentials(&self) -> Result<(ClientId, String)> {
+        match (self.client_id, &self.client_secret) {
+            (Some(client_id), Some(secret)) => Ok((client_id, secret.clone())),
+            _ => anyhow::bail!("client not registered"),
         }
+    }
 
-        Ok(true) // Heartbeat succeeded
+    /// Run the client until Ctrl-C or SIGTERM is received
+    ///
+    /// See [`CrsClient::run_until`].
+    pub async fn run(self) -> Result<()> {
+        self.run_until(shutdown_signal()).await
     }
 
-    /// Run the client heartbeat loop
+    /// Run the client heartbeat loop until `shutdown` completes
     ///
-    /// This function runs indefinitely, sending heartbeats at the
-    /// configured interval. It will automatically register on startup,
-    /// re-register if the server forgets about the client, and retry
-    /// on connection failures.
-    pub async fn run(mut self) -> Result<()> {
-        // Initial registration with retry
-        loop {
-            match self.register().await {
-                Ok(()) => {
-                    println!("Successfully connected to server");
-                    break;
-                }
-                Err(e) => {
-                    eprintln!("Failed to register with server: {}", e);
-                    eprintln!("Retrying in 10 seconds...");
-                    tokio::time::sleep(Duration::from_secs(10)).await;
-                }
+    /// Sends heartbeats at the configured interval. It will automatically
+    /// register on startup, re-register if the server forgets about the
+    /// client, and retry on connection failures, failing over to the next
+    /// server if there is one. Once `shutdown` completes the client
+    /// deregisters from the server it is using (best effort) and returns.
+    pub async fn run_until(
+        mut self,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<()> {
+        tokio::select! {
+            result = self.heartbeat_loop() => return result,
+            () = shutdown => {}
+        }
+
+        println!("Shutting down...");
+        if self.client_id.is_some() {
+            match self.deregister().await {
+                Ok(()) => println!("Deregistered from server"),
+                Err(e) => eprintln!("Failed to deregister: {}", e),
             }
         }
 
+        Ok(())
+    }
+
+    /// Register and then send heartbeats forever
+    async fn heartbeat_loop(&mut self) -> Result<()> {
+        self.connect().await;
+        println!("Successfully connected to server");
+
         let mut interval = tokio::time::interval(self.heartbeat_interval);
+        let mut failback_check = tokio::time::Instant::now();
 
         loop {
             interval.tick().await;
 
+            // Go back to the primary as soon as it is ready again
+            if self.active_server != 0
+                && failback_check.elapsed() >= self.failback_interval
+            {
+                failback_check = tokio::time::Instant::now();
+                if self.primary_ready().await {
+                    println!(
+                        "Primary server {} is back, switching to it",
+                        self.server_urls[0]
+                    );
+                    self.use_server(0);
+                    self.connect().await;
+                    interval = tokio::time::interval(self.heartbeat_interval);
+                    continue;
+                }
+            }
+
             // Try to send heartbeat
             match self.heartbeat().await {
                 Ok(true) => {
@@ -215,22 +465,21 @@ impl CrsClient {
                     eprintln!(
                         "Server does not recognize client, re-registering..."
                     );
-                    loop {
-                        match self.register().await {
-                            Ok(()) => {
-                                println!(
-                                    "Successfully re-registered with server"
-                                );
-                                break;
-                            }
-                            Err(e) => {
-                                eprintln!("Failed to re-register: {}", e);
-                                eprintln!("Retrying in 10 seconds...");
-                                tokio::time::sleep(Duration::from_secs(10))
-                                    .await;
-                            }
-                        }
-                    }
+                    self.connect().await;
+                    println!("Successfully re-registered with server");
+                    failback_check = tokio::time::Instant::now();
+                    interval = tokio::time::interval(self.heartbeat_interval);
+                }
+                Err(e) if is_unreachable(&e) && self.server_urls.len() > 1 => {
+                    eprintln!("Heartbeat failed: {}", e);
+                    self.fail_over();
+                    self.connect().await;
+                    println!(
+                        "Successfully registered with {}",
+                        self.server_url()
+                    );
+                    failback_check = tokio::time::Instant::now();
+                    interval = tokio::time::interval(self.heartbeat_interval);
                 }
                 Err(e) => {
                     eprintln!("Heartbeat failed: {}", e);
@@ -240,6 +489,61 @@ impl CrsClient {
         }
     }
 
+    /// Register with the current server, retrying until it succeeds
+    ///
+    /// If the server cannot be reached, tries the next one straight away.
+    /// Once every server has been tried, waits 10 seconds and starts over
+    /// from the primary. A server that answered with an error is retried
+    /// after 10 seconds.
+    async fn connect(&mut self) {
+        let mut attempts = 0;
+        loop {
+            match self.register().await {
+                Ok(()) => {
+                    self.start_checks();
+                    return;
+                }
+                Err(e) => {
+                    eprintln!(
+                        "Failed to register with {}: {}",
+                        self.server_url(),
+                        e
+                    );
+                    attempts += 1;
+                    let unreachable = is_unreachable(&e);
+                    if unreachable
+                        && attempts < self.server_urls.len()
+                        && self.fail_over()
+                    {
+                        continue;
+                    }
+                    eprintln!("Retrying in 10 seconds...");
+                    tokio::time::sleep(Duration::from_secs(10)).await;
+                    attempts = 0;
+                    if unreachable {
+                        self.use_server(0);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Start running the health checks every heartbeat interval, unless
+    /// they already are
+    ///
+    /// Servers can hand out different intervals, so the checks are
+    /// restarted whenever the interval changes.
+    fn start_checks(&mut self) {
+        let running = self
+            .check_runner
+            .as_ref()
+            .is_some_and(|runner| runner.period() == self.heartbeat_interval);
+        if !self.checks.is_empty() && !running {
+            self.check_runner =
+                Some(CheckRunner::start(&self.checks, self.heartbeat_interval));
+        }
+    }
+
     /// Get the local IP address (best effort)
     fn get_local_ip() -> Option<String> {
         // Try to get a non-loopback local IP
@@ -276,6 +580,62 @@ impl CrsClient {
     }
 }
 
+/// Whether an error means the server could not be reached at all, as
+/// opposed to the server answering with an error
+fn is_unreachable(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<crs_api_client::Error>(),
+        Some(crs_api_client::Error::Communication(_))
+    )
+}
+
+/// Build the HTTP client used to talk to the server
+///
+/// If CA certificates are given, only those are trusted when verifying the
+/// server's certificate.
+fn build_http_client(tls: &TlsOptions) -> Result<reqwest::Client> {
+    let mut builder =
+        reqwest::Client::builder().timeout(Duration::from_secs(10));
+
+    if !tls.ca_certs.is_empty() {
+        builder = builder.tls_built_in_root_certs(false);
+        for cert in &tls.ca_certs {
+            builder = builder.add_root_certificate(cert.clone());
+        }
+    }
+    if let Some(identity) = &tls.identity {
+        builder = builder.identity(identity.clone());
+    }
+
+    builder.build().context("failed to create HTTP client")
+}
+
+/// Wait for Ctrl-C, or SIGTERM on Unix platforms
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = sigterm.recv() => {}
+                }
+            }
+            Err(e) => {
+                eprintln!("Failed to install SIGTERM handler: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
 /// Add custom tags to client information
 ///
 /// Can be used before creating the client to add metadata.
@@ -301,10 +661,52 @@ mod tests {
 
         assert!(result.is_ok());
         let client = result.unwrap();
-        assert_eq!(client.server_url, "http://127.0.0.1:8081");
+        assert_eq!(client.server_url(), "http://127.0.0.1:8081");
         assert_eq!(client.client_info.version, "1.0.0");
         assert_eq!(client.client_info.os, std::env::consts::OS);
         assert!(client.client_id.is_none()); // Not registered yet
+        assert!(client.enrollment_token.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_client_fails_over_in_order() {
+        let mut client = CrsClient::new(
+            "http://primary:8081".to_string(),
+            "1.0.0".to_string(),
+        )
+        .await
+        .unwrap();
+        assert!(!client.fail_over());
+
+        let mut client = client.with_failover_servers(vec![
+            "http://secondary:8081".to_string(),
+            "http://tertiary:8081".to_string(),
+        ]);
+        client.client_id = Some(client.client_info.client_id());
+        client.client_secret = Some("secret".to_string());
+
+        // Credentials belong to one server and are dropped on failover
+        assert!(client.fail_over());
+        assert_eq!(client.server_url(), "http://secondary:8081");
+        assert!(client.client_id.is_none());
+        assert!(client.client_secret.is_none());
+        assert!(client.fail_over());
+        assert_eq!(client.server_url(), "http://tertiary:8081");
+        assert!(client.fail_over());
+        assert_eq!(client.server_url(), "http://primary:8081");
+    }
+
+    #[tokio::test]
+    async fn test_client_with_enrollment_token() {
+        let client = CrsClient::new(
+            "http://127.0.0.1:8081".to_string(),
+            "1.0.0".to_string(),
+        )
+        .await
+        .unwrap()
+        .with_enrollment_token("enroll-me".to_string());
+
+        assert_eq!(client.enrollment_token.as_deref(), Some("enroll-me"));
     }
 
     #[tokio::test]
@@ -333,6 +735,24 @@ mod tests {
         assert!(!client.client_info.ip_address.is_empty());
     }
 
+    #[tokio::test]
+    async fn test_client_rejects_empty_ca_bundle() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("empty-ca.pem");
+        std::fs::write(&path, "not a certificate\n").unwrap();
+
+        let result = CrsClient::new(
+            "https://127.0.0.1:8081".to_string(),
+            "1.0.0".to_string(),
+        )
+        .await
+        .unwrap()
+        .with_ca_bundle(&path);
+
+        let error = result.err().expect("empty CA bundle was accepted");
+        assert!(error.to_string().contains("no certificates found"));
+    }
+
     #[test]
     fn test_add_client_tags() {
         let mut tags = HashMap::new();
@@ -344,6 +764,24 @@ mod tests {
         assert_eq!(tags.get("region"), Some(&"us-west".to_string()));
     }
 
+    #[tokio::test]
+    async fn test_client_rejects_duplicate_check_names() {
+        let check = HealthCheck {
+            name: "web".to_string(),
+            kind: checks::CheckKind::Tcp("127.0.0.1:80".to_string()),
+            timeout_secs: None,
+        };
+        let client = CrsClient::new(
+            "http://127.0.0.1:8081".to_string(),
+            "1.0.0".to_string(),
+        )
+        .await
+        .unwrap();
+
+        let result = client.with_health_checks(vec![check.clone(), check]);
+        assert!(result.is_err());
+    }
+
     #[tokio::test]
     async fn test_heartbeat_requires_registration() {
         let client = CrsClient::new(
@@ -361,4 +799,39 @@ mod tests {
             .to_string()
             .contains("client not registered"));
     }
+
+    #[tokio::test]
+    async fn test_deregister_requires_registration() {
+        let client = CrsClient::new(
+            "http://127.0.0.1:8081".to_string(),
+            "1.0.0".to_string(),
+        )
+        .await
+        .unwrap();
+
+        let result = client.deregister().await;
+        assert!(result.is_err());
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("client not registered"));
+    }
+
+    #[tokio::test]
+    async fn test_run_until_returns_on_shutdown() {
+        // Nothing listens on this port, so the client never registers
+        let client = CrsClient::new(
+            "http://127.0.0.1:1".to_string(),
+            "1.0.0".to_string(),
+        )
+        .await
+        .unwrap();
+
+        let result = tokio::time::timeout(
+            Duration::from_secs(5),
+            client.run_until(std::future::ready(())),
+        )
+        .await;
+        assert!(result.expect("run_until did not stop").is_ok());
+    }
 }
@@ -7,6 +7,7 @@
 
 use anyhow::{Context, Result};
 use clap::Parser;
+use crs_client::checks::HealthCheck;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
@@ -14,25 +15,89 @@ use std::path::PathBuf;
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
-    /// URL of the CRS server
-    #[arg(short, long)]
-    server: Option<String>,
+    /// URL of the CRS server; repeat it (or separate URLs with commas) to
+    /// give servers to fail over to, in order of preference
+    #[arg(short, long, value_delimiter = ',')]
+    server: Vec<String>,
 
     /// Path to TOML configuration file
     #[arg(short, long)]
     config: Option<PathBuf>,
+
+    /// Enrollment token required by the CRS server
+    #[arg(long)]
+    enrollment_token: Option<String>,
+
+    /// PEM file with the CA certificates to verify the server against
+    #[arg(long)]
+    ca_bundle: Option<PathBuf>,
+
+    /// PEM client certificate to present to the server
+    #[arg(long, requires = "client_key")]
+    client_cert: Option<PathBuf>,
+
+    /// PEM PKCS#8 private key for the client certificate
+    #[arg(long, requires = "client_cert")]
+    client_key: Option<PathBuf>,
+
+    /// Report load average, memory, disk use and uptime with each heartbeat
+    #[arg(long)]
+    report_metrics: bool,
 }
 
 /// Configuration file structure
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 struct Config {
-    /// URL of the CRS server
-    server: Option<String>,
+    /// URL of the CRS server, or a list of them in order of preference
+    server: Option<ServerList>,
+
+    /// Enrollment token required by the CRS server
+    enrollment_token: Option<String>,
+
+    /// PEM file with the CA certificates to verify the server against
+    ca_bundle: Option<PathBuf>,
+
+    /// PEM client certificate to present to the server
+    client_cert: Option<PathBuf>,
+
+    /// PEM PKCS#8 private key for the client certificate
+    client_key: Option<PathBuf>,
+
+    /// Report system metrics with each heartbeat
+    report_metrics: Option<bool>,
+
+    /// Health checks to run once per heartbeat interval
+    #[serde(default)]
+    checks: Vec<HealthCheck>,
+}
+
+/// One server URL, or several to fail over between
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+enum ServerList {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl ServerList {
+    fn into_vec(self) -> Vec<String> {
+        match self {
+            ServerList::One(server) => vec![server],
+            ServerList::Many(servers) => servers,
+        }
+    }
 }
 
 /// Final resolved configuration
 struct ResolvedConfig {
-    server: String,
+    /// Server URLs in order of preference, never empty
+    servers: Vec<String>,
+    enrollment_token: Option<String>,
+    ca_bundle: Option<PathBuf>,
+    client_cert: Option<PathBuf>,
+    client_key: Option<PathBuf>,
+    report_metrics: bool,
+    checks: Vec<HealthCheck>,
 }
 
 fn load_config(path: &PathBuf) -> Result<Config> {
@@ -50,8 +115,8 @@ fn resolve_config(args: Args) -> Result<ResolvedConfig> {
         None
     };
 
-    // Resolve server, preferring CLI over config file
-    let server = if let Some(cli_server) = args.server {
+    // Resolve servers, preferring CLI over config file
+    let servers = if !args.server.is_empty() {
         if let Some(ref file_cfg) = file_config {
             if file_cfg.server.is_some() {
                 eprintln!(
@@ -59,19 +124,44 @@ fn resolve_config(args: Args) -> Result<ResolvedConfig> {
                 );
             }
         }
-        cli_server
+        args.server
     } else if let Some(ref file_cfg) = file_config {
         file_cfg
             .server
             .clone()
             .context("Server not specified in config file")?
+            .into_vec()
     } else {
         anyhow::bail!(
             "Server URL must be specified via --server or in config file"
         );
     };
+    if servers.is_empty() {
+        anyhow::bail!("At least one server URL must be specified");
+    }
 
-    Ok(ResolvedConfig { server })
+    // Resolve the remaining options, preferring CLI over config file
+    let file_config = file_config.unwrap_or_default();
+    let enrollment_token =
+        args.enrollment_token.or(file_config.enrollment_token);
+    let ca_bundle = args.ca_bundle.or(file_config.ca_bundle);
+    let client_cert = args.client_cert.or(file_config.client_cert);
+    let client_key = args.client_key.or(file_config.client_key);
+    if client_cert.is_some() != client_key.is_some() {
+        anyhow::bail!("client_cert and client_key must be given together");
+    }
+    let report_metrics =
+        args.report_metrics || file_config.report_metrics.unwrap_or(false);
+
+    Ok(ResolvedConfig {
+        servers,
+        enrollment_token,
+        ca_bundle,
+        client_cert,
+        client_key,
+        report_metrics,
+        checks: file_config.checks,
+    })
 }
 
 #[tokio::main]
@@ -83,12 +173,31 @@ async fn main() -> Result<()> {
     let version = env!("CARGO_PKG_VERSION").to_string();
 
     println!("CRS Client starting...");
-    println!("Server: {}", config.server);
+    println!("Server: {}", config.servers.join(", "));
     println!("Client Version: {}", version);
     println!();
 
     // Create and run the client
-    let client = crs_client::CrsClient::new(config.server, version).await?;
+    let mut servers = config.servers.into_iter();
+    let primary = servers.next().expect("resolve_config checks for a server");
+    let mut client = crs_client::CrsClient::new(primary, version)
+        .await?
+        .with_failover_servers(servers.collect());
+    if let Some(token) = config.enrollment_token {
+        client = client.with_enrollment_token(token);
+    }
+    if let Some(ca_bundle) = &config.ca_bundle {
+        client = client.with_ca_bundle(ca_bundle)?;
+    }
+    if let (Some(cert), Some(key)) = (&config.client_cert, &config.client_key) {
+        client = client.with_client_certificate(cert, key)?;
+    }
+    if config.report_metrics {
+        client = client.with_system_metrics();
+    }
+    if !config.checks.is_empty() {
+        client = client.with_health_checks(config.checks)?;
+    }
 
     println!("Starting heartbeat loop...");
     client.run().await?;
@@ -112,6 +221,11 @@ mod tests {
             let mut fields = HashSet::new();
             // Manually list all CLI option fields here
             fields.insert("server");
+            fields.insert("enrollment_token");
+            fields.insert("ca_bundle");
+            fields.insert("client_cert");
+            fields.insert("client_key");
+            fields.insert("report_metrics");
             fields
         };
 
@@ -120,6 +234,12 @@ mod tests {
             let mut fields = HashSet::new();
             // Manually list all Config fields here
             fields.insert("server");
+            fields.insert("enrollment_token");
+            fields.insert("ca_bundle");
+            fields.insert("client_cert");
+            fields.insert("client_key");
+            fields.insert("report_metrics");
+            // `checks` is a list of tables and only exists in the config file
             fields
         };
 
@@ -149,7 +269,174 @@ mod tests {
             server = "http://localhost:8081"
         "#;
         let config: Config = toml::from_str(toml_str).unwrap();
-        assert_eq!(config.server, Some("http://localhost:8081".to_string()));
+        assert_eq!(
+            config.server,
+            Some(ServerList::One("http://localhost:8081".to_string()))
+        );
+        assert_eq!(config.enrollment_token, None);
+    }
+
+    #[test]
+    fn test_failover_servers_from_cli_or_config() {
+        let toml_str = r#"
+            server = ["http://crs-a:8081", "http://crs-b:8081"]
+        "#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(
+            config.server.unwrap().into_vec(),
+            ["http://crs-a:8081", "http://crs-b:8081"]
+        );
+
+        let args = Args::parse_from([
+            "crs-client",
+            "--server",
+            "http://crs-a:8081",
+            "-s",
+            "http://crs-b:8081,http://crs-c:8081",
+        ]);
+        assert_eq!(
+            resolve_config(args).unwrap().servers,
+            [
+                "http://crs-a:8081",
+                "http://crs-b:8081",
+                "http://crs-c:8081"
+            ]
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "server = []\n").unwrap();
+        let args = Args::parse_from([
+            "crs-client",
+            "--config",
+            path.to_str().unwrap(),
+        ]);
+        assert!(resolve_config(args).is_err());
+    }
+
+    #[test]
+    fn test_config_with_enrollment_token() {
+        let toml_str = r#"
+            server = "http://localhost:8081"
+            enrollment_token = "enroll-me"
+        "#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.enrollment_token, Some("enroll-me".to_string()));
+    }
+
+    #[test]
+    fn test_config_with_ca_bundle() {
+        let toml_str = r#"
+            server = "https://crs.example.com:8081"
+            ca_bundle = "/etc/crs/ca.pem"
+        "#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.ca_bundle, Some(PathBuf::from("/etc/crs/ca.pem")));
+    }
+
+    #[test]
+    fn test_client_cert_requires_key() {
+        let args = Args::parse_from([
+            "crs-client",
+            "--server",
+            "https://crs.example.com:8081",
+        ]);
+        assert!(resolve_config(args).is_ok());
+
+        // clap enforces the pairing on the command line ...
+        assert!(Args::try_parse_from([
+            "crs-client",
+            "--server",
+            "https://crs.example.com:8081",
+            "--client-cert",
+            "/etc/crs/client.pem",
+        ])
+        .is_err());
+
+        // ... and resolve_config enforces it across CLI and config file
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "client_key = \"/etc/crs/client.key\"\n")
+            .unwrap();
+        let args = Args::parse_from([
+            "crs-client",
+            "--server",
+            "https://crs.example.com:8081",
+            "--config",
+            path.to_str().unwrap(),
+        ]);
+        assert!(resolve_config(args).is_err());
+    }
+
+    #[test]
+    fn test_enrollment_token_cli_overrides_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            "server = \"http://localhost:8081\"\nenrollment_token = \"from-file\"\n",
+        )
+        .unwrap();
+
+        let args = Args::parse_from([
+            "crs-client",
+            "--config",
+            path.to_str().unwrap(),
+        ]);
+        let config = resolve_config(args).unwrap();
+        assert_eq!(config.enrollment_token.as_deref(), Some("from-file"));
+
+        let args = Args::parse_from([
+            "crs-client",
+            "--config",
+            path.to_str().unwrap(),
+            "--enrollment-token",
+            "from-cli",
+        ]);
+        let config = resolve_config(args).unwrap();
+        assert_eq!(config.enrollment_token.as_deref(), Some("from-cli"));
+    }
+
+    #[test]
+    fn test_report_metrics_from_cli_or_config() {
+        let toml_str = r#"
+            server = "http://localhost:8081"
+            report_metrics = true
+        "#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.report_metrics, Some(true));
+
+        let args = Args::parse_from([
+            "crs-client",
+            "--server",
+            "http://localhost:8081",
+        ]);
+        assert!(!resolve_config(args).unwrap().report_metrics);
+        let args = Args::parse_from([
+            "crs-client",
+            "--server",
+            "http://localhost:8081",
+            "--report-metrics",
+        ]);
+        assert!(resolve_config(args).unwrap().report_metrics);
+    }
+
+    #[test]
+    fn test_config_with_checks() {
+        let toml_str = r#"
+            server = "http://localhost:8081"
+
+            [[checks]]
+            name = "sshd"
+            tcp = "127.0.0.1:22"
+        "#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.checks.len(), 1);
+        assert_eq!(config.checks[0].name, "sshd");
+
+        let config: Config =
+            toml::from_str("server = \"http://localhost:8081\"\n").unwrap();
+        assert!(config.checks.is_empty());
     }
 
     #[test]
@@ -0,0 +1,126 @@
+// Copyright 2025 Oxide Computer Company
+
+//! System metrics reported with heartbeats
+//!
+//! Load average, memory use and uptime are read from `/proc/loadavg`,
+//! `/proc/meminfo` and `/proc/uptime`, and root filesystem use comes from
+//! `statvfs(2)`. Anything that cannot be read on this platform is left out
+//! of the report.
+
+use crs_common::{LoadAverage, SystemMetrics, Usage};
+
+/// Collect the current system metrics
+pub fn collect() -> SystemMetrics {
+    SystemMetrics {
+        load_average: read_proc("loadavg").and_then(|s| parse_loadavg(&s)),
+        memory: read_proc("meminfo").and_then(|s| parse_meminfo(&s)),
+        root_fs: root_fs_usage(),
+        uptime_secs: read_proc("uptime").and_then(|s| parse_uptime(&s)),
+    }
+}
+
+fn read_proc(name: &str) -> Option<String> {
+    std::fs::read_to_string(format!("/proc/{}", name)).ok()
+}
+
+/// Parse `/proc/loadavg`, such as `0.52 0.58 0.59 1/467 12345`
+fn parse_loadavg(contents: &str) -> Option<LoadAverage> {
+    let mut fields = contents.split_whitespace().map(str::parse);
+    Some(LoadAverage {
+        one: fields.next()?.ok()?,
+        five: fields.next()?.ok()?,
+        fifteen: fields.next()?.ok()?,
+    })
+}
+
+/// Parse `/proc/meminfo`, counting memory that is not available as used
+fn parse_meminfo(contents: &str) -> Option<Usage> {
+    let mut total = None;
+    let mut available = None;
+    for line in contents.lines() {
+        let mut fields = line.split_whitespace();
+        let field = match fields.next() {
+            Some("MemTotal:") => &mut total,
+            Some("MemAvailable:") => &mut available,
+            _ => continue,
+        };
+        let kib: u64 = fields.next()?.parse().ok()?;
+        *field = Some(kib * 1024);
+    }
+
+    let (total, available) = (total?, available?);
+    Some(Usage {
+        used_bytes: total.saturating_sub(available),
+        total_bytes: total,
+    })
+}
+
+/// Parse `/proc/uptime`, such as `12345.67 54321.00`
+fn parse_uptime(contents: &str) -> Option<u64> {
+    let secs: f64 = contents.split_whitespace().next()?.parse().ok()?;
+    Some(secs as u64)
+}
+
+/// Use of the filesystem mounted at `/`
+// The statvfs field types differ between platforms.
+#[cfg(unix)]
+#[allow(clippy::useless_conversion)]
+fn root_fs_usage() -> Option<Usage> {
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: the path is a NUL-terminated string and `stat` is large
+    // enough for the result, which is only read if the call succeeds.
+    let stat = unsafe {
+        if libc::statvfs(c"/".as_ptr(), stat.as_mut_ptr()) != 0 {
+            return None;
+        }
+        stat.assume_init()
+    };
+
+    let fragment = u64::from(stat.f_frsize);
+    let total = u64::from(stat.f_blocks) * fragment;
+    let free = u64::from(stat.f_bfree) * fragment;
+    Some(Usage {
+        used_bytes: total.saturating_sub(free),
+        total_bytes: total,
+    })
+}
+
+#[cfg(not(unix))]
+fn root_fs_usage() -> Option<Usage> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_proc_files() {
+        let load = parse_loadavg("0.52 0.58 1.25 1/467 12345\n").unwrap();
+        assert_eq!((load.one, load.five, load.fifteen), (0.52, 0.58, 1.25));
+        assert!(parse_loadavg("0.52\n").is_none());
+
+        let meminfo = "MemTotal:        8000000 kB\n\
+                       MemFree:         1000000 kB\n\
+                       MemAvailable:    6000000 kB\n";
+        let memory = parse_meminfo(meminfo).unwrap();
+        assert_eq!(memory.total_bytes, 8_000_000 * 1024);
+        assert_eq!(memory.used_bytes, 2_000_000 * 1024);
+        assert_eq!(memory.used_percent(), 25.0);
+        assert!(parse_meminfo("MemTotal: 8000000 kB\n").is_none());
+
+        assert_eq!(parse_uptime("12345.67 54321.00\n"), Some(12345));
+        assert_eq!(parse_uptime(""), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_collect() {
+        let metrics = collect();
+        assert!(metrics.load_average.is_some());
+        assert!(metrics.memory.is_some());
+        assert!(metrics.uptime_secs.is_some());
+        let root_fs = metrics.root_fs.unwrap();
+        assert!(root_fs.used_bytes <= root_fs.total_bytes);
+    }
+}
@@ -8,25 +8,114 @@
 //! # Protocol Overview
 //!
 //! The CRS protocol is based on REST API with JSON payloads. The protocol
-//! supports three main operations:
+//! supports four main operations:
 //!
 //! ## Registration
 //!
 //! Clients send a [`RegisterRequest`] containing their information (hostname,
-//! OS, IP address, version, and optional tags). The server responds with a
-//! [`RegisterResponse`] containing the client's deterministic ID and the
-//! recommended heartbeat interval.
+//! OS, IP address, version, and optional tags). If the server is configured
+//! with an enrollment token, the request must carry that token. The server
+//! responds with a [`RegisterResponse`] containing the client's deterministic
+//! ID, a freshly generated client secret, and the recommended heartbeat
+//! interval.
 //!
 //! ## Heartbeat
 //!
 //! Clients periodically send [`HeartbeatRequest`] messages to indicate they
-//! are still online. The server responds with [`HeartbeatResponse`] containing
+//! are still online. Each heartbeat carries the client secret from the most
+//! recent registration, so knowing a client's ID is not enough to heartbeat
+//! on its behalf. The server responds with [`HeartbeatResponse`] containing
 //! the current server time.
 //!
+//! A heartbeat may also carry the [`CheckResult`] of each health check the
+//! client runs. A client that heartbeats while any of its checks fail is
+//! [`ClientStatus::Degraded`].
+//!
+//! A heartbeat may also carry [`SystemMetrics`]: load average, memory and
+//! root filesystem use, and uptime. The server keeps the latest
+//! [`MetricsSample`] with the client and a short run of recent ones, which
+//! are returned as a [`ClientMetricsResponse`]. Heartbeats without metrics
+//! are accepted as before.
+//!
+//! ## Deregistration
+//!
+//! Clients that shut down cleanly send a [`DeregisterRequest`], which also
+//! carries the client secret. The server keeps the client listed but marks
+//! it [`ClientStatus::Departed`], so an intentional shutdown can be told
+//! apart from a crash.
+//!
 //! ## Client Listing
 //!
-//! The server provides a [`ListClientsResponse`] containing all registered
-//! clients with their current status and metadata.
+//! The server provides a [`ListClientsResponse`] containing registered
+//! clients with their current status and metadata. The list can be filtered
+//! and sorted as described by [`ClientScanParams`], and is paginated:
+//! `next_page` is set when more clients follow and is passed back as
+//! `page_token` to fetch them.
+//!
+//! ## History
+//!
+//! The server keeps a per-client history of [`HistoryEntry`] records: every
+//! registration, status transition, change to the client's information, and
+//! quarantine. It is returned as a [`ClientHistoryResponse`].
+//!
+//! ## Availability
+//!
+//! From each client's history the server computes [`AvailabilityStats`]
+//! over the windows in [`AvailabilityWindow`]: the share of time the client
+//! was online, how many outages it had, the longest one, and the mean time
+//! to recovery. The client listing includes them for every client.
+//!
+//! ## Events
+//!
+//! The server publishes a [`ClientEvent`] whenever a client registers,
+//! resumes heartbeating, goes stale or catches up, goes offline,
+//! deregisters, changes its information, is removed, enters or leaves
+//! maintenance, or its health checks start or stop failing. Events are
+//! streamed as Server-Sent Events and carry a sequence number, so a
+//! subscriber that reconnects can ask for everything after the last event
+//! it saw.
+//!
+//! ## Webhooks
+//!
+//! The server can also POST a [`WebhookPayload`] to configured URLs when a
+//! client registers for the first time, goes offline, comes back, becomes
+//! degraded, or recovers, unless it is in a maintenance window. Each
+//! request is signed with HMAC-SHA256 over the body using a secret shared
+//! with the receiver, sent as `X-CRS-Signature: sha256=<hex>`.
+//!
+//! ## Tag Selectors
+//!
+//! Clients carry free-form tags, and a [`Selector`] such as
+//! `env=prod,region in (us-west,us-east),!decommissioned` picks out clients
+//! by them. The [`selector`] module describes the syntax.
+//!
+//! ## Server Information
+//!
+//! The server describes itself with a [`ServerInfo`]: its version, build,
+//! start time, address and heartbeat thresholds. Its liveness and readiness
+//! checks answer with a [`HealthResponse`] and a [`ReadinessResponse`].
+//!
+//! ## Administration
+//!
+//! Operators can remove clients from the registry, [`Ban`] a client ID or
+//! hostname so that its registrations are rejected, and put a client in
+//! [`Quarantine`] so that it stays listed but is flagged.
+//!
+//! ## Maintenance Windows
+//!
+//! A [`MaintenanceWindow`] covers a client, or every client matching a tag
+//! selector, for a stretch of time or on a recurring schedule. Clients in a
+//! window still change status as usual, but their status changes are
+//! recorded as expected, their events are flagged
+//! [`expected`](ClientEvent::expected), and no webhooks are sent for them.
+//!
+//! ## Clustering
+//!
+//! Several servers can form a cluster that replicates the registry, so that
+//! any of them can list clients and accept heartbeats. Servers exchange
+//! their [`RegistryState`] in [`ClusterSync`] messages and keep the most
+//! recently changed version of every record. Each server describes the
+//! cluster as it sees it with a [`ClusterStatus`].
 //!
 //! # Client ID Generation
 //!
@@ -36,15 +125,30 @@
 //!
 //! # Client Status
 //!
-//! Clients are categorized into two states:
-//! - [`ClientStatus::Online`] - Recent heartbeat (< 15 seconds, < 1.5x interval)
+//! Clients are categorized into six states:
+//! - [`ClientStatus::Online`] - Recent heartbeat (< 12 seconds, < 1.2x interval)
+//! - [`ClientStatus::Degraded`] - Recent heartbeat, but a health check fails
+//! - [`ClientStatus::Stale`] - Missed a heartbeat (12+ seconds, >= 1.2x interval)
 //! - [`ClientStatus::Offline`] - No heartbeat for 15+ seconds (>= 1.5x interval)
+//! - [`ClientStatus::Departed`] - Client deregistered before shutting down
+//! - [`ClientStatus::Maintenance`] - An operator put the client in
+//!   [`Maintenance`]
+//!
+//! The thresholds shown are the defaults; the server can be configured with
+//! others.
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use uuid::Uuid;
 
+pub mod selector;
+
+pub use selector::{Selector, SelectorError};
+
+/// Sort order of a paginated listing, such as [`ClientScanParams::order`]
+pub use dropshot::PaginationOrder;
+
 /// Unique identifier for a client
 #[derive(
     Debug,
@@ -52,6 +156,8 @@ use uuid::Uuid;
     Copy,
     PartialEq,
     Eq,
+    PartialOrd,
+    Ord,
     Hash,
     Serialize,
     Deserialize,
@@ -78,78 +184,1060 @@ impl ClientId {
     }
 }
 
-impl std::fmt::Display for ClientId {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+impl std::fmt::Display for ClientId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Information about a client registering with the CRS
+#[derive(
+    Debug, Clone, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema,
+)]
+pub struct ClientInfo {
+    /// Hostname of the client machine
+    pub hostname: String,
+
+    /// Operating system (e.g., "Linux", "macOS", "Windows")
+    pub os: String,
+
+    /// IP address of the client
+    pub ip_address: String,
+
+    /// Client software version
+    pub version: String,
+
+    /// Host ID of the system (if available)
+    #[serde(default)]
+    pub host_id: Option<String>,
+
+    /// Optional custom metadata as key-value pairs
+    #[serde(default)]
+    pub tags: HashMap<String, String>,
+}
+
+impl ClientInfo {
+    /// Calculate the deterministic client ID for this client info
+    pub fn client_id(&self) -> ClientId {
+        ClientId::from_client_data(
+            &self.hostname,
+            &self.os,
+            self.host_id.as_deref(),
+        )
+    }
+}
+
+/// Request to register a new client
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct RegisterRequest {
+    pub client_info: ClientInfo,
+
+    /// Pre-shared enrollment token (required if the server has one set)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enrollment_token: Option<String>,
+}
+
+/// Response after successful registration
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct RegisterResponse {
+    /// Assigned/confirmed client ID (deterministic based on client info)
+    pub client_id: ClientId,
+
+    /// Secret to present with every heartbeat and deregistration
+    ///
+    /// A new secret is issued on every registration, replacing the previous
+    /// one.
+    pub client_secret: String,
+
+    /// Heartbeat interval in seconds the server expects from this client
+    pub heartbeat_interval_secs: u64,
+}
+
+/// Request to send a heartbeat
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct HeartbeatRequest {
+    pub client_id: ClientId,
+
+    /// Client secret returned by the most recent registration
+    pub client_secret: String,
+
+    /// System metrics, if the client reports them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<SystemMetrics>,
+
+    /// Results of the client's health checks, if it runs any
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub checks: Vec<CheckResult>,
+}
+
+/// Outcome of one of a client's health checks
+#[derive(
+    Debug, Clone, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema,
+)]
+pub struct CheckResult {
+    /// Name of the check, as configured on the client
+    pub name: String,
+
+    /// Whether the check passed
+    pub passed: bool,
+
+    /// Why the check failed, or other detail from the check
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+/// System metrics a client may attach to its heartbeats
+///
+/// Every part is optional: clients fill in what they could read on their
+/// platform.
+#[derive(
+    Debug,
+    Clone,
+    Default,
+    PartialEq,
+    Serialize,
+    Deserialize,
+    schemars::JsonSchema,
+)]
+pub struct SystemMetrics {
+    /// Load averages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub load_average: Option<LoadAverage>,
+
+    /// Physical memory use
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory: Option<Usage>,
+
+    /// Root filesystem use
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub root_fs: Option<Usage>,
+
+    /// Seconds since the system booted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uptime_secs: Option<u64>,
+}
+
+impl SystemMetrics {
+    /// Whether no metric was filled in
+    pub fn is_empty(&self) -> bool {
+        self.load_average.is_none()
+            && self.memory.is_none()
+            && self.root_fs.is_none()
+            && self.uptime_secs.is_none()
+    }
+}
+
+/// System load averaged over 1, 5 and 15 minutes
+#[derive(
+    Debug, Clone, Copy, PartialEq, Serialize, Deserialize, schemars::JsonSchema,
+)]
+pub struct LoadAverage {
+    pub one: f64,
+    pub five: f64,
+    pub fifteen: f64,
+}
+
+/// How much of a resource is in use, in bytes
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    schemars::JsonSchema,
+)]
+pub struct Usage {
+    pub used_bytes: u64,
+    pub total_bytes: u64,
+}
+
+impl Usage {
+    /// Share of the resource in use, from 0 to 100
+    pub fn used_percent(&self) -> f64 {
+        if self.total_bytes == 0 {
+            return 0.0;
+        }
+        self.used_bytes as f64 * 100.0 / self.total_bytes as f64
+    }
+}
+
+/// System metrics as received with one heartbeat
+#[derive(
+    Debug, Clone, PartialEq, Serialize, Deserialize, schemars::JsonSchema,
+)]
+pub struct MetricsSample {
+    /// When the heartbeat carrying the metrics arrived (RFC3339 format)
+    #[schemars(with = "String")]
+    pub timestamp: DateTime<Utc>,
+
+    /// The metrics
+    #[serde(flatten)]
+    pub metrics: SystemMetrics,
+}
+
+/// A client's recent system metrics
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct ClientMetricsResponse {
+    /// The client the samples belong to
+    pub client_id: ClientId,
+
+    /// Samples received since the server started, oldest first
+    pub samples: Vec<MetricsSample>,
+}
+
+/// Response to a heartbeat
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct HeartbeatResponse {
+    /// Server timestamp when heartbeat was received (RFC3339 format)
+    #[schemars(with = "String")]
+    pub server_time: DateTime<Utc>,
+}
+
+/// Request to deregister a client that is shutting down
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct DeregisterRequest {
+    pub client_id: ClientId,
+
+    /// Client secret returned by the most recent registration
+    pub client_secret: String,
+}
+
+/// Status of a registered client
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    schemars::JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientStatus {
+    /// Client is currently online (recent heartbeat)
+    Online,
+
+    /// Client is heartbeating, but at least one of its health checks fails
+    Degraded,
+
+    /// Client missed a heartbeat, but has not yet timed out
+    Stale,
+
+    /// Client has timed out (no heartbeat for extended period)
+    Offline,
+
+    /// Client deregistered itself as part of a clean shutdown
+    Departed,
+
+    /// An operator put the client in maintenance, so its heartbeats are not
+    /// tracked until the maintenance ends
+    Maintenance,
+}
+
+impl ClientStatus {
+    /// Whether the client is heartbeating, whether or not its checks pass
+    pub fn is_alive(self) -> bool {
+        matches!(self, ClientStatus::Online | ClientStatus::Degraded)
+    }
+}
+
+/// Complete information about a registered client
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct RegisteredClient {
+    /// Unique identifier for this client
+    pub client_id: ClientId,
+
+    /// Client information
+    #[serde(flatten)]
+    pub info: ClientInfo,
+
+    /// Current status
+    pub status: ClientStatus,
+
+    /// When the client first connected (never changes, RFC3339 format)
+    #[schemars(with = "String")]
+    pub first_connected: DateTime<Utc>,
+
+    /// When the client most recently connected/registered (RFC3339 format)
+    #[schemars(with = "String")]
+    pub registered_at: DateTime<Utc>,
+
+    /// When the last heartbeat was received (RFC3339 format)
+    #[schemars(with = "String")]
+    pub last_heartbeat: DateTime<Utc>,
+
+    /// Set if an operator has quarantined this client
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quarantine: Option<Quarantine>,
+
+    /// Set while an operator has the client in maintenance
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maintenance: Option<Maintenance>,
+
+    /// The maintenance window covering the client, if any, as of the last
+    /// status update
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maintenance_window: Option<Uuid>,
+
+    /// The most recent system metrics the client reported, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<MetricsSample>,
+
+    /// Results of the client's health checks from its latest heartbeat
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub checks: Vec<CheckResult>,
+
+    /// Availability statistics, filled in by the client listing
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub availability: Vec<AvailabilityStats>,
+}
+
+impl RegisteredClient {
+    /// Calculate time connected
+    ///
+    /// Returns the duration the client has been connected:
+    /// - For online, degraded and stale clients: time from registered_at to
+    ///   now
+    /// - For offline, departed and maintenance clients: always zero
+    ///   (Duration::zero())
+    pub fn time_connected(&self) -> chrono::Duration {
+        match self.status {
+            ClientStatus::Online
+            | ClientStatus::Degraded
+            | ClientStatus::Stale => Utc::now() - self.registered_at,
+            ClientStatus::Offline
+            | ClientStatus::Departed
+            | ClientStatus::Maintenance => chrono::Duration::zero(),
+        }
+    }
+
+    /// The status a heartbeating client has given its latest check results
+    ///
+    /// [`ClientStatus::Degraded`] if any check failed, otherwise
+    /// [`ClientStatus::Online`].
+    pub fn alive_status(&self) -> ClientStatus {
+        if self.checks.iter().all(|check| check.passed) {
+            ClientStatus::Online
+        } else {
+            ClientStatus::Degraded
+        }
+    }
+}
+
+/// Period that availability statistics are computed over
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Serialize,
+    Deserialize,
+    schemars::JsonSchema,
+)]
+pub enum AvailabilityWindow {
+    /// The last 24 hours
+    #[serde(rename = "24h")]
+    Day,
+
+    /// The last 7 days
+    #[serde(rename = "7d")]
+    Week,
+
+    /// The last 30 days
+    #[serde(rename = "30d")]
+    Month,
+}
+
+impl AvailabilityWindow {
+    /// Every window, shortest first
+    pub const ALL: [AvailabilityWindow; 3] = [
+        AvailabilityWindow::Day,
+        AvailabilityWindow::Week,
+        AvailabilityWindow::Month,
+    ];
+
+    /// Length of the window
+    pub fn duration(&self) -> chrono::Duration {
+        match self {
+            AvailabilityWindow::Day => chrono::Duration::days(1),
+            AvailabilityWindow::Week => chrono::Duration::days(7),
+            AvailabilityWindow::Month => chrono::Duration::days(30),
+        }
+    }
+
+    /// Name of the window, as used in the API
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AvailabilityWindow::Day => "24h",
+            AvailabilityWindow::Week => "7d",
+            AvailabilityWindow::Month => "30d",
+        }
+    }
+}
+
+impl std::fmt::Display for AvailabilityWindow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for AvailabilityWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        AvailabilityWindow::ALL
+            .into_iter()
+            .find(|window| window.as_str() == s)
+            .ok_or_else(|| {
+                format!("invalid window {:?}, expected 24h, 7d or 30d", s)
+            })
+    }
+}
+
+/// How available a client was over an [`AvailabilityWindow`]
+///
+/// Only time covered by the client's history counts: time before the
+/// client first registered, and time it spent departed after a clean
+/// shutdown, are left out.
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct AvailabilityStats {
+    /// The window the statistics cover
+    pub window: AvailabilityWindow,
+
+    /// Seconds of the window in which the client's status is known and it
+    /// had not departed
+    pub observed_secs: u64,
+
+    /// Seconds of observed time the client was online
+    pub online_secs: u64,
+
+    /// Percentage of observed time the client was online, if any time was
+    /// observed
+    pub availability_percent: Option<f64>,
+
+    /// Number of outages that overlap the window
+    pub outages: u32,
+
+    /// Length of the longest of those outages in seconds, including any
+    /// part outside the window
+    pub longest_outage_secs: u64,
+
+    /// Mean time to recovery: the average length in seconds of the outages
+    /// that ended within the window, if any did
+    pub mttr_secs: Option<u64>,
+}
+
+/// Keys the client list can be sorted by
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    schemars::JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientSortKey {
+    /// Hostname, ignoring case
+    Hostname,
+    /// IP address, numerically
+    #[default]
+    IpAddress,
+    /// Operating system, ignoring case
+    Os,
+    /// Client version
+    Version,
+    /// Status
+    Status,
+    /// When the client first connected
+    FirstConnected,
+    /// When the client last sent a heartbeat
+    LastHeartbeat,
+}
+
+/// Filters and sort order for listing clients
+///
+/// Every filter that is given must match for a client to be listed.
+#[derive(
+    Debug, Clone, Default, Serialize, Deserialize, schemars::JsonSchema,
+)]
+pub struct ClientScanParams {
+    /// Only list clients with this status
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<ClientStatus>,
+
+    /// Only list clients running this operating system, ignoring case
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os: Option<String>,
+
+    /// Only list clients running this client version
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+
+    /// Only list clients whose hostname matches this glob, ignoring case
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+
+    /// Only list clients whose tags match this selector, given as
+    /// comma-separated `key=value` pairs or any other selector syntax
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+
+    /// Only list clients whose tags match this selector, such as
+    /// `env=prod,region in (us-west,us-east),!decommissioned`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selector: Option<String>,
+
+    /// What to sort clients by (IP address by default)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sort: Option<ClientSortKey>,
+
+    /// Sort order (ascending by default)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub order: Option<PaginationOrder>,
+
+    /// Only compute availability over this window (24h, 7d or 30d)
+    ///
+    /// Without it, availability is computed over every window.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window: Option<AvailabilityWindow>,
+}
+
+/// Response listing registered clients
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct ListClientsResponse {
+    pub clients: Vec<RegisteredClient>,
+    /// Token for fetching the next page of clients, if there are more
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_page: Option<String>,
+    /// Server start time (RFC3339 format)
+    #[schemars(with = "String")]
+    pub server_start_time: DateTime<Utc>,
+}
+
+/// Information about the server itself
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct ServerInfo {
+    /// Server version
+    pub version: String,
+
+    /// Git commit the server was built from, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git_commit: Option<String>,
+
+    /// Hostname of the machine the server runs on
+    pub hostname: String,
+
+    /// Operating system the server runs on
+    pub os: String,
+
+    /// Server start time (RFC3339 format)
+    #[schemars(with = "String")]
+    pub start_time: DateTime<Utc>,
+
+    /// Address the server listens on
+    pub bind_address: String,
+
+    /// Default number of seconds between client heartbeats
+    pub heartbeat_interval_secs: u64,
+
+    /// Default number of seconds without a heartbeat before a client is
+    /// considered stale
+    pub stale_threshold_secs: u64,
+
+    /// Default number of seconds without a heartbeat before a client is
+    /// considered offline
+    pub offline_threshold_secs: u64,
+
+    /// Number of seconds between client status updates
+    pub status_sweep_secs: u64,
+}
+
+/// Response from the liveness check
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct HealthResponse {
+    /// Always `ok`; the server answering at all is the signal
+    pub status: String,
+}
+
+/// Response from the readiness check
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct ReadinessResponse {
+    /// Whether the registry has been loaded from its store
+    pub registry_loaded: bool,
+
+    /// Whether the status sweeper has run recently
+    pub sweeper_running: bool,
+
+    /// When the status sweeper last ran (RFC3339 format)
+    #[schemars(with = "Option<String>")]
+    pub last_sweep: Option<DateTime<Utc>>,
+}
+
+impl ReadinessResponse {
+    /// Whether the server is ready to serve clients
+    pub fn is_ready(&self) -> bool {
+        self.registry_loaded && self.sweeper_running
+    }
+}
+
+/// Details of an operator-imposed quarantine
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct Quarantine {
+    /// Why the client was quarantined
+    #[serde(default)]
+    pub reason: Option<String>,
+
+    /// When the quarantine started (RFC3339 format)
+    #[schemars(with = "String")]
+    pub since: DateTime<Utc>,
+}
+
+/// Details of an operator putting a client in maintenance
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct Maintenance {
+    /// Why the client is in maintenance
+    #[serde(default)]
+    pub reason: Option<String>,
+
+    /// When the maintenance started (RFC3339 format)
+    #[schemars(with = "String")]
+    pub since: DateTime<Utc>,
+}
+
+/// Request to put a client in maintenance
+#[derive(
+    Debug, Clone, Default, Serialize, Deserialize, schemars::JsonSchema,
+)]
+pub struct MaintenanceRequest {
+    /// Why the client is going into maintenance
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+/// A scheduled period during which clients are expected to go offline
+///
+/// A window covers either a single client or every client matching a tag
+/// selector. A one-off window runs for `duration_secs` from `starts_at`. A
+/// recurring window has a cron `schedule` instead, and runs for
+/// `duration_secs` from every time the schedule matches after `starts_at`.
+#[derive(
+    Debug, Clone, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema,
+)]
+pub struct MaintenanceWindow {
+    /// Unique identifier for this window
+    pub id: Uuid,
+
+    /// The client the window covers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<ClientId>,
+
+    /// Tag selector picking out the clients the window covers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selector: Option<String>,
+
+    /// Why the window was scheduled
+    #[serde(default)]
+    pub reason: Option<String>,
+
+    /// When the window starts, or its schedule takes effect (RFC3339
+    /// format)
+    #[schemars(with = "String")]
+    pub starts_at: DateTime<Utc>,
+
+    /// How long the window lasts each time it starts
+    pub duration_secs: u64,
+
+    /// Five-field cron schedule (minute, hour, day of month, month, day of
+    /// week, in UTC) the window recurs on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<String>,
+
+    /// When the window was scheduled (RFC3339 format)
+    #[schemars(with = "String")]
+    pub created_at: DateTime<Utc>,
+}
+
+/// Request to schedule a maintenance window
+///
+/// Exactly one of `client_id` and `selector` must be given.
+#[derive(
+    Debug, Clone, Default, Serialize, Deserialize, schemars::JsonSchema,
+)]
+pub struct MaintenanceWindowRequest {
+    /// The client the window covers
+    #[serde(default)]
+    pub client_id: Option<ClientId>,
+
+    /// Tag selector picking out the clients the window covers
+    #[serde(default)]
+    pub selector: Option<String>,
+
+    /// Why the window is being scheduled
+    #[serde(default)]
+    pub reason: Option<String>,
+
+    /// When the window starts, or its schedule takes effect (RFC3339
+    /// format, defaults to now)
+    #[serde(default)]
+    #[schemars(with = "Option<String>")]
+    pub starts_at: Option<DateTime<Utc>>,
+
+    /// How long the window lasts each time it starts
+    pub duration_secs: u64,
+
+    /// Five-field cron schedule (in UTC) to repeat the window on
+    #[serde(default)]
+    pub schedule: Option<String>,
+}
+
+/// Response listing all maintenance windows
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct ListMaintenanceWindowsResponse {
+    pub windows: Vec<MaintenanceWindow>,
+}
+
+/// Header carrying the admin token on requests that change the registry on
+/// an operator's behalf
+pub const ADMIN_TOKEN_HEADER: &str = "X-CRS-Admin-Token";
+
+/// Request to quarantine a client
+#[derive(
+    Debug, Clone, Default, Serialize, Deserialize, schemars::JsonSchema,
+)]
+pub struct QuarantineRequest {
+    /// Why the client is being quarantined
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+/// What a [`Ban`] matches against
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    schemars::JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum BanKind {
+    /// Matches a single client ID
+    ClientId,
+
+    /// Matches every client reporting this hostname (case-insensitive)
+    Hostname,
+}
+
+impl std::fmt::Display for BanKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BanKind::ClientId => write!(f, "client_id"),
+            BanKind::Hostname => write!(f, "hostname"),
+        }
+    }
+}
+
+/// A ban preventing matching clients from registering
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct Ban {
+    /// What `value` is matched against
+    pub kind: BanKind,
+
+    /// The banned client ID or hostname
+    pub value: String,
+
+    /// Why the ban was put in place
+    #[serde(default)]
+    pub reason: Option<String>,
+
+    /// When the ban was created (RFC3339 format)
+    #[schemars(with = "String")]
+    pub created_at: DateTime<Utc>,
+}
+
+impl Ban {
+    /// Check whether this ban applies to a client
+    pub fn matches(&self, client_id: ClientId, hostname: &str) -> bool {
+        match self.kind {
+            BanKind::ClientId => self.value == client_id.to_string(),
+            BanKind::Hostname => self.value.eq_ignore_ascii_case(hostname),
+        }
     }
 }
 
-/// Information about a client registering with the CRS
+/// Request to ban a client ID or hostname
 #[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
-pub struct ClientInfo {
-    /// Hostname of the client machine
-    pub hostname: String,
+pub struct BanRequest {
+    /// What `value` is matched against
+    pub kind: BanKind,
 
-    /// Operating system (e.g., "Linux", "macOS", "Windows")
-    pub os: String,
+    /// The client ID or hostname to ban
+    pub value: String,
 
-    /// IP address of the client
-    pub ip_address: String,
+    /// Why the ban is being put in place
+    #[serde(default)]
+    pub reason: Option<String>,
+}
 
-    /// Client software version
-    pub version: String,
+/// Response listing all bans
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct ListBansResponse {
+    pub bans: Vec<Ban>,
+}
 
-    /// Host ID of the system (if available)
-    #[serde(default)]
-    pub host_id: Option<String>,
+/// A client record as replicated between cluster nodes
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct ReplicatedClient {
+    /// The client as the sending node has it
+    pub client: RegisteredClient,
 
-    /// Optional custom metadata as key-value pairs
+    /// Hash of the secret from the client's most recent registration
     #[serde(default)]
-    pub tags: HashMap<String, String>,
+    pub secret_hash: Option<String>,
+
+    /// When the record last changed other than by a status sweep (RFC3339
+    /// format)
+    #[schemars(with = "String")]
+    pub updated_at: DateTime<Utc>,
 }
 
-impl ClientInfo {
-    /// Calculate the deterministic client ID for this client info
-    pub fn client_id(&self) -> ClientId {
-        ClientId::from_client_data(
-            &self.hostname,
-            &self.os,
-            self.host_id.as_deref(),
-        )
-    }
+/// A client that was removed from the registry
+#[derive(
+    Debug, Clone, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema,
+)]
+pub struct RemovedClient {
+    /// ID of the removed client
+    pub client_id: ClientId,
+
+    /// When the client was removed (RFC3339 format)
+    #[schemars(with = "String")]
+    pub removed_at: DateTime<Utc>,
 }
 
-/// Request to register a new client
+/// Everything one cluster node replicates to the others
+///
+/// Bans and maintenance windows are replicated as whole lists, along with
+/// when the list last changed; a node that has never changed a list sends
+/// no time for it.
+#[derive(
+    Debug, Clone, Default, Serialize, Deserialize, schemars::JsonSchema,
+)]
+pub struct RegistryState {
+    /// Every client the node knows about
+    pub clients: Vec<ReplicatedClient>,
+
+    /// Clients the node has seen removed
+    pub removed: Vec<RemovedClient>,
+
+    /// All bans
+    pub bans: Vec<Ban>,
+
+    /// When the bans last changed (RFC3339 format)
+    #[serde(default)]
+    #[schemars(with = "Option<String>")]
+    pub bans_updated_at: Option<DateTime<Utc>>,
+
+    /// All maintenance windows
+    pub maintenance_windows: Vec<MaintenanceWindow>,
+
+    /// When the maintenance windows last changed (RFC3339 format)
+    #[serde(default)]
+    #[schemars(with = "Option<String>")]
+    pub maintenance_windows_updated_at: Option<DateTime<Utc>>,
+}
+
+/// Registry state sent from one cluster node to another
+///
+/// A node answers a sync with its own state, after merging in the state it
+/// was sent, so one exchange brings both nodes up to date.
 #[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
-pub struct RegisterRequest {
-    pub client_info: ClientInfo,
+pub struct ClusterSync {
+    /// Name of the sending node
+    pub node: String,
+
+    /// The sending node's registry state
+    pub state: RegistryState,
 }
 
-/// Response after successful registration
+/// A peer in a server's cluster, as that server sees it
 #[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
-pub struct RegisterResponse {
-    /// Assigned/confirmed client ID (deterministic based on client info)
-    pub client_id: ClientId,
+pub struct ClusterMember {
+    /// URL the peer is reached at
+    pub url: String,
 
-    /// Recommended heartbeat interval in seconds
-    pub heartbeat_interval_secs: u64,
+    /// The peer's node name, once it has answered a sync
+    pub node: Option<String>,
+
+    /// Whether the most recent sync with the peer succeeded
+    pub reachable: bool,
+
+    /// When a sync with the peer last succeeded (RFC3339 format)
+    #[schemars(with = "Option<String>")]
+    pub last_sync: Option<DateTime<Utc>>,
+
+    /// Why the most recent sync failed, if it did
+    pub error: Option<String>,
 }
 
-/// Request to send a heartbeat
+/// Header carrying the shared cluster secret on `POST /api/cluster/sync`
+pub const CLUSTER_SECRET_HEADER: &str = "X-CRS-Cluster-Secret";
+
+/// Cluster membership, as served by `GET /api/cluster`
 #[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
-pub struct HeartbeatRequest {
-    pub client_id: ClientId,
+pub struct ClusterStatus {
+    /// Name of the answering node
+    pub node: String,
+
+    /// Whether the answering node replicates its registry at all
+    pub enabled: bool,
+
+    /// The node's peers, in the order they were configured
+    pub members: Vec<ClusterMember>,
 }
 
-/// Response to a heartbeat
+/// What changed in a [`HistoryEntry`]
+#[derive(
+    Debug, Clone, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema,
+)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum HistoryChange {
+    /// The client registered, for the first time if `first` is set
+    Registered { first: bool },
+
+    /// The client's status changed
+    ///
+    /// `expected` is set for changes made while the client was in a
+    /// maintenance window.
+    StatusChanged {
+        from: ClientStatus,
+        to: ClientStatus,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        expected: bool,
+    },
+
+    /// The client registered again with different information
+    InfoChanged {
+        previous: Box<ClientInfo>,
+        current: Box<ClientInfo>,
+    },
+
+    /// An operator quarantined the client
+    Quarantined {
+        #[serde(default)]
+        reason: Option<String>,
+    },
+
+    /// An operator lifted the client's quarantine
+    QuarantineLifted,
+}
+
+/// A single entry in a client's history
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// When the change happened (RFC3339 format)
+    pub timestamp: DateTime<Utc>,
+
+    /// What changed
+    #[serde(flatten)]
+    pub change: HistoryChange,
+}
+
+// The derived schema for a struct with a flattened enum gives the object
+// both a type and `oneOf` subschemas, which OpenAPI does not allow. Describe
+// it as the timestamp and the change combined with `allOf` instead.
+impl schemars::JsonSchema for HistoryEntry {
+    fn schema_name() -> String {
+        "HistoryEntry".to_string()
+    }
+
+    fn json_schema(
+        gen: &mut schemars::gen::SchemaGenerator,
+    ) -> schemars::schema::Schema {
+        use schemars::schema::{
+            InstanceType, Metadata, ObjectValidation, SchemaObject,
+            SubschemaValidation,
+        };
+
+        let mut timestamp = gen.subschema_for::<String>().into_object();
+        timestamp.metadata().description =
+            Some("When the change happened (RFC3339 format)".to_string());
+        let timestamp_object = SchemaObject {
+            instance_type: Some(InstanceType::Object.into()),
+            object: Some(Box::new(ObjectValidation {
+                properties: [("timestamp".to_string(), timestamp.into())]
+                    .into_iter()
+                    .collect(),
+                required: ["timestamp".to_string()].into_iter().collect(),
+                ..Default::default()
+            })),
+            ..Default::default()
+        };
+
+        SchemaObject {
+            metadata: Some(Box::new(Metadata {
+                description: Some(
+                    "A single entry in a client's history".to_string(),
+                ),
+                ..Default::default()
+            })),
+            subschemas: Some(Box::new(SubschemaValidation {
+                all_of: Some(vec![
+                    timestamp_object.into(),
+                    gen.subschema_for::<HistoryChange>(),
+                ]),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+impl HistoryEntry {
+    /// The status the client was left in by this entry, if it says
+    ///
+    /// First registrations put a client online; status changes name the
+    /// new status. Other entries do not affect the status.
+    pub fn resulting_status(&self) -> Option<ClientStatus> {
+        match &self.change {
+            HistoryChange::Registered { first: true } => {
+                Some(ClientStatus::Online)
+            }
+            HistoryChange::StatusChanged { to, .. } => Some(*to),
+            _ => None,
+        }
+    }
+}
+
+/// Response listing a client's history
 #[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
-pub struct HeartbeatResponse {
-    /// Server timestamp when heartbeat was received (RFC3339 format)
-    #[schemars(with = "String")]
-    pub server_time: DateTime<Utc>,
+pub struct ClientHistoryResponse {
+    /// The client the history belongs to
+    pub client_id: ClientId,
+
+    /// History entries, oldest first
+    ///
+    /// Entries are kept for 30 days, so long-lived clients are missing
+    /// their earliest history. The newest entry from before then is kept
+    /// too, for the status the client was in at the time.
+    pub entries: Vec<HistoryEntry>,
 }
 
-/// Status of a registered client
+/// What happened to a client in a [`ClientEvent`]
 #[derive(
     Debug,
     Clone,
@@ -160,62 +1248,185 @@ pub struct HeartbeatResponse {
     Deserialize,
     schemars::JsonSchema,
 )]
-#[serde(rename_all = "lowercase")]
-pub enum ClientStatus {
-    /// Client is currently online (recent heartbeat)
-    Online,
+#[serde(rename_all = "snake_case")]
+pub enum ClientEventKind {
+    /// A new client registered, or an offline or departed client registered
+    /// again
+    Registered,
 
-    /// Client has timed out (no heartbeat for extended period)
-    Offline,
+    /// An offline or departed client sent a heartbeat, or a client came out
+    /// of maintenance heartbeating
+    HeartbeatResumed,
+
+    /// A client missed a heartbeat
+    WentStale,
+
+    /// A stale client heartbeated or registered again before going offline
+    CaughtUp,
+
+    /// A client missed its offline threshold
+    WentOffline,
+
+    /// A client deregistered as part of a clean shutdown
+    Deregistered,
+
+    /// A client registered again with different information
+    InfoChanged,
+
+    /// An operator removed a client from the registry
+    Removed,
+
+    /// A heartbeating client's health checks started failing
+    Degraded,
+
+    /// All of a degraded client's health checks pass again
+    Recovered,
+
+    /// An operator put a client in maintenance
+    MaintenanceStarted,
+
+    /// An operator ended a client's maintenance
+    ///
+    /// Followed by the event for the status the client goes back to.
+    MaintenanceEnded,
 }
 
-/// Complete information about a registered client
+impl ClientEventKind {
+    /// Name used for the event on the event stream
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ClientEventKind::Registered => "registered",
+            ClientEventKind::HeartbeatResumed => "heartbeat_resumed",
+            ClientEventKind::WentStale => "went_stale",
+            ClientEventKind::CaughtUp => "caught_up",
+            ClientEventKind::WentOffline => "went_offline",
+            ClientEventKind::Deregistered => "deregistered",
+            ClientEventKind::InfoChanged => "info_changed",
+            ClientEventKind::Removed => "removed",
+            ClientEventKind::Degraded => "degraded",
+            ClientEventKind::Recovered => "recovered",
+            ClientEventKind::MaintenanceStarted => "maintenance_started",
+            ClientEventKind::MaintenanceEnded => "maintenance_ended",
+        }
+    }
+}
+
+/// A change to a registered client
 #[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
-pub struct RegisteredClient {
-    /// Unique identifier for this client
-    pub client_id: ClientId,
+pub struct ClientEvent {
+    /// Position of this event in the server's event stream
+    ///
+    /// Sequence numbers start at 1 when the server starts and increase by
+    /// one with every event.
+    pub sequence: u64,
 
-    /// Client information
-    #[serde(flatten)]
-    pub info: ClientInfo,
+    /// When the change happened (RFC3339 format)
+    #[schemars(with = "String")]
+    pub timestamp: DateTime<Utc>,
 
-    /// Current status
-    pub status: ClientStatus,
+    /// What happened
+    pub kind: ClientEventKind,
 
-    /// When the client first connected (never changes, RFC3339 format)
-    #[schemars(with = "String")]
-    pub first_connected: DateTime<Utc>,
+    /// Whether the client was in a maintenance window, so the change was
+    /// expected
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub expected: bool,
 
-    /// When the client most recently connected/registered (RFC3339 format)
-    #[schemars(with = "String")]
-    pub registered_at: DateTime<Utc>,
+    /// Whether a [`Registered`](ClientEventKind::Registered) event was the
+    /// client's first registration
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub first: bool,
 
-    /// When the last heartbeat was received (RFC3339 format)
-    #[schemars(with = "String")]
-    pub last_heartbeat: DateTime<Utc>,
+    /// The client as it was right after the change
+    pub client: RegisteredClient,
 }
 
-impl RegisteredClient {
-    /// Calculate time connected
+/// Client transition reported to webhooks
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Serialize,
+    Deserialize,
+    schemars::JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// A client the server has never seen before registered
+    Registered,
+
+    /// A client stopped heartbeating and is now offline
+    WentOffline,
+
+    /// An offline or departed client registered or heartbeated again
+    CameBack,
+
+    /// A heartbeating client's health checks started failing
+    Degraded,
+
+    /// All of a degraded client's health checks pass again
+    Recovered,
+}
+
+impl WebhookEvent {
+    /// The webhook event, if any, that a client event represents
     ///
-    /// Returns the duration the client has been connected:
-    /// - For online clients: time from registered_at to now
-    /// - For offline clients: always zero (Duration::zero())
-    pub fn time_connected(&self) -> chrono::Duration {
-        match self.status {
-            ClientStatus::Online => Utc::now() - self.registered_at,
-            ClientStatus::Offline => chrono::Duration::zero(),
+    /// Expected events, for clients in a maintenance window, are not
+    /// reported to webhooks.
+    pub fn from_client_event(event: &ClientEvent) -> Option<Self> {
+        if event.expected {
+            return None;
+        }
+        match event.kind {
+            ClientEventKind::Registered if event.first => {
+                Some(WebhookEvent::Registered)
+            }
+            ClientEventKind::Registered | ClientEventKind::HeartbeatResumed => {
+                Some(WebhookEvent::CameBack)
+            }
+            ClientEventKind::WentOffline => Some(WebhookEvent::WentOffline),
+            ClientEventKind::Degraded => Some(WebhookEvent::Degraded),
+            ClientEventKind::Recovered => Some(WebhookEvent::Recovered),
+            ClientEventKind::WentStale
+            | ClientEventKind::CaughtUp
+            | ClientEventKind::Deregistered
+            | ClientEventKind::InfoChanged
+            | ClientEventKind::Removed
+            | ClientEventKind::MaintenanceStarted
+            | ClientEventKind::MaintenanceEnded => None,
+        }
+    }
+
+    /// Name used for the event in the `X-CRS-Event` header
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEvent::Registered => "registered",
+            WebhookEvent::WentOffline => "went_offline",
+            WebhookEvent::CameBack => "came_back",
+            WebhookEvent::Degraded => "degraded",
+            WebhookEvent::Recovered => "recovered",
         }
     }
 }
 
-/// Response listing all registered clients
+/// Body of a webhook request
 #[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
-pub struct ListClientsResponse {
-    pub clients: Vec<RegisteredClient>,
-    /// Server start time (RFC3339 format)
+pub struct WebhookPayload {
+    /// What happened
+    pub event: WebhookEvent,
+
+    /// Sequence number of the underlying [`ClientEvent`]
+    pub sequence: u64,
+
+    /// When the transition happened (RFC3339 format)
     #[schemars(with = "String")]
-    pub server_start_time: DateTime<Utc>,
+    pub timestamp: DateTime<Utc>,
+
+    /// The client as it was right after the transition
+    pub client: RegisteredClient,
 }
 
 /// Error types for the CRS protocol
@@ -399,9 +1610,205 @@ mod tests {
         let json = serde_json::to_string(&status).unwrap();
         assert_eq!(json, "\"online\"");
 
+        let status = ClientStatus::Degraded;
+        let json = serde_json::to_string(&status).unwrap();
+        assert_eq!(json, "\"degraded\"");
+
         let status = ClientStatus::Offline;
         let json = serde_json::to_string(&status).unwrap();
         assert_eq!(json, "\"offline\"");
+
+        let status = ClientStatus::Departed;
+        let json = serde_json::to_string(&status).unwrap();
+        assert_eq!(json, "\"departed\"");
+
+        let status = ClientStatus::Stale;
+        let json = serde_json::to_string(&status).unwrap();
+        assert_eq!(json, "\"stale\"");
+
+        let status = ClientStatus::Maintenance;
+        let json = serde_json::to_string(&status).unwrap();
+        assert_eq!(json, "\"maintenance\"");
+    }
+
+    #[test]
+    fn test_ban_matches() {
+        let id = ClientId::from_client_data("testhost", "linux", None);
+        let other = ClientId::from_client_data("otherhost", "linux", None);
+
+        let ban = Ban {
+            kind: BanKind::ClientId,
+            value: id.to_string(),
+            reason: None,
+            created_at: Utc::now(),
+        };
+        assert!(ban.matches(id, "testhost"));
+        assert!(!ban.matches(other, "testhost"));
+
+        let ban = Ban {
+            kind: BanKind::Hostname,
+            value: "testhost".to_string(),
+            reason: None,
+            created_at: Utc::now(),
+        };
+        assert!(ban.matches(other, "TestHost"));
+        assert!(!ban.matches(id, "otherhost"));
+    }
+
+    #[test]
+    fn test_registered_client_quarantine_defaults_to_none() {
+        let json = r#"{
+            "client_id": "6ba7b810-9dad-11d1-80b4-00c04fd430c8",
+            "hostname": "testhost",
+            "os": "linux",
+            "ip_address": "192.168.1.100",
+            "version": "1.0.0",
+            "status": "online",
+            "first_connected": "2025-01-01T00:00:00Z",
+            "registered_at": "2025-01-01T00:00:00Z",
+            "last_heartbeat": "2025-01-01T00:00:00Z"
+        }"#;
+        let client: RegisteredClient = serde_json::from_str(json).unwrap();
+        assert!(client.quarantine.is_none());
+    }
+
+    #[test]
+    fn test_client_event_kind_names_match_serialization() {
+        for kind in [
+            ClientEventKind::Registered,
+            ClientEventKind::HeartbeatResumed,
+            ClientEventKind::WentOffline,
+            ClientEventKind::Deregistered,
+            ClientEventKind::InfoChanged,
+            ClientEventKind::Removed,
+            ClientEventKind::Degraded,
+            ClientEventKind::Recovered,
+            ClientEventKind::WentStale,
+            ClientEventKind::CaughtUp,
+            ClientEventKind::MaintenanceStarted,
+            ClientEventKind::MaintenanceEnded,
+        ] {
+            let json = serde_json::to_string(&kind).unwrap();
+            assert_eq!(json, format!("\"{}\"", kind.as_str()));
+        }
+    }
+
+    #[test]
+    fn test_webhook_event_from_client_event() {
+        let now = Utc::now();
+        let info = ClientInfo {
+            hostname: "testhost".to_string(),
+            os: "linux".to_string(),
+            ip_address: "192.168.1.100".to_string(),
+            version: "1.0.0".to_string(),
+            host_id: None,
+            tags: HashMap::new(),
+        };
+        let mut event = ClientEvent {
+            sequence: 1,
+            timestamp: now,
+            kind: ClientEventKind::Registered,
+            expected: false,
+            first: true,
+            client: RegisteredClient {
+                client_id: info.client_id(),
+                info,
+                status: ClientStatus::Online,
+                first_connected: now,
+                registered_at: now,
+                last_heartbeat: now,
+                quarantine: None,
+                maintenance: None,
+                maintenance_window: None,
+                metrics: None,
+                checks: Vec::new(),
+                availability: Vec::new(),
+            },
+        };
+        assert_eq!(
+            WebhookEvent::from_client_event(&event),
+            Some(WebhookEvent::Registered)
+        );
+
+        // Registering again after the first time is a return
+        event.first = false;
+        assert_eq!(
+            WebhookEvent::from_client_event(&event),
+            Some(WebhookEvent::CameBack)
+        );
+
+        event.kind = ClientEventKind::WentOffline;
+        assert_eq!(
+            WebhookEvent::from_client_event(&event),
+            Some(WebhookEvent::WentOffline)
+        );
+
+        event.kind = ClientEventKind::Degraded;
+        assert_eq!(
+            WebhookEvent::from_client_event(&event),
+            Some(WebhookEvent::Degraded)
+        );
+
+        event.kind = ClientEventKind::InfoChanged;
+        assert_eq!(WebhookEvent::from_client_event(&event), None);
+
+        // Nothing is sent for clients in a maintenance window
+        event.kind = ClientEventKind::WentOffline;
+        event.expected = true;
+        assert_eq!(WebhookEvent::from_client_event(&event), None);
+    }
+
+    #[test]
+    fn test_alive_status_follows_checks() {
+        let check = |name: &str, passed| CheckResult {
+            name: name.to_string(),
+            passed,
+            message: None,
+        };
+        let mut request: HeartbeatRequest =
+            serde_json::from_value(serde_json::json!({
+                "client_id": ClientId::from_client_data("h", "linux", None),
+                "client_secret": "secret",
+            }))
+            .unwrap();
+        // Heartbeats from clients without checks carry none
+        assert!(request.checks.is_empty());
+        request.checks = vec![check("web", true), check("db", false)];
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(json["checks"][1]["passed"], false);
+        assert!(json["checks"][0].get("message").is_none());
+
+        let now = Utc::now();
+        let info = ClientInfo {
+            hostname: "h".to_string(),
+            os: "linux".to_string(),
+            ip_address: "192.168.1.100".to_string(),
+            version: "1.0.0".to_string(),
+            host_id: None,
+            tags: HashMap::new(),
+        };
+        let mut client = RegisteredClient {
+            client_id: info.client_id(),
+            info,
+            status: ClientStatus::Online,
+            first_connected: now,
+            registered_at: now,
+            last_heartbeat: now,
+            quarantine: None,
+            maintenance: None,
+            maintenance_window: None,
+            metrics: None,
+            checks: Vec::new(),
+            availability: Vec::new(),
+        };
+        assert_eq!(client.alive_status(), ClientStatus::Online);
+        client.checks = request.checks;
+        assert_eq!(client.alive_status(), ClientStatus::Degraded);
+        client.checks[1].passed = true;
+        assert_eq!(client.alive_status(), ClientStatus::Online);
+
+        assert!(ClientStatus::Degraded.is_alive());
+        assert!(!ClientStatus::Offline.is_alive());
     }
 
     #[test]
@@ -411,5 +1818,41 @@ mod tests {
 
         let status: ClientStatus = serde_json::from_str("\"offline\"").unwrap();
         assert_eq!(status, ClientStatus::Offline);
+
+        let status: ClientStatus =
+            serde_json::from_str("\"departed\"").unwrap();
+        assert_eq!(status, ClientStatus::Departed);
+    }
+
+    #[test]
+    fn test_history_entry_serialization() {
+        let entry = HistoryEntry {
+            timestamp: "2025-01-02T03:04:05Z".parse().unwrap(),
+            change: HistoryChange::StatusChanged {
+                from: ClientStatus::Online,
+                to: ClientStatus::Offline,
+                expected: false,
+            },
+        };
+        let json = serde_json::to_value(&entry).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "timestamp": "2025-01-02T03:04:05Z",
+                "kind": "status_changed",
+                "from": "online",
+                "to": "offline",
+            })
+        );
+        let parsed: HistoryEntry = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, entry);
+        assert_eq!(parsed.resulting_status(), Some(ClientStatus::Offline));
+
+        let lifted: HistoryEntry = serde_json::from_str(
+            r#"{"timestamp":"2025-01-02T03:04:05Z","kind":"quarantine_lifted"}"#,
+        )
+        .unwrap();
+        assert_eq!(lifted.change, HistoryChange::QuarantineLifted);
+        assert_eq!(lifted.resulting_status(), None);
     }
 }
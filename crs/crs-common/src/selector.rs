@@ -0,0 +1,522 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Tag selectors
+//!
+//! A [`Selector`] picks out clients by their tags. It is a comma-separated
+//! list of requirements, all of which must hold:
+//!
+//! - `key=value` (or `key==value`) - the tag is set to `value`
+//! - `key!=value` - the tag is not set to `value`, or is not set at all
+//! - `key in (a,b)` - the tag is set to one of the listed values
+//! - `key notin (a,b)` - the tag is not set to any of the listed values,
+//!   or is not set at all
+//! - `key` - the tag is set, to anything
+//! - `!key` - the tag is not set
+//!
+//! For example, `env=prod,region in (us-west,us-east),!decommissioned`
+//! selects production clients in either western or eastern US regions that
+//! have not been tagged as decommissioned. An empty selector selects every
+//! client.
+//!
+//! Keys and values are made of letters, digits, `-`, `_`, `.`, `/` and
+//! `:`. Whitespace between tokens is ignored.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// A parsed tag selector
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Selector {
+    requirements: Vec<Requirement>,
+}
+
+/// A single condition on one tag
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Requirement {
+    /// Tag key the condition applies to
+    pub key: String,
+
+    /// What the tag's value must satisfy
+    pub operator: Operator,
+}
+
+/// Conditions a tag can be required to meet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operator {
+    /// The tag is set to this value
+    Equals(String),
+    /// The tag is not set to this value
+    NotEquals(String),
+    /// The tag is set to one of these values
+    In(Vec<String>),
+    /// The tag is not set to any of these values
+    NotIn(Vec<String>),
+    /// The tag is set
+    Exists,
+    /// The tag is not set
+    DoesNotExist,
+}
+
+/// Errors from parsing a selector
+///
+/// Positions are character offsets into the selector, starting at 0.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum SelectorError {
+    #[error("expected a tag key at position {position}, found {found}")]
+    ExpectedKey { position: usize, found: String },
+
+    #[error(
+        "expected a value for {key:?} at position {position}, found {found}"
+    )]
+    ExpectedValue {
+        key: String,
+        position: usize,
+        found: String,
+    },
+
+    #[error(
+        "expected `(` to start the value list for {key:?} at position \
+         {position}, found {found}"
+    )]
+    ExpectedOpenParen {
+        key: String,
+        position: usize,
+        found: String,
+    },
+
+    #[error(
+        "expected `,` or `)` in the value list for {key:?} at position \
+         {position}, found {found}"
+    )]
+    UnclosedList {
+        key: String,
+        position: usize,
+        found: String,
+    },
+
+    #[error(
+        "expected `,` between requirements at position {position}, found \
+         {found}"
+    )]
+    ExpectedComma { position: usize, found: String },
+
+    #[error(
+        "`!{key}` cannot be combined with an operator (position {position})"
+    )]
+    NegatedOperator { key: String, position: usize },
+}
+
+impl Selector {
+    /// The selector's requirements, in the order they were written
+    pub fn requirements(&self) -> &[Requirement] {
+        &self.requirements
+    }
+
+    /// Whether the selector has no requirements and so matches everything
+    pub fn is_empty(&self) -> bool {
+        self.requirements.is_empty()
+    }
+
+    /// Whether a set of tags meets every requirement
+    pub fn matches(&self, tags: &HashMap<String, String>) -> bool {
+        self.requirements.iter().all(|r| r.matches(tags))
+    }
+}
+
+impl Requirement {
+    /// Whether a set of tags meets this requirement
+    pub fn matches(&self, tags: &HashMap<String, String>) -> bool {
+        let value = tags.get(&self.key);
+        match &self.operator {
+            Operator::Equals(expected) => value == Some(expected),
+            Operator::NotEquals(expected) => value != Some(expected),
+            Operator::In(values) => value.is_some_and(|v| values.contains(v)),
+            Operator::NotIn(values) => {
+                !value.is_some_and(|v| values.contains(v))
+            }
+            Operator::Exists => value.is_some(),
+            Operator::DoesNotExist => value.is_none(),
+        }
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, requirement) in self.requirements.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}", requirement)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let key = &self.key;
+        match &self.operator {
+            Operator::Equals(value) => write!(f, "{}={}", key, value),
+            Operator::NotEquals(value) => write!(f, "{}!={}", key, value),
+            Operator::In(values) => {
+                write!(f, "{} in ({})", key, values.join(","))
+            }
+            Operator::NotIn(values) => {
+                write!(f, "{} notin ({})", key, values.join(","))
+            }
+            Operator::Exists => write!(f, "{}", key),
+            Operator::DoesNotExist => write!(f, "!{}", key),
+        }
+    }
+}
+
+impl FromStr for Selector {
+    type Err = SelectorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Parser::new(s).parse()
+    }
+}
+
+/// Whether `c` may appear in a key or value
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | '/' | ':')
+}
+
+/// Recursive descent parser over the characters of a selector
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl Parser {
+    fn new(s: &str) -> Self {
+        Self {
+            chars: s.chars().collect(),
+            position: 0,
+        }
+    }
+
+    fn parse(mut self) -> Result<Selector, SelectorError> {
+        let mut requirements = Vec::new();
+        self.skip_whitespace();
+        if self.peek().is_none() {
+            return Ok(Selector { requirements });
+        }
+
+        loop {
+            requirements.push(self.requirement()?);
+            self.skip_whitespace();
+            match self.peek() {
+                None => break,
+                Some(',') => self.position += 1,
+                Some(_) => {
+                    return Err(SelectorError::ExpectedComma {
+                        position: self.position,
+                        found: self.describe_next(),
+                    })
+                }
+            }
+        }
+        Ok(Selector { requirements })
+    }
+
+    fn requirement(&mut self) -> Result<Requirement, SelectorError> {
+        self.skip_whitespace();
+        if self.eat('!') {
+            let key = self.key()?;
+            self.skip_whitespace();
+            if matches!(self.peek(), Some('=' | '!')) {
+                return Err(SelectorError::NegatedOperator {
+                    key,
+                    position: self.position,
+                });
+            }
+            return Ok(Requirement {
+                key,
+                operator: Operator::DoesNotExist,
+            });
+        }
+
+        let key = self.key()?;
+        self.skip_whitespace();
+        let operator = if self.eat('=') {
+            self.eat('=');
+            Operator::Equals(self.value(&key)?)
+        } else if self.peek() == Some('!') && self.peek_at(1) == Some('=') {
+            self.position += 2;
+            Operator::NotEquals(self.value(&key)?)
+        } else if self.eat_word("in") {
+            Operator::In(self.value_list(&key)?)
+        } else if self.eat_word("notin") {
+            Operator::NotIn(self.value_list(&key)?)
+        } else {
+            Operator::Exists
+        };
+        Ok(Requirement { key, operator })
+    }
+
+    fn key(&mut self) -> Result<String, SelectorError> {
+        self.skip_whitespace();
+        let position = self.position;
+        let key = self.word();
+        if key.is_empty() {
+            return Err(SelectorError::ExpectedKey {
+                position,
+                found: self.describe_next(),
+            });
+        }
+        Ok(key)
+    }
+
+    fn value(&mut self, key: &str) -> Result<String, SelectorError> {
+        self.skip_whitespace();
+        let position = self.position;
+        let value = self.word();
+        if value.is_empty() {
+            return Err(SelectorError::ExpectedValue {
+                key: key.to_string(),
+                position,
+                found: self.describe_next(),
+            });
+        }
+        Ok(value)
+    }
+
+    fn value_list(&mut self, key: &str) -> Result<Vec<String>, SelectorError> {
+        self.skip_whitespace();
+        if !self.eat('(') {
+            return Err(SelectorError::ExpectedOpenParen {
+                key: key.to_string(),
+                position: self.position,
+                found: self.describe_next(),
+            });
+        }
+
+        let mut values = Vec::new();
+        loop {
+            values.push(self.value(key)?);
+            self.skip_whitespace();
+            if self.eat(')') {
+                return Ok(values);
+            }
+            if !self.eat(',') {
+                return Err(SelectorError::UnclosedList {
+                    key: key.to_string(),
+                    position: self.position,
+                    found: self.describe_next(),
+                });
+            }
+        }
+    }
+
+    /// Consume a run of key or value characters
+    fn word(&mut self) -> String {
+        let start = self.position;
+        while self.peek().is_some_and(is_word_char) {
+            self.position += 1;
+        }
+        self.chars[start..self.position].iter().collect()
+    }
+
+    /// Consume `word` if it is next and stands on its own
+    ///
+    /// `in` and `notin` are only operators when followed by something other
+    /// than a key character, so a key such as `index` is not mistaken for
+    /// `in` followed by `dex`.
+    fn eat_word(&mut self, word: &str) -> bool {
+        let len = word.chars().count();
+        let matches = word
+            .chars()
+            .enumerate()
+            .all(|(i, c)| self.peek_at(i) == Some(c));
+        if matches && !self.peek_at(len).is_some_and(is_word_char) {
+            self.position += len;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.peek_at(0)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.position + offset).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.position += 1;
+        }
+    }
+
+    /// Describe the next character for error messages
+    fn describe_next(&self) -> String {
+        match self.peek() {
+            Some(c) => format!("{:?}", c),
+            None => "end of selector".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn parse(s: &str) -> Selector {
+        s.parse()
+            .unwrap_or_else(|e| panic!("failed to parse {:?}: {}", s, e))
+    }
+
+    #[test]
+    fn test_parse_operators() {
+        let selector =
+            parse("env=prod, tier==web,zone!=a,region in (us-west, us-east)");
+        assert_eq!(
+            selector.requirements(),
+            [
+                Requirement {
+                    key: "env".to_string(),
+                    operator: Operator::Equals("prod".to_string()),
+                },
+                Requirement {
+                    key: "tier".to_string(),
+                    operator: Operator::Equals("web".to_string()),
+                },
+                Requirement {
+                    key: "zone".to_string(),
+                    operator: Operator::NotEquals("a".to_string()),
+                },
+                Requirement {
+                    key: "region".to_string(),
+                    operator: Operator::In(vec![
+                        "us-west".to_string(),
+                        "us-east".to_string()
+                    ]),
+                },
+            ]
+        );
+
+        let selector = parse("rack notin (r1),gpu,!decommissioned");
+        assert_eq!(
+            selector.requirements()[0].operator,
+            Operator::NotIn(vec!["r1".to_string()])
+        );
+        assert_eq!(selector.requirements()[1].operator, Operator::Exists);
+        assert_eq!(selector.requirements()[2].operator, Operator::DoesNotExist);
+
+        // Keys that start like an operator are still keys
+        let selector = parse("index, notinstalled");
+        assert_eq!(selector.requirements()[0].key, "index");
+        assert_eq!(selector.requirements()[1].key, "notinstalled");
+
+        assert!(parse("").is_empty());
+        assert!(parse("   ").is_empty());
+    }
+
+    #[test]
+    fn test_display_round_trips() {
+        let text = "env=prod,region in (us-west,us-east),rack notin (r1),\
+                    zone!=a,gpu,!decommissioned";
+        let selector = parse(text);
+        assert_eq!(selector.to_string(), text);
+        assert_eq!(parse(&selector.to_string()), selector);
+    }
+
+    #[test]
+    fn test_matches() {
+        let selector =
+            parse("env=prod,region in (us-west,us-east),!decommissioned");
+        assert!(
+            selector.matches(&tags(&[("env", "prod"), ("region", "us-west")]))
+        );
+        assert!(
+            !selector.matches(&tags(&[("env", "dev"), ("region", "us-west")]))
+        );
+        assert!(!selector.matches(&tags(&[("env", "prod"), ("region", "eu")])));
+        assert!(!selector.matches(&tags(&[("env", "prod")])));
+        assert!(!selector.matches(&tags(&[
+            ("env", "prod"),
+            ("region", "us-east"),
+            ("decommissioned", "yes"),
+        ])));
+
+        // Negative operators also match clients without the tag
+        let selector = parse("zone!=a,rack notin (r1,r2)");
+        assert!(selector.matches(&tags(&[])));
+        assert!(selector.matches(&tags(&[("zone", "b"), ("rack", "r3")])));
+        assert!(!selector.matches(&tags(&[("zone", "a")])));
+        assert!(!selector.matches(&tags(&[("rack", "r2")])));
+
+        assert!(parse("gpu").matches(&tags(&[("gpu", "")])));
+        assert!(Selector::default().matches(&tags(&[])));
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |s: &str| s.parse::<Selector>().unwrap_err().to_string();
+
+        assert_eq!(
+            error("env=prod,"),
+            "expected a tag key at position 9, found end of selector"
+        );
+        assert_eq!(
+            error("=prod"),
+            "expected a tag key at position 0, found '='"
+        );
+        assert_eq!(
+            error("env="),
+            "expected a value for \"env\" at position 4, found end of selector"
+        );
+        assert_eq!(
+            error("region in us-west"),
+            "expected `(` to start the value list for \"region\" at position \
+             10, found 'u'"
+        );
+        assert_eq!(
+            error("region in (us-west,"),
+            "expected a value for \"region\" at position 19, found end of \
+             selector"
+        );
+        assert_eq!(
+            error("region in (us-west"),
+            "expected `,` or `)` in the value list for \"region\" at position \
+             18, found end of selector"
+        );
+        assert_eq!(
+            error("region in ()"),
+            "expected a value for \"region\" at position 11, found ')'"
+        );
+        assert_eq!(
+            error("env=prod region=us"),
+            "expected `,` between requirements at position 9, found 'r'"
+        );
+        assert_eq!(
+            error("!env=prod"),
+            "`!env` cannot be combined with an operator (position 4)"
+        );
+        assert_eq!(
+            error("env>3"),
+            "expected `,` between requirements at position 3, found '>'"
+        );
+    }
+}
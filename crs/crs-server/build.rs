@@ -0,0 +1,42 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Build script
+//!
+//! Records the git commit the server is built from in `CRS_GIT_COMMIT`, so
+//! that `GET /api/server` can report it. Builds outside a git checkout, or
+//! without git installed, simply leave it unset.
+
+use std::path::Path;
+use std::process::Command;
+
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let text = String::from_utf8(output.stdout).ok()?;
+    Some(text.trim().to_string())
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    if let Some(commit) = git(&["rev-parse", "--short=12", "HEAD"]) {
+        println!("cargo:rustc-env=CRS_GIT_COMMIT={}", commit);
+    }
+
+    // Rebuild when HEAD moves, whether to another branch or another commit
+    if let Some(git_dir) = git(&["rev-parse", "--absolute-git-dir"]) {
+        let git_dir = Path::new(&git_dir);
+        let head = git_dir.join("HEAD");
+        if head.exists() {
+            println!("cargo:rerun-if-changed={}", head.display());
+        }
+        if let Some(reference) = git(&["symbolic-ref", "-q", "HEAD"]) {
+            let reference = git_dir.join(reference);
+            if reference.exists() {
+                println!("cargo:rerun-if-changed={}", reference.display());
+            }
+        }
+    }
+}
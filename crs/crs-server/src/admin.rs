@@ -0,0 +1,286 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Administrative API handlers
+//!
+//! This module contains the Dropshot endpoint handlers operators use to
+//! manage the registry: removing clients, banning client IDs or hostnames,
+//! quarantining clients, putting them in maintenance, and scheduling
+//! maintenance windows.
+//!
+//! Handlers that change anything require the server's admin token in the
+//! `X-CRS-Admin-Token` header, and a client certificate if the server
+//! requires client certificates. A server started without an admin token
+//! refuses them all with 403 Forbidden. Listing bans and maintenance
+//! windows is open to anyone, like listing clients.
+
+// Suppress warnings for Dropshot's macro-generated phantom types
+#![allow(dead_code)]
+
+use crate::api::{tokens_match, ApiContext};
+use crs_common::{
+    Ban, BanKind, BanRequest, ClientId, ListBansResponse,
+    ListMaintenanceWindowsResponse, MaintenanceRequest, MaintenanceWindow,
+    MaintenanceWindowRequest, QuarantineRequest, ADMIN_TOKEN_HEADER,
+};
+use dropshot::{
+    endpoint, HttpError, HttpResponseCreated, HttpResponseDeleted,
+    HttpResponseOk, HttpResponseUpdatedNoContent, Path, RequestContext,
+    TypedBody,
+};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use uuid::Uuid;
+
+/// Path parameters identifying a single client
+#[derive(Deserialize, JsonSchema)]
+pub struct ClientPath {
+    /// The client's ID
+    pub client_id: ClientId,
+}
+
+/// Path parameters identifying a single maintenance window
+#[derive(Deserialize, JsonSchema)]
+pub struct MaintenanceWindowPath {
+    /// The window's ID
+    pub window_id: Uuid,
+}
+
+/// Path parameters identifying a single ban
+#[derive(Deserialize, JsonSchema)]
+pub struct BanPath {
+    /// What the ban matches against
+    pub kind: BanKind,
+    /// The banned client ID or hostname
+    pub value: String,
+}
+
+/// Reject requests that are not from an operator
+///
+/// Requests must carry the admin token and come with a client certificate,
+/// if the server requires them. Without an admin token nobody is an
+/// operator, so every request is refused.
+fn check_admin(ctx: &RequestContext<ApiContext>) -> Result<(), HttpError> {
+    let api_context = ctx.context();
+    api_context.peer_identity(&ctx.request)?;
+
+    let Some(expected) = &api_context.admin_token else {
+        return Err(HttpError::for_client_error(
+            Some("AdminDisabled".to_string()),
+            http::StatusCode::FORBIDDEN,
+            "Administration is disabled, the server has no admin token"
+                .to_string(),
+        ));
+    };
+    let presented = ctx
+        .request
+        .headers()
+        .get(ADMIN_TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("");
+    if tokens_match(presented, expected) {
+        return Ok(());
+    }
+    Err(HttpError::for_client_error(
+        Some("InvalidAdminToken".to_string()),
+        http::StatusCode::UNAUTHORIZED,
+        "Missing or invalid admin token".to_string(),
+    ))
+}
+
+/// Remove a client from the registry
+///
+/// The client disappears from the client list. If it is still running it
+/// will reappear the next time it registers, unless it is also banned.
+#[endpoint {
+    method = DELETE,
+    path = "/api/clients/{client_id}",
+}]
+pub async fn remove_client(
+    ctx: RequestContext<ApiContext>,
+    path: Path<ClientPath>,
+) -> Result<HttpResponseDeleted, HttpError> {
+    check_admin(&ctx)?;
+    let client_id = path.into_inner().client_id;
+    ctx.context().registry.remove_client(client_id)?;
+    Ok(HttpResponseDeleted())
+}
+
+/// Quarantine a client
+///
+/// The client stays listed and keeps heartbeating, but is flagged until
+/// the quarantine is lifted.
+#[endpoint {
+    method = PUT,
+    path = "/api/clients/{client_id}/quarantine",
+}]
+pub async fn quarantine_client(
+    ctx: RequestContext<ApiContext>,
+    path: Path<ClientPath>,
+    body: TypedBody<QuarantineRequest>,
+) -> Result<HttpResponseUpdatedNoContent, HttpError> {
+    check_admin(&ctx)?;
+    let client_id = path.into_inner().client_id;
+    let request = body.into_inner();
+    ctx.context()
+        .registry
+        .quarantine(client_id, request.reason)?;
+    Ok(HttpResponseUpdatedNoContent())
+}
+
+/// Lift a client's quarantine
+#[endpoint {
+    method = DELETE,
+    path = "/api/clients/{client_id}/quarantine",
+}]
+pub async fn release_client(
+    ctx: RequestContext<ApiContext>,
+    path: Path<ClientPath>,
+) -> Result<HttpResponseDeleted, HttpError> {
+    check_admin(&ctx)?;
+    let client_id = path.into_inner().client_id;
+    ctx.context().registry.release_quarantine(client_id)?;
+    Ok(HttpResponseDeleted())
+}
+
+/// Put a client in maintenance
+///
+/// The client's status stays maintenance, whatever its heartbeats say,
+/// until the maintenance is ended.
+#[endpoint {
+    method = PUT,
+    path = "/api/clients/{client_id}/maintenance",
+}]
+pub async fn start_maintenance(
+    ctx: RequestContext<ApiContext>,
+    path: Path<ClientPath>,
+    body: TypedBody<MaintenanceRequest>,
+) -> Result<HttpResponseUpdatedNoContent, HttpError> {
+    check_admin(&ctx)?;
+    let client_id = path.into_inner().client_id;
+    let request = body.into_inner();
+    ctx.context()
+        .registry
+        .start_maintenance(client_id, request.reason)?;
+    Ok(HttpResponseUpdatedNoContent())
+}
+
+/// End a client's maintenance
+///
+/// The client goes back to the status its heartbeats warrant. Fails with
+/// 409 Conflict if the client is not in maintenance.
+#[endpoint {
+    method = DELETE,
+    path = "/api/clients/{client_id}/maintenance",
+}]
+pub async fn end_maintenance(
+    ctx: RequestContext<ApiContext>,
+    path: Path<ClientPath>,
+) -> Result<HttpResponseDeleted, HttpError> {
+    check_admin(&ctx)?;
+    let client_id = path.into_inner().client_id;
+    ctx.context().registry.end_maintenance(client_id)?;
+    Ok(HttpResponseDeleted())
+}
+
+/// List all maintenance windows
+///
+/// Windows are listed in order of when they start. One-off windows are
+/// removed once they are over.
+#[endpoint {
+    method = GET,
+    path = "/api/maintenance-windows",
+}]
+pub async fn list_maintenance_windows(
+    ctx: RequestContext<ApiContext>,
+) -> Result<HttpResponseOk<ListMaintenanceWindowsResponse>, HttpError> {
+    let windows = ctx.context().registry.list_maintenance_windows()?;
+    Ok(HttpResponseOk(ListMaintenanceWindowsResponse { windows }))
+}
+
+/// Schedule a maintenance window
+///
+/// The window covers a client or every client matching a tag selector, for
+/// a duration or on a recurring cron schedule. Status changes of covered
+/// clients are recorded as expected and not sent to webhooks.
+#[endpoint {
+    method = POST,
+    path = "/api/maintenance-windows",
+}]
+pub async fn create_maintenance_window(
+    ctx: RequestContext<ApiContext>,
+    body: TypedBody<MaintenanceWindowRequest>,
+) -> Result<HttpResponseCreated<MaintenanceWindow>, HttpError> {
+    check_admin(&ctx)?;
+    let window = ctx
+        .context()
+        .registry
+        .schedule_maintenance_window(body.into_inner())?;
+    Ok(HttpResponseCreated(window))
+}
+
+/// Cancel a maintenance window
+#[endpoint {
+    method = DELETE,
+    path = "/api/maintenance-windows/{window_id}",
+}]
+pub async fn delete_maintenance_window(
+    ctx: RequestContext<ApiContext>,
+    path: Path<MaintenanceWindowPath>,
+) -> Result<HttpResponseDeleted, HttpError> {
+    check_admin(&ctx)?;
+    let window_id = path.into_inner().window_id;
+    ctx.context()
+        .registry
+        .cancel_maintenance_window(window_id)?;
+    Ok(HttpResponseDeleted())
+}
+
+/// List all bans
+#[endpoint {
+    method = GET,
+    path = "/api/bans",
+}]
+pub async fn list_bans(
+    ctx: RequestContext<ApiContext>,
+) -> Result<HttpResponseOk<ListBansResponse>, HttpError> {
+    let mut bans = ctx.context().registry.list_bans()?;
+    bans.sort_by(|a, b| (a.kind, &a.value).cmp(&(b.kind, &b.value)));
+    Ok(HttpResponseOk(ListBansResponse { bans }))
+}
+
+/// Ban a client ID or hostname
+///
+/// Matching clients are refused with 403 Forbidden when they next register
+/// or heartbeat.
+#[endpoint {
+    method = POST,
+    path = "/api/bans",
+}]
+pub async fn create_ban(
+    ctx: RequestContext<ApiContext>,
+    body: TypedBody<BanRequest>,
+) -> Result<HttpResponseCreated<Ban>, HttpError> {
+    check_admin(&ctx)?;
+    let request = body.into_inner();
+    let ban = ctx.context().registry.ban(
+        request.kind,
+        &request.value,
+        request.reason,
+    )?;
+    Ok(HttpResponseCreated(ban))
+}
+
+/// Remove a ban
+#[endpoint {
+    method = DELETE,
+    path = "/api/bans/{kind}/{value}",
+}]
+pub async fn delete_ban(
+    ctx: RequestContext<ApiContext>,
+    path: Path<BanPath>,
+) -> Result<HttpResponseDeleted, HttpError> {
+    check_admin(&ctx)?;
+    let path = path.into_inner();
+    ctx.context().registry.unban(path.kind, &path.value)?;
+    Ok(HttpResponseDeleted())
+}
@@ -8,14 +8,22 @@
 // Suppress warnings for Dropshot's macro-generated phantom types
 #![allow(dead_code)]
 
-use crate::registry::Registry;
+use crate::admin::ClientPath;
+use crate::cluster::Cluster;
+use crate::health::{Health, ServerSettings};
+use crate::listing::{self, ClientPageSelector};
+use crate::mtls::{PeerIdentity, PeerTable};
+use crate::registry::{Registry, RegistryError};
 use chrono::Utc;
 use crs_common::{
-    HeartbeatRequest, HeartbeatResponse, ListClientsResponse, RegisterRequest,
-    RegisterResponse,
+    AvailabilityWindow, ClientHistoryResponse, ClientMetricsResponse,
+    ClientScanParams, DeregisterRequest, HeartbeatRequest, HeartbeatResponse,
+    ListClientsResponse, RegisterRequest, RegisterResponse,
 };
 use dropshot::{
-    endpoint, HttpError, HttpResponseOk, RequestContext, TypedBody,
+    endpoint, ApiDescription, HttpError, HttpResponseOk,
+    HttpResponseUpdatedNoContent, PaginationParams, Path, Query,
+    RequestContext, RequestInfo, ResultsPage, TypedBody, WhichPage,
 };
 
 /// Context passed to all API handlers
@@ -26,13 +34,181 @@ pub struct ApiContext {
     pub registry: Registry,
     /// Server start time
     pub start_time: chrono::DateTime<chrono::Utc>,
+    /// Pre-shared token clients must present to register, if any
+    pub enrollment_token: Option<String>,
+    /// Token operators must present to change the registry; without one
+    /// the administrative endpoints are disabled
+    pub admin_token: Option<String>,
+    /// Client certificate identities, if client certificates are required
+    pub peers: Option<PeerTable>,
+    /// Readiness of the registry and status sweeper
+    pub health: Health,
+    /// Settings reported by `GET /api/server`
+    pub settings: ServerSettings,
+    /// Cluster this server replicates its registry with, if any
+    pub cluster: Option<Cluster>,
+}
+
+impl ApiContext {
+    /// Look up the verified client certificate behind a request
+    ///
+    /// Returns `None` when client certificates are not required. When they
+    /// are, requests that did not come through the mutual TLS front end are
+    /// rejected.
+    pub fn peer_identity(
+        &self,
+        request: &RequestInfo,
+    ) -> Result<Option<PeerIdentity>, HttpError> {
+        let Some(peers) = &self.peers else {
+            return Ok(None);
+        };
+
+        match peers.get(request.remote_addr()) {
+            Some(identity) => Ok(Some(identity)),
+            None => Err(HttpError::for_client_error(
+                Some("CertificateRequired".to_string()),
+                http::StatusCode::UNAUTHORIZED,
+                "A client certificate is required".to_string(),
+            )),
+        }
+    }
+}
+
+/// Reject requests that did not come through the mutual TLS front end
+///
+/// When client certificates are required, Dropshot listens on loopback,
+/// where any local process could reach it directly. Handlers that do not
+/// check the certificate themselves call this first, so that reading the
+/// registry needs a certificate just as changing it does.
+pub fn require_peer(ctx: &RequestContext<ApiContext>) -> Result<(), HttpError> {
+    ctx.context().peer_identity(&ctx.request).map(|_| ())
+}
+
+/// Reject a client whose certificate was not issued for `hostname`
+fn check_certificate(
+    identity: &PeerIdentity,
+    hostname: &str,
+) -> Result<(), HttpError> {
+    if identity.matches(hostname) {
+        return Ok(());
+    }
+
+    Err(HttpError::for_client_error(
+        Some("CertificateMismatch".to_string()),
+        http::StatusCode::FORBIDDEN,
+        format!(
+            "Hostname {:?} does not match the client certificate ({})",
+            hostname,
+            identity.subject()
+        ),
+    ))
+}
+
+/// Build the API description containing every CRS endpoint
+///
+/// This includes the REST API handlers in this module, the administrative
+/// handlers, the web dashboard, the metrics and health endpoints, and the
+/// OpenAPI document.
+pub fn api_description() -> ApiDescription<ApiContext> {
+    let mut api = ApiDescription::new();
+    api.register(register).expect("failed to register endpoint");
+    api.register(heartbeat)
+        .expect("failed to register endpoint");
+    api.register(deregister)
+        .expect("failed to register endpoint");
+    api.register(list_clients)
+        .expect("failed to register endpoint");
+    api.register(client_history)
+        .expect("failed to register endpoint");
+    api.register(client_metrics)
+        .expect("failed to register endpoint");
+    api.register(crate::admin::remove_client)
+        .expect("failed to register endpoint");
+    api.register(crate::admin::quarantine_client)
+        .expect("failed to register endpoint");
+    api.register(crate::admin::release_client)
+        .expect("failed to register endpoint");
+    api.register(crate::admin::start_maintenance)
+        .expect("failed to register endpoint");
+    api.register(crate::admin::end_maintenance)
+        .expect("failed to register endpoint");
+    api.register(crate::admin::list_maintenance_windows)
+        .expect("failed to register endpoint");
+    api.register(crate::admin::create_maintenance_window)
+        .expect("failed to register endpoint");
+    api.register(crate::admin::delete_maintenance_window)
+        .expect("failed to register endpoint");
+    api.register(crate::admin::list_bans)
+        .expect("failed to register endpoint");
+    api.register(crate::admin::create_ban)
+        .expect("failed to register endpoint");
+    api.register(crate::admin::delete_ban)
+        .expect("failed to register endpoint");
+    api.register(crate::events::stream_events)
+        .expect("failed to register endpoint");
+    api.register(crate::web::dashboard)
+        .expect("failed to register endpoint");
+    api.register(crate::web::client_detail)
+        .expect("failed to register endpoint");
+    api.register(crate::metrics::serve_metrics)
+        .expect("failed to register endpoint");
+    api.register(crate::health::healthz)
+        .expect("failed to register endpoint");
+    api.register(crate::health::readyz)
+        .expect("failed to register endpoint");
+    api.register(crate::health::get_server_info)
+        .expect("failed to register endpoint");
+    api.register(crate::cluster::get_cluster_status)
+        .expect("failed to register endpoint");
+    api.register(crate::cluster::sync_cluster)
+        .expect("failed to register endpoint");
+    api.register(crate::openapi::get_openapi)
+        .expect("failed to register endpoint");
+    api
+}
+
+impl From<RegistryError> for HttpError {
+    fn from(error: RegistryError) -> Self {
+        match error {
+            RegistryError::ClientNotFound(_)
+            | RegistryError::BanNotFound(_)
+            | RegistryError::MaintenanceWindowNotFound(_) => {
+                HttpError::for_not_found(None, error.to_string())
+            }
+            RegistryError::InvalidSecret(_) => HttpError::for_client_error(
+                Some("InvalidClientSecret".to_string()),
+                http::StatusCode::UNAUTHORIZED,
+                error.to_string(),
+            ),
+            RegistryError::Banned(_) => HttpError::for_client_error(
+                Some("Banned".to_string()),
+                http::StatusCode::FORBIDDEN,
+                error.to_string(),
+            ),
+            RegistryError::NotInMaintenance(_) => HttpError::for_client_error(
+                Some("NotInMaintenance".to_string()),
+                http::StatusCode::CONFLICT,
+                error.to_string(),
+            ),
+            RegistryError::InvalidBan(_)
+            | RegistryError::InvalidMaintenanceWindow(_) => {
+                HttpError::for_bad_request(None, error.to_string())
+            }
+            RegistryError::Store(_) => {
+                HttpError::for_internal_error(error.to_string())
+            }
+        }
+    }
 }
 
 /// Register a new client
 ///
 /// Accepts client information (hostname, OS, IP, version, tags) and
-/// registers the client in the registry. Returns the client's deterministic
-/// ID and the recommended heartbeat interval.
+/// registers the client in the registry. If the server has an enrollment
+/// token, the request must carry it. If client certificates are required,
+/// the hostname must match the certificate, which ties the deterministic
+/// client ID to the certificate subject. Returns the client's ID, a new
+/// client secret, and the recommended heartbeat interval.
 #[endpoint {
     method = POST,
     path = "/api/register",
@@ -42,27 +218,66 @@ pub async fn register(
     body: TypedBody<RegisterRequest>,
 ) -> Result<HttpResponseOk<RegisterResponse>, HttpError> {
     let request = body.into_inner();
-    let registry = &ctx.context().registry;
+    let api_context = ctx.context();
+    let registry = &api_context.registry;
+    let _timer = registry.metrics().time_handler("register");
+
+    if let Some(expected) = &api_context.enrollment_token {
+        let presented = request.enrollment_token.as_deref().unwrap_or("");
+        if !tokens_match(presented, expected) {
+            return Err(HttpError::for_client_error(
+                Some("InvalidEnrollmentToken".to_string()),
+                http::StatusCode::UNAUTHORIZED,
+                "Missing or invalid enrollment token".to_string(),
+            ));
+        }
+    }
+
+    let identity = api_context.peer_identity(&ctx.request)?;
+    if let Some(identity) = &identity {
+        check_certificate(identity, &request.client_info.hostname)?;
+    }
 
     // Extract the client's actual IP address from the connection
-    let client_ip = ctx.request.remote_addr().ip().to_string();
+    let client_ip = match &identity {
+        Some(identity) => identity.peer_addr.ip(),
+        None => ctx.request.remote_addr().ip(),
+    };
 
     // Override the IP address with what we actually see
     let mut client_info = request.client_info;
-    client_info.ip_address = client_ip;
+    client_info.ip_address = client_ip.to_string();
 
-    let client_id = registry.register(client_info);
+    let registration = registry.register(client_info)?;
+    registry.metrics().record_registration(registration.first);
 
     Ok(HttpResponseOk(RegisterResponse {
-        client_id,
-        heartbeat_interval_secs: 10,
+        client_id: registration.client_id,
+        client_secret: registration.client_secret,
+        heartbeat_interval_secs: registration
+            .heartbeat_policy
+            .interval
+            .as_secs(),
     }))
 }
 
+/// Compare two tokens without returning early on the first mismatch
+pub fn tokens_match(presented: &str, expected: &str) -> bool {
+    presented.len() == expected.len()
+        && presented
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
 /// Record a client heartbeat
 ///
-/// Updates the last heartbeat timestamp for a registered client.
-/// Returns an error if the client ID is not found in the registry.
+/// Updates the last heartbeat timestamp for a registered client, along with
+/// any system metrics and health check results the heartbeat carries. A
+/// client with a failing check is marked degraded.
+/// Returns 401 if the client ID is not found in the registry or the client
+/// secret does not match, without saying which.
 #[endpoint {
     method = POST,
     path = "/api/heartbeat",
@@ -72,34 +287,162 @@ pub async fn heartbeat(
     body: TypedBody<HeartbeatRequest>,
 ) -> Result<HttpResponseOk<HeartbeatResponse>, HttpError> {
     let request = body.into_inner();
-    let registry = &ctx.context().registry;
+    let api_context = ctx.context();
+    let registry = &api_context.registry;
+    let metrics = registry.metrics();
+    let _timer = metrics.time_handler("heartbeat");
+
+    if let Some(identity) = api_context.peer_identity(&ctx.request)? {
+        let client = registry
+            .authenticate(request.client_id, &request.client_secret)
+            .inspect_err(|e| metrics.record_heartbeat_error(e))?;
+        check_certificate(&identity, &client.info.hostname)?;
+    }
 
+    // Heartbeats from clients that do not report metrics carry none
+    let system_metrics = request.metrics.filter(|m| !m.is_empty());
     registry
-        .heartbeat(request.client_id)
-        .map_err(|e| HttpError::for_not_found(None, e.to_string()))?;
+        .heartbeat(
+            request.client_id,
+            &request.client_secret,
+            system_metrics,
+            request.checks,
+        )
+        .inspect_err(|e| metrics.record_heartbeat_error(e))?;
+    metrics.record_heartbeat();
 
     Ok(HttpResponseOk(HeartbeatResponse {
         server_time: Utc::now(),
     }))
 }
 
-/// List all registered clients
+/// Deregister a client that is shutting down
 ///
-/// Returns a list of all clients registered in the system with their
-/// current status, registration time, and last heartbeat time.
+/// Marks the client as departed so that a clean shutdown is distinguishable
+/// from a crash. Returns 401 if the client ID is not found in the registry
+/// or the client secret does not match, without saying which.
+#[endpoint {
+    method = POST,
+    path = "/api/deregister",
+}]
+pub async fn deregister(
+    ctx: RequestContext<ApiContext>,
+    body: TypedBody<DeregisterRequest>,
+) -> Result<HttpResponseUpdatedNoContent, HttpError> {
+    let request = body.into_inner();
+    let api_context = ctx.context();
+    let registry = &api_context.registry;
+    let _timer = registry.metrics().time_handler("deregister");
+
+    if let Some(identity) = api_context.peer_identity(&ctx.request)? {
+        let client =
+            registry.authenticate(request.client_id, &request.client_secret)?;
+        check_certificate(&identity, &client.info.hostname)?;
+    }
+
+    registry.deregister(request.client_id, &request.client_secret)?;
+
+    Ok(HttpResponseUpdatedNoContent())
+}
+
+/// List registered clients
+///
+/// Returns registered clients with their current status, registration
+/// time, last heartbeat time, and availability statistics. Clients can be
+/// filtered by status, operating system, version, hostname glob and tags,
+/// and are sorted by IP address unless another sort key is given.
+///
+/// The list is paginated: when more clients match than fit on one page,
+/// `next_page` holds a token that fetches the rest when passed back as
+/// `page_token`.
 #[endpoint {
     method = GET,
     path = "/api/clients",
 }]
 pub async fn list_clients(
     ctx: RequestContext<ApiContext>,
+    query: Query<PaginationParams<ClientScanParams, ClientPageSelector>>,
 ) -> Result<HttpResponseOk<ListClientsResponse>, HttpError> {
+    require_peer(&ctx)?;
     let api_context = ctx.context();
     let registry = &api_context.registry;
-    let clients = registry.list_clients();
+    let _timer = registry.metrics().time_handler("list_clients");
+    let pag_params = query.into_inner();
+    let limit = ctx.page_limit(&pag_params)?.get() as usize;
+    let (scan, after) = match &pag_params.page {
+        WhichPage::First(scan) => (scan, None),
+        WhichPage::Next(selector) => (
+            &selector.scan,
+            Some((selector.last_key.as_str(), selector.last_id)),
+        ),
+    };
+    let windows = match scan.window {
+        Some(window) => vec![window],
+        None => AvailabilityWindow::ALL.to_vec(),
+    };
+
+    let page = listing::page(registry.list_clients()?, scan, after, limit)
+        .map_err(|e| HttpError::for_bad_request(None, e.to_string()))?;
+    let mut clients = page.clients;
+    for client in &mut clients {
+        client.availability =
+            registry.availability(client.client_id, &windows)?;
+    }
+
+    let sort = scan.sort.unwrap_or_default();
+    let results =
+        ResultsPage::new(clients, scan, |client, scan| ClientPageSelector {
+            scan: scan.clone(),
+            last_key: listing::sort_key(client, sort),
+            last_id: client.client_id,
+        })?;
 
     Ok(HttpResponseOk(ListClientsResponse {
-        clients,
+        clients: results.items,
+        next_page: results.next_page.filter(|_| page.more),
         server_start_time: api_context.start_time,
     }))
 }
+
+/// Get a client's history
+///
+/// Returns every recorded registration, status transition, information
+/// change and quarantine for the client, oldest first.
+#[endpoint {
+    method = GET,
+    path = "/api/clients/{client_id}/history",
+}]
+pub async fn client_history(
+    ctx: RequestContext<ApiContext>,
+    path: Path<ClientPath>,
+) -> Result<HttpResponseOk<ClientHistoryResponse>, HttpError> {
+    require_peer(&ctx)?;
+    let registry = &ctx.context().registry;
+    let _timer = registry.metrics().time_handler("client_history");
+    let client_id = path.into_inner().client_id;
+    let entries = registry.client_history(client_id)?;
+
+    Ok(HttpResponseOk(ClientHistoryResponse { client_id, entries }))
+}
+
+/// Get a client's recent system metrics
+///
+/// Returns the metrics samples the client attached to its recent
+/// heartbeats, oldest first. Samples are kept in memory only, so the list
+/// starts out empty when the server restarts.
+#[endpoint {
+    method = GET,
+    path = "/api/clients/{client_id}/metrics",
+}]
+pub async fn client_metrics(
+    ctx: RequestContext<ApiContext>,
+    path: Path<ClientPath>,
+) -> Result<HttpResponseOk<ClientMetricsResponse>, HttpError> {
+    require_peer(&ctx)?;
+    let registry = &ctx.context().registry;
+    let _timer = registry.metrics().time_handler("client_metrics");
+    let client_id = path.into_inner().client_id;
+    let samples = registry.metrics_samples(client_id)?;
+
+    Ok(HttpResponseOk(ClientMetricsResponse { client_id, samples }))
+}
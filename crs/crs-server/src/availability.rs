@@ -0,0 +1,269 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Availability statistics
+//!
+//! A client's history records every status it has been in and when it got
+//! there, which is enough to replay its status over any window of time.
+//! [`compute`] does that replay and summarizes it as [`AvailabilityStats`]:
+//! the share of time online, the number and length of outages, and the mean
+//! time to recovery.
+//!
+//! An outage is a continuous stretch of time offline. Departed and
+//! maintenance time is not an outage, since the client was shut down or
+//! taken out of service deliberately, and is left out of the observed time
+//! altogether. So is offline time that began during a maintenance window,
+//! when the client was expected to go down, and time before the earliest
+//! status in the history, either because the client had not registered yet
+//! or because older history has been dropped. Stale time counts as online,
+//! since the client has only just missed a heartbeat.
+
+use chrono::{DateTime, Duration, Utc};
+use crs_common::{
+    AvailabilityStats, AvailabilityWindow, ClientStatus, HistoryChange,
+    HistoryEntry,
+};
+
+/// A stretch of time a client spent in one status
+struct Span {
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    status: ClientStatus,
+
+    /// Whether the client entered the status during a maintenance window
+    expected: bool,
+
+    /// Whether the span is still going on at `now`
+    ongoing: bool,
+}
+
+/// Split a history into consecutive status spans ending at `now`
+fn status_spans(history: &[HistoryEntry], now: DateTime<Utc>) -> Vec<Span> {
+    let mut spans = Vec::new();
+    let mut current: Option<(DateTime<Utc>, ClientStatus, bool)> = None;
+
+    for entry in history {
+        let Some(status) = entry.resulting_status() else {
+            continue;
+        };
+        let expected = matches!(
+            entry.change,
+            HistoryChange::StatusChanged { expected: true, .. }
+        );
+        match current {
+            Some((_, current_status, _)) if current_status == status => {}
+            Some((start, current_status, current_expected)) => {
+                spans.push(Span {
+                    start,
+                    end: entry.timestamp,
+                    status: current_status,
+                    expected: current_expected,
+                    ongoing: false,
+                });
+                current = Some((entry.timestamp, status, expected));
+            }
+            None => current = Some((entry.timestamp, status, expected)),
+        }
+    }
+
+    if let Some((start, status, expected)) = current {
+        spans.push(Span {
+            start,
+            end: now.max(start),
+            status,
+            expected,
+            ongoing: true,
+        });
+    }
+    spans
+}
+
+/// Compute a client's availability over `window`, ending at `now`
+///
+/// `history` must be in chronological order, as returned by the registry.
+pub fn compute(
+    window: AvailabilityWindow,
+    history: &[HistoryEntry],
+    now: DateTime<Utc>,
+) -> AvailabilityStats {
+    let window_start = now - window.duration();
+    let mut observed = Duration::zero();
+    let mut online = Duration::zero();
+    let mut outages = 0;
+    let mut longest_outage = Duration::zero();
+    let mut recoveries = Vec::new();
+
+    for span in status_spans(history, now) {
+        if span.end <= window_start {
+            continue;
+        }
+        let in_window = span.end - span.start.max(window_start);
+
+        match span.status {
+            // Degraded and stale clients are reachable, if not entirely
+            // healthy or on time
+            ClientStatus::Online
+            | ClientStatus::Degraded
+            | ClientStatus::Stale => {
+                observed += in_window;
+                online += in_window;
+            }
+            // Going offline during a maintenance window is not an outage
+            ClientStatus::Offline if span.expected => {}
+            ClientStatus::Offline => {
+                observed += in_window;
+                let length = span.end - span.start;
+                outages += 1;
+                longest_outage = longest_outage.max(length);
+                if !span.ongoing {
+                    recoveries.push(length);
+                }
+            }
+            ClientStatus::Departed | ClientStatus::Maintenance => {}
+        }
+    }
+
+    let availability_percent = (observed > Duration::zero())
+        .then(|| online.as_seconds_f64() * 100.0 / observed.as_seconds_f64());
+    let mttr_secs = (!recoveries.is_empty()).then(|| {
+        let total: i64 = recoveries.iter().map(|d| d.num_seconds()).sum();
+        (total / recoveries.len() as i64) as u64
+    });
+
+    AvailabilityStats {
+        window,
+        observed_secs: observed.num_seconds() as u64,
+        online_secs: online.num_seconds() as u64,
+        availability_percent,
+        outages,
+        longest_outage_secs: longest_outage.num_seconds() as u64,
+        mttr_secs,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(now: DateTime<Utc>, hours_ago: i64) -> DateTime<Utc> {
+        now - Duration::hours(hours_ago)
+    }
+
+    fn registered(timestamp: DateTime<Utc>) -> HistoryEntry {
+        HistoryEntry {
+            timestamp,
+            change: HistoryChange::Registered { first: true },
+        }
+    }
+
+    fn status(
+        timestamp: DateTime<Utc>,
+        from: ClientStatus,
+        to: ClientStatus,
+    ) -> HistoryEntry {
+        HistoryEntry {
+            timestamp,
+            change: HistoryChange::StatusChanged {
+                from,
+                to,
+                expected: false,
+            },
+        }
+    }
+
+    #[test]
+    fn test_always_online() {
+        let now = Utc::now();
+        let history = [registered(at(now, 48))];
+
+        let stats = compute(AvailabilityWindow::Day, &history, now);
+        assert_eq!(stats.observed_secs, 24 * 3600);
+        assert_eq!(stats.availability_percent, Some(100.0));
+        assert_eq!(stats.outages, 0);
+        assert_eq!(stats.longest_outage_secs, 0);
+        assert_eq!(stats.mttr_secs, None);
+
+        // A client that registered an hour ago is judged on that hour only
+        let history = [registered(at(now, 1))];
+        let stats = compute(AvailabilityWindow::Week, &history, now);
+        assert_eq!(stats.observed_secs, 3600);
+        assert_eq!(stats.availability_percent, Some(100.0));
+    }
+
+    #[test]
+    fn test_outages() {
+        use ClientStatus::{Offline, Online};
+
+        let now = Utc::now();
+        let history = [
+            registered(at(now, 100)),
+            // A 4 hour outage that started before the last 24 hours
+            status(at(now, 26), Online, Offline),
+            status(at(now, 22), Offline, Online),
+            // A 2 hour outage inside them
+            status(at(now, 10), Online, Offline),
+            status(at(now, 8), Offline, Online),
+            // An ongoing 1 hour outage
+            status(at(now, 1), Online, Offline),
+        ];
+
+        let stats = compute(AvailabilityWindow::Day, &history, now);
+        assert_eq!(stats.observed_secs, 24 * 3600);
+        assert_eq!(stats.online_secs, 19 * 3600);
+        assert_eq!(stats.outages, 3);
+        assert_eq!(stats.longest_outage_secs, 4 * 3600);
+        assert_eq!(stats.mttr_secs, Some(3 * 3600));
+        let percent = stats.availability_percent.unwrap();
+        assert!((percent - 100.0 * 19.0 / 24.0).abs() < 1e-9);
+
+        // Over a week only the history since registration counts
+        let stats = compute(AvailabilityWindow::Week, &history, now);
+        assert_eq!(stats.observed_secs, 100 * 3600);
+        assert_eq!(stats.online_secs, 93 * 3600);
+        assert_eq!(stats.outages, 3);
+    }
+
+    #[test]
+    fn test_expected_offline_time_is_not_observed() {
+        use ClientStatus::{Offline, Online};
+
+        let now = Utc::now();
+        let mut went_offline = status(at(now, 6), Online, Offline);
+        went_offline.change = HistoryChange::StatusChanged {
+            from: Online,
+            to: Offline,
+            expected: true,
+        };
+        let history = [
+            registered(at(now, 12)),
+            went_offline,
+            status(at(now, 4), Offline, Online),
+        ];
+
+        let stats = compute(AvailabilityWindow::Day, &history, now);
+        assert_eq!(stats.observed_secs, 10 * 3600);
+        assert_eq!(stats.availability_percent, Some(100.0));
+        assert_eq!(stats.outages, 0);
+        assert_eq!(stats.mttr_secs, None);
+    }
+
+    #[test]
+    fn test_departed_time_is_not_observed() {
+        use ClientStatus::{Departed, Online};
+
+        let now = Utc::now();
+        let history = [
+            registered(at(now, 12)),
+            status(at(now, 6), Online, Departed),
+        ];
+
+        let stats = compute(AvailabilityWindow::Day, &history, now);
+        assert_eq!(stats.observed_secs, 6 * 3600);
+        assert_eq!(stats.availability_percent, Some(100.0));
+        assert_eq!(stats.outages, 0);
+
+        // Nothing observed at all
+        let stats = compute(AvailabilityWindow::Day, &[], now);
+        assert_eq!(stats.observed_secs, 0);
+        assert_eq!(stats.availability_percent, None);
+    }
+}
@@ -2,14 +2,24 @@
 
 //! CRS Check - Command-line status viewer for CRS server
 //!
-//! Displays server and client status in an 80-column text format.
+//! Displays server and client status and an availability report in an
+//! 80-column text format, and provides subcommands for removing, banning
+//! and quarantining clients and for scheduling maintenance windows.
 
 use anyhow::{Context, Result};
-use chrono::Utc;
-use clap::Parser;
-use crs_common::{ClientStatus, ListClientsResponse};
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand};
+use crs_api_client::Client as ApiClient;
+use crs_common::{
+    AvailabilityStats, AvailabilityWindow, BanKind, BanRequest, ClientId,
+    ClientScanParams, ClientStatus, ListBansResponse, ListClientsResponse,
+    ListMaintenanceWindowsResponse, MaintenanceRequest, MaintenanceWindow,
+    MaintenanceWindowRequest, MetricsSample, QuarantineRequest, Selector,
+    Usage,
+};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
 
 /// CRS Check - View CRS server status
 #[derive(Parser, Debug)]
@@ -22,6 +32,244 @@ struct Args {
     /// Path to TOML configuration file
     #[arg(short, long)]
     config: Option<PathBuf>,
+
+    /// PEM file with the CA certificates to verify the server against
+    #[arg(long)]
+    ca_bundle: Option<PathBuf>,
+
+    /// PEM client certificate to present to the server
+    #[arg(long, requires = "client_key")]
+    client_cert: Option<PathBuf>,
+
+    /// PEM PKCS#8 private key for the client certificate
+    #[arg(long, requires = "client_cert")]
+    client_key: Option<PathBuf>,
+
+    /// Token the server requires to remove, ban, quarantine or put clients
+    /// in maintenance
+    #[arg(long, env = "CRS_ADMIN_TOKEN", hide_env_values = true)]
+    admin_token: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// Operations supported by crs-check
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Show server and client status (the default)
+    Status {
+        #[command(flatten)]
+        filter: ClientFilter,
+    },
+
+    /// Show each client's availability, outages and mean time to recovery
+    Availability {
+        /// Only report this window (24h, 7d or 30d) instead of all of them
+        #[arg(long)]
+        window: Option<AvailabilityWindow>,
+
+        #[command(flatten)]
+        filter: ClientFilter,
+    },
+
+    /// Show each client's latest load, memory, disk use and uptime
+    Metrics {
+        #[command(flatten)]
+        filter: ClientFilter,
+    },
+
+    /// Remove a client from the registry
+    Remove {
+        /// Client ID or hostname of the client
+        client: String,
+    },
+
+    /// Quarantine a client so that it is flagged in the client list
+    Quarantine {
+        /// Client ID or hostname of the client
+        client: String,
+
+        /// Why the client is being quarantined
+        #[arg(long)]
+        reason: Option<String>,
+    },
+
+    /// Lift a client's quarantine
+    Release {
+        /// Client ID or hostname of the client
+        client: String,
+    },
+
+    /// Put clients in maintenance, or schedule maintenance windows
+    Maintenance {
+        #[command(subcommand)]
+        command: MaintenanceCommand,
+    },
+
+    /// List banned client IDs and hostnames
+    Bans,
+
+    /// Ban a client ID or hostname from registering
+    Ban {
+        #[command(flatten)]
+        target: BanTarget,
+
+        /// Why the ban is being put in place
+        #[arg(long)]
+        reason: Option<String>,
+    },
+
+    /// Remove a ban
+    Unban {
+        #[command(flatten)]
+        target: BanTarget,
+    },
+}
+
+/// Maintenance operations
+#[derive(Subcommand, Debug)]
+enum MaintenanceCommand {
+    /// Put a client in maintenance until it is ended
+    Start {
+        /// Client ID or hostname of the client
+        client: String,
+
+        /// Why the client is going into maintenance
+        #[arg(long)]
+        reason: Option<String>,
+    },
+
+    /// End a client's maintenance
+    End {
+        /// Client ID or hostname of the client
+        client: String,
+    },
+
+    /// Schedule a window during which clients are expected to go offline
+    Schedule {
+        #[command(flatten)]
+        target: WindowTarget,
+
+        /// How long the window lasts, such as "30m" or "2h"
+        #[arg(long, value_parser = humantime::parse_duration)]
+        duration: std::time::Duration,
+
+        /// Repeat the window on a five-field cron schedule, in UTC, such as
+        /// "0 2 * * 6" for 02:00 every Saturday
+        #[arg(long)]
+        schedule: Option<String>,
+
+        /// When the window (or its schedule) takes effect, in RFC 3339
+        /// format; defaults to now
+        #[arg(long)]
+        start: Option<DateTime<Utc>>,
+
+        /// Why the clients are going into maintenance
+        #[arg(long)]
+        reason: Option<String>,
+    },
+
+    /// List maintenance windows
+    Windows,
+
+    /// Cancel a maintenance window
+    Cancel {
+        /// ID of the window
+        window_id: Uuid,
+    },
+}
+
+/// The clients a maintenance window covers
+#[derive(clap::Args, Debug)]
+#[group(required = true, multiple = false)]
+struct WindowTarget {
+    /// Client ID or hostname of a single client
+    #[arg(long)]
+    client: Option<String>,
+
+    /// Every client whose tags match this selector
+    #[arg(long, short = 'l')]
+    selector: Option<Selector>,
+}
+
+/// Filters narrowing down which clients are listed
+#[derive(clap::Args, Debug, Default)]
+struct ClientFilter {
+    /// Only list clients with this status (online, degraded, stale, offline,
+    /// departed or maintenance)
+    #[arg(long, value_parser = parse_status)]
+    status: Option<ClientStatus>,
+
+    /// Only list clients running this operating system
+    #[arg(long)]
+    os: Option<String>,
+
+    /// Only list clients running this client version
+    #[arg(long)]
+    client_version: Option<String>,
+
+    /// Only list clients whose hostname matches this glob
+    #[arg(long)]
+    hostname: Option<String>,
+
+    /// Only list clients carrying this tag, as key=value (repeatable)
+    #[arg(long)]
+    tag: Vec<String>,
+
+    /// Only list clients whose tags match a selector, such as
+    /// "env=prod,region in (us-west,us-east),!decommissioned"
+    #[arg(long, short = 'l')]
+    selector: Option<Selector>,
+}
+
+impl ClientFilter {
+    /// Scan parameters selecting the filtered clients
+    fn scan(&self) -> ClientScanParams {
+        ClientScanParams {
+            status: self.status,
+            os: self.os.clone(),
+            version: self.client_version.clone(),
+            hostname: self.hostname.clone(),
+            tag: (!self.tag.is_empty()).then(|| self.tag.join(",")),
+            selector: self.selector.as_ref().map(Selector::to_string),
+            ..Default::default()
+        }
+    }
+}
+
+fn parse_status(s: &str) -> Result<ClientStatus, String> {
+    let value = serde_json::Value::String(s.to_lowercase());
+    serde_json::from_value(value).map_err(|_| {
+        format!(
+            "invalid status {:?}, expected online, degraded, stale, offline, \
+             departed or maintenance",
+            s
+        )
+    })
+}
+
+/// The client ID or hostname a ban applies to
+#[derive(clap::Args, Debug)]
+#[group(required = true, multiple = false)]
+struct BanTarget {
+    /// Client ID to ban
+    #[arg(long)]
+    client_id: Option<Uuid>,
+
+    /// Hostname to ban
+    #[arg(long)]
+    hostname: Option<String>,
+}
+
+impl BanTarget {
+    fn kind_and_value(&self) -> (BanKind, String) {
+        match (&self.client_id, &self.hostname) {
+            (Some(id), _) => (BanKind::ClientId, id.to_string()),
+            (None, Some(hostname)) => (BanKind::Hostname, hostname.clone()),
+            (None, None) => unreachable!("clap requires one ban target"),
+        }
+    }
 }
 
 /// Configuration file structure
@@ -29,11 +277,27 @@ struct Args {
 struct Config {
     /// URL of the CRS server
     server: Option<String>,
+
+    /// PEM file with the CA certificates to verify the server against
+    ca_bundle: Option<PathBuf>,
+
+    /// PEM client certificate to present to the server
+    client_cert: Option<PathBuf>,
+
+    /// PEM PKCS#8 private key for the client certificate
+    client_key: Option<PathBuf>,
+
+    /// Token the server requires to remove, ban, quarantine or put clients
+    /// in maintenance
+    admin_token: Option<String>,
 }
 
 /// Final resolved configuration
 struct ResolvedConfig {
     server: String,
+    ca_bundle: Option<PathBuf>,
+    client_cert: Option<(PathBuf, PathBuf)>,
+    admin_token: Option<String>,
 }
 
 fn load_config(path: &PathBuf) -> Result<Config> {
@@ -72,32 +336,296 @@ fn resolve_config(args: Args) -> Result<ResolvedConfig> {
         );
     };
 
-    Ok(ResolvedConfig { server })
+    // Resolve the TLS files and admin token, preferring CLI over config
+    // file
+    let (file_ca_bundle, file_cert, file_key, file_admin_token) =
+        match file_config {
+            Some(cfg) => (
+                cfg.ca_bundle,
+                cfg.client_cert,
+                cfg.client_key,
+                cfg.admin_token,
+            ),
+            None => (None, None, None, None),
+        };
+    let ca_bundle = args.ca_bundle.or(file_ca_bundle);
+    let admin_token = args.admin_token.or(file_admin_token);
+    let client_cert =
+        match (args.client_cert.or(file_cert), args.client_key.or(file_key)) {
+            (Some(cert), Some(key)) => Some((cert, key)),
+            (None, None) => None,
+            _ => anyhow::bail!(
+                "client_cert and client_key must be given together"
+            ),
+        };
+
+    Ok(ResolvedConfig {
+        server,
+        ca_bundle,
+        client_cert,
+        admin_token,
+    })
 }
 
-async fn fetch_clients(server_url: &str) -> Result<ListClientsResponse> {
-    let client = reqwest::Client::new();
-    let url = format!("{}/api/clients", server_url);
+/// Build the HTTP client used to talk to the server
+///
+/// If `ca_bundle` is given, only the CA certificates in that PEM file are
+/// trusted when verifying the server's certificate. If `client_cert` is
+/// given, the certificate and key in those PEM files are presented to the
+/// server.
+fn build_http_client(
+    ca_bundle: Option<&Path>,
+    client_cert: Option<(&Path, &Path)>,
+) -> Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder();
+
+    if let Some(path) = ca_bundle {
+        let pem = std::fs::read(path)
+            .with_context(|| format!("Failed to read CA bundle: {:?}", path))?;
+        let certs =
+            reqwest::Certificate::from_pem_bundle(&pem).with_context(|| {
+                format!("Failed to parse CA bundle: {:?}", path)
+            })?;
+        if certs.is_empty() {
+            anyhow::bail!("No certificates found in CA bundle: {:?}", path);
+        }
 
-    let response = client.get(&url).send().await.with_context(|| {
-        format!("Failed to connect to server: {}", server_url)
-    })?;
+        builder = builder.tls_built_in_root_certs(false);
+        for cert in certs {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
 
-    if !response.status().is_success() {
-        anyhow::bail!("Server returned error: {}", response.status());
+    if let Some((cert_path, key_path)) = client_cert {
+        let cert = std::fs::read(cert_path).with_context(|| {
+            format!("Failed to read client certificate: {:?}", cert_path)
+        })?;
+        let key = std::fs::read(key_path).with_context(|| {
+            format!("Failed to read client key: {:?}", key_path)
+        })?;
+        let identity = reqwest::Identity::from_pkcs8_pem(&cert, &key)
+            .context("Failed to load client certificate")?;
+        builder = builder.identity(identity);
     }
 
-    let clients_response = response
-        .json::<ListClientsResponse>()
+    builder.build().context("Failed to create HTTP client")
+}
+
+/// Fetch every client matching `filter`, sorted by IP address
+///
+/// The server hands out the client list a page at a time, so this keeps
+/// asking for the next page until there are no more.
+async fn fetch_clients(
+    client: &ApiClient,
+    filter: &ClientFilter,
+) -> Result<ListClientsResponse> {
+    client
+        .list_all_clients(&filter.scan())
         .await
-        .context("Failed to parse server response")?;
+        .with_context(|| failed_request(client))
+}
+
+async fn fetch_maintenance_windows(
+    client: &ApiClient,
+) -> Result<ListMaintenanceWindowsResponse> {
+    client
+        .list_maintenance_windows()
+        .await
+        .with_context(|| failed_request(client))
+}
 
-    Ok(clients_response)
+async fn fetch_bans(client: &ApiClient) -> Result<ListBansResponse> {
+    client
+        .list_bans()
+        .await
+        .with_context(|| failed_request(client))
+}
+
+/// Context for an error talking to the server
+fn failed_request(client: &ApiClient) -> String {
+    format!("Request to {} failed", client.baseurl())
+}
+
+/// Resolve a client ID or hostname to a client ID
+///
+/// Anything that parses as a UUID is used as-is. Otherwise the client list
+/// is searched for exactly one client with that hostname.
+async fn resolve_client(api: &ApiClient, client: &str) -> Result<ClientId> {
+    if let Ok(id) = Uuid::parse_str(client) {
+        return Ok(ClientId(id));
+    }
+
+    let filter = ClientFilter {
+        hostname: Some(client.to_string()),
+        ..Default::default()
+    };
+    let response = fetch_clients(api, &filter).await?;
+    let matches: Vec<_> = response
+        .clients
+        .iter()
+        .filter(|c| c.info.hostname.eq_ignore_ascii_case(client))
+        .collect();
+
+    match matches.as_slice() {
+        [] => anyhow::bail!("No client with hostname {:?}", client),
+        [only] => Ok(only.client_id),
+        _ => {
+            let ids: Vec<_> =
+                matches.iter().map(|c| c.client_id.to_string()).collect();
+            anyhow::bail!(
+                "Hostname {:?} matches several clients, use a client ID: {}",
+                client,
+                ids.join(", ")
+            )
+        }
+    }
+}
+
+async fn run_command(client: &ApiClient, command: Command) -> Result<()> {
+    match command {
+        Command::Status { filter } => {
+            let response = fetch_clients(client, &filter).await?;
+            display_status(response);
+        }
+        Command::Availability { window, filter } => {
+            let response = fetch_clients(client, &filter).await?;
+            display_availability(response, window);
+        }
+        Command::Metrics { filter } => {
+            let response = fetch_clients(client, &filter).await?;
+            display_metrics(&response);
+        }
+        Command::Remove { client: target } => {
+            let client_id = resolve_client(client, &target).await?;
+            client
+                .remove_client(client_id)
+                .await
+                .with_context(|| failed_request(client))?;
+            println!("Removed client {}", client_id);
+        }
+        Command::Quarantine {
+            client: target,
+            reason,
+        } => {
+            let client_id = resolve_client(client, &target).await?;
+            let request = QuarantineRequest { reason };
+            client
+                .quarantine_client(client_id, &request)
+                .await
+                .with_context(|| failed_request(client))?;
+            println!("Quarantined client {}", client_id);
+        }
+        Command::Release { client: target } => {
+            let client_id = resolve_client(client, &target).await?;
+            client
+                .release_client(client_id)
+                .await
+                .with_context(|| failed_request(client))?;
+            println!("Released client {} from quarantine", client_id);
+        }
+        Command::Maintenance {
+            command:
+                MaintenanceCommand::Start {
+                    client: target,
+                    reason,
+                },
+        } => {
+            let client_id = resolve_client(client, &target).await?;
+            let request = MaintenanceRequest { reason };
+            client
+                .start_maintenance(client_id, &request)
+                .await
+                .with_context(|| failed_request(client))?;
+            println!("Put client {} in maintenance", client_id);
+        }
+        Command::Maintenance {
+            command: MaintenanceCommand::End { client: target },
+        } => {
+            let client_id = resolve_client(client, &target).await?;
+            client
+                .end_maintenance(client_id)
+                .await
+                .with_context(|| failed_request(client))?;
+            println!("Ended maintenance of client {}", client_id);
+        }
+        Command::Maintenance {
+            command:
+                MaintenanceCommand::Schedule {
+                    target,
+                    duration,
+                    schedule,
+                    start,
+                    reason,
+                },
+        } => {
+            let client_id = match &target.client {
+                Some(target) => Some(resolve_client(client, target).await?),
+                None => None,
+            };
+            let request = MaintenanceWindowRequest {
+                client_id,
+                selector: target.selector.as_ref().map(Selector::to_string),
+                reason,
+                starts_at: start,
+                duration_secs: duration.as_secs(),
+                schedule,
+            };
+            let window = client
+                .create_maintenance_window(&request)
+                .await
+                .with_context(|| failed_request(client))?;
+            println!("Scheduled maintenance window {}", window.id);
+        }
+        Command::Maintenance {
+            command: MaintenanceCommand::Windows,
+        } => {
+            let response = fetch_maintenance_windows(client).await?;
+            display_maintenance_windows(&response);
+        }
+        Command::Maintenance {
+            command: MaintenanceCommand::Cancel { window_id },
+        } => {
+            client
+                .delete_maintenance_window(window_id)
+                .await
+                .with_context(|| failed_request(client))?;
+            println!("Cancelled maintenance window {}", window_id);
+        }
+        Command::Bans => {
+            let response = fetch_bans(client).await?;
+            display_bans(&response);
+        }
+        Command::Ban { target, reason } => {
+            let (kind, value) = target.kind_and_value();
+            let request = BanRequest {
+                kind,
+                value,
+                reason,
+            };
+            client
+                .create_ban(&request)
+                .await
+                .with_context(|| failed_request(client))?;
+            println!("Banned {} {}", request.kind, request.value);
+        }
+        Command::Unban { target } => {
+            let (kind, value) = target.kind_and_value();
+            client
+                .delete_ban(kind, &value)
+                .await
+                .with_context(|| failed_request(client))?;
+            println!("Removed ban on {} {}", kind, value);
+        }
+    }
+
+    Ok(())
 }
 
 fn format_duration(client: &crs_common::RegisteredClient) -> String {
-    let duration = client.time_connected();
+    format_span(client.time_connected())
+}
 
+fn format_span(duration: chrono::Duration) -> String {
     if duration.num_days() > 0 {
         format!("{}d {}h", duration.num_days(), duration.num_hours() % 24)
     } else if duration.num_hours() > 0 {
@@ -112,19 +640,59 @@ fn format_duration(client: &crs_common::RegisteredClient) -> String {
 fn format_status(status: ClientStatus) -> &'static str {
     match status {
         ClientStatus::Online => "online",
+        ClientStatus::Degraded => "degraded",
+        ClientStatus::Stale => "stale",
         ClientStatus::Offline => "offline",
+        ClientStatus::Departed => "departed",
+        // Abbreviated to fit the status column
+        ClientStatus::Maintenance => "maint",
+    }
+}
+
+/// List a client's failing health checks, with why each one failed
+/// Whether a client is missing heartbeats inside a maintenance window
+fn is_expected_outage(client: &crs_common::RegisteredClient) -> bool {
+    client.maintenance_window.is_some()
+        && matches!(client.status, ClientStatus::Stale | ClientStatus::Offline)
+}
+
+/// A client's status, marked with "*" if it is an expected outage
+fn format_client_status(client: &crs_common::RegisteredClient) -> String {
+    let status = format_status(client.status);
+    if is_expected_outage(client) {
+        format!("{}*", status)
+    } else {
+        status.to_string()
     }
 }
 
+fn format_failed_checks(client: &crs_common::RegisteredClient) -> String {
+    client
+        .checks
+        .iter()
+        .filter(|check| !check.passed)
+        .map(|check| match &check.message {
+            Some(message) => format!("{} ({})", check.name, message),
+            None => check.name.clone(),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 fn truncate_str(s: &str, max_len: usize) -> String {
-    if s.len() <= max_len {
+    if s.chars().count() <= max_len {
         s.to_string()
     } else {
-        format!("{}...", &s[..max_len - 3])
+        // Cut on a character boundary, since check output need not be ASCII
+        let end = s
+            .char_indices()
+            .nth(max_len - 3)
+            .map_or(s.len(), |(i, _)| i);
+        format!("{}...", &s[..end])
     }
 }
 
-fn display_status(mut response: ListClientsResponse) {
+fn display_status(response: ListClientsResponse) {
     println!("{}", "=".repeat(80));
     println!("CRS Server Status");
     println!("{}", "=".repeat(80));
@@ -154,11 +722,6 @@ fn display_status(mut response: ListClientsResponse) {
     println!("Server Uptime: {}", uptime_str);
     println!();
 
-    // Sort clients by IP address
-    response
-        .clients
-        .sort_by(|a, b| a.info.ip_address.cmp(&b.info.ip_address));
-
     // Client table header
     println!("Registered Clients ({}):", response.clients.len());
     println!("{}", "-".repeat(80));
@@ -174,7 +737,7 @@ fn display_status(mut response: ListClientsResponse) {
         let ip = truncate_str(&client.info.ip_address, 15);
         let os = truncate_str(&client.info.os, 7);
         let first_connected = client.first_connected.format("%Y-%m-%d %H:%M:%S").to_string();
-        let status = format_status(client.status);
+        let status = format_client_status(client);
         let time_connected = format_duration(client);
 
         println!(
@@ -184,17 +747,264 @@ fn display_status(mut response: ListClientsResponse) {
     }
 
     println!("{}", "-".repeat(80));
+    if response.clients.iter().any(is_expected_outage) {
+        println!("* expected: in a maintenance window");
+    }
+
+    // Degraded clients, with the checks that failed
+    let degraded: Vec<_> = response
+        .clients
+        .iter()
+        .filter(|c| c.status == ClientStatus::Degraded)
+        .collect();
+    if !degraded.is_empty() {
+        println!();
+        println!("Degraded Clients ({}):", degraded.len());
+        for client in degraded {
+            println!(
+                "{:<16} {}",
+                truncate_str(&client.info.hostname, 16),
+                truncate_str(&format_failed_checks(client), 63)
+            );
+        }
+    }
+
+    // Quarantined clients, with the reason given by the operator
+    let quarantined: Vec<_> = response
+        .clients
+        .iter()
+        .filter_map(|c| c.quarantine.as_ref().map(|q| (c, q)))
+        .collect();
+    if !quarantined.is_empty() {
+        println!();
+        println!("Quarantined Clients ({}):", quarantined.len());
+        for (client, quarantine) in quarantined {
+            println!(
+                "{:<16} {:<36} {}",
+                truncate_str(&client.info.hostname, 16),
+                client.client_id,
+                truncate_str(quarantine.reason.as_deref().unwrap_or("-"), 26)
+            );
+        }
+    }
+
+    // Clients in maintenance, with the reason given by the operator
+    let in_maintenance: Vec<_> = response
+        .clients
+        .iter()
+        .filter_map(|c| c.maintenance.as_ref().map(|m| (c, m)))
+        .collect();
+    if !in_maintenance.is_empty() {
+        println!();
+        println!("Clients in Maintenance ({}):", in_maintenance.len());
+        for (client, maintenance) in in_maintenance {
+            println!(
+                "{:<16} {:<36} {}",
+                truncate_str(&client.info.hostname, 16),
+                client.client_id,
+                truncate_str(maintenance.reason.as_deref().unwrap_or("-"), 26)
+            );
+        }
+    }
+}
+
+/// Format one row of the availability report
+fn format_availability_row(
+    hostname: &str,
+    stats: &AvailabilityStats,
+) -> String {
+    let secs = |secs: u64| format_span(chrono::Duration::seconds(secs as i64));
+    format!(
+        "{:<16} {:<6} {:>9} {:>8} {:>14} {:>14}",
+        truncate_str(hostname, 16),
+        stats.window.as_str(),
+        stats
+            .availability_percent
+            .map(|p| format!("{:.2}%", p))
+            .unwrap_or_else(|| "-".to_string()),
+        stats.outages,
+        if stats.outages > 0 {
+            secs(stats.longest_outage_secs)
+        } else {
+            "-".to_string()
+        },
+        stats.mttr_secs.map(secs).unwrap_or_else(|| "-".to_string()),
+    )
+}
+
+fn display_availability(
+    response: ListClientsResponse,
+    window: Option<AvailabilityWindow>,
+) {
+    println!("Client Availability ({}):", response.clients.len());
+    println!("{}", "-".repeat(80));
+    println!(
+        "{:<16} {:<6} {:>9} {:>8} {:>14} {:>14}",
+        "Hostname", "Window", "Avail", "Outages", "Longest", "MTTR"
+    );
+    println!("{}", "-".repeat(80));
+    for client in &response.clients {
+        for stats in &client.availability {
+            if window.is_some_and(|w| w != stats.window) {
+                continue;
+            }
+            println!(
+                "{}",
+                format_availability_row(&client.info.hostname, stats)
+            );
+        }
+    }
+    println!("{}", "-".repeat(80));
+}
+
+/// Format a size in bytes with a binary unit
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Format one row of the metrics report
+fn format_metrics_row(
+    hostname: &str,
+    sample: Option<&MetricsSample>,
+) -> String {
+    let dash = || "-".to_string();
+    let metrics = sample.map(|s| &s.metrics);
+    let percent = |usage: Option<&Usage>| {
+        usage
+            .map(|u| format!("{:.0}%", u.used_percent()))
+            .unwrap_or_else(dash)
+    };
+    let total = |usage: Option<&Usage>| {
+        usage
+            .map(|u| format_bytes(u.total_bytes))
+            .unwrap_or_else(dash)
+    };
+    let memory = metrics.and_then(|m| m.memory.as_ref());
+    let root_fs = metrics.and_then(|m| m.root_fs.as_ref());
+    format!(
+        "{:<16} {:>17} {:>6} {:>10} {:>6} {:>10} {:>9}",
+        truncate_str(hostname, 16),
+        metrics
+            .and_then(|m| m.load_average)
+            .map(|l| format!("{:.2} {:.2} {:.2}", l.one, l.five, l.fifteen))
+            .unwrap_or_else(dash),
+        percent(memory),
+        total(memory),
+        percent(root_fs),
+        total(root_fs),
+        metrics
+            .and_then(|m| m.uptime_secs)
+            .map(|secs| format_span(chrono::Duration::seconds(secs as i64)))
+            .unwrap_or_else(dash),
+    )
+}
+
+fn display_metrics(response: &ListClientsResponse) {
+    println!("Client Metrics ({}):", response.clients.len());
+    println!("{}", "-".repeat(80));
+    println!(
+        "{:<16} {:>17} {:>6} {:>10} {:>6} {:>10} {:>9}",
+        "Hostname",
+        "Load (1m 5m 15m)",
+        "Mem",
+        "Mem Total",
+        "Disk",
+        "Disk Size",
+        "Uptime"
+    );
+    println!("{}", "-".repeat(80));
+    for client in &response.clients {
+        println!(
+            "{}",
+            format_metrics_row(&client.info.hostname, client.metrics.as_ref())
+        );
+    }
+    println!("{}", "-".repeat(80));
+}
+
+/// Describe when a maintenance window is in effect
+fn format_window_when(window: &MaintenanceWindow) -> String {
+    let duration =
+        format_span(chrono::Duration::seconds(window.duration_secs as i64));
+    match &window.schedule {
+        Some(schedule) => format!("{} at \"{}\"", duration, schedule),
+        None => format!(
+            "{} from {}",
+            duration,
+            window.starts_at.format("%Y-%m-%d %H:%M")
+        ),
+    }
+}
+
+fn display_maintenance_windows(response: &ListMaintenanceWindowsResponse) {
+    println!("Maintenance Windows ({}):", response.windows.len());
+    println!("{}", "-".repeat(80));
+    println!("{:<36} {:<20} {:<22}", "ID", "Clients", "When");
+    println!("{}", "-".repeat(80));
+    for window in &response.windows {
+        let clients = match (&window.client_id, &window.selector) {
+            (Some(client_id), _) => client_id.to_string(),
+            (None, Some(selector)) => selector.clone(),
+            (None, None) => "-".to_string(),
+        };
+        println!(
+            "{:<36} {:<20} {:<22}",
+            window.id,
+            truncate_str(&clients, 20),
+            truncate_str(&format_window_when(window), 22)
+        );
+    }
+    println!("{}", "-".repeat(80));
+}
+
+fn display_bans(response: &ListBansResponse) {
+    println!("Bans ({}):", response.bans.len());
+    println!("{}", "-".repeat(80));
+    println!("{:<9} {:<36} {:<10} Reason", "Kind", "Value", "Created");
+    println!("{}", "-".repeat(80));
+    for ban in &response.bans {
+        println!(
+            "{:<9} {:<36} {:<10} {}",
+            ban.kind.to_string(),
+            truncate_str(&ban.value, 36),
+            ban.created_at.format("%Y-%m-%d"),
+            truncate_str(ban.reason.as_deref().unwrap_or("-"), 21)
+        );
+    }
+    println!("{}", "-".repeat(80));
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let args = Args::parse();
+    let mut args = Args::parse();
+    let command = args.command.take().unwrap_or(Command::Status {
+        filter: ClientFilter::default(),
+    });
     let config = resolve_config(args)?;
+    let http = build_http_client(
+        config.ca_bundle.as_deref(),
+        config
+            .client_cert
+            .as_ref()
+            .map(|(cert, key)| (cert.as_path(), key.as_path())),
+    )?;
+    let mut client = ApiClient::new_with_client(&config.server, http);
+    if let Some(token) = config.admin_token {
+        client = client.with_admin_token(token);
+    }
 
-    let response = fetch_clients(&config.server).await?;
-    display_status(response);
-
-    Ok(())
+    run_command(&client, command).await
 }
 
 #[cfg(test)]
@@ -202,6 +1012,7 @@ mod tests {
     use super::*;
 
     /// Test that ensures all CLI arguments are represented in the Config struct.
+    /// Subcommands and their arguments are per-invocation and are excluded.
     #[test]
     fn test_cli_and_config_fields_match() {
         use std::collections::HashSet;
@@ -209,12 +1020,20 @@ mod tests {
         let cli_fields: HashSet<&str> = {
             let mut fields = HashSet::new();
             fields.insert("server");
+            fields.insert("ca_bundle");
+            fields.insert("client_cert");
+            fields.insert("client_key");
+            fields.insert("admin_token");
             fields
         };
 
         let config_fields: HashSet<&str> = {
             let mut fields = HashSet::new();
             fields.insert("server");
+            fields.insert("ca_bundle");
+            fields.insert("client_cert");
+            fields.insert("client_key");
+            fields.insert("admin_token");
             fields
         };
 
@@ -235,6 +1054,43 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_build_http_client_with_ca_bundle() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let ca = rcgen::generate_simple_self_signed(vec!["localhost".into()])
+            .unwrap();
+        let ca_path = dir.path().join("ca.pem");
+        std::fs::write(&ca_path, ca.cert.pem()).unwrap();
+        assert!(build_http_client(Some(&ca_path), None).is_ok());
+
+        let bogus_path = dir.path().join("bogus.pem");
+        std::fs::write(&bogus_path, "not a certificate\n").unwrap();
+        let error = build_http_client(Some(&bogus_path), None).unwrap_err();
+        assert!(error.to_string().contains("No certificates found"));
+
+        let missing_path = dir.path().join("missing.pem");
+        assert!(build_http_client(Some(&missing_path), None).is_err());
+    }
+
+    #[test]
+    fn test_build_http_client_with_client_cert() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let client =
+            rcgen::generate_simple_self_signed(vec!["host".into()]).unwrap();
+        let cert_path = dir.path().join("client.pem");
+        let key_path = dir.path().join("client.key");
+        std::fs::write(&cert_path, client.cert.pem()).unwrap();
+        std::fs::write(&key_path, client.key_pair.serialize_pem()).unwrap();
+        assert!(build_http_client(None, Some((&cert_path, &key_path))).is_ok());
+
+        let missing_path = dir.path().join("missing.key");
+        assert!(
+            build_http_client(None, Some((&cert_path, &missing_path))).is_err()
+        );
+    }
+
     #[test]
     fn test_format_duration() {
         use chrono::Duration;
@@ -258,6 +1114,12 @@ mod tests {
             first_connected: now - Duration::try_seconds(30).unwrap(),
             registered_at: now - Duration::try_seconds(30).unwrap(),
             last_heartbeat: now,
+            quarantine: None,
+            maintenance: None,
+            maintenance_window: None,
+            metrics: None,
+            checks: Vec::new(),
+            availability: Vec::new(),
         };
         assert_eq!(format_duration(&client), "30s");
     }
@@ -285,6 +1147,12 @@ mod tests {
             first_connected: now - Duration::try_seconds(600).unwrap(),
             registered_at: now - Duration::try_seconds(300).unwrap(),
             last_heartbeat: now - Duration::try_seconds(300).unwrap(),
+            quarantine: None,
+            maintenance: None,
+            maintenance_window: None,
+            metrics: None,
+            checks: Vec::new(),
+            availability: Vec::new(),
         };
         assert_eq!(format_duration(&client), "0s");
     }
@@ -292,7 +1160,370 @@ mod tests {
     #[test]
     fn test_format_status() {
         assert_eq!(format_status(ClientStatus::Online), "online");
+        assert_eq!(format_status(ClientStatus::Degraded), "degraded");
         assert_eq!(format_status(ClientStatus::Offline), "offline");
+        assert_eq!(format_status(ClientStatus::Departed), "departed");
+        assert_eq!(format_status(ClientStatus::Stale), "stale");
+        assert_eq!(format_status(ClientStatus::Maintenance), "maint");
+
+        // Every status fits the status column
+        for status in [
+            ClientStatus::Online,
+            ClientStatus::Degraded,
+            ClientStatus::Stale,
+            ClientStatus::Offline,
+            ClientStatus::Departed,
+            ClientStatus::Maintenance,
+        ] {
+            assert!(format_status(status).len() <= 8);
+        }
+    }
+
+    #[test]
+    fn test_format_client_status() {
+        use crs_common::{ClientId, ClientInfo, RegisteredClient};
+        use std::collections::HashMap;
+
+        let now = Utc::now();
+        let mut client = RegisteredClient {
+            client_id: ClientId::from_client_data("test", "linux", None),
+            info: ClientInfo {
+                hostname: "test".to_string(),
+                os: "linux".to_string(),
+                ip_address: "127.0.0.1".to_string(),
+                version: "1.0.0".to_string(),
+                host_id: None,
+                tags: HashMap::new(),
+            },
+            status: ClientStatus::Offline,
+            first_connected: now,
+            registered_at: now,
+            last_heartbeat: now,
+            quarantine: None,
+            maintenance: None,
+            maintenance_window: None,
+            metrics: None,
+            checks: Vec::new(),
+            availability: Vec::new(),
+        };
+        assert_eq!(format_client_status(&client), "offline");
+
+        // Outages inside a maintenance window are marked as expected
+        client.maintenance_window = Some(Uuid::nil());
+        assert_eq!(format_client_status(&client), "offline*");
+        client.status = ClientStatus::Stale;
+        assert_eq!(format_client_status(&client), "stale*");
+        client.status = ClientStatus::Online;
+        assert_eq!(format_client_status(&client), "online");
+    }
+
+    #[test]
+    fn test_format_window_when() {
+        let window = MaintenanceWindow {
+            id: Uuid::nil(),
+            client_id: None,
+            selector: Some("rack=r12".to_string()),
+            reason: None,
+            starts_at: "2025-03-01T02:00:00Z".parse().unwrap(),
+            duration_secs: 2 * 3600,
+            schedule: None,
+            created_at: "2025-02-28T12:00:00Z".parse().unwrap(),
+        };
+        assert_eq!(format_window_when(&window), "2h 0m from 2025-03-01 02:00");
+
+        let window = MaintenanceWindow {
+            schedule: Some("0 2 * * 6".to_string()),
+            ..window
+        };
+        assert_eq!(format_window_when(&window), "2h 0m at \"0 2 * * 6\"");
+    }
+
+    #[test]
+    fn test_subcommand_parsing() {
+        let args =
+            Args::try_parse_from(["crs-check", "-s", "http://x"]).unwrap();
+        assert!(args.command.is_none());
+
+        let args = Args::try_parse_from([
+            "crs-check",
+            "ban",
+            "--hostname",
+            "oldhost",
+            "--reason",
+            "decommissioned",
+        ])
+        .unwrap();
+        match args.command {
+            Some(Command::Ban { target, reason }) => {
+                assert_eq!(
+                    target.kind_and_value(),
+                    (BanKind::Hostname, "oldhost".to_string())
+                );
+                assert_eq!(reason.as_deref(), Some("decommissioned"));
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+
+        let args = Args::try_parse_from([
+            "crs-check",
+            "status",
+            "--status",
+            "Offline",
+            "--tag",
+            "env=prod",
+            "--tag",
+            "role=db",
+        ])
+        .unwrap();
+        match args.command {
+            Some(Command::Status { filter }) => {
+                let scan = filter.scan();
+                assert_eq!(scan.status, Some(ClientStatus::Offline));
+                assert_eq!(scan.tag.as_deref(), Some("env=prod,role=db"));
+                assert!(scan.os.is_none() && scan.selector.is_none());
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+
+        // Selectors are checked before anything is sent
+        let args = Args::try_parse_from([
+            "crs-check",
+            "availability",
+            "-l",
+            "region in (us-west, us-east), !decommissioned",
+        ])
+        .unwrap();
+        match args.command {
+            Some(Command::Availability { filter, .. }) => assert_eq!(
+                filter.scan().selector.as_deref(),
+                Some("region in (us-west,us-east),!decommissioned")
+            ),
+            other => panic!("unexpected command: {:?}", other),
+        }
+        let error = Args::try_parse_from([
+            "crs-check",
+            "status",
+            "--selector",
+            "region in us-west",
+        ])
+        .unwrap_err();
+        assert!(error.to_string().contains("expected `(`"));
+        assert!(Args::try_parse_from([
+            "crs-check",
+            "status",
+            "--status",
+            "gone"
+        ])
+        .is_err());
+
+        let args = Args::try_parse_from([
+            "crs-check",
+            "maintenance",
+            "start",
+            "web-01",
+            "--reason",
+            "kernel upgrade",
+        ])
+        .unwrap();
+        match args.command {
+            Some(Command::Maintenance {
+                command: MaintenanceCommand::Start { client, reason },
+            }) => {
+                assert_eq!(client, "web-01");
+                assert_eq!(reason.as_deref(), Some("kernel upgrade"));
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+        let args = Args::try_parse_from([
+            "crs-check",
+            "status",
+            "--status",
+            "maintenance",
+        ])
+        .unwrap();
+        match args.command {
+            Some(Command::Status { filter }) => {
+                assert_eq!(filter.status, Some(ClientStatus::Maintenance))
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+
+        let args = Args::try_parse_from([
+            "crs-check",
+            "maintenance",
+            "schedule",
+            "-l",
+            "rack=r12",
+            "--duration",
+            "2h",
+            "--schedule",
+            "0 2 * * 6",
+        ])
+        .unwrap();
+        match args.command {
+            Some(Command::Maintenance {
+                command:
+                    MaintenanceCommand::Schedule {
+                        target,
+                        duration,
+                        schedule,
+                        start,
+                        ..
+                    },
+            }) => {
+                assert_eq!(target.client, None);
+                assert_eq!(
+                    target.selector.map(|s| s.to_string()).as_deref(),
+                    Some("rack=r12")
+                );
+                assert_eq!(duration.as_secs(), 2 * 3600);
+                assert_eq!(schedule.as_deref(), Some("0 2 * * 6"));
+                assert_eq!(start, None);
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+
+        // A maintenance window needs exactly one target and a duration
+        assert!(Args::try_parse_from([
+            "crs-check",
+            "maintenance",
+            "schedule",
+            "--duration",
+            "1h",
+        ])
+        .is_err());
+        assert!(Args::try_parse_from([
+            "crs-check",
+            "maintenance",
+            "schedule",
+            "--client",
+            "web-01",
+        ])
+        .is_err());
+        assert!(Args::try_parse_from([
+            "crs-check",
+            "maintenance",
+            "schedule",
+            "--client",
+            "web-01",
+            "-l",
+            "rack=r12",
+            "--duration",
+            "1h",
+        ])
+        .is_err());
+
+        // A ban needs exactly one target
+        assert!(Args::try_parse_from(["crs-check", "ban"]).is_err());
+        assert!(Args::try_parse_from([
+            "crs-check",
+            "unban",
+            "--hostname",
+            "a",
+            "--client-id",
+            "6ba7b810-9dad-11d1-80b4-00c04fd430c8",
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_format_availability_row() {
+        let stats = AvailabilityStats {
+            window: AvailabilityWindow::Week,
+            observed_secs: 7 * 86400,
+            online_secs: 7 * 86400 - 5400,
+            availability_percent: Some(99.10714),
+            outages: 2,
+            longest_outage_secs: 3600,
+            mttr_secs: Some(2700),
+        };
+        let row = format_availability_row(&"h".repeat(20), &stats);
+        assert_eq!(
+            row,
+            format!(
+                "{:<16} {:<6} {:>9} {:>8} {:>14} {:>14}",
+                "hhhhhhhhhhhhh...", "7d", "99.11%", 2, "1h 0m", "45m"
+            )
+        );
+        assert!(row.len() <= 80);
+
+        // Clients without outages or observed time show dashes
+        let stats = AvailabilityStats {
+            availability_percent: None,
+            outages: 0,
+            longest_outage_secs: 0,
+            mttr_secs: None,
+            ..stats
+        };
+        let row = format_availability_row("idle", &stats);
+        assert_eq!(row.split_whitespace().filter(|f| *f == "-").count(), 3);
+
+        let args = Args::try_parse_from([
+            "crs-check",
+            "availability",
+            "--window",
+            "30d",
+        ])
+        .unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Availability {
+                window: Some(AvailabilityWindow::Month),
+                ..
+            })
+        ));
+        assert!(Args::try_parse_from([
+            "crs-check",
+            "availability",
+            "--window",
+            "1y",
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_format_metrics_row() {
+        let sample = MetricsSample {
+            timestamp: Utc::now(),
+            metrics: crs_common::SystemMetrics {
+                load_average: Some(crs_common::LoadAverage {
+                    one: 12.5,
+                    five: 10.25,
+                    fifteen: 9.0,
+                }),
+                memory: Some(Usage {
+                    used_bytes: 3 << 30,
+                    total_bytes: 4 << 30,
+                }),
+                root_fs: Some(Usage {
+                    used_bytes: 1000 << 30,
+                    total_bytes: 1023 << 30,
+                }),
+                uptime_secs: Some(999 * 86400 + 23 * 3600),
+            },
+        };
+        let row = format_metrics_row(&"h".repeat(20), Some(&sample));
+        assert_eq!(
+            row,
+            format!(
+                "{:<16} {:>17} {:>6} {:>10} {:>6} {:>10} {:>9}",
+                "hhhhhhhhhhhhh...",
+                "12.50 10.25 9.00",
+                "75%",
+                "4.0 GiB",
+                "98%",
+                "1023.0 GiB",
+                "999d 23h"
+            )
+        );
+        assert_eq!(row.len(), 80);
+
+        // Clients that send no metrics show dashes
+        let row = format_metrics_row("quiet", None);
+        assert_eq!(row.split_whitespace().filter(|f| *f == "-").count(), 6);
+
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
     }
 
     #[test]
@@ -300,6 +1531,7 @@ mod tests {
         assert_eq!(truncate_str("short", 10), "short");
         assert_eq!(truncate_str("verylongstring", 10), "verylon...");
         assert_eq!(truncate_str("exact", 5), "exact");
+        assert_eq!(truncate_str("ééééé", 4), "é...");
     }
 
     #[test]
@@ -334,5 +1566,14 @@ mod tests {
             "Data row must be exactly 80 characters, got {}",
             row.len()
         );
+
+        // Maintenance window rows: 36 + 1 + 20 + 1 + 22 = 80
+        let row = format!(
+            "{:<36} {:<20} {:<22}",
+            Uuid::nil(),
+            "a".repeat(20),
+            "b".repeat(22)
+        );
+        assert_eq!(row.len(), 80);
     }
 }
@@ -0,0 +1,448 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Registry replication between servers
+//!
+//! A cluster is a handful of servers that each keep a full copy of the
+//! registry, so that clients can register and heartbeat with any of them
+//! and any of them can list clients. There is no primary: every node
+//! periodically sends its [`RegistryState`] to each of its configured peers
+//! with `POST /api/cluster/sync`, merges in the state the peer answers
+//! with, and the peer does the same with the state it was sent. Records
+//! are merged by keeping whichever copy changed most recently, so clocks
+//! on the nodes should be kept in sync.
+//!
+//! Sync requests carry the cluster's shared secret in the
+//! `X-CRS-Cluster-Secret` header, and requests without it are rejected.
+//! Peers served over HTTPS are verified against the system's CA
+//! certificates, or only against those in the `ca_bundle` file if one is
+//! configured.
+//! Only the registry is replicated: client history, events and webhooks
+//! stay with the node that saw each change, and so does each client's
+//! status, which every node works out from the heartbeat times it has.
+
+// Suppress warnings for Dropshot's macro-generated phantom types
+#![allow(dead_code)]
+
+use crate::api::{require_peer, tokens_match, ApiContext};
+use crate::health::server_info;
+use crate::registry::Registry;
+use chrono::Utc;
+use crs_common::{
+    ClusterMember, ClusterStatus, ClusterSync, CLUSTER_SECRET_HEADER,
+};
+use dropshot::{
+    endpoint, HttpError, HttpResponseOk, RequestContext, TypedBody,
+};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinSet;
+
+/// Default seconds between syncs with each peer
+const DEFAULT_SYNC_INTERVAL_SECS: u64 = 5;
+
+/// Timeout for a single sync request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The `[cluster]` table of the server config file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClusterSpec {
+    /// Name this node goes by (defaults to its hostname and port)
+    pub node_name: Option<String>,
+
+    /// Base URLs of the other nodes
+    pub peers: Vec<String>,
+
+    /// Shared secret every node presents when syncing
+    pub secret: String,
+
+    /// Seconds between syncs with each peer
+    pub sync_interval_secs: Option<u64>,
+
+    /// PEM bundle of the CA certificates to trust for `https` peers,
+    /// instead of the system's
+    pub ca_bundle: Option<PathBuf>,
+}
+
+/// One configured peer
+struct Peer {
+    client: crs_api_client::Client,
+    member: Mutex<ClusterMember>,
+}
+
+struct Inner {
+    node: String,
+    secret: String,
+    sync_interval: Duration,
+    peers: Vec<Peer>,
+}
+
+/// This node's view of the cluster
+#[derive(Clone)]
+pub struct Cluster {
+    inner: Arc<Inner>,
+}
+
+impl Cluster {
+    /// Validate the cluster configuration
+    ///
+    /// `default_node` names this node if the configuration does not.
+    pub fn from_spec(
+        spec: &ClusterSpec,
+        default_node: String,
+    ) -> Result<Self, ClusterError> {
+        if spec.secret.is_empty() {
+            return Err(ClusterError::EmptySecret);
+        }
+        let sync_interval_secs = spec
+            .sync_interval_secs
+            .unwrap_or(DEFAULT_SYNC_INTERVAL_SECS);
+        if sync_interval_secs == 0 {
+            return Err(ClusterError::ZeroSyncInterval);
+        }
+        let node = spec.node_name.clone().unwrap_or(default_node);
+        if node.is_empty() {
+            return Err(ClusterError::EmptyNodeName);
+        }
+
+        let mut builder = reqwest::Client::builder().timeout(REQUEST_TIMEOUT);
+        if let Some(path) = &spec.ca_bundle {
+            builder = builder.tls_built_in_root_certs(false);
+            for cert in load_ca_bundle(path)? {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        let http = builder
+            .build()
+            .expect("failed to create cluster HTTP client");
+        let peers = spec
+            .peers
+            .iter()
+            .map(|url| {
+                let parsed = reqwest::Url::parse(url).map_err(|e| {
+                    ClusterError::InvalidPeerUrl(url.clone(), e.to_string())
+                })?;
+                if !matches!(parsed.scheme(), "http" | "https") {
+                    return Err(ClusterError::UnsupportedScheme(url.clone()));
+                }
+                Ok(Peer {
+                    client: crs_api_client::Client::new_with_client(
+                        url,
+                        http.clone(),
+                    )
+                    .with_cluster_secret(spec.secret.clone()),
+                    member: Mutex::new(ClusterMember {
+                        url: url.clone(),
+                        node: None,
+                        reachable: false,
+                        last_sync: None,
+                        error: None,
+                    }),
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            inner: Arc::new(Inner {
+                node,
+                secret: spec.secret.clone(),
+                sync_interval: Duration::from_secs(sync_interval_secs),
+                peers,
+            }),
+        })
+    }
+
+    /// Name this node goes by
+    pub fn node(&self) -> &str {
+        &self.inner.node
+    }
+
+    /// How often each peer is synced with
+    pub fn sync_interval(&self) -> Duration {
+        self.inner.sync_interval
+    }
+
+    /// Whether `presented` is the cluster's shared secret
+    pub fn secret_matches(&self, presented: &str) -> bool {
+        tokens_match(presented, &self.inner.secret)
+    }
+
+    /// Membership as this node sees it
+    pub fn status(&self) -> ClusterStatus {
+        ClusterStatus {
+            node: self.inner.node.clone(),
+            enabled: true,
+            members: self
+                .inner
+                .peers
+                .iter()
+                .map(|peer| peer.member.lock().unwrap().clone())
+                .collect(),
+        }
+    }
+
+    /// Sync with every peer once, waiting until all of them are done
+    pub async fn sync_once(&self, registry: &Registry) {
+        let mut syncs = JoinSet::new();
+        for index in 0..self.inner.peers.len() {
+            let cluster = self.clone();
+            let registry = registry.clone();
+            syncs.spawn(
+                async move { cluster.sync_peer(index, &registry).await },
+            );
+        }
+        while syncs.join_next().await.is_some() {}
+    }
+
+    /// Exchange state with one peer and record how it went
+    async fn sync_peer(&self, index: usize, registry: &Registry) {
+        let peer = &self.inner.peers[index];
+        let result = async {
+            let request = ClusterSync {
+                node: self.inner.node.clone(),
+                state: registry.replication_state()?,
+            };
+            let response = peer.client.sync_cluster(&request).await?;
+            registry.merge_replicated_state(response.state)?;
+            Ok::<_, anyhow::Error>(response.node)
+        }
+        .await;
+
+        let mut member = peer.member.lock().unwrap();
+        match result {
+            Ok(node) => {
+                member.node = Some(node);
+                member.reachable = true;
+                member.last_sync = Some(Utc::now());
+                member.error = None;
+            }
+            Err(e) => {
+                if member.error.is_none() {
+                    eprintln!("Failed to sync with {}: {:#}", member.url, e);
+                }
+                member.reachable = false;
+                member.error = Some(format!("{:#}", e));
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for Cluster {
+    // Leaves out the secret
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let peers: Vec<_> = self
+            .inner
+            .peers
+            .iter()
+            .map(|p| p.client.baseurl())
+            .collect();
+        f.debug_struct("Cluster")
+            .field("node", &self.inner.node)
+            .field("peers", &peers)
+            .field("sync_interval", &self.inner.sync_interval)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Start syncing `registry` with the cluster's peers
+///
+/// Returns immediately; syncing happens on a background task.
+pub fn start(cluster: Cluster, registry: Registry) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(cluster.sync_interval());
+        loop {
+            interval.tick().await;
+            cluster.sync_once(&registry).await;
+        }
+    });
+}
+
+/// Fetch cluster membership
+///
+/// Lists the peers this server syncs with, whether the most recent sync
+/// with each succeeded, and when each was last synced. A server that is not
+/// in a cluster reports itself as not enabled, with no members.
+#[endpoint {
+    method = GET,
+    path = "/api/cluster",
+}]
+pub async fn get_cluster_status(
+    ctx: RequestContext<ApiContext>,
+) -> Result<HttpResponseOk<ClusterStatus>, HttpError> {
+    require_peer(&ctx)?;
+    let status = match &ctx.context().cluster {
+        Some(cluster) => cluster.status(),
+        None => ClusterStatus {
+            node: server_info(&ctx).hostname,
+            enabled: false,
+            members: Vec::new(),
+        },
+    };
+    Ok(HttpResponseOk(status))
+}
+
+/// Exchange registry state with another cluster node
+///
+/// Merges the sender's state into this server's registry and answers with
+/// this server's state, after the merge. The request must carry the
+/// cluster's shared secret in the `X-CRS-Cluster-Secret` header. Servers
+/// that are not in a cluster answer 404 Not Found.
+#[endpoint {
+    method = POST,
+    path = "/api/cluster/sync",
+}]
+pub async fn sync_cluster(
+    ctx: RequestContext<ApiContext>,
+    body: TypedBody<ClusterSync>,
+) -> Result<HttpResponseOk<ClusterSync>, HttpError> {
+    require_peer(&ctx)?;
+    let api_context = ctx.context();
+    let Some(cluster) = &api_context.cluster else {
+        return Err(HttpError::for_not_found(
+            None,
+            "This server is not in a cluster".to_string(),
+        ));
+    };
+
+    let presented = ctx
+        .request
+        .headers()
+        .get(CLUSTER_SECRET_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("");
+    if !cluster.secret_matches(presented) {
+        return Err(HttpError::for_client_error(
+            Some("InvalidClusterSecret".to_string()),
+            http::StatusCode::UNAUTHORIZED,
+            "Missing or invalid cluster secret".to_string(),
+        ));
+    }
+
+    let registry = &api_context.registry;
+    registry.merge_replicated_state(body.into_inner().state)?;
+    Ok(HttpResponseOk(ClusterSync {
+        node: cluster.node().to_string(),
+        state: registry.replication_state()?,
+    }))
+}
+
+/// Read the CA certificates in a PEM bundle
+fn load_ca_bundle(
+    path: &Path,
+) -> Result<Vec<reqwest::Certificate>, ClusterError> {
+    let pem = std::fs::read(path)
+        .map_err(|e| ClusterError::ReadCaBundle(path.to_path_buf(), e))?;
+    let certs = reqwest::Certificate::from_pem_bundle(&pem)
+        .map_err(|e| ClusterError::ParseCaBundle(path.to_path_buf(), e))?;
+    if certs.is_empty() {
+        return Err(ClusterError::EmptyCaBundle(path.to_path_buf()));
+    }
+    Ok(certs)
+}
+
+/// Cluster configuration errors
+#[derive(Debug, thiserror::Error)]
+pub enum ClusterError {
+    #[error("Invalid cluster peer URL {0:?}: {1}")]
+    InvalidPeerUrl(String, String),
+
+    #[error("Cluster peer URL {0:?} must use http or https")]
+    UnsupportedScheme(String),
+
+    #[error("The cluster needs a non-empty secret")]
+    EmptySecret,
+
+    #[error("The cluster node name must not be empty")]
+    EmptyNodeName,
+
+    #[error("sync_interval_secs must be at least 1")]
+    ZeroSyncInterval,
+
+    #[error("Failed to read CA bundle {0:?}: {1}")]
+    ReadCaBundle(PathBuf, std::io::Error),
+
+    #[error("Failed to parse CA bundle {0:?}: {1}")]
+    ParseCaBundle(PathBuf, reqwest::Error),
+
+    #[error("No certificates found in CA bundle {0:?}")]
+    EmptyCaBundle(PathBuf),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(peers: &[&str]) -> ClusterSpec {
+        ClusterSpec {
+            node_name: None,
+            peers: peers.iter().map(|p| p.to_string()).collect(),
+            secret: "s3cret".to_string(),
+            sync_interval_secs: None,
+            ca_bundle: None,
+        }
+    }
+
+    #[test]
+    fn test_cluster_from_spec() {
+        let cluster = Cluster::from_spec(
+            &spec(&["http://10.0.0.2:8081", "https://crs-3.example.com"]),
+            "crs-1:8081".to_string(),
+        )
+        .unwrap();
+        assert_eq!(cluster.node(), "crs-1:8081");
+        assert_eq!(
+            cluster.sync_interval(),
+            Duration::from_secs(DEFAULT_SYNC_INTERVAL_SECS)
+        );
+        assert!(cluster.secret_matches("s3cret"));
+        assert!(!cluster.secret_matches("s3cre"));
+
+        let status = cluster.status();
+        assert!(status.enabled);
+        assert_eq!(status.members.len(), 2);
+        assert_eq!(status.members[1].url, "https://crs-3.example.com");
+        assert!(!status.members[0].reachable);
+
+        let named = ClusterSpec {
+            node_name: Some("east".to_string()),
+            ..spec(&[])
+        };
+        let cluster = Cluster::from_spec(&named, "crs-1:8081".to_string());
+        assert_eq!(cluster.unwrap().node(), "east");
+    }
+
+    #[test]
+    fn test_invalid_cluster_spec() {
+        let invalid = |spec: ClusterSpec| {
+            Cluster::from_spec(&spec, "crs-1:8081".to_string()).is_err()
+        };
+        assert!(invalid(spec(&["not a url"])));
+        assert!(invalid(spec(&["ftp://10.0.0.2"])));
+        assert!(invalid(ClusterSpec {
+            secret: String::new(),
+            ..spec(&[])
+        }));
+        assert!(invalid(ClusterSpec {
+            sync_interval_secs: Some(0),
+            ..spec(&[])
+        }));
+        assert!(invalid(ClusterSpec {
+            node_name: Some(String::new()),
+            ..spec(&[])
+        }));
+
+        let dir = tempfile::tempdir().unwrap();
+        let empty = dir.path().join("empty.pem");
+        std::fs::write(&empty, "not a certificate\n").unwrap();
+        assert!(invalid(ClusterSpec {
+            ca_bundle: Some(dir.path().join("missing.pem")),
+            ..spec(&["https://10.0.0.2"])
+        }));
+        assert!(invalid(ClusterSpec {
+            ca_bundle: Some(empty),
+            ..spec(&["https://10.0.0.2"])
+        }));
+    }
+}
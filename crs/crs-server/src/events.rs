@@ -0,0 +1,445 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Client event stream
+//!
+//! The [`Registry`](crate::registry::Registry) publishes a [`ClientEvent`]
+//! on its [`EventBus`] for every client state change. The bus numbers the
+//! events, keeps the most recent ones so that subscribers can resume after
+//! a reconnect, and fans them out to live subscribers.
+//!
+//! `GET /api/events` streams the events as Server-Sent Events. Each event
+//! is sent with its sequence number as the SSE `id`, so a reconnecting
+//! `EventSource` resumes automatically through the `Last-Event-ID` header.
+//! Other subscribers can pass `?since=<sequence>` instead.
+
+// Suppress warnings for Dropshot's macro-generated phantom types
+#![allow(dead_code)]
+
+use crate::api::{require_peer, ApiContext};
+use bytes::Bytes;
+use chrono::Utc;
+use crs_common::{ClientEvent, ClientEventKind, RegisteredClient};
+use dropshot::{endpoint, Body, HttpError, Query, RequestContext};
+use http::{header, Response, StatusCode};
+use http_body::Frame;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+
+/// Number of recent events kept for resuming subscribers
+pub const DEFAULT_EVENT_HISTORY: usize = 1024;
+
+/// How often an idle stream sends a comment to keep the connection open
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Numbered, replayable fan-out of client events
+///
+/// Cloning the bus is cheap and every clone publishes to the same
+/// subscribers.
+#[derive(Clone)]
+pub struct EventBus {
+    log: Arc<Mutex<EventLog>>,
+    sender: broadcast::Sender<ClientEvent>,
+}
+
+/// Sequence counter and the most recent events
+struct EventLog {
+    next_sequence: u64,
+    recent: VecDeque<ClientEvent>,
+    capacity: usize,
+}
+
+/// Events to replay to a new subscriber, followed by live events
+pub struct Subscription {
+    /// Sequence number the subscription starts after
+    pub since: u64,
+
+    /// Retained events after `since`
+    pub backlog: Vec<ClientEvent>,
+
+    /// Events published after the subscription was created
+    pub receiver: broadcast::Receiver<ClientEvent>,
+}
+
+impl EventBus {
+    /// Create a bus that keeps up to `history` recent events
+    pub fn new(history: usize) -> Self {
+        let history = history.max(1);
+        let (sender, _) = broadcast::channel(history);
+        Self {
+            log: Arc::new(Mutex::new(EventLog {
+                next_sequence: 1,
+                recent: VecDeque::with_capacity(history),
+                capacity: history,
+            })),
+            sender,
+        }
+    }
+
+    /// Publish an event about `client`, returning its sequence number
+    ///
+    /// The event is flagged expected if the client is in a maintenance
+    /// window.
+    pub fn publish(
+        &self,
+        kind: ClientEventKind,
+        client: &RegisteredClient,
+    ) -> u64 {
+        self.publish_event(kind, client, false)
+    }
+
+    /// Publish a [`ClientEventKind::Registered`] event about `client`,
+    /// flagged as its first registration if `first` is set
+    pub fn publish_registration(
+        &self,
+        client: &RegisteredClient,
+        first: bool,
+    ) -> u64 {
+        self.publish_event(ClientEventKind::Registered, client, first)
+    }
+
+    fn publish_event(
+        &self,
+        kind: ClientEventKind,
+        client: &RegisteredClient,
+        first: bool,
+    ) -> u64 {
+        let mut log = self.log.lock().unwrap();
+        let event = ClientEvent {
+            sequence: log.next_sequence,
+            timestamp: Utc::now(),
+            kind,
+            expected: client.maintenance_window.is_some(),
+            first,
+            client: client.clone(),
+        };
+        log.next_sequence += 1;
+
+        if log.recent.len() == log.capacity {
+            log.recent.pop_front();
+        }
+        log.recent.push_back(event.clone());
+
+        // Sending only fails when nobody is subscribed
+        let _ = self.sender.send(event.clone());
+        event.sequence
+    }
+
+    /// Sequence number of the most recent event, or 0 if there is none
+    pub fn latest_sequence(&self) -> u64 {
+        self.log.lock().unwrap().next_sequence - 1
+    }
+
+    /// Subscribe to events after sequence number `since`
+    ///
+    /// With `since` of `None`, only events published from now on are
+    /// delivered. Fails if events after `since` have already been dropped
+    /// from the history, or if `since` is ahead of the stream, which happens
+    /// when the server restarted and its numbering started over.
+    pub fn subscribe(
+        &self,
+        since: Option<u64>,
+    ) -> Result<Subscription, EventError> {
+        // Holding the log lock keeps publish() from slipping an event in
+        // between the backlog and the receiver
+        let log = self.log.lock().unwrap();
+        let receiver = self.sender.subscribe();
+
+        let latest = log.next_sequence - 1;
+        let since = since.unwrap_or(latest);
+        if since > latest {
+            return Err(EventError::Unknown { since, latest });
+        }
+        let oldest = log
+            .recent
+            .front()
+            .map_or(log.next_sequence, |event| event.sequence);
+        if since + 1 < oldest {
+            return Err(EventError::Expired { since, oldest });
+        }
+
+        let backlog = log
+            .recent
+            .iter()
+            .filter(|event| event.sequence > since)
+            .cloned()
+            .collect();
+        Ok(Subscription {
+            since,
+            backlog,
+            receiver,
+        })
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new(DEFAULT_EVENT_HISTORY)
+    }
+}
+
+/// Errors subscribing to the event stream
+#[derive(Debug, thiserror::Error)]
+pub enum EventError {
+    #[error(
+        "Events after sequence {since} are no longer available (oldest is \
+         {oldest})"
+    )]
+    Expired { since: u64, oldest: u64 },
+
+    #[error(
+        "Sequence {since} is ahead of the event stream (latest is {latest})"
+    )]
+    Unknown { since: u64, latest: u64 },
+}
+
+impl From<EventError> for HttpError {
+    fn from(error: EventError) -> Self {
+        // Either way the subscriber has to re-read the client list and
+        // subscribe again without a sequence number
+        HttpError::for_client_error(
+            Some("EventsUnavailable".to_string()),
+            StatusCode::GONE,
+            error.to_string(),
+        )
+    }
+}
+
+/// Query parameters for the event stream
+#[derive(Deserialize, JsonSchema)]
+pub struct EventsQuery {
+    /// Only send events with a sequence number greater than this
+    pub since: Option<u64>,
+}
+
+/// Stream client events as Server-Sent Events
+///
+/// Each event's `id` is its sequence number, its `event` is the kind of
+/// change, and its `data` is the JSON-encoded [`ClientEvent`]. Resume after
+/// a reconnect with the `Last-Event-ID` header or `?since=`; if the
+/// requested events are no longer available the server responds with 410
+/// Gone.
+#[endpoint {
+    method = GET,
+    path = "/api/events",
+}]
+pub async fn stream_events(
+    ctx: RequestContext<ApiContext>,
+    query: Query<EventsQuery>,
+) -> Result<Response<Body>, HttpError> {
+    require_peer(&ctx)?;
+    let since = match ctx.request.headers().get("last-event-id") {
+        Some(value) => Some(
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| value.trim().parse().ok())
+                .ok_or_else(|| {
+                    HttpError::for_bad_request(
+                        None,
+                        "Last-Event-ID must be a sequence number".to_string(),
+                    )
+                })?,
+        ),
+        None => query.into_inner().since,
+    };
+
+    let events = ctx.context().registry.events().clone();
+    let subscription = events.subscribe(since)?;
+
+    let (tx, rx) = mpsc::channel(16);
+    tokio::spawn(forward_events(events, subscription, tx));
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "text/event-stream")
+        .header(header::CACHE_CONTROL, "no-cache")
+        .body(Body::wrap(SseBody { rx }))
+        .map_err(|e| HttpError::for_internal_error(e.to_string()))
+}
+
+/// Write a subscription to an SSE stream until the subscriber goes away
+async fn forward_events(
+    events: EventBus,
+    subscription: Subscription,
+    tx: mpsc::Sender<Bytes>,
+) {
+    let mut last_sequence = subscription.since;
+    let mut backlog = subscription.backlog;
+    let mut receiver = subscription.receiver;
+    let mut keepalive = tokio::time::interval(KEEPALIVE_INTERVAL);
+    keepalive.tick().await;
+
+    loop {
+        for event in backlog.drain(..) {
+            last_sequence = event.sequence;
+            if tx.send(format_event(&event)).await.is_err() {
+                return;
+            }
+        }
+
+        let frame = tokio::select! {
+            received = receiver.recv() => match received {
+                // Skip anything the backlog already covered
+                Ok(event) if event.sequence <= last_sequence => continue,
+                Ok(event) => {
+                    last_sequence = event.sequence;
+                    format_event(&event)
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    // Catch up from the history. If that is gone too, end
+                    // the stream and let the subscriber find out when it
+                    // reconnects.
+                    match events.subscribe(Some(last_sequence)) {
+                        Ok(resumed) => {
+                            backlog = resumed.backlog;
+                            receiver = resumed.receiver;
+                            continue;
+                        }
+                        Err(_) => return,
+                    }
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            },
+            _ = keepalive.tick() => Bytes::from_static(b": keepalive\n\n"),
+            _ = tx.closed() => return,
+        };
+
+        if tx.send(frame).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Encode an event as an SSE message
+fn format_event(event: &ClientEvent) -> Bytes {
+    // serde_json never emits raw newlines, so the data fits on one line
+    let data = serde_json::to_string(event)
+        .expect("client events are always serializable");
+    Bytes::from(format!(
+        "id: {}\nevent: {}\ndata: {}\n\n",
+        event.sequence,
+        event.kind.as_str(),
+        data
+    ))
+}
+
+/// Response body fed by [`forward_events`]
+struct SseBody {
+    rx: mpsc::Receiver<Bytes>,
+}
+
+impl http_body::Body for SseBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Infallible>>> {
+        self.rx
+            .poll_recv(cx)
+            .map(|chunk| chunk.map(|bytes| Ok(Frame::data(bytes))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crs_common::{ClientInfo, ClientStatus};
+    use std::collections::HashMap;
+
+    fn test_client() -> RegisteredClient {
+        let info = ClientInfo {
+            hostname: "testhost".to_string(),
+            os: "linux".to_string(),
+            ip_address: "192.168.1.100".to_string(),
+            version: "1.0.0".to_string(),
+            host_id: None,
+            tags: HashMap::new(),
+        };
+        RegisteredClient {
+            client_id: info.client_id(),
+            info,
+            status: ClientStatus::Online,
+            first_connected: Utc::now(),
+            registered_at: Utc::now(),
+            last_heartbeat: Utc::now(),
+            quarantine: None,
+            maintenance: None,
+            maintenance_window: None,
+            metrics: None,
+            checks: Vec::new(),
+            availability: Vec::new(),
+        }
+    }
+
+    fn sequences(events: &[ClientEvent]) -> Vec<u64> {
+        events.iter().map(|event| event.sequence).collect()
+    }
+
+    #[test]
+    fn test_sequence_numbers_increase() {
+        let bus = EventBus::new(8);
+        let client = test_client();
+        assert_eq!(bus.latest_sequence(), 0);
+        assert_eq!(bus.publish(ClientEventKind::Registered, &client), 1);
+        assert_eq!(bus.publish(ClientEventKind::WentOffline, &client), 2);
+        assert_eq!(bus.latest_sequence(), 2);
+    }
+
+    #[test]
+    fn test_subscribe_replays_after_sequence() {
+        let bus = EventBus::new(8);
+        let client = test_client();
+        for _ in 0..3 {
+            bus.publish(ClientEventKind::Registered, &client);
+        }
+
+        let subscription = bus.subscribe(Some(1)).unwrap();
+        assert_eq!(sequences(&subscription.backlog), vec![2, 3]);
+        assert!(bus.subscribe(Some(3)).unwrap().backlog.is_empty());
+        assert!(bus.subscribe(None).unwrap().backlog.is_empty());
+
+        // Live events follow the backlog
+        let mut receiver = subscription.receiver;
+        bus.publish(ClientEventKind::Deregistered, &client);
+        let event = receiver.try_recv().unwrap();
+        assert_eq!(event.sequence, 4);
+        assert_eq!(event.kind, ClientEventKind::Deregistered);
+    }
+
+    #[test]
+    fn test_subscribe_outside_history_fails() {
+        let bus = EventBus::new(2);
+        let client = test_client();
+        for _ in 0..4 {
+            bus.publish(ClientEventKind::Registered, &client);
+        }
+
+        // Only events 3 and 4 are kept
+        assert_eq!(sequences(&bus.subscribe(Some(2)).unwrap().backlog), [3, 4]);
+        assert!(matches!(
+            bus.subscribe(Some(1)),
+            Err(EventError::Expired {
+                since: 1,
+                oldest: 3
+            })
+        ));
+
+        // A sequence from before a server restart
+        assert!(matches!(
+            bus.subscribe(Some(10)),
+            Err(EventError::Unknown {
+                since: 10,
+                latest: 4
+            })
+        ));
+    }
+}
@@ -0,0 +1,232 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Health checks and server information
+//!
+//! - `GET /healthz` answers as long as the server is up, for liveness
+//!   probes.
+//! - `GET /readyz` answers 503 Service Unavailable until the registry has
+//!   been loaded from its store and the status sweeper is running, for
+//!   load balancers and deploy tooling. The sweeper counts as running while
+//!   its last pass is no more than a few sweep intervals old.
+//! - `GET /api/server` describes the server: version, build, start time,
+//!   address and heartbeat thresholds. The dashboard shows the same
+//!   information.
+
+// Suppress warnings for Dropshot's macro-generated phantom types
+#![allow(dead_code)]
+
+use crate::api::{require_peer, ApiContext};
+use crate::policy::HeartbeatPolicies;
+use chrono::{DateTime, Utc};
+use crs_common::{HealthResponse, ReadinessResponse, ServerInfo};
+use dropshot::{endpoint, HttpError, HttpResponseOk, RequestContext};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Default number of seconds between status sweeps
+pub const DEFAULT_STATUS_SWEEP_SECS: u64 = 30;
+
+/// Default time between status sweeps under `policies`
+///
+/// Sweeps run every [`DEFAULT_STATUS_SWEEP_SECS`], or every half of the
+/// shortest window any policy gives clients between going stale and going
+/// offline if that is sooner, so that a sweep lands inside the window even
+/// when one runs late and clients are seen going stale before they go
+/// offline. Sweeps run at most once a second.
+pub fn default_status_sweep(policies: &HeartbeatPolicies) -> Duration {
+    let default = Duration::from_secs(DEFAULT_STATUS_SWEEP_SECS);
+    let sweep = policies
+        .shortest_stale_window()
+        .map_or(default, |window| default.min(window / 2));
+    Duration::from_secs(sweep.as_secs().max(1))
+}
+
+/// How many sweep intervals may pass without a sweep before the sweeper is
+/// considered stuck
+const MISSED_SWEEPS: u32 = 3;
+
+/// Server settings reported by `GET /api/server`
+#[derive(Debug, Clone)]
+pub struct ServerSettings {
+    /// Address clients connect to
+    ///
+    /// When the server sits behind the mutual TLS front end, Dropshot
+    /// itself listens on a loopback port, so the public address is given
+    /// here. Without it the address Dropshot listens on is reported.
+    pub bind_address: Option<SocketAddr>,
+
+    /// How often the status sweeper runs
+    pub status_sweep: Duration,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        Self {
+            bind_address: None,
+            status_sweep: Duration::from_secs(DEFAULT_STATUS_SWEEP_SECS),
+        }
+    }
+}
+
+/// Readiness of the server's moving parts
+///
+/// Cloning is cheap and every clone sees the same state. A new `Health` is
+/// not ready: the startup code marks the registry loaded and the status
+/// sweeper records each of its passes.
+#[derive(Clone, Default)]
+pub struct Health {
+    inner: Arc<HealthState>,
+}
+
+#[derive(Default)]
+struct HealthState {
+    registry_loaded: AtomicBool,
+    last_sweep: Mutex<Option<DateTime<Utc>>>,
+}
+
+impl Health {
+    /// Record that the registry has been loaded from its store
+    pub fn set_registry_loaded(&self) {
+        self.inner.registry_loaded.store(true, Ordering::SeqCst);
+    }
+
+    /// Record that the status sweeper has just run
+    pub fn record_sweep(&self) {
+        *self.inner.last_sweep.lock().unwrap() = Some(Utc::now());
+    }
+
+    /// Check readiness, given how often the sweeper is meant to run
+    pub fn readiness(&self, status_sweep: Duration) -> ReadinessResponse {
+        let last_sweep = *self.inner.last_sweep.lock().unwrap();
+        let sweeper_running = last_sweep.is_some_and(|last| {
+            (Utc::now() - last).to_std().unwrap_or_default()
+                <= status_sweep * MISSED_SWEEPS
+        });
+        ReadinessResponse {
+            registry_loaded: self.inner.registry_loaded.load(Ordering::SeqCst),
+            sweeper_running,
+            last_sweep,
+        }
+    }
+}
+
+/// Describe the server handling a request
+pub fn server_info(ctx: &RequestContext<ApiContext>) -> ServerInfo {
+    let api_context = ctx.context();
+    let settings = &api_context.settings;
+    let policy = api_context.registry.default_heartbeat_policy();
+
+    ServerInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        git_commit: option_env!("CRS_GIT_COMMIT").map(str::to_string),
+        hostname: hostname::get()
+            .ok()
+            .and_then(|h| h.into_string().ok())
+            .unwrap_or_else(|| "unknown".to_string()),
+        os: std::env::consts::OS.to_string(),
+        start_time: api_context.start_time,
+        bind_address: settings
+            .bind_address
+            .unwrap_or(ctx.server.local_addr)
+            .to_string(),
+        heartbeat_interval_secs: policy.interval.as_secs(),
+        stale_threshold_secs: policy.stale_threshold.as_secs(),
+        offline_threshold_secs: policy.offline_threshold.as_secs(),
+        status_sweep_secs: settings.status_sweep.as_secs(),
+    }
+}
+
+/// Liveness check
+///
+/// Always succeeds while the server is able to answer requests.
+#[endpoint {
+    method = GET,
+    path = "/healthz",
+}]
+pub async fn healthz(
+    ctx: RequestContext<ApiContext>,
+) -> Result<HttpResponseOk<HealthResponse>, HttpError> {
+    require_peer(&ctx)?;
+    Ok(HttpResponseOk(HealthResponse {
+        status: "ok".to_string(),
+    }))
+}
+
+/// Readiness check
+///
+/// Succeeds once the registry has been loaded and the status sweeper is
+/// running. Otherwise answers 503 Service Unavailable, naming what is not
+/// ready yet.
+#[endpoint {
+    method = GET,
+    path = "/readyz",
+}]
+pub async fn readyz(
+    ctx: RequestContext<ApiContext>,
+) -> Result<HttpResponseOk<ReadinessResponse>, HttpError> {
+    require_peer(&ctx)?;
+    let api_context = ctx.context();
+    let readiness = api_context
+        .health
+        .readiness(api_context.settings.status_sweep);
+    if readiness.is_ready() {
+        return Ok(HttpResponseOk(readiness));
+    }
+
+    let mut problems = Vec::new();
+    if !readiness.registry_loaded {
+        problems.push("registry not loaded");
+    }
+    if !readiness.sweeper_running {
+        problems.push("status sweeper not running");
+    }
+    Err(HttpError::for_unavail(
+        Some("NotReady".to_string()),
+        format!("Not ready: {}", problems.join(", ")),
+    ))
+}
+
+/// Get server information
+///
+/// Returns the server's version, the git commit it was built from, its
+/// start time and address, and the default heartbeat thresholds.
+#[endpoint {
+    method = GET,
+    path = "/api/server",
+}]
+pub async fn get_server_info(
+    ctx: RequestContext<ApiContext>,
+) -> Result<HttpResponseOk<ServerInfo>, HttpError> {
+    require_peer(&ctx)?;
+    Ok(HttpResponseOk(server_info(&ctx)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_readiness() {
+        let health = Health::default();
+        let sweep = Duration::from_secs(30);
+        let readiness = health.readiness(sweep);
+        assert!(!readiness.registry_loaded);
+        assert!(!readiness.sweeper_running);
+        assert!(!readiness.is_ready());
+
+        health.set_registry_loaded();
+        assert!(!health.readiness(sweep).is_ready());
+
+        health.record_sweep();
+        assert!(health.readiness(sweep).is_ready());
+
+        // A sweeper that has stopped sweeping is not running
+        *health.inner.last_sweep.lock().unwrap() =
+            Some(Utc::now() - chrono::Duration::try_minutes(2).unwrap());
+        let readiness = health.readiness(sweep);
+        assert!(readiness.registry_loaded);
+        assert!(!readiness.sweeper_running);
+    }
+}
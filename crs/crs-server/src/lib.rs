@@ -4,6 +4,19 @@
 //!
 //! This library exposes the server components for testing and reuse.
 
+pub mod admin;
 pub mod api;
+pub mod availability;
+pub mod cluster;
+pub mod events;
+pub mod health;
+pub mod listing;
+pub mod maintenance;
+pub mod metrics;
+pub mod mtls;
+pub mod openapi;
+pub mod policy;
 pub mod registry;
+pub mod store;
 pub mod web;
+pub mod webhooks;
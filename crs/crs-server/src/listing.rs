@@ -0,0 +1,420 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Client list queries
+//!
+//! `GET /api/clients` can filter the client list, sort it and hand it out
+//! a page at a time. [`ClientScanParams`] holds the filters and sort order
+//! given on the first request of a scan. Every page after that is requested
+//! with the opaque `page_token` from the previous response, which carries
+//! the same scan parameters along with the sort key and ID of the last
+//! client handed out.
+//!
+//! Clients are always ordered by their sort key and then by client ID, so
+//! the order is total and a scan never repeats or skips a client that stays
+//! registered, even when clients come and go between pages.
+
+use chrono::SecondsFormat;
+use crs_common::{
+    ClientId, ClientScanParams, ClientSortKey, ClientStatus, RegisteredClient,
+    Selector, SelectorError,
+};
+use dropshot::PaginationOrder;
+use globset::{GlobBuilder, GlobMatcher};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::net::IpAddr;
+
+/// Where a scan of the client list continues
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientPageSelector {
+    /// The scan being continued
+    #[serde(flatten)]
+    pub scan: ClientScanParams,
+
+    /// Sort key of the last client on the previous page
+    pub last_key: String,
+
+    /// ID of the last client on the previous page
+    pub last_id: ClientId,
+}
+
+/// Errors in client list query parameters
+#[derive(Debug, thiserror::Error)]
+pub enum ListingError {
+    #[error("Invalid tag filter {0:?}: {1}")]
+    Tag(String, SelectorError),
+
+    #[error("Invalid hostname pattern {0:?}: {1}")]
+    HostnamePattern(String, globset::Error),
+
+    #[error("Invalid selector {0:?}: {1}")]
+    Selector(String, SelectorError),
+}
+
+/// A compiled set of client list filters
+#[derive(Debug, Clone)]
+pub struct ClientFilter {
+    status: Option<ClientStatus>,
+    os: Option<String>,
+    version: Option<String>,
+    hostname: Option<GlobMatcher>,
+    tag: Option<Selector>,
+    selector: Option<Selector>,
+}
+
+impl ClientFilter {
+    /// Compile the filters in `scan`
+    pub fn new(scan: &ClientScanParams) -> Result<Self, ListingError> {
+        let hostname = match &scan.hostname {
+            Some(pattern) => Some(
+                GlobBuilder::new(pattern)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|e| {
+                        ListingError::HostnamePattern(pattern.clone(), e)
+                    })?
+                    .compile_matcher(),
+            ),
+            None => None,
+        };
+
+        let tag = match &scan.tag {
+            Some(tag) => Some(
+                tag.parse().map_err(|e| ListingError::Tag(tag.clone(), e))?,
+            ),
+            None => None,
+        };
+
+        let selector =
+            match &scan.selector {
+                Some(selector) => Some(selector.parse().map_err(|e| {
+                    ListingError::Selector(selector.clone(), e)
+                })?),
+                None => None,
+            };
+
+        Ok(Self {
+            status: scan.status,
+            os: scan.os.clone(),
+            version: scan.version.clone(),
+            hostname,
+            tag,
+            selector,
+        })
+    }
+
+    /// Whether `client` passes every filter
+    pub fn matches(&self, client: &RegisteredClient) -> bool {
+        let info = &client.info;
+        self.status.is_none_or(|status| client.status == status)
+            && self
+                .os
+                .as_ref()
+                .is_none_or(|os| info.os.eq_ignore_ascii_case(os))
+            && self.version.as_ref().is_none_or(|v| info.version == *v)
+            && self
+                .hostname
+                .as_ref()
+                .is_none_or(|glob| glob.is_match(&info.hostname))
+            && self
+                .tag
+                .as_ref()
+                .is_none_or(|selector| selector.matches(&info.tags))
+            && self
+                .selector
+                .as_ref()
+                .is_none_or(|selector| selector.matches(&info.tags))
+    }
+}
+
+/// The value `client` is sorted by under `key`
+///
+/// Values are strings that sort in the intended order, so they can be
+/// carried in page tokens as they are. IP addresses are written out as
+/// fixed-width hex with IPv4 before IPv6, and timestamps as RFC 3339 in
+/// UTC with nanoseconds, which sort the same as the values they encode.
+pub fn sort_key(client: &RegisteredClient, key: ClientSortKey) -> String {
+    let info = &client.info;
+    match key {
+        ClientSortKey::Hostname => info.hostname.to_lowercase(),
+        ClientSortKey::IpAddress => match info.ip_address.parse::<IpAddr>() {
+            Ok(IpAddr::V4(ip)) => format!("4:{:08x}", u32::from(ip)),
+            Ok(IpAddr::V6(ip)) => format!("6:{:032x}", u128::from(ip)),
+            Err(_) => format!("x:{}", info.ip_address),
+        },
+        ClientSortKey::Os => info.os.to_lowercase(),
+        ClientSortKey::Version => info.version.clone(),
+        ClientSortKey::Status => match client.status {
+            ClientStatus::Online => "online",
+            ClientStatus::Degraded => "degraded",
+            ClientStatus::Stale => "stale",
+            ClientStatus::Offline => "offline",
+            ClientStatus::Departed => "departed",
+            ClientStatus::Maintenance => "maintenance",
+        }
+        .to_string(),
+        ClientSortKey::FirstConnected => client
+            .first_connected
+            .to_rfc3339_opts(SecondsFormat::Nanos, true),
+        ClientSortKey::LastHeartbeat => client
+            .last_heartbeat
+            .to_rfc3339_opts(SecondsFormat::Nanos, true),
+    }
+}
+
+/// Sort clients for listing
+///
+/// Clients with the same sort key are ordered by client ID.
+pub fn sort_clients(
+    clients: &mut [RegisteredClient],
+    key: ClientSortKey,
+    order: PaginationOrder,
+) {
+    clients.sort_by_cached_key(|c| (sort_key(c, key), c.client_id.0));
+    if order == PaginationOrder::Descending {
+        clients.reverse();
+    }
+}
+
+/// A page of the client list
+pub struct ClientPage {
+    /// Clients on this page
+    pub clients: Vec<RegisteredClient>,
+
+    /// Whether more clients follow this page
+    pub more: bool,
+}
+
+/// Pick out one page of matching clients
+///
+/// `after` is the sort key and ID of the last client on the previous page,
+/// if this is not the first page.
+pub fn page(
+    mut clients: Vec<RegisteredClient>,
+    scan: &ClientScanParams,
+    after: Option<(&str, ClientId)>,
+    limit: usize,
+) -> Result<ClientPage, ListingError> {
+    let filter = ClientFilter::new(scan)?;
+    let key = scan.sort.unwrap_or_default();
+    let order = scan.order.unwrap_or(PaginationOrder::Ascending);
+
+    clients.retain(|c| filter.matches(c));
+    sort_clients(&mut clients, key, order);
+
+    let start = match after {
+        Some((last_key, last_id)) => clients.partition_point(|c| {
+            let position = (sort_key(c, key).as_str(), c.client_id.0)
+                .cmp(&(last_key, last_id.0));
+            match order {
+                PaginationOrder::Ascending => position != Ordering::Greater,
+                PaginationOrder::Descending => position != Ordering::Less,
+            }
+        }),
+        None => 0,
+    };
+
+    let more = clients.len() - start > limit;
+    let clients = clients.into_iter().skip(start).take(limit).collect();
+    Ok(ClientPage { clients, more })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, Utc};
+    use crs_common::ClientInfo;
+    use std::collections::HashMap;
+
+    fn client(
+        hostname: &str,
+        ip: &str,
+        tags: &[(&str, &str)],
+    ) -> RegisteredClient {
+        let now = Utc::now();
+        RegisteredClient {
+            client_id: ClientId(uuid::Uuid::new_v4()),
+            info: ClientInfo {
+                hostname: hostname.to_string(),
+                os: "linux".to_string(),
+                ip_address: ip.to_string(),
+                version: "1.0.0".to_string(),
+                host_id: None,
+                tags: tags
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect::<HashMap<_, _>>(),
+            },
+            status: ClientStatus::Online,
+            first_connected: now,
+            registered_at: now,
+            last_heartbeat: now,
+            quarantine: None,
+            maintenance: None,
+            maintenance_window: None,
+            metrics: None,
+            checks: Vec::new(),
+            availability: Vec::new(),
+        }
+    }
+
+    fn hostnames(clients: &[RegisteredClient]) -> Vec<&str> {
+        clients.iter().map(|c| c.info.hostname.as_str()).collect()
+    }
+
+    #[test]
+    fn test_filters() {
+        let mut laptop = client("Laptop-1", "10.0.0.2", &[("role", "laptop")]);
+        laptop.info.os = "macOS".to_string();
+        let mut core = client("core-1", "10.0.0.1", &[("role", "core")]);
+        core.status = ClientStatus::Offline;
+        core.info.tags.insert("env".to_string(), "prod".to_string());
+        let clients = vec![laptop, core];
+
+        let list = |scan: ClientScanParams| {
+            let page = page(clients.clone(), &scan, None, 100).unwrap();
+            hostnames(&page.clients)
+                .into_iter()
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(list(ClientScanParams::default()), ["core-1", "Laptop-1"]);
+        assert_eq!(
+            list(ClientScanParams {
+                status: Some(ClientStatus::Offline),
+                ..Default::default()
+            }),
+            ["core-1"]
+        );
+        assert_eq!(
+            list(ClientScanParams {
+                os: Some("MACOS".to_string()),
+                ..Default::default()
+            }),
+            ["Laptop-1"]
+        );
+        assert_eq!(
+            list(ClientScanParams {
+                hostname: Some("laptop-*".to_string()),
+                ..Default::default()
+            }),
+            ["Laptop-1"]
+        );
+        assert_eq!(
+            list(ClientScanParams {
+                tag: Some("role=core, env=prod".to_string()),
+                ..Default::default()
+            }),
+            ["core-1"]
+        );
+        assert!(list(ClientScanParams {
+            tag: Some("role=core,env=dev".to_string()),
+            ..Default::default()
+        })
+        .is_empty());
+
+        assert_eq!(
+            list(ClientScanParams {
+                selector: Some("role in (core,db),!decommissioned".to_string()),
+                ..Default::default()
+            }),
+            ["core-1"]
+        );
+
+        let error = ClientFilter::new(&ClientScanParams {
+            selector: Some("role in core".to_string()),
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid selector \"role in core\": expected `(` to start the \
+             value list for \"role\" at position 8, found 'c'"
+        );
+
+        let error = ClientFilter::new(&ClientScanParams {
+            tag: Some("=core".to_string()),
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid tag filter \"=core\": expected a tag key at position 0, \
+             found '='"
+        );
+    }
+
+    #[test]
+    fn test_sort_keys() {
+        let a = client("b", "10.0.0.10", &[]);
+        let b = client("a", "10.0.0.9", &[]);
+        let c = client("c", "::1", &[]);
+
+        // IP addresses sort numerically, IPv4 first
+        let mut clients = vec![c.clone(), a.clone(), b.clone()];
+        sort_clients(
+            &mut clients,
+            ClientSortKey::IpAddress,
+            PaginationOrder::Ascending,
+        );
+        assert_eq!(hostnames(&clients), ["a", "b", "c"]);
+
+        sort_clients(
+            &mut clients,
+            ClientSortKey::Hostname,
+            PaginationOrder::Descending,
+        );
+        assert_eq!(hostnames(&clients), ["c", "b", "a"]);
+
+        // Timestamps sort chronologically
+        let mut old = a.clone();
+        old.last_heartbeat = Utc::now() - Duration::days(400);
+        let mut clients = vec![b, old];
+        sort_clients(
+            &mut clients,
+            ClientSortKey::LastHeartbeat,
+            PaginationOrder::Ascending,
+        );
+        assert_eq!(hostnames(&clients), ["b", "a"]);
+    }
+
+    #[test]
+    fn test_pages_resume_after_marker() {
+        let clients: Vec<_> = (0..5)
+            .map(|i| client(&format!("host-{}", i), "10.0.0.1", &[]))
+            .collect();
+
+        for order in [PaginationOrder::Ascending, PaginationOrder::Descending] {
+            let scan = ClientScanParams {
+                sort: Some(ClientSortKey::Hostname),
+                order: Some(order),
+                ..Default::default()
+            };
+            let mut seen = Vec::new();
+            let mut after: Option<(String, ClientId)> = None;
+            loop {
+                let marker = after.as_ref().map(|(k, id)| (k.as_str(), *id));
+                let page = page(clients.clone(), &scan, marker, 2).unwrap();
+                seen.extend(
+                    page.clients.iter().map(|c| c.info.hostname.clone()),
+                );
+                if !page.more {
+                    break;
+                }
+                let last = page.clients.last().unwrap();
+                after = Some((
+                    sort_key(last, ClientSortKey::Hostname),
+                    last.client_id,
+                ));
+            }
+
+            let mut expected: Vec<_> =
+                (0..5).map(|i| format!("host-{}", i)).collect();
+            if order == PaginationOrder::Descending {
+                expected.reverse();
+            }
+            assert_eq!(seen, expected);
+        }
+    }
+}
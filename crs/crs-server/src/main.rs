@@ -23,72 +23,674 @@
 //!
 //! The server listens on `127.0.0.1:8081` by default.
 //!
+//! # Configuration
+//!
+//! Every option can also be set in a TOML file passed with `--config`.
+//! Options given on the command line override the file. Besides the bind
+//! address, port, store and TLS settings, the file controls the heartbeat
+//! interval handed to clients, the stale and offline thresholds, how often
+//! client statuses are swept, and the request body size limit. See
+//! `example-config.toml` for every option.
+//!
+//! The config file can also give some clients their own heartbeat interval
+//! and stale and offline thresholds. Each `[[heartbeat_policies]]` entry
+//! matches a tag selector (`role=laptop`), a hostname glob (`core-*`), or
+//! both, and the first matching entry wins. Clients are told their interval
+//! when they register and are judged stale and offline against their own
+//! thresholds.
+//!
+//! # TLS
+//!
+//! Pass `--tls-cert <PEM>` and `--tls-key <PEM>` to serve HTTPS instead of
+//! plain HTTP. The certificate file holds the server certificate followed by
+//! any intermediates, and the key file holds a PKCS#8 private key. Clients
+//! signed by a private CA can be pointed at it with their `--ca-bundle`
+//! option.
+//!
+//! Adding `--tls-client-ca <PEM>` makes client certificates mandatory. Every
+//! connection must present a certificate signed by one of the given CAs,
+//! and clients may only register, heartbeat and deregister under a hostname
+//! that matches the certificate's common name or one of its DNS names. Since
+//! client IDs are derived from the hostname, this binds each client ID to a
+//! certificate subject. In this mode TLS is terminated by crs-server's own
+//! front end, which forwards requests to the API on a loopback port.
+//!
 //! # API Endpoints
 //!
 //! - `POST /api/register` - Register a new client
 //! - `POST /api/heartbeat` - Send heartbeat from registered client
-//! - `GET /api/clients` - List all registered clients
+//! - `POST /api/deregister` - Deregister a client that is shutting down
+//! - `GET /api/clients` - List registered clients, filtered, sorted and
+//!   paginated
+//! - `GET /api/clients/{id}/history` - Get a client's history
+//! - `GET /api/clients/{id}/metrics` - Get a client's recent system metrics
+//! - `GET /api/events` - Stream client events as Server-Sent Events
+//! - `DELETE /api/clients/{id}` - Remove a client from the registry
+//! - `PUT /api/clients/{id}/quarantine` - Quarantine a client
+//! - `DELETE /api/clients/{id}/quarantine` - Lift a client's quarantine
+//! - `PUT /api/clients/{id}/maintenance` - Put a client in maintenance
+//! - `DELETE /api/clients/{id}/maintenance` - End a client's maintenance
+//! - `GET /api/maintenance-windows` - List maintenance windows
+//! - `POST /api/maintenance-windows` - Schedule a maintenance window
+//! - `DELETE /api/maintenance-windows/{id}` - Cancel a maintenance window
+//! - `GET /api/bans` - List banned client IDs and hostnames
+//! - `POST /api/bans` - Ban a client ID or hostname
+//! - `DELETE /api/bans/{kind}/{value}` - Remove a ban
 //! - `GET /` - Web dashboard
+//! - `GET /clients/{id}` - Client detail page with its history
+//! - `GET /metrics` - Prometheus metrics
+//! - `GET /api/server` - Server version, start time, address and thresholds
+//! - `GET /api/cluster` - Cluster membership as this server sees it
+//! - `POST /api/cluster/sync` - Exchange registry state with a cluster peer
+//! - `GET /healthz` - Liveness check
+//! - `GET /readyz` - Readiness check
+//! - `GET /api/openapi.json` - OpenAPI document describing the API
 //!
 //! # Client Status
 //!
 //! Clients are automatically categorized based on their last heartbeat:
-//! - **Online**: Last heartbeat < 15 seconds ago (< 1.5x heartbeat interval)
-//! - **Offline**: Last heartbeat >= 15 seconds ago (>= 1.5x heartbeat interval)
+//! - **Online**: Last heartbeat within the stale threshold
+//! - **Degraded**: Last heartbeat within the stale threshold, but one of
+//!   the client's health checks failed
+//! - **Stale**: No heartbeat within the stale threshold, but one within the
+//!   offline threshold, so the client has missed a heartbeat
+//! - **Offline**: No heartbeat within the offline threshold
+//! - **Departed**: Client deregistered itself during a clean shutdown
+//! - **Maintenance**: Put in maintenance by an operator, with
+//!   `crs-check maintenance start`, until the maintenance is ended
+//!
+//! By default clients heartbeat every 10 seconds, are considered stale
+//! after 12 seconds (1.2x the heartbeat interval) and offline after 15
+//! seconds (1.5x), unless a heartbeat policy says otherwise. Setting the
+//! stale threshold to the offline threshold skips the stale status.
+//! Heartbeats from a client in maintenance are still recorded, but its
+//! status does not change until the maintenance ends, and maintenance time
+//! is left out of its availability. Statuses are updated by a background
+//! sweep every 30 seconds, or every half of the shortest gap between a
+//! policy's stale and offline thresholds if that is sooner (every second
+//! with the defaults), so that clients are seen going stale before they go
+//! offline. When a client transitions to offline, its time connected
+//! counter resets to zero.
+//!
+//! Clients report the results of their own health checks, configured as
+//! `[[checks]]` in the crs-client config file, with each heartbeat. A
+//! client stays degraded until a heartbeat reports every check passing.
+//! Degraded time counts as available, since the client is reachable. The
+//! dashboard's client page lists each check's latest result, and
+//! `crs-check status` lists the checks failing on degraded clients.
+//!
+//! # Maintenance Windows
+//!
+//! A maintenance window announces that some clients are expected to go
+//! offline, for example while a rack is patched. It covers one client or
+//! every client matching a tag selector, and lasts for a duration either
+//! once or each time a five-field cron schedule (in UTC) matches:
+//!
+//! ```bash
+//! crs-check maintenance schedule --client web-01 --duration 30m
+//! crs-check maintenance schedule -l rack=r12 --duration 2h \
+//!     --schedule "0 2 * * 6" --reason "weekly patching"
+//! ```
+//!
+//! Unlike `crs-check maintenance start`, a window does not change the
+//! status of the clients it covers. Their transitions are still recorded,
+//! but flagged as expected: they are not sent to webhooks, expected offline
+//! time does not count against availability, and the dashboard and
+//! `crs-check status` mark expected outages. One-off windows are removed
+//! once they are over; recurring windows stay until they are cancelled with
+//! `crs-check maintenance cancel`.
+//!
+//! # Listing Clients
+//!
+//! `GET /api/clients` accepts these query parameters:
+//!
+//! - `status`, `os`, `version` - Only clients with this status, operating
+//!   system (ignoring case) or client version
+//! - `hostname` - Only clients whose hostname matches a glob such as
+//!   `web-*`, ignoring case
+//! - `tag` - Only clients carrying every one of these comma-separated
+//!   `key=value` tags; any selector is accepted, as for `selector`
+//! - `selector` - Only clients whose tags match a selector such as
+//!   `env=prod,region in (us-west,us-east),!decommissioned` (see
+//!   `crs_common::selector` for the syntax)
+//! - `sort` - `hostname`, `ip_address` (the default), `os`, `version`,
+//!   `status`, `first_connected` or `last_heartbeat`
+//! - `order` - `ascending` (the default) or `descending`
+//! - `limit` - Clients per page (100 by default)
+//!
+//! When more clients match than fit on a page, the response's `next_page`
+//! token fetches the next one when passed back as `page_token` (together
+//! with `limit`, if one was given). The token carries the filters and sort
+//! order, so they need not be repeated. `crs-check status` and
+//! `crs-check availability` take the same filters as options, and the
+//! dashboard takes `?selector=`.
+//!
+//! # History
+//!
+//! Every registration, status transition, change to a client's information
+//! and quarantine is recorded in that client's history, which is kept in the
+//! same store as the client. Entries are kept for 30 days, the longest
+//! availability window, and removing a client discards its history.
+//!
+//! The history is also used to work out each client's availability over
+//! the last 24 hours, 7 days and 30 days: the share of time online, the
+//! number of outages, the longest outage, and the mean time to recovery.
+//! `GET /api/clients` includes them for every client (`?window=` picks a
+//! single window), the dashboard shows one window at a time, and
+//! `crs-check availability` prints a report.
+//!
+//! # System Metrics
+//!
+//! Clients started with `--report-metrics` attach their load average,
+//! memory and root filesystem use, and uptime to each heartbeat. The latest
+//! sample is kept with the client and included in `GET /api/clients`, and
+//! the last 60 samples are kept in memory and served by
+//! `GET /api/clients/{id}/metrics`. The dashboard and `crs-check metrics`
+//! show them. Clients that send no metrics keep working as before.
+//!
+//! # Events
+//!
+//! `GET /api/events` streams a numbered event whenever a client registers,
+//! resumes heartbeating, goes stale, catches up, goes offline, deregisters,
+//! changes its information, enters or leaves maintenance, or is removed. The
+//! most recent events are kept in memory, so a subscriber that reconnects
+//! with `Last-Event-ID` (or `?since=`) receives what it missed. Numbering
+//! starts over when the server restarts; resuming from a sequence number the
+//! server no longer has returns 410 Gone, after which the subscriber should
+//! re-read `/api/clients`. Events for a client inside a maintenance window
+//! are marked `expected`.
+//!
+//! # Metrics
+//!
+//! `GET /metrics` serves Prometheus metrics: the number of clients by
+//! status, operating system and version (`crs_clients`, refreshed by every
+//! status update), counters of registrations, re-registrations, heartbeats
+//! and heartbeats from unknown clients, and a histogram of API handler
+//! latency (`crs_handler_duration_seconds`). Counters start from zero when
+//! the server restarts.
+//!
+//! # Health Checks
+//!
+//! `GET /healthz` succeeds whenever the server can answer at all. `GET
+//! /readyz` answers 503 Service Unavailable until the registry has been
+//! loaded from its store and the status sweeper has run, and again if the
+//! sweeper misses three sweeps in a row, so load balancers and deploy
+//! tooling can hold traffic back until the server is usable.
+//!
+//! `GET /api/server` reports the server's version, the git commit it was
+//! built from, its hostname, start time and bind address, and the default
+//! heartbeat interval, stale and offline thresholds and status sweep period.
+//! The dashboard header shows the same information.
+//!
+//! # OpenAPI
+//!
+//! `GET /api/openapi.json` serves an OpenAPI 3 document describing every
+//! API endpoint and type, generated from the endpoint definitions. The same
+//! document is committed as `crs-server/openapi.json` for integrations to
+//! build against. A test fails when the API changes and the committed copy
+//! is not regenerated with `CRS_UPDATE_OPENAPI=1 cargo test -p crs-server
+//! openapi`.
+//!
+//! The `crs-api-client` crate is a typed Rust client with a method for every
+//! operation in the document. `crs-client` and `crs-check` use it.
+//!
+//! # Webhooks
+//!
+//! Each `[[webhooks]]` entry in the config file names a URL that receives a
+//! JSON POST when a client registers for the first time, goes offline, comes
+//! back, becomes degraded, or recovers. An entry can be limited to some of
+//! those events and to clients matching a tag selector. Transitions inside a
+//! maintenance window are expected, and are not sent. Every request is
+//! signed with the entry's secret in the `X-CRS-Signature` header
+//! (`sha256=<hex HMAC-SHA256 of the body>`). Failed deliveries are retried
+//! with exponential backoff.
+//!
+//! # Clustering
+//!
+//! A few servers can share one registry so that losing a server does not
+//! lose the registry. Give each of them a `[cluster]` table in its config
+//! file listing the other servers' URLs and a secret shared by all of them.
+//! Every node then syncs with each of its peers every `sync_interval_secs`
+//! (5 by default), sending its registry and merging in the peer's, so any
+//! node can list clients and accept registrations and heartbeats, and once
+//! a registration has been synced the client's secret works with every
+//! node. Point `crs-client` at several nodes with `--server` to have it
+//! fail over between them.
+//!
+//! The most recent change to each client, and to the bans and maintenance
+//! windows, wins, so the nodes' clocks should be kept in sync. Each node
+//! works out clients' statuses from the heartbeats it has, and keeps its own
+//! history and events; webhooks are sent by the node that sees a
+//! transition, so configure them on one node only, or expect duplicates.
+//! When each record last changed is kept in the store along with the
+//! registry, and removed clients are remembered for seven days, so a node
+//! that is out of touch for longer may bring them back. Peers served over
+//! HTTPS are verified against the system's CA certificates, or only
+//! against those in the PEM file given as `ca_bundle`. Clustering cannot be
+//! combined with `--tls-client-ca`. The dashboard and `GET /api/cluster`
+//! list each node's peers and whether they were reachable at the last
+//! sync.
+//!
+//! # Enrollment
 //!
-//! Heartbeat interval is 10 seconds.
-//! Status updates occur every 30 seconds via a background task.
-//! When a client transitions to offline, its time connected counter resets to zero.
+//! Pass `--enrollment-token <TOKEN>` (or set `CRS_ENROLLMENT_TOKEN`) to only
+//! accept registrations that carry the same pre-shared token. Every
+//! successful registration returns a new per-client secret, which the client
+//! must present with each heartbeat and deregistration. Without an enrollment
+//! token any host that can reach the server may register.
+//!
+//! # Administration
+//!
+//! Pass `--admin-token <TOKEN>` (or set `CRS_ADMIN_TOKEN`) to enable the
+//! administrative endpoints and require that token in the
+//! `X-CRS-Admin-Token` header of requests that change the
+//! registry on an operator's behalf: removing, quarantining and releasing
+//! clients, starting and ending maintenance, scheduling and cancelling
+//! maintenance windows, and creating and removing bans. Requests without it
+//! are refused with 401 Unauthorized. With `--tls-client-ca`, these
+//! requests must come with a client certificate as well. `crs-check` sends
+//! the token given with its own `--admin-token`. Without an admin token
+//! these requests are refused with 403 Forbidden, so nobody can make these
+//! changes.
+//!
+//! # Persistence
+//!
+//! By default the registry is held in memory only. Pass `--store sqlite` or
+//! `--store json` together with `--store-path <PATH>` to keep it in a SQLite
+//! database or a JSON snapshot file instead. On startup the registry is
+//! rehydrated from the store and restored clients are shown as offline until
+//! they heartbeat again. The JSON store writes heartbeats that change nothing
+//! else at most every 30 seconds, so a crash can lose the latest heartbeat
+//! times.
 
+mod admin;
 mod api;
+mod availability;
+mod cluster;
+mod events;
+mod health;
+mod listing;
+mod maintenance;
+mod metrics;
+mod mtls;
+mod openapi;
+mod policy;
 mod registry;
+mod store;
 mod web;
+mod webhooks;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use api::ApiContext;
 use clap::Parser;
+use cluster::{Cluster, ClusterSpec};
 use dropshot::{
-    ConfigDropshot, ConfigLogging, ConfigLoggingLevel, HttpServerStarter,
+    ConfigDropshot, ConfigLogging, ConfigLoggingLevel, ConfigTls,
+    HttpServerStarter,
 };
+use health::{default_status_sweep, Health, ServerSettings};
+use mtls::PeerTable;
+use policy::{HeartbeatPolicies, HeartbeatPolicy, HeartbeatRuleSpec};
 use registry::Registry;
-use std::net::SocketAddr;
+use serde::Deserialize;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
 use std::time::Duration;
+use store::StoreKind;
+use webhooks::{Webhook, WebhookSpec};
+
+/// Default IP address to bind to
+const DEFAULT_SERVER_ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+/// Default port to listen on
+const DEFAULT_PORT: u16 = 8081;
+
+/// Default limit on request body size (1 MiB)
+const DEFAULT_REQUEST_BODY_MAX_BYTES: usize = 1024 * 1024;
 
 /// CRS Server - Central Registry Service
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
+    /// Path to TOML configuration file
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// IP address to bind to [default: 127.0.0.1]
+    #[arg(short, long)]
+    server_address: Option<IpAddr>,
+
+    /// Port to listen on [default: 8081]
+    #[arg(short, long)]
+    port: Option<u16>,
+
+    /// Storage backend for the registry [default: memory]
+    #[arg(long, value_enum)]
+    store: Option<StoreKind>,
+
+    /// Path to the SQLite database or JSON snapshot file
+    ///
+    /// Required for the `sqlite` and `json` stores.
+    #[arg(long)]
+    store_path: Option<PathBuf>,
+
+    /// PEM certificate chain to serve HTTPS with
+    ///
+    /// Requires `--tls-key`. Without it the server speaks plain HTTP.
+    #[arg(long)]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM-encoded PKCS#8 private key for `--tls-cert`
+    #[arg(long)]
+    tls_key: Option<PathBuf>,
+
+    /// Require client certificates signed by the CAs in this PEM file
+    ///
+    /// Requires `--tls-cert`. Clients may then only register under a
+    /// hostname that matches their certificate, and every endpoint,
+    /// including the dashboard, metrics and health checks, needs one.
+    #[arg(long)]
+    tls_client_ca: Option<PathBuf>,
+
+    /// Pre-shared token clients must present to register
+    #[arg(long, env = "CRS_ENROLLMENT_TOKEN", hide_env_values = true)]
+    enrollment_token: Option<String>,
+
+    /// Token operators must present to change the registry
+    ///
+    /// Without one, removing, banning, quarantining and maintenance are
+    /// disabled.
+    #[arg(long, env = "CRS_ADMIN_TOKEN", hide_env_values = true)]
+    admin_token: Option<String>,
+
+    /// Seconds between client heartbeats [default: 10]
+    #[arg(long)]
+    heartbeat_interval_secs: Option<u64>,
+
+    /// Seconds without a heartbeat before a client is considered stale
+    /// [default: 1.2x the heartbeat interval, at most the offline threshold]
+    #[arg(long)]
+    stale_threshold_secs: Option<u64>,
+
+    /// Seconds without a heartbeat before a client is considered offline
+    /// [default: 1.5x the heartbeat interval]
+    #[arg(long)]
+    offline_threshold_secs: Option<u64>,
+
+    /// Seconds between client status sweeps [default: 30, or half the
+    /// shortest gap between a stale and an offline threshold]
+    #[arg(long)]
+    status_sweep_secs: Option<u64>,
+
+    /// Maximum request body size in bytes [default: 1048576]
+    #[arg(long)]
+    request_body_max_bytes: Option<usize>,
+}
+
+/// Configuration file structure
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
     /// IP address to bind to
-    #[arg(short, long, default_value = "127.0.0.1")]
-    server_address: String,
+    server_address: Option<IpAddr>,
 
     /// Port to listen on
-    #[arg(short, long, default_value = "8081")]
-    port: u16,
+    port: Option<u16>,
+
+    /// Storage backend for the registry
+    store: Option<StoreKind>,
+
+    /// Path to the SQLite database or JSON snapshot file
+    store_path: Option<PathBuf>,
+
+    /// PEM certificate chain to serve HTTPS with
+    tls_cert: Option<PathBuf>,
+
+    /// PEM-encoded PKCS#8 private key for `tls_cert`
+    tls_key: Option<PathBuf>,
+
+    /// Require client certificates signed by the CAs in this PEM file
+    tls_client_ca: Option<PathBuf>,
+
+    /// Pre-shared token clients must present to register
+    enrollment_token: Option<String>,
+
+    /// Token operators must present to change the registry
+    admin_token: Option<String>,
+
+    /// Seconds between client heartbeats
+    heartbeat_interval_secs: Option<u64>,
+
+    /// Seconds without a heartbeat before a client is considered stale
+    stale_threshold_secs: Option<u64>,
+
+    /// Seconds without a heartbeat before a client is considered offline
+    offline_threshold_secs: Option<u64>,
+
+    /// Seconds between client status sweeps
+    status_sweep_secs: Option<u64>,
+
+    /// Maximum request body size in bytes
+    request_body_max_bytes: Option<usize>,
+
+    /// Heartbeat policies for clients matching a tag or hostname pattern
+    ///
+    /// Only available in the config file.
+    #[serde(default)]
+    heartbeat_policies: Vec<HeartbeatRuleSpec>,
+
+    /// URLs to notify when clients register, go offline or come back
+    ///
+    /// Only available in the config file.
+    #[serde(default)]
+    webhooks: Vec<WebhookSpec>,
+
+    /// Other servers to replicate the registry with
+    ///
+    /// Only available in the config file.
+    cluster: Option<ClusterSpec>,
+}
+
+/// Final resolved configuration
+#[derive(Debug)]
+struct ResolvedConfig {
+    bind_address: SocketAddr,
+    store: StoreKind,
+    store_path: Option<PathBuf>,
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+    tls_client_ca: Option<PathBuf>,
+    enrollment_token: Option<String>,
+    admin_token: Option<String>,
+    heartbeat_policies: HeartbeatPolicies,
+    status_sweep: Duration,
+    request_body_max_bytes: usize,
+    webhooks: Vec<Webhook>,
+    cluster: Option<Cluster>,
+}
+
+fn load_config(path: &PathBuf) -> Result<Config> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file: {:?}", path))?;
+    let config: Config = toml::from_str(&contents)
+        .with_context(|| format!("Failed to parse config file: {:?}", path))?;
+    Ok(config)
+}
+
+fn resolve_config(args: Args) -> Result<ResolvedConfig> {
+    let file_config = match &args.config {
+        Some(config_path) => load_config(config_path)?,
+        None => Config::default(),
+    };
+
+    // Every option prefers the CLI over the config file
+    let server_address = args
+        .server_address
+        .or(file_config.server_address)
+        .unwrap_or(DEFAULT_SERVER_ADDRESS);
+    let port = args.port.or(file_config.port).unwrap_or(DEFAULT_PORT);
+    let store = args
+        .store
+        .or(file_config.store)
+        .unwrap_or(StoreKind::Memory);
+    let store_path = args.store_path.or(file_config.store_path);
+    let tls_cert = args.tls_cert.or(file_config.tls_cert);
+    let tls_key = args.tls_key.or(file_config.tls_key);
+    let tls_client_ca = args.tls_client_ca.or(file_config.tls_client_ca);
+    let enrollment_token =
+        args.enrollment_token.or(file_config.enrollment_token);
+    let admin_token = args.admin_token.or(file_config.admin_token);
+
+    if tls_cert.is_some() != tls_key.is_some() {
+        anyhow::bail!("tls_cert and tls_key must be given together");
+    }
+    if tls_client_ca.is_some() && tls_cert.is_none() {
+        anyhow::bail!("tls_client_ca requires tls_cert and tls_key");
+    }
+
+    // The default thresholds follow whichever interval is in use
+    let heartbeat_policy = HeartbeatPolicy::with_thresholds(
+        args.heartbeat_interval_secs
+            .or(file_config.heartbeat_interval_secs)
+            .map_or(HeartbeatPolicy::default().interval, Duration::from_secs),
+        args.stale_threshold_secs
+            .or(file_config.stale_threshold_secs)
+            .map(Duration::from_secs),
+        args.offline_threshold_secs
+            .or(file_config.offline_threshold_secs)
+            .map(Duration::from_secs),
+    );
+    let heartbeat_policies = HeartbeatPolicies::new(
+        heartbeat_policy,
+        &file_config.heartbeat_policies,
+    )
+    .context("Invalid heartbeat policy")?;
+
+    let status_sweep =
+        match args.status_sweep_secs.or(file_config.status_sweep_secs) {
+            Some(0) => anyhow::bail!("status_sweep_secs must be at least 1"),
+            Some(secs) => Duration::from_secs(secs),
+            None => default_status_sweep(&heartbeat_policies),
+        };
+
+    let request_body_max_bytes = args
+        .request_body_max_bytes
+        .or(file_config.request_body_max_bytes)
+        .unwrap_or(DEFAULT_REQUEST_BODY_MAX_BYTES);
+
+    let webhooks = file_config
+        .webhooks
+        .iter()
+        .map(Webhook::from_spec)
+        .collect::<Result<_, _>>()
+        .context("Invalid webhook")?;
+
+    // Nodes go by their hostname and port unless they are given a name
+    let cluster = match &file_config.cluster {
+        Some(_) if tls_client_ca.is_some() => {
+            anyhow::bail!("cluster cannot be combined with tls_client_ca")
+        }
+        Some(spec) => {
+            let hostname = hostname::get()
+                .ok()
+                .and_then(|h| h.into_string().ok())
+                .unwrap_or_else(|| "unknown".to_string());
+            let node = format!("{}:{}", hostname, port);
+            Some(Cluster::from_spec(spec, node).context("Invalid cluster")?)
+        }
+        None => None,
+    };
+
+    Ok(ResolvedConfig {
+        bind_address: SocketAddr::new(server_address, port),
+        store,
+        store_path,
+        tls_cert,
+        tls_key,
+        tls_client_ca,
+        enrollment_token,
+        admin_token,
+        heartbeat_policies,
+        status_sweep,
+        request_body_max_bytes,
+        webhooks,
+        cluster,
+    })
 }
 
 /// Main entry point for the CRS server
 ///
 /// Initializes the registry, starts a background task to update client
 /// statuses, configures and starts the HTTP server with REST API endpoints
-/// and web dashboard.
+/// and web dashboard. On Ctrl-C or SIGTERM the server stops and anything
+/// the store is holding back is written out.
 #[tokio::main]
 async fn main() -> Result<()> {
-    let args = Args::parse();
+    let config = resolve_config(Args::parse())?;
+    if let Some(window) = config.heartbeat_policies.shortest_stale_window() {
+        if config.status_sweep > window {
+            eprintln!(
+                "Warning: status sweeps are {}s apart but clients can go \
+                 from stale to offline in {}s, so some will never be seen \
+                 stale.",
+                config.status_sweep.as_secs(),
+                window.as_secs_f64()
+            );
+        }
+    }
 
     // Record server start time
     let start_time = chrono::Utc::now();
 
-    // Create the registry
-    let registry = Registry::new();
+    // Open the store and rehydrate the registry from it
+    let store = store::open(config.store, config.store_path.as_deref())
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to open {:?} store: {}", config.store, e);
+            std::process::exit(1);
+        });
+    let registry = Registry::with_store(store)
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to load registry: {}", e);
+            std::process::exit(1);
+        })
+        .with_heartbeat_policies(config.heartbeat_policies);
+    let health = Health::default();
+    health.set_registry_loaded();
+    if let (StoreKind::Sqlite | StoreKind::Json, Some(path)) =
+        (config.store, &config.store_path)
+    {
+        let count = registry.list_clients().map(|c| c.len()).unwrap_or(0);
+        println!("Loaded {} client(s) from {:?}", count, path);
+    }
+
+    // Notify webhooks of client transitions
+    if !config.webhooks.is_empty() {
+        println!("Sending webhooks to {} URL(s)", config.webhooks.len());
+        webhooks::start(registry.events().clone(), config.webhooks);
+    }
+
+    // Replicate the registry with the rest of the cluster
+    if let Some(cluster) = &config.cluster {
+        println!(
+            "Replicating the registry as node {:?} with {} peer(s)",
+            cluster.node(),
+            cluster.status().members.len()
+        );
+        cluster::start(cluster.clone(), registry.clone());
+    }
 
     // Start background status updater task
     let registry_clone = registry.clone();
+    let sweep_health = health.clone();
     tokio::spawn(async move {
-        let mut interval = tokio::time::interval(Duration::from_secs(30));
+        let mut interval = tokio::time::interval(config.status_sweep);
         loop {
             interval.tick().await;
-            registry_clone.update_statuses();
+            if let Err(e) = registry_clone.update_statuses() {
+                eprintln!("Failed to update client statuses: {}", e);
+            } else {
+                sweep_health.record_sweep();
+            }
         }
     });
 
@@ -101,52 +703,511 @@ async fn main() -> Result<()> {
         .expect("failed to create logger");
 
     // Configure dropshot server
-    let bind_address: SocketAddr =
-        format!("{}:{}", args.server_address, args.port)
-            .parse()
-            .expect("failed to parse bind address");
-    let config = ConfigDropshot {
-        bind_address,
-        request_body_max_bytes: 1024 * 1024, // 1MB
+    let bind_address = config.bind_address;
+
+    // With client certificates, TLS is terminated by the mutual TLS front
+    // end and Dropshot only listens on loopback
+    let mtls_config =
+        match (&config.tls_client_ca, &config.tls_cert, &config.tls_key) {
+            (Some(client_ca), Some(cert), Some(key)) => Some(
+                mtls::load_server_config(cert, key, client_ca).unwrap_or_else(
+                    |e| {
+                        eprintln!("Failed to load TLS configuration: {}", e);
+                        std::process::exit(1);
+                    },
+                ),
+            ),
+            _ => None,
+        };
+    let peers = mtls_config.as_ref().map(|_| PeerTable::new());
+    let dropshot_address = if mtls_config.is_some() {
+        SocketAddr::from(([127, 0, 0, 1], 0))
+    } else {
+        bind_address
+    };
+
+    let dropshot_config = ConfigDropshot {
+        bind_address: dropshot_address,
+        request_body_max_bytes: config.request_body_max_bytes,
         default_handler_task_mode: dropshot::HandlerTaskMode::Detached,
         log_headers: vec![],
     };
 
+    if config.enrollment_token.is_none() {
+        eprintln!(
+            "Warning: no enrollment token set, any host can register. \
+             Use --enrollment-token to require one."
+        );
+    }
+    if config.admin_token.is_none() {
+        eprintln!(
+            "Warning: no admin token set, clients cannot be removed, \
+             banned, quarantined or put in maintenance. Use --admin-token \
+             to enable administration."
+        );
+    }
+
+    // Kept to write out the store on shutdown
+    let flush_registry = registry.clone();
+
     // Create API context
     let context = ApiContext {
         registry,
         start_time,
+        enrollment_token: config.enrollment_token,
+        admin_token: config.admin_token,
+        peers: peers.clone(),
+        health,
+        settings: ServerSettings {
+            bind_address: Some(bind_address),
+            status_sweep: config.status_sweep,
+        },
+        cluster: config.cluster,
     };
 
     // Build API description
-    let mut api = dropshot::ApiDescription::new();
-    api.register(api::register)
-        .expect("failed to register endpoint");
-    api.register(api::heartbeat)
-        .expect("failed to register endpoint");
-    api.register(api::list_clients)
-        .expect("failed to register endpoint");
-    api.register(web::dashboard)
-        .expect("failed to register endpoint");
+    let api = api::api_description();
+
+    // Let Dropshot serve HTTPS itself if a certificate was given and client
+    // certificates are not required
+    let tls = match (config.tls_cert, config.tls_key) {
+        (Some(cert_file), Some(key_file)) if mtls_config.is_none() => {
+            Some(ConfigTls::AsFile {
+                cert_file,
+                key_file,
+            })
+        }
+        _ => None,
+    };
+    let scheme = if tls.is_some() || mtls_config.is_some() {
+        "https"
+    } else {
+        "http"
+    };
 
     // Start the server
-    let server = HttpServerStarter::new(&config, api, context, &log)
-        .map_err(|e| {
-            eprintln!("Failed to bind to {}: {}", bind_address, e);
-            std::process::exit(1);
-        })
-        .unwrap()
-        .start();
+    let server = HttpServerStarter::new_with_tls(
+        &dropshot_config,
+        api,
+        context,
+        &log,
+        tls,
+    )
+    .map_err(|e| {
+        eprintln!("Failed to start server on {}: {}", bind_address, e);
+        std::process::exit(1);
+    })
+    .unwrap()
+    .start();
 
-    println!("CRS Server listening on http://{}", bind_address);
-    println!("Dashboard: http://{}/", bind_address);
+    if let (Some(tls_config), Some(peers)) = (mtls_config, peers) {
+        let listener = tokio::net::TcpListener::bind(bind_address)
+            .await
+            .unwrap_or_else(|e| {
+                eprintln!("Failed to bind to {}: {}", bind_address, e);
+                std::process::exit(1);
+            });
+        let upstream = server.local_addr();
+        tokio::spawn(async move {
+            if let Err(e) =
+                mtls::serve(listener, tls_config, upstream, peers).await
+            {
+                eprintln!("TLS listener failed: {}", e);
+                std::process::exit(1);
+            }
+        });
+        println!("Client certificates required");
+    }
+
+    println!("CRS Server listening on {}://{}", scheme, bind_address);
+    println!("Dashboard: {}://{}/", scheme, bind_address);
     println!("API endpoints:");
-    println!("  POST http://{}/api/register", bind_address);
-    println!("  POST http://{}/api/heartbeat", bind_address);
-    println!("  GET  http://{}/api/clients", bind_address);
+    println!("  POST {}://{}/api/register", scheme, bind_address);
+    println!("  POST {}://{}/api/heartbeat", scheme, bind_address);
+    println!("  POST {}://{}/api/deregister", scheme, bind_address);
+    println!("  GET  {}://{}/api/clients", scheme, bind_address);
+    println!("  GET  {}://{}/api/events", scheme, bind_address);
+    println!("  GET  {}://{}/api/bans", scheme, bind_address);
+    println!("  GET  {}://{}/api/server", scheme, bind_address);
+    println!("  GET  {}://{}/api/cluster", scheme, bind_address);
+    println!("  GET  {}://{}/api/openapi.json", scheme, bind_address);
+    println!("Metrics: {}://{}/metrics", scheme, bind_address);
+    println!("Health: {}://{}/healthz", scheme, bind_address);
+    println!("Readiness: {}://{}/readyz", scheme, bind_address);
 
-    server.await.map_err(|e| {
+    // Run until the server fails or is told to stop
+    let result = tokio::select! {
+        result = server.wait_for_shutdown() => result,
+        _ = shutdown_signal() => {
+            println!("Shutting down");
+            server.close().await
+        }
+    };
+    if let Err(e) = result {
         eprintln!("Server error: {}", e);
         std::process::exit(1);
-    })
+    }
+
+    // Write out anything the store is still holding back
+    if let Err(e) = flush_registry.flush() {
+        eprintln!("Failed to write the registry: {}", e);
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Wait for Ctrl-C or, on Unix, SIGTERM
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate())
+            .expect("failed to install the SIGTERM handler");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test that ensures all CLI arguments are represented in the Config struct.
+    /// This test will fail if someone adds a new CLI argument without adding
+    /// it to the TOML config structure.
+    #[test]
+    fn test_cli_and_config_fields_match() {
+        use std::collections::HashSet;
+
+        // All CLI option fields, excluding 'config' since it's meta
+        let cli_fields: HashSet<&str> = [
+            "server_address",
+            "port",
+            "store",
+            "store_path",
+            "tls_cert",
+            "tls_key",
+            "tls_client_ca",
+            "enrollment_token",
+            "admin_token",
+            "heartbeat_interval_secs",
+            "stale_threshold_secs",
+            "offline_threshold_secs",
+            "status_sweep_secs",
+            "request_body_max_bytes",
+        ]
+        .into_iter()
+        .collect();
+
+        // Options that only make sense in the config file
+        let file_only_fields: HashSet<&str> =
+            ["heartbeat_policies", "webhooks", "cluster"]
+                .into_iter()
+                .collect();
+
+        // All Config fields, excluding file-only ones
+        let config_fields: HashSet<&str> = [
+            "server_address",
+            "port",
+            "store",
+            "store_path",
+            "tls_cert",
+            "tls_key",
+            "tls_client_ca",
+            "enrollment_token",
+            "admin_token",
+            "heartbeat_interval_secs",
+            "stale_threshold_secs",
+            "offline_threshold_secs",
+            "status_sweep_secs",
+            "request_body_max_bytes",
+            "heartbeat_policies",
+            "webhooks",
+            "cluster",
+        ]
+        .into_iter()
+        .filter(|field| !file_only_fields.contains(field))
+        .collect();
+
+        let missing_in_config: Vec<_> =
+            cli_fields.difference(&config_fields).collect();
+        assert!(
+            missing_in_config.is_empty(),
+            "CLI fields missing from Config struct: {:?}. \
+             All command line options must be supported in the TOML config file.",
+            missing_in_config
+        );
+
+        let extra_in_config: Vec<_> =
+            config_fields.difference(&cli_fields).collect();
+        assert!(
+            extra_in_config.is_empty(),
+            "Config fields not in CLI: {:?}",
+            extra_in_config
+        );
+    }
+
+    /// Write `contents` to a config file and resolve it with extra CLI args
+    fn resolve_with_file(
+        contents: &str,
+        extra_args: &[&str],
+    ) -> Result<ResolvedConfig> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, contents).unwrap();
+
+        let mut argv = vec!["crs-server", "--config", path.to_str().unwrap()];
+        argv.extend_from_slice(extra_args);
+        resolve_config(Args::parse_from(argv))
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = resolve_config(Args::parse_from(["crs-server"])).unwrap();
+        assert_eq!(config.bind_address, "127.0.0.1:8081".parse().unwrap());
+        assert_eq!(config.store, StoreKind::Memory);
+        assert_eq!(
+            config.heartbeat_policies.default_policy(),
+            HeartbeatPolicy::default()
+        );
+        // Sweeps are frequent enough to catch clients in the 3 seconds
+        // between the default stale and offline thresholds
+        assert_eq!(config.status_sweep, Duration::from_secs(1));
+        assert_eq!(config.request_body_max_bytes, 1024 * 1024);
+    }
+
+    #[test]
+    fn test_config_file() {
+        let config = resolve_with_file(
+            r#"
+            server_address = "0.0.0.0"
+            port = 9000
+            store = "sqlite"
+            store_path = "/var/lib/crs/registry.db"
+            heartbeat_interval_secs = 30
+            offline_threshold_secs = 90
+            status_sweep_secs = 5
+            request_body_max_bytes = 65536
+            "#,
+            &[],
+        )
+        .unwrap();
+        assert_eq!(config.bind_address, "0.0.0.0:9000".parse().unwrap());
+        assert_eq!(config.store, StoreKind::Sqlite);
+        assert_eq!(
+            config.store_path,
+            Some(PathBuf::from("/var/lib/crs/registry.db"))
+        );
+        assert_eq!(
+            config.heartbeat_policies.default_policy().interval,
+            Duration::from_secs(30)
+        );
+        assert_eq!(
+            config.heartbeat_policies.default_policy().offline_threshold,
+            Duration::from_secs(90)
+        );
+        assert_eq!(config.status_sweep, Duration::from_secs(5));
+        assert_eq!(config.request_body_max_bytes, 65536);
+    }
+
+    #[test]
+    fn test_status_sweep_follows_policies() {
+        let sweep = |config: &str| {
+            resolve_with_file(config, &[])
+                .unwrap()
+                .status_sweep
+                .as_secs()
+        };
+        assert_eq!(sweep("heartbeat_interval_secs = 300\n"), 30);
+        assert_eq!(sweep("heartbeat_interval_secs = 60\n"), 9);
+        assert_eq!(
+            sweep(
+                "heartbeat_interval_secs = 60\n\
+                 stale_threshold_secs = 90\n\
+                 offline_threshold_secs = 90\n"
+            ),
+            30
+        );
+        assert_eq!(
+            sweep(
+                r#"
+                heartbeat_interval_secs = 300
+                [[heartbeat_policies]]
+                tag = "role=core"
+                interval_secs = 5
+                stale_threshold_secs = 6
+                offline_threshold_secs = 10
+                "#
+            ),
+            2
+        );
+    }
+
+    #[test]
+    fn test_example_config_matches_defaults() {
+        let config =
+            resolve_with_file(include_str!("../example-config.toml"), &[])
+                .unwrap();
+        let defaults =
+            resolve_config(Args::parse_from(["crs-server"])).unwrap();
+        assert_eq!(format!("{:?}", config), format!("{:?}", defaults));
+    }
+
+    #[test]
+    fn test_cli_overrides_config_file() {
+        let config = resolve_with_file(
+            "port = 9000\nheartbeat_interval_secs = 30\n",
+            &["--port", "9001", "--heartbeat-interval-secs", "20"],
+        )
+        .unwrap();
+        assert_eq!(config.bind_address.port(), 9001);
+        assert_eq!(
+            config.heartbeat_policies.default_policy().interval,
+            Duration::from_secs(20)
+        );
+    }
+
+    #[test]
+    fn test_offline_threshold_follows_interval() {
+        let config =
+            resolve_with_file("heartbeat_interval_secs = 60\n", &[]).unwrap();
+        assert_eq!(
+            config.heartbeat_policies.default_policy().stale_threshold,
+            Duration::from_secs(72)
+        );
+        assert_eq!(
+            config.heartbeat_policies.default_policy().offline_threshold,
+            Duration::from_secs(90)
+        );
+    }
+
+    #[test]
+    fn test_stale_threshold() {
+        let config = resolve_with_file(
+            "stale_threshold_secs = 20\noffline_threshold_secs = 60\n",
+            &[],
+        )
+        .unwrap();
+        let policy = config.heartbeat_policies.default_policy();
+        assert_eq!(policy.stale_threshold, Duration::from_secs(20));
+        assert_eq!(policy.offline_threshold, Duration::from_secs(60));
+
+        // Clients cannot go stale after going offline
+        assert!(resolve_with_file(
+            "stale_threshold_secs = 20\noffline_threshold_secs = 15\n",
+            &[],
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_heartbeat_policies_from_config_file() {
+        use crs_common::ClientInfo;
+
+        let config = resolve_with_file(
+            r#"
+            [[heartbeat_policies]]
+            tag = "role=laptop"
+            interval_secs = 60
+
+            [[heartbeat_policies]]
+            hostname = "core-*"
+            interval_secs = 5
+            offline_threshold_secs = 10
+            "#,
+            &[],
+        )
+        .unwrap();
+
+        let mut info = ClientInfo {
+            hostname: "core-router".to_string(),
+            os: "linux".to_string(),
+            ip_address: "10.0.0.1".to_string(),
+            version: "1.0.0".to_string(),
+            host_id: None,
+            tags: Default::default(),
+        };
+        let policy = config.heartbeat_policies.policy_for(&info);
+        assert_eq!(policy.interval, Duration::from_secs(5));
+        assert_eq!(policy.offline_threshold, Duration::from_secs(10));
+
+        info.tags.insert("role".to_string(), "laptop".to_string());
+        let policy = config.heartbeat_policies.policy_for(&info);
+        assert_eq!(policy.interval, Duration::from_secs(60));
+
+        // Rules are validated like the default policy
+        assert!(resolve_with_file(
+            "[[heartbeat_policies]]\ntag = \"role=\"\ninterval_secs = 5\n",
+            &[],
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_webhooks_from_config_file() {
+        let config = resolve_with_file(
+            r#"
+            [[webhooks]]
+            url = "https://hooks.example.com/crs"
+            secret = "s3cret"
+            events = ["went_offline", "came_back"]
+            tag = "role=core"
+            max_attempts = 3
+            "#,
+            &[],
+        )
+        .unwrap();
+        assert_eq!(config.webhooks.len(), 1);
+        assert_eq!(
+            config.webhooks[0].url().as_str(),
+            "https://hooks.example.com/crs"
+        );
+
+        assert!(resolve_with_file(
+            "[[webhooks]]\nurl = \"https://hooks.example.com\"\nsecret = \"\"\n",
+            &[],
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_cluster_from_config_file() {
+        let config = resolve_with_file(
+            r#"
+            port = 9000
+            [cluster]
+            peers = ["http://crs-2:9000", "http://crs-3:9000"]
+            secret = "s3cret"
+            "#,
+            &[],
+        )
+        .unwrap();
+        let cluster = config.cluster.unwrap();
+        assert!(cluster.node().ends_with(":9000"));
+        assert_eq!(cluster.status().members.len(), 2);
+        assert!(resolve_with_file("", &[]).unwrap().cluster.is_none());
+
+        assert!(resolve_with_file(
+            "[cluster]\npeers = [\"http://crs-2:9000\"]\nsecret = \"\"\n",
+            &[],
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        // Clients would flap between online and offline
+        assert!(resolve_with_file(
+            "heartbeat_interval_secs = 30\noffline_threshold_secs = 30\n",
+            &[],
+        )
+        .is_err());
+        assert!(resolve_with_file("status_sweep_secs = 0\n", &[]).is_err());
+        assert!(resolve_with_file("tls_cert = \"cert.pem\"\n", &[]).is_err());
+        assert!(resolve_with_file("no_such_option = 1\n", &[]).is_err());
+    }
 }
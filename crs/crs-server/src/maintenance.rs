@@ -0,0 +1,297 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Maintenance windows
+//!
+//! A [`MaintenanceWindow`] marks a stretch of time during which some
+//! clients are expected to go offline, such as a rack being rebooted for
+//! patching. It covers one client, or every client whose tags match a
+//! selector. A one-off window lasts `duration_secs` from `starts_at`; a
+//! recurring window has a five-field cron schedule, evaluated in UTC, and
+//! lasts `duration_secs` from each time the schedule matches.
+//!
+//! Windows do not change a client's status. The registry's status update
+//! notes which window, if any, covers each client, and status changes made
+//! while a client is covered are recorded as expected. Expected events are
+//! not sent to webhooks, and expected offline time does not count against a
+//! client's availability.
+
+use chrono::{DateTime, Duration, Utc};
+use croner::Cron;
+use crs_common::{
+    ClientId, MaintenanceWindow, MaintenanceWindowRequest, RegisteredClient,
+    Selector,
+};
+use uuid::Uuid;
+
+/// Check a maintenance window request and turn it into a window
+///
+/// Returns a description of the problem if the request names both or
+/// neither of a client and a selector, has a zero duration, or has a
+/// selector or schedule that does not parse.
+pub fn new_window(
+    request: MaintenanceWindowRequest,
+    now: DateTime<Utc>,
+) -> Result<MaintenanceWindow, String> {
+    match (&request.client_id, &request.selector) {
+        (Some(_), Some(_)) => {
+            return Err("give a client ID or a selector, not both".to_string())
+        }
+        (None, None) => {
+            return Err("a client ID or a selector is required".to_string())
+        }
+        (None, Some(selector)) => {
+            selector.parse::<Selector>().map_err(|e| e.to_string())?;
+        }
+        (Some(_), None) => {}
+    }
+    if request.duration_secs == 0 {
+        return Err("the duration must be at least one second".to_string());
+    }
+    if let Some(schedule) = &request.schedule {
+        parse_schedule(schedule)?;
+    }
+
+    Ok(MaintenanceWindow {
+        id: Uuid::new_v4(),
+        client_id: request.client_id,
+        selector: request.selector,
+        reason: request.reason,
+        starts_at: request.starts_at.unwrap_or(now),
+        duration_secs: request.duration_secs,
+        schedule: request.schedule,
+        created_at: now,
+    })
+}
+
+/// Parse a five-field cron schedule
+fn parse_schedule(schedule: &str) -> Result<Cron, String> {
+    Cron::new(schedule)
+        .parse()
+        .map_err(|e| format!("invalid schedule {:?}: {}", schedule, e))
+}
+
+fn duration(window: &MaintenanceWindow) -> Duration {
+    i64::try_from(window.duration_secs)
+        .ok()
+        .and_then(Duration::try_seconds)
+        .unwrap_or(Duration::MAX)
+}
+
+/// Whether `window` is in effect at `now`
+pub fn is_active(window: &MaintenanceWindow, now: DateTime<Utc>) -> bool {
+    if now < window.starts_at {
+        return false;
+    }
+    let duration = duration(window);
+    let Some(schedule) = &window.schedule else {
+        return now - window.starts_at < duration;
+    };
+    let Ok(schedule) = parse_schedule(schedule) else {
+        return false;
+    };
+
+    // The window is active if the schedule matched less than a duration ago
+    let earliest = now
+        .checked_sub_signed(duration)
+        .map_or(window.starts_at, |t| t.max(window.starts_at));
+    match schedule.find_next_occurrence(&earliest, true) {
+        Ok(start) => start <= now && now - start < duration,
+        Err(_) => false,
+    }
+}
+
+/// Whether `window` is over and will never be in effect again
+///
+/// Recurring windows never end; they stay until they are removed.
+pub fn has_ended(window: &MaintenanceWindow, now: DateTime<Utc>) -> bool {
+    window.schedule.is_none() && now - window.starts_at >= duration(window)
+}
+
+/// What a window covers
+enum Target {
+    Client(ClientId),
+    Selector(Selector),
+}
+
+/// The maintenance windows in effect at some moment, ready to be matched
+/// against clients
+pub struct ActiveWindows {
+    windows: Vec<(Uuid, Target)>,
+}
+
+impl ActiveWindows {
+    /// Collect the windows among `windows` that are in effect at `now`
+    pub fn new(windows: &[MaintenanceWindow], now: DateTime<Utc>) -> Self {
+        let windows = windows
+            .iter()
+            .filter(|window| is_active(window, now))
+            .filter_map(|window| {
+                let target = match (&window.client_id, &window.selector) {
+                    (Some(client_id), _) => Target::Client(*client_id),
+                    (None, Some(selector)) => {
+                        Target::Selector(selector.parse().ok()?)
+                    }
+                    (None, None) => return None,
+                };
+                Some((window.id, target))
+            })
+            .collect();
+        Self { windows }
+    }
+
+    /// The first window in effect that covers `client`
+    pub fn covering(&self, client: &RegisteredClient) -> Option<Uuid> {
+        self.windows
+            .iter()
+            .find(|(_, target)| match target {
+                Target::Client(client_id) => *client_id == client.client_id,
+                Target::Selector(selector) => {
+                    selector.matches(&client.info.tags)
+                }
+            })
+            .map(|(id, _)| *id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    fn request(duration_secs: u64) -> MaintenanceWindowRequest {
+        MaintenanceWindowRequest {
+            selector: Some("rack=r12".to_string()),
+            duration_secs,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_new_window() {
+        let now = at("2025-03-01T10:00:00Z");
+        let window = new_window(request(3600), now).unwrap();
+        assert_eq!(window.starts_at, now);
+        assert_eq!(window.created_at, now);
+
+        let both = MaintenanceWindowRequest {
+            client_id: Some(ClientId(Uuid::nil())),
+            ..request(3600)
+        };
+        assert!(new_window(both, now).is_err());
+        let neither = MaintenanceWindowRequest {
+            selector: None,
+            ..request(3600)
+        };
+        assert!(new_window(neither, now).is_err());
+        assert!(new_window(request(0), now).is_err());
+        let bad_selector = MaintenanceWindowRequest {
+            selector: Some("rack in r12".to_string()),
+            ..request(3600)
+        };
+        assert!(new_window(bad_selector, now).is_err());
+        let bad_schedule = MaintenanceWindowRequest {
+            schedule: Some("every saturday".to_string()),
+            ..request(3600)
+        };
+        assert!(new_window(bad_schedule, now).is_err());
+    }
+
+    #[test]
+    fn test_one_off_window() {
+        let now = at("2025-03-01T10:00:00Z");
+        let window = new_window(request(3600), now).unwrap();
+        assert!(!is_active(&window, at("2025-03-01T09:59:59Z")));
+        assert!(is_active(&window, now));
+        assert!(is_active(&window, at("2025-03-01T10:59:59Z")));
+        assert!(!is_active(&window, at("2025-03-01T11:00:00Z")));
+        assert!(!has_ended(&window, at("2025-03-01T10:59:59Z")));
+        assert!(has_ended(&window, at("2025-03-01T11:00:00Z")));
+    }
+
+    #[test]
+    fn test_recurring_window() {
+        // Two hours from 02:00 every Saturday (2025-03-01 is a Saturday)
+        let window = new_window(
+            MaintenanceWindowRequest {
+                schedule: Some("0 2 * * 6".to_string()),
+                ..request(2 * 3600)
+            },
+            at("2025-02-26T12:00:00Z"),
+        )
+        .unwrap();
+        assert!(!is_active(&window, at("2025-03-01T01:59:00Z")));
+        assert!(is_active(&window, at("2025-03-01T02:00:00Z")));
+        assert!(is_active(&window, at("2025-03-01T03:59:59Z")));
+        assert!(!is_active(&window, at("2025-03-01T04:00:00Z")));
+        assert!(is_active(&window, at("2025-03-08T03:00:00Z")));
+        assert!(!is_active(&window, at("2025-03-09T03:00:00Z")));
+        assert!(!has_ended(&window, at("2030-01-01T00:00:00Z")));
+
+        // Nothing before the schedule takes effect
+        let later = MaintenanceWindow {
+            starts_at: at("2025-03-01T03:00:00Z"),
+            ..window
+        };
+        assert!(!is_active(&later, at("2025-03-01T03:30:00Z")));
+        assert!(is_active(&later, at("2025-03-08T03:30:00Z")));
+    }
+
+    #[test]
+    fn test_active_windows_cover_clients() {
+        let now = at("2025-03-01T10:00:00Z");
+        let info = |hostname: &str, rack: &str| crs_common::ClientInfo {
+            hostname: hostname.to_string(),
+            os: "linux".to_string(),
+            ip_address: "10.0.0.1".to_string(),
+            version: "1.0.0".to_string(),
+            host_id: None,
+            tags: [("rack".to_string(), rack.to_string())]
+                .into_iter()
+                .collect(),
+        };
+        let client = |info: crs_common::ClientInfo| RegisteredClient {
+            client_id: info.client_id(),
+            info,
+            status: crs_common::ClientStatus::Online,
+            first_connected: now,
+            registered_at: now,
+            last_heartbeat: now,
+            quarantine: None,
+            maintenance: None,
+            maintenance_window: None,
+            metrics: None,
+            checks: Vec::new(),
+            availability: Vec::new(),
+        };
+        let in_rack = client(info("a", "r12"));
+        let elsewhere = client(info("b", "r13"));
+
+        let by_selector = new_window(request(3600), now).unwrap();
+        let by_client = new_window(
+            MaintenanceWindowRequest {
+                client_id: Some(elsewhere.client_id),
+                selector: None,
+                ..request(3600)
+            },
+            now,
+        )
+        .unwrap();
+        let upcoming = MaintenanceWindow {
+            starts_at: at("2025-03-02T10:00:00Z"),
+            ..new_window(request(3600), now).unwrap()
+        };
+
+        let active = ActiveWindows::new(
+            &[upcoming, by_selector.clone(), by_client.clone()],
+            now,
+        );
+        assert_eq!(active.covering(&in_rack), Some(by_selector.id));
+        assert_eq!(active.covering(&elsewhere), Some(by_client.id));
+
+        let active = ActiveWindows::new(&[by_selector], now);
+        assert_eq!(active.covering(&elsewhere), None);
+    }
+}
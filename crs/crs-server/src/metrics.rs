@@ -0,0 +1,278 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Prometheus metrics
+//!
+//! The [`Registry`](crate::registry::Registry) owns a [`Metrics`] set. The
+//! API handlers count registrations and heartbeats and time themselves,
+//! and every periodic status update recounts the clients by status,
+//! operating system and version. `GET /metrics` serves the lot in the
+//! Prometheus text format:
+//!
+//! - `crs_clients{status, os, version}` - Registered clients, as of the
+//!   last status update
+//! - `crs_registrations_total` - Successful registrations
+//! - `crs_reregistrations_total` - Registrations by clients that were
+//!   already registered
+//! - `crs_heartbeats_total` - Accepted heartbeats
+//! - `crs_heartbeats_rejected_total` - Heartbeats from unknown clients or
+//!   with the wrong secret, answered with 401
+//! - `crs_handler_duration_seconds{handler}` - API handler latency
+
+// Suppress warnings for Dropshot's macro-generated phantom types
+#![allow(dead_code)]
+
+use crate::api::{require_peer, ApiContext};
+use crate::registry::RegistryError;
+use crs_common::{ClientStatus, RegisteredClient};
+use dropshot::{endpoint, Body, HttpError, RequestContext};
+use http::{header, Response, StatusCode};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramTimer, HistogramVec, IntCounter,
+    IntGaugeVec, Opts, TextEncoder,
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// The server's metrics
+///
+/// Cloning is cheap and every clone updates the same metrics. Each set has
+/// its own Prometheus registry, so several servers in one process (as in
+/// tests) do not share counts.
+#[derive(Clone)]
+pub struct Metrics {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    registry: prometheus::Registry,
+    clients: IntGaugeVec,
+    registrations: IntCounter,
+    reregistrations: IntCounter,
+    heartbeats: IntCounter,
+    heartbeats_rejected: IntCounter,
+    handler_duration: HistogramVec,
+}
+
+impl Metrics {
+    /// Create a set of metrics, all starting from zero
+    pub fn new() -> Self {
+        let clients = IntGaugeVec::new(
+            Opts::new("crs_clients", "Registered clients"),
+            &["status", "os", "version"],
+        )
+        .unwrap();
+        let registrations = IntCounter::new(
+            "crs_registrations_total",
+            "Successful client registrations",
+        )
+        .unwrap();
+        let reregistrations = IntCounter::new(
+            "crs_reregistrations_total",
+            "Registrations by clients that were already registered",
+        )
+        .unwrap();
+        let heartbeats =
+            IntCounter::new("crs_heartbeats_total", "Accepted heartbeats")
+                .unwrap();
+        let heartbeats_rejected = IntCounter::new(
+            "crs_heartbeats_rejected_total",
+            "Heartbeats from unknown clients or with the wrong secret",
+        )
+        .unwrap();
+        let handler_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "crs_handler_duration_seconds",
+                "Time spent handling API requests",
+            ),
+            &["handler"],
+        )
+        .unwrap();
+
+        let registry = prometheus::Registry::new();
+        registry.register(Box::new(clients.clone())).unwrap();
+        registry.register(Box::new(registrations.clone())).unwrap();
+        registry
+            .register(Box::new(reregistrations.clone()))
+            .unwrap();
+        registry.register(Box::new(heartbeats.clone())).unwrap();
+        registry
+            .register(Box::new(heartbeats_rejected.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(handler_duration.clone()))
+            .unwrap();
+
+        Self {
+            inner: Arc::new(Inner {
+                registry,
+                clients,
+                registrations,
+                reregistrations,
+                heartbeats,
+                heartbeats_rejected,
+                handler_duration,
+            }),
+        }
+    }
+
+    /// Count a successful registration
+    ///
+    /// `first` is whether the client had never registered before.
+    pub fn record_registration(&self, first: bool) {
+        self.inner.registrations.inc();
+        if !first {
+            self.inner.reregistrations.inc();
+        }
+    }
+
+    /// Count an accepted heartbeat
+    pub fn record_heartbeat(&self) {
+        self.inner.heartbeats.inc();
+    }
+
+    /// Count a rejected heartbeat if it was from an unknown client or with
+    /// the wrong secret
+    pub fn record_heartbeat_error(&self, error: &RegistryError) {
+        if matches!(error, RegistryError::InvalidSecret(_)) {
+            self.inner.heartbeats_rejected.inc();
+        }
+    }
+
+    /// Start timing an API handler
+    ///
+    /// The time is recorded when the returned timer is dropped.
+    pub fn time_handler(&self, handler: &str) -> HistogramTimer {
+        self.inner
+            .handler_duration
+            .with_label_values(&[handler])
+            .start_timer()
+    }
+
+    /// Recount clients by status, operating system and version
+    pub fn set_clients(&self, clients: &[RegisteredClient]) {
+        let mut counts: HashMap<(&str, &str, &str), i64> = HashMap::new();
+        for client in clients {
+            let status = match client.status {
+                ClientStatus::Online => "online",
+                ClientStatus::Degraded => "degraded",
+                ClientStatus::Stale => "stale",
+                ClientStatus::Offline => "offline",
+                ClientStatus::Departed => "departed",
+                ClientStatus::Maintenance => "maintenance",
+            };
+            *counts
+                .entry((status, &client.info.os, &client.info.version))
+                .or_default() += 1;
+        }
+
+        // Start over so that combinations no client has any more disappear
+        self.inner.clients.reset();
+        for ((status, os, version), count) in counts {
+            self.inner
+                .clients
+                .with_label_values(&[status, os, version])
+                .set(count);
+        }
+    }
+
+    /// Render every metric in the Prometheus text format
+    pub fn render(&self) -> Result<String, prometheus::Error> {
+        TextEncoder::new().encode_to_string(&self.inner.registry.gather())
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Serve metrics for Prometheus to scrape
+#[endpoint {
+    method = GET,
+    path = "/metrics",
+}]
+pub async fn serve_metrics(
+    ctx: RequestContext<ApiContext>,
+) -> Result<Response<Body>, HttpError> {
+    require_peer(&ctx)?;
+    let text = ctx
+        .context()
+        .registry
+        .metrics()
+        .render()
+        .map_err(|e| HttpError::for_internal_error(e.to_string()))?;
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, TextEncoder::new().format_type())
+        .body(text.into())
+        .map_err(|e| HttpError::for_internal_error(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use crs_common::{ClientId, ClientInfo};
+
+    fn client(os: &str, status: ClientStatus) -> RegisteredClient {
+        let now = Utc::now();
+        RegisteredClient {
+            client_id: ClientId(uuid::Uuid::new_v4()),
+            info: ClientInfo {
+                hostname: "host".to_string(),
+                os: os.to_string(),
+                ip_address: "10.0.0.1".to_string(),
+                version: "1.0.0".to_string(),
+                host_id: None,
+                tags: HashMap::new(),
+            },
+            status,
+            first_connected: now,
+            registered_at: now,
+            last_heartbeat: now,
+            quarantine: None,
+            maintenance: None,
+            maintenance_window: None,
+            metrics: None,
+            checks: Vec::new(),
+            availability: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::new();
+        metrics.record_registration(true);
+        metrics.record_registration(false);
+        metrics.record_heartbeat();
+        metrics.record_heartbeat_error(&RegistryError::InvalidSecret(
+            ClientId(uuid::Uuid::nil()),
+        ));
+        drop(metrics.time_handler("heartbeat"));
+        metrics.set_clients(&[
+            client("linux", ClientStatus::Online),
+            client("linux", ClientStatus::Online),
+            client("illumos", ClientStatus::Offline),
+        ]);
+
+        let text = metrics.render().unwrap();
+        assert!(text.contains("crs_registrations_total 2"));
+        assert!(text.contains("crs_reregistrations_total 1"));
+        assert!(text.contains("crs_heartbeats_total 1"));
+        assert!(text.contains("crs_heartbeats_rejected_total 1"));
+        assert!(text.contains(
+            r#"crs_clients{os="linux",status="online",version="1.0.0"} 2"#
+        ));
+        assert!(text.contains(
+            r#"crs_handler_duration_seconds_count{handler="heartbeat"} 1"#
+        ));
+
+        // Combinations no longer seen are dropped
+        metrics.set_clients(&[client("linux", ClientStatus::Offline)]);
+        let text = metrics.render().unwrap();
+        assert!(!text.contains(r#"status="online""#));
+        assert!(!text.contains(r#"os="illumos""#));
+    }
+}
@@ -0,0 +1,252 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Mutual TLS front end
+//!
+//! Dropshot can serve HTTPS on its own, but it does not pass the client's
+//! certificate on to request handlers. When client certificates are
+//! required, crs-server terminates TLS here instead. Each connection is
+//! verified against the client CA, the identity in its certificate is
+//! recorded in a [`PeerTable`], and the decrypted stream is forwarded to a
+//! Dropshot server listening on loopback. Handlers then look up the identity
+//! by the remote address of the forwarded connection.
+//!
+//! Any local process can connect to the loopback port directly, so every
+//! handler rejects requests whose remote address is not in the
+//! [`PeerTable`]: with client certificates required, nothing is served
+//! without one, read-only endpoints included.
+
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::TlsAcceptor;
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+/// Identity taken from a verified client certificate
+#[derive(Debug, Clone)]
+pub struct PeerIdentity {
+    /// Address the client actually connected from
+    pub peer_addr: SocketAddr,
+
+    /// Common name from the certificate subject, if any
+    pub common_name: Option<String>,
+
+    /// DNS names from the subject alternative name extension
+    pub dns_names: Vec<String>,
+}
+
+impl PeerIdentity {
+    /// Extract the identity from a DER-encoded certificate
+    pub fn from_certificate(
+        peer_addr: SocketAddr,
+        der: &[u8],
+    ) -> Result<Self, MtlsError> {
+        let (_, cert) = X509Certificate::from_der(der)
+            .map_err(|e| MtlsError::Certificate(e.to_string()))?;
+
+        let common_name = cert
+            .subject()
+            .iter_common_name()
+            .next()
+            .and_then(|cn| cn.as_str().ok())
+            .map(str::to_string);
+
+        let dns_names = match cert
+            .subject_alternative_name()
+            .map_err(|e| MtlsError::Certificate(e.to_string()))?
+        {
+            Some(san) => san
+                .value
+                .general_names
+                .iter()
+                .filter_map(|name| match name {
+                    GeneralName::DNSName(dns) => Some(dns.to_string()),
+                    _ => None,
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+
+        Ok(Self {
+            peer_addr,
+            common_name,
+            dns_names,
+        })
+    }
+
+    /// Whether the certificate was issued for `hostname`
+    ///
+    /// The hostname must equal the subject common name or one of the DNS
+    /// names, ignoring case.
+    pub fn matches(&self, hostname: &str) -> bool {
+        self.common_name
+            .iter()
+            .chain(&self.dns_names)
+            .any(|name| name.eq_ignore_ascii_case(hostname))
+    }
+
+    /// Describe the certificate subject for error messages
+    pub fn subject(&self) -> String {
+        match (&self.common_name, self.dns_names.as_slice()) {
+            (Some(cn), _) => format!("CN={}", cn),
+            (None, []) => "(empty subject)".to_string(),
+            (None, names) => format!("DNS={}", names.join(",")),
+        }
+    }
+}
+
+/// Identities of the connections currently forwarded to Dropshot
+///
+/// Keyed by the local address of each forwarded connection, which is the
+/// remote address Dropshot reports for its requests.
+#[derive(Clone, Default)]
+pub struct PeerTable {
+    peers: Arc<Mutex<HashMap<SocketAddr, PeerIdentity>>>,
+}
+
+impl PeerTable {
+    /// Create an empty table
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Look up the identity behind a forwarded connection
+    pub fn get(&self, forwarded_addr: SocketAddr) -> Option<PeerIdentity> {
+        self.peers.lock().unwrap().get(&forwarded_addr).cloned()
+    }
+
+    fn insert(&self, forwarded_addr: SocketAddr, identity: PeerIdentity) {
+        self.peers.lock().unwrap().insert(forwarded_addr, identity);
+    }
+
+    fn remove(&self, forwarded_addr: SocketAddr) {
+        self.peers.lock().unwrap().remove(&forwarded_addr);
+    }
+}
+
+/// Build a TLS configuration that requires client certificates
+///
+/// `cert_file` and `key_file` hold the server's certificate chain and
+/// private key; `client_ca_file` holds the CA certificates that client
+/// certificates must chain to.
+pub fn load_server_config(
+    cert_file: &Path,
+    key_file: &Path,
+    client_ca_file: &Path,
+) -> Result<Arc<ServerConfig>, MtlsError> {
+    let certs = load_certs(cert_file)?;
+
+    let mut reader = std::io::BufReader::new(std::fs::File::open(key_file)?);
+    let key: PrivateKeyDer<'static> = rustls_pemfile::private_key(&mut reader)?
+        .ok_or_else(|| MtlsError::NoPrivateKey(key_file.to_path_buf()))?;
+
+    let mut roots = RootCertStore::empty();
+    for ca in load_certs(client_ca_file)? {
+        roots.add(ca)?;
+    }
+    let verifier = WebPkiClientVerifier::builder(Arc::new(roots)).build()?;
+
+    let config = ServerConfig::builder()
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(certs, key)?;
+    Ok(Arc::new(config))
+}
+
+/// Read every certificate from a PEM file
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, MtlsError> {
+    let mut reader = std::io::BufReader::new(std::fs::File::open(path)?);
+    let certs =
+        rustls_pemfile::certs(&mut reader).collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(MtlsError::NoCertificates(path.to_path_buf()));
+    }
+    Ok(certs)
+}
+
+/// Accept TLS connections on `listener` and forward them to `upstream`
+///
+/// Runs until the listener fails. Connections without a valid client
+/// certificate are dropped during the handshake.
+pub async fn serve(
+    listener: TcpListener,
+    config: Arc<ServerConfig>,
+    upstream: SocketAddr,
+    peers: PeerTable,
+) -> std::io::Result<()> {
+    let acceptor = TlsAcceptor::from(config);
+
+    loop {
+        let (stream, peer_addr) = listener.accept().await?;
+        let acceptor = acceptor.clone();
+        let peers = peers.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) =
+                forward(acceptor, stream, peer_addr, upstream, peers).await
+            {
+                eprintln!("TLS connection from {} failed: {}", peer_addr, e);
+            }
+        });
+    }
+}
+
+/// Complete the handshake for one connection and relay it upstream
+async fn forward(
+    acceptor: TlsAcceptor,
+    stream: TcpStream,
+    peer_addr: SocketAddr,
+    upstream: SocketAddr,
+    peers: PeerTable,
+) -> Result<(), MtlsError> {
+    let mut tls = acceptor.accept(stream).await?;
+
+    // The verifier only lets connections with a client certificate through
+    let der = tls
+        .get_ref()
+        .1
+        .peer_certificates()
+        .and_then(|certs| certs.first())
+        .ok_or_else(|| {
+            MtlsError::Certificate("no client certificate".to_string())
+        })?;
+    let identity = PeerIdentity::from_certificate(peer_addr, der)?;
+
+    let mut upstream_conn = TcpStream::connect(upstream).await?;
+    let forwarded_addr = upstream_conn.local_addr()?;
+
+    // Register the identity before any request bytes reach Dropshot
+    peers.insert(forwarded_addr, identity);
+    let result =
+        tokio::io::copy_bidirectional(&mut tls, &mut upstream_conn).await;
+    peers.remove(forwarded_addr);
+
+    result?;
+    Ok(())
+}
+
+/// Mutual TLS errors
+#[derive(Debug, thiserror::Error)]
+pub enum MtlsError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("No certificates found in {0:?}")]
+    NoCertificates(PathBuf),
+
+    #[error("No private key found in {0:?}")]
+    NoPrivateKey(PathBuf),
+
+    #[error("TLS error: {0}")]
+    Tls(#[from] rustls::Error),
+
+    #[error("Invalid client CA: {0}")]
+    ClientCa(#[from] rustls::server::VerifierBuilderError),
+
+    #[error("Invalid client certificate: {0}")]
+    Certificate(String),
+}
@@ -0,0 +1,120 @@
+// Copyright 2025 Oxide Computer Company
+
+//! OpenAPI document
+//!
+//! Dropshot derives an OpenAPI document from the endpoint definitions and
+//! the `JsonSchema` implementations of the `crs-common` types. The server
+//! serves it at `GET /api/openapi.json`, and a copy is committed to the
+//! repository as `crs-server/openapi.json` so that changes to the API show
+//! up in review.
+//!
+//! A test compares the committed copy with the document the code produces
+//! and fails when they differ. After changing the API, regenerate the copy
+//! with:
+//!
+//! ```bash
+//! CRS_UPDATE_OPENAPI=1 cargo test -p crs-server openapi
+//! ```
+//!
+//! The `crs-api-client` crate's tests check that it implements every
+//! operation in the committed copy, so a new endpoint also needs a method
+//! there.
+//!
+//! The HTML pages are left out of the document, since they are not part of
+//! the API.
+
+// Suppress warnings for Dropshot's macro-generated phantom types
+#![allow(dead_code)]
+
+use crate::api::{self, require_peer, ApiContext};
+use dropshot::{endpoint, HttpError, HttpResponseOk, RequestContext};
+
+/// Title of the OpenAPI document
+const TITLE: &str = "Central Registry Service";
+
+/// Build the OpenAPI document for every published endpoint
+///
+/// The document's version is the server's version.
+pub fn openapi_document() -> serde_json::Value {
+    let version = env!("CARGO_PKG_VERSION")
+        .parse()
+        .expect("package version is valid semver");
+    api::api_description()
+        .openapi(TITLE, version)
+        .description(
+            "Clients register with the Central Registry Service and send \
+             periodic heartbeats, and the service tracks which of them are \
+             online.",
+        )
+        .license("MPL-2.0", "https://mozilla.org/MPL/2.0/")
+        .json()
+        .expect("OpenAPI document serializes to JSON")
+}
+
+/// Render the OpenAPI document the way the committed copy is written
+pub fn openapi_text() -> String {
+    let mut text = serde_json::to_string_pretty(&openapi_document())
+        .expect("OpenAPI document serializes to JSON");
+    text.push('\n');
+    text
+}
+
+/// Get the OpenAPI document describing this API
+#[endpoint {
+    method = GET,
+    path = "/api/openapi.json",
+}]
+pub async fn get_openapi(
+    ctx: RequestContext<ApiContext>,
+) -> Result<HttpResponseOk<serde_json::Value>, HttpError> {
+    require_peer(&ctx)?;
+    Ok(HttpResponseOk(openapi_document()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    /// Fail if the committed OpenAPI document is out of date
+    ///
+    /// With `CRS_UPDATE_OPENAPI` set, rewrite it instead.
+    #[test]
+    fn test_openapi_up_to_date() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("openapi.json");
+        let generated = openapi_text();
+
+        if std::env::var_os("CRS_UPDATE_OPENAPI").is_some() {
+            std::fs::write(&path, &generated).unwrap();
+            return;
+        }
+
+        let committed = std::fs::read_to_string(&path).unwrap_or_default();
+        assert!(
+            committed == generated,
+            "{} is out of date with the API. Regenerate it with \
+             `CRS_UPDATE_OPENAPI=1 cargo test -p crs-server openapi` and \
+             commit the result.",
+            path.display()
+        );
+    }
+
+    #[test]
+    fn test_openapi_document() {
+        let document = openapi_document();
+        let paths = document["paths"].as_object().unwrap();
+        for path in [
+            "/api/register",
+            "/api/heartbeat",
+            "/api/clients",
+            "/api/server",
+            "/api/openapi.json",
+        ] {
+            assert!(paths.contains_key(path), "missing {}", path);
+        }
+
+        // HTML pages are not part of the API
+        assert!(!paths.contains_key("/"));
+        assert!(!paths.contains_key("/clients/{client_id}"));
+    }
+}
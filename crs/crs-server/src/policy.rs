@@ -0,0 +1,476 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Heartbeat policies
+//!
+//! A [`HeartbeatPolicy`] sets how often a client is asked to heartbeat and
+//! the rules for its status when heartbeats stop arriving: how long the
+//! server waits before considering it stale, and then offline. Most clients
+//! use the default policy, but [`HeartbeatPolicies`] can hold rules that
+//! give matching clients their own policy, for example a long interval for
+//! laptops and a short one for core infrastructure. Rules match on a tag
+//! selector (`role=laptop`), a hostname glob (`core-*`), or both, and the
+//! first matching rule wins.
+
+use crs_common::{ClientInfo, ClientStatus, Selector, SelectorError};
+use globset::{GlobBuilder, GlobMatcher};
+use serde::Deserialize;
+use std::time::Duration;
+
+/// Heartbeat timing handed out to clients and used to judge their status
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeartbeatPolicy {
+    /// How often clients are asked to send a heartbeat
+    pub interval: Duration,
+
+    /// How long after its last heartbeat a client is considered stale
+    pub stale_threshold: Duration,
+
+    /// How long after its last heartbeat a client is considered offline
+    pub offline_threshold: Duration,
+}
+
+impl HeartbeatPolicy {
+    /// Policy with the given interval, a stale threshold of 1.2x the
+    /// interval and an offline threshold of 1.5x the interval
+    pub fn with_interval(interval: Duration) -> Self {
+        Self {
+            interval,
+            stale_threshold: interval * 6 / 5,
+            offline_threshold: interval * 3 / 2,
+        }
+    }
+
+    /// Policy with the given interval and any thresholds that were set
+    ///
+    /// Thresholds that were not set follow the interval as in
+    /// [`HeartbeatPolicy::with_interval`], except that the stale threshold
+    /// never defaults to more than the offline threshold.
+    pub fn with_thresholds(
+        interval: Duration,
+        stale_threshold: Option<Duration>,
+        offline_threshold: Option<Duration>,
+    ) -> Self {
+        let mut policy = Self::with_interval(interval);
+        if let Some(offline_threshold) = offline_threshold {
+            policy.offline_threshold = offline_threshold;
+        }
+        policy.stale_threshold = stale_threshold
+            .unwrap_or(policy.stale_threshold.min(policy.offline_threshold));
+        policy
+    }
+
+    /// Check that the policy can actually be followed
+    ///
+    /// The interval must be non-zero and both thresholds longer than the
+    /// interval, or clients would flap between online and stale or offline.
+    /// A stale threshold equal to the offline threshold means clients go
+    /// straight from online to offline.
+    pub fn validate(&self) -> Result<(), PolicyError> {
+        if self.interval.is_zero() {
+            return Err(PolicyError::ZeroInterval);
+        }
+        if self.offline_threshold <= self.interval {
+            return Err(PolicyError::ThresholdTooShort {
+                interval_secs: self.interval.as_secs(),
+                offline_threshold_secs: self.offline_threshold.as_secs(),
+            });
+        }
+        if self.stale_threshold <= self.interval
+            || self.stale_threshold > self.offline_threshold
+        {
+            return Err(PolicyError::StaleThresholdOutOfRange {
+                interval_secs: self.interval.as_secs(),
+                stale_threshold_secs: self.stale_threshold.as_secs(),
+                offline_threshold_secs: self.offline_threshold.as_secs(),
+            });
+        }
+        Ok(())
+    }
+
+    /// The status of a client whose last heartbeat was `elapsed` ago, if it
+    /// has fallen behind
+    ///
+    /// Returns [`ClientStatus::Offline`] past the offline threshold,
+    /// [`ClientStatus::Stale`] past the stale threshold, and `None` while
+    /// the client is heartbeating on time.
+    pub fn overdue_status(&self, elapsed: Duration) -> Option<ClientStatus> {
+        if elapsed >= self.offline_threshold {
+            Some(ClientStatus::Offline)
+        } else if elapsed >= self.stale_threshold {
+            Some(ClientStatus::Stale)
+        } else {
+            None
+        }
+    }
+
+    /// How long a client that stops heartbeating stays stale before it goes
+    /// offline, or `None` if it goes straight to offline
+    pub fn stale_window(&self) -> Option<Duration> {
+        let window =
+            self.offline_threshold.saturating_sub(self.stale_threshold);
+        (!window.is_zero()).then_some(window)
+    }
+}
+
+impl Default for HeartbeatPolicy {
+    /// Heartbeat every 10 seconds, stale after 12 seconds and offline after
+    /// 15 seconds
+    fn default() -> Self {
+        Self::with_interval(Duration::from_secs(10))
+    }
+}
+
+/// A heartbeat policy rule as written in the server config file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HeartbeatRuleSpec {
+    /// Selector the client's tags must match, such as `role=laptop`
+    pub tag: Option<String>,
+
+    /// Glob the client's hostname must match, ignoring case
+    pub hostname: Option<String>,
+
+    /// Seconds between heartbeats for matching clients
+    pub interval_secs: u64,
+
+    /// Seconds without a heartbeat before a matching client is considered
+    /// stale (defaults to 1.2x `interval_secs`, but no more than the offline
+    /// threshold)
+    pub stale_threshold_secs: Option<u64>,
+
+    /// Seconds without a heartbeat before a matching client is considered
+    /// offline (defaults to 1.5x `interval_secs`)
+    pub offline_threshold_secs: Option<u64>,
+}
+
+/// A rule giving matching clients their own heartbeat policy
+#[derive(Debug, Clone)]
+struct HeartbeatRule {
+    /// Selector the client's tags must match
+    tag: Option<Selector>,
+
+    /// Required hostname pattern
+    hostname: Option<GlobMatcher>,
+
+    policy: HeartbeatPolicy,
+}
+
+impl HeartbeatRule {
+    fn from_spec(spec: &HeartbeatRuleSpec) -> Result<Self, PolicyError> {
+        if spec.tag.is_none() && spec.hostname.is_none() {
+            return Err(PolicyError::NoMatcher);
+        }
+
+        let tag = match &spec.tag {
+            Some(tag) => Some(
+                tag.parse()
+                    .map_err(|e| PolicyError::InvalidTag(tag.clone(), e))?,
+            ),
+            None => None,
+        };
+
+        let hostname = match &spec.hostname {
+            Some(pattern) => Some(
+                GlobBuilder::new(pattern)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|e| {
+                        PolicyError::InvalidHostname(pattern.clone(), e)
+                    })?
+                    .compile_matcher(),
+            ),
+            None => None,
+        };
+
+        let policy = HeartbeatPolicy::with_thresholds(
+            Duration::from_secs(spec.interval_secs),
+            spec.stale_threshold_secs.map(Duration::from_secs),
+            spec.offline_threshold_secs.map(Duration::from_secs),
+        );
+        policy.validate()?;
+
+        Ok(Self {
+            tag,
+            hostname,
+            policy,
+        })
+    }
+
+    fn matches(&self, info: &ClientInfo) -> bool {
+        let tag_matches = self
+            .tag
+            .as_ref()
+            .is_none_or(|selector| selector.matches(&info.tags));
+        let hostname_matches = self
+            .hostname
+            .as_ref()
+            .is_none_or(|glob| glob.is_match(&info.hostname));
+        tag_matches && hostname_matches
+    }
+}
+
+/// The default heartbeat policy plus any per-client rules
+#[derive(Debug, Clone, Default)]
+pub struct HeartbeatPolicies {
+    default: HeartbeatPolicy,
+    rules: Vec<HeartbeatRule>,
+}
+
+impl HeartbeatPolicies {
+    /// Build the policies from a default and a list of rules
+    ///
+    /// Rules are tried in order and the first match wins. Clients that match
+    /// no rule get `default`.
+    pub fn new(
+        default: HeartbeatPolicy,
+        rules: &[HeartbeatRuleSpec],
+    ) -> Result<Self, PolicyError> {
+        default.validate()?;
+        let rules = rules
+            .iter()
+            .map(HeartbeatRule::from_spec)
+            .collect::<Result<_, _>>()?;
+        Ok(Self { default, rules })
+    }
+
+    /// Policy for clients that match no rule
+    pub fn default_policy(&self) -> HeartbeatPolicy {
+        self.default
+    }
+
+    /// Policy that applies to a client
+    pub fn policy_for(&self, info: &ClientInfo) -> HeartbeatPolicy {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(info))
+            .map_or(self.default, |rule| rule.policy)
+    }
+
+    /// The shortest [`HeartbeatPolicy::stale_window`] of the default policy
+    /// and every rule
+    pub fn shortest_stale_window(&self) -> Option<Duration> {
+        std::iter::once(&self.default)
+            .chain(self.rules.iter().map(|rule| &rule.policy))
+            .filter_map(HeartbeatPolicy::stale_window)
+            .min()
+    }
+}
+
+/// Heartbeat policy errors
+#[derive(Debug, thiserror::Error)]
+pub enum PolicyError {
+    #[error("Heartbeat interval must be at least 1 second")]
+    ZeroInterval,
+
+    #[error(
+        "Offline threshold ({offline_threshold_secs}s) must be greater than \
+         the heartbeat interval ({interval_secs}s)"
+    )]
+    ThresholdTooShort {
+        interval_secs: u64,
+        offline_threshold_secs: u64,
+    },
+
+    #[error(
+        "Stale threshold ({stale_threshold_secs}s) must be greater than the \
+         heartbeat interval ({interval_secs}s) and no greater than the \
+         offline threshold ({offline_threshold_secs}s)"
+    )]
+    StaleThresholdOutOfRange {
+        interval_secs: u64,
+        stale_threshold_secs: u64,
+        offline_threshold_secs: u64,
+    },
+
+    #[error("Heartbeat policy rule needs a tag or hostname to match")]
+    NoMatcher,
+
+    #[error("Invalid tag selector {0:?}: {1}")]
+    InvalidTag(String, SelectorError),
+
+    #[error("Invalid hostname pattern {0:?}: {1}")]
+    InvalidHostname(String, globset::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn client_info(hostname: &str, tags: &[(&str, &str)]) -> ClientInfo {
+        ClientInfo {
+            hostname: hostname.to_string(),
+            os: "linux".to_string(),
+            ip_address: "192.168.1.100".to_string(),
+            version: "1.0.0".to_string(),
+            host_id: None,
+            tags: tags
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<HashMap<_, _>>(),
+        }
+    }
+
+    fn rule(
+        tag: Option<&str>,
+        hostname: Option<&str>,
+        interval_secs: u64,
+    ) -> HeartbeatRuleSpec {
+        HeartbeatRuleSpec {
+            tag: tag.map(str::to_string),
+            hostname: hostname.map(str::to_string),
+            interval_secs,
+            stale_threshold_secs: None,
+            offline_threshold_secs: None,
+        }
+    }
+
+    #[test]
+    fn test_first_matching_rule_wins() {
+        let policies = HeartbeatPolicies::new(
+            HeartbeatPolicy::default(),
+            &[
+                rule(Some("role=core"), None, 5),
+                rule(None, Some("laptop-*"), 60),
+                rule(Some("role=laptop"), None, 120),
+                rule(Some("role in (db,cache),!primary"), None, 30),
+            ],
+        )
+        .unwrap();
+
+        let core = client_info("core-1", &[("role", "core")]);
+        assert_eq!(policies.policy_for(&core).interval.as_secs(), 5);
+
+        // Hostname globs ignore case
+        let laptop = client_info("Laptop-7", &[("role", "laptop")]);
+        assert_eq!(policies.policy_for(&laptop).interval.as_secs(), 60);
+
+        let tagged = client_info("mbp", &[("role", "laptop")]);
+        let policy = policies.policy_for(&tagged);
+        assert_eq!(policy.interval.as_secs(), 120);
+        assert_eq!(policy.stale_threshold.as_secs(), 144);
+        assert_eq!(policy.offline_threshold.as_secs(), 180);
+
+        let replica = client_info("db-2", &[("role", "db")]);
+        assert_eq!(policies.policy_for(&replica).interval.as_secs(), 30);
+        let primary = client_info("db-1", &[("role", "db"), ("primary", "")]);
+        assert_eq!(policies.policy_for(&primary), HeartbeatPolicy::default());
+
+        let other = client_info("db-1", &[("role", "database")]);
+        assert_eq!(policies.policy_for(&other), HeartbeatPolicy::default());
+    }
+
+    #[test]
+    fn test_rule_with_tag_and_hostname_needs_both() {
+        let policies = HeartbeatPolicies::new(
+            HeartbeatPolicy::default(),
+            &[rule(Some("site=lab"), Some("*.example.com"), 30)],
+        )
+        .unwrap();
+
+        let both = client_info("a.example.com", &[("site", "lab")]);
+        assert_eq!(policies.policy_for(&both).interval.as_secs(), 30);
+
+        let tag_only = client_info("a.example.org", &[("site", "lab")]);
+        assert_eq!(policies.policy_for(&tag_only).interval.as_secs(), 10);
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        let invalid = [
+            rule(None, None, 10),
+            rule(Some("role="), None, 10),
+            rule(Some("role in laptop"), None, 10),
+            rule(None, Some("[unclosed"), 10),
+            rule(Some("role=x"), None, 0),
+            HeartbeatRuleSpec {
+                offline_threshold_secs: Some(5),
+                ..rule(Some("role=x"), None, 10)
+            },
+            HeartbeatRuleSpec {
+                stale_threshold_secs: Some(10),
+                ..rule(Some("role=x"), None, 10)
+            },
+            HeartbeatRuleSpec {
+                stale_threshold_secs: Some(20),
+                offline_threshold_secs: Some(15),
+                ..rule(Some("role=x"), None, 10)
+            },
+        ];
+        for spec in invalid {
+            assert!(HeartbeatPolicies::new(
+                HeartbeatPolicy::default(),
+                &[spec]
+            )
+            .is_err());
+        }
+    }
+
+    #[test]
+    fn test_thresholds() {
+        // The stale threshold follows the interval, but never passes the
+        // offline threshold
+        let policy = HeartbeatPolicy::with_thresholds(
+            Duration::from_secs(10),
+            None,
+            Some(Duration::from_secs(11)),
+        );
+        assert_eq!(policy.stale_threshold, Duration::from_secs(11));
+        assert!(policy.validate().is_ok());
+
+        let policy = HeartbeatPolicy::default();
+        assert_eq!(policy.stale_threshold, Duration::from_secs(12));
+        let status = |secs| policy.overdue_status(Duration::from_secs(secs));
+        assert_eq!(status(11), None);
+        assert_eq!(status(12), Some(ClientStatus::Stale));
+        assert_eq!(status(15), Some(ClientStatus::Offline));
+        assert_eq!(policy.stale_window(), Some(Duration::from_secs(3)));
+
+        // Equal thresholds skip stale altogether
+        let policy = HeartbeatPolicy::with_thresholds(
+            Duration::from_secs(10),
+            Some(Duration::from_secs(15)),
+            None,
+        );
+        assert!(policy.validate().is_ok());
+        assert_eq!(
+            policy.overdue_status(Duration::from_secs(15)),
+            Some(ClientStatus::Offline)
+        );
+        assert_eq!(policy.stale_window(), None);
+    }
+
+    #[test]
+    fn test_shortest_stale_window() {
+        let policies = HeartbeatPolicies::new(
+            HeartbeatPolicy::default(),
+            &[
+                rule(Some("role=laptop"), None, 120),
+                HeartbeatRuleSpec {
+                    stale_threshold_secs: Some(6),
+                    offline_threshold_secs: Some(8),
+                    ..rule(Some("role=core"), None, 5)
+                },
+                HeartbeatRuleSpec {
+                    stale_threshold_secs: Some(8),
+                    offline_threshold_secs: Some(8),
+                    ..rule(Some("role=edge"), None, 5)
+                },
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            policies.shortest_stale_window(),
+            Some(Duration::from_secs(2))
+        );
+
+        let straight_to_offline = HeartbeatPolicy::with_thresholds(
+            Duration::from_secs(10),
+            Some(Duration::from_secs(15)),
+            None,
+        );
+        let policies =
+            HeartbeatPolicies::new(straight_to_offline, &[]).unwrap();
+        assert_eq!(policies.shortest_stale_window(), None);
+    }
+}
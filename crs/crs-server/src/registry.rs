@@ -4,23 +4,53 @@
 //!
 //! This module provides the [`Registry`] type which manages all registered
 //! clients and their status. The registry is thread-safe and can be shared
-//! across multiple async tasks.
+//! across multiple async tasks. Clients are kept in a pluggable
+//! [`RegistryStore`], so registrations can optionally survive a server
+//! restart. Every client state change is also published on the registry's
+//! [`EventBus`], and registrations, status transitions, information changes
+//! and quarantines are recorded in each client's history.
+//!
+//! System metrics that clients attach to their heartbeats are kept too: the
+//! latest sample is stored with the client, and the last
+//! [`METRICS_SAMPLES`] samples are held in memory.
+
+use crate::api::tokens_match;
+use crate::availability;
+use crate::events::EventBus;
+use crate::maintenance::{self, ActiveWindows};
+use crate::metrics::Metrics;
+use crate::policy::{HeartbeatPolicies, HeartbeatPolicy};
+use crate::store::{
+    MemoryStore, RegistryStore, Revision, RevisionKey, StoreError,
+};
+use chrono::DateTime;
+use chrono::Utc;
+use crs_common::{
+    AvailabilityStats, AvailabilityWindow, Ban, BanKind, CheckResult,
+    ClientEventKind, ClientId, ClientInfo, ClientStatus, HistoryChange,
+    HistoryEntry, Maintenance, MaintenanceWindow, MaintenanceWindowRequest,
+    MetricsSample, Quarantine, RegisteredClient, RegistryState, RemovedClient,
+    ReplicatedClient, SystemMetrics,
+};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
 
-use chrono::{Duration, Utc};
-use crs_common::{ClientId, ClientInfo, ClientStatus, RegisteredClient};
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+/// How many recent metrics samples are kept per client
+pub const METRICS_SAMPLES: usize = 60;
 
-/// Thresholds for client status transitions (in seconds)
-/// Heartbeat interval is 10 seconds
-/// Offline: 1.5x heartbeat interval (15 seconds)
-const HEARTBEAT_INTERVAL_SECS: i64 = 10;
-const OFFLINE_THRESHOLD_SECS: i64 = (HEARTBEAT_INTERVAL_SECS * 3) / 2;
+/// How long a client's removal is remembered and replicated to other
+/// cluster nodes
+///
+/// A node that has been out of touch with the rest of the cluster for
+/// longer than this may bring a removed client back when it rejoins.
+pub const REMOVAL_RETENTION: chrono::Duration = chrono::Duration::days(7);
 
 /// Registry for tracking connected clients
 ///
-/// The registry maintains an in-memory map of all registered clients and
-/// their current status. It is thread-safe and can be cloned cheaply (uses
+/// The registry maintains all registered clients and their current status
+/// in a [`RegistryStore`]. It is thread-safe and can be cloned cheaply (uses
 /// `Arc` internally).
 ///
 /// # Example
@@ -38,102 +68,1195 @@ const OFFLINE_THRESHOLD_SECS: i64 = (HEARTBEAT_INTERVAL_SECS * 3) / 2;
 /// #     host_id: None,
 /// #     tags: Default::default(),
 /// # };
-/// let client_id = registry.register(client_info);
+/// let registration = registry.register(client_info).unwrap();
+/// registry
+///     .heartbeat(
+///         registration.client_id,
+///         &registration.client_secret,
+///         None,
+///         Vec::new(),
+///     )
+///     .unwrap();
 /// ```
 #[derive(Clone)]
 pub struct Registry {
-    clients: Arc<RwLock<HashMap<ClientId, RegisteredClient>>>,
+    store: Arc<dyn RegistryStore>,
+    policies: Arc<HeartbeatPolicies>,
+    events: EventBus,
+    metrics: Metrics,
+    /// Recent metrics samples per client, oldest first
+    samples: Arc<Mutex<HashMap<ClientId, VecDeque<MetricsSample>>>>,
+    /// Serializes read-modify-write updates to the store
+    update_lock: Arc<Mutex<()>>,
+    /// When replicated records last changed, for merging with other nodes
+    revisions: Arc<Mutex<Revisions>>,
+}
+
+/// When the records replicated to other cluster nodes last changed here
+///
+/// Revisions are kept in the registry's store, with a copy in memory.
+/// Status sweeps do not count as changes, since every node sweeps for
+/// itself, and heartbeats need not be stored as changes: a client has
+/// changed no earlier than its last heartbeat (or registration, if that was
+/// later). Removals are remembered for [`REMOVAL_RETENTION`].
+struct Revisions {
+    store: Arc<dyn RegistryStore>,
+    known: HashMap<RevisionKey, DateTime<Utc>>,
+}
+
+impl Revisions {
+    /// Load the revisions kept in `store`
+    fn load(store: Arc<dyn RegistryStore>) -> Result<Self, StoreError> {
+        let known = store
+            .list_revisions()?
+            .into_iter()
+            .map(|revision| (revision.key, revision.updated_at))
+            .collect();
+        Ok(Self { store, known })
+    }
+
+    /// When a record last changed, if that is known
+    fn get(&self, key: RevisionKey) -> Option<DateTime<Utc>> {
+        self.known.get(&key).copied()
+    }
+
+    /// When `client` last changed
+    fn client(&self, client: &RegisteredClient) -> DateTime<Utc> {
+        let heartbeat = client.last_heartbeat.max(client.registered_at);
+        self.get(RevisionKey::Client(client.client_id))
+            .map_or(heartbeat, |at| at.max(heartbeat))
+    }
+
+    /// Note that a record changed at `at`
+    fn set(
+        &mut self,
+        key: RevisionKey,
+        at: DateTime<Utc>,
+    ) -> Result<(), StoreError> {
+        self.store.put_revision(&Revision {
+            key,
+            updated_at: at,
+        })?;
+        self.known.insert(key, at);
+        Ok(())
+    }
+
+    /// Forget when a record last changed
+    fn forget(&mut self, key: RevisionKey) -> Result<(), StoreError> {
+        if self.known.contains_key(&key) {
+            self.store.remove_revision(key)?;
+            self.known.remove(&key);
+        }
+        Ok(())
+    }
+
+    /// Note that `client`, as it now stands, changed at `at`
+    ///
+    /// Nothing needs storing for a change no later than the client's last
+    /// heartbeat or registration.
+    fn touch(
+        &mut self,
+        client: &RegisteredClient,
+        at: DateTime<Utc>,
+    ) -> Result<(), StoreError> {
+        let key = RevisionKey::Client(client.client_id);
+        if at > client.last_heartbeat.max(client.registered_at) {
+            self.set(key, at)?;
+        } else {
+            self.forget(key)?;
+        }
+        self.forget(RevisionKey::Removed(client.client_id))
+    }
+
+    /// Note that a client was removed at `at`
+    fn remove(
+        &mut self,
+        client_id: ClientId,
+        at: DateTime<Utc>,
+    ) -> Result<(), StoreError> {
+        self.forget(RevisionKey::Client(client_id))?;
+        self.set(RevisionKey::Removed(client_id), at)
+    }
+
+    /// Removals that are still remembered
+    fn removals(&self) -> Vec<RemovedClient> {
+        self.known
+            .iter()
+            .filter_map(|(key, at)| match key {
+                RevisionKey::Removed(client_id) => Some(RemovedClient {
+                    client_id: *client_id,
+                    removed_at: *at,
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Forget removals that are more than [`REMOVAL_RETENTION`] old
+    fn expire(&mut self, now: DateTime<Utc>) -> Result<(), StoreError> {
+        for removed in self.removals() {
+            if removed.removed_at < now - REMOVAL_RETENTION {
+                self.forget(RevisionKey::Removed(removed.client_id))?;
+            }
+        }
+        Ok(())
+    }
 }
 
 impl Registry {
-    /// Create a new empty registry
+    /// Create a new empty in-memory registry
     pub fn new() -> Self {
+        let store: Arc<dyn RegistryStore> = Arc::new(MemoryStore::new());
+        let revisions = Revisions {
+            store: store.clone(),
+            known: HashMap::new(),
+        };
         Self {
-            clients: Arc::new(RwLock::new(HashMap::new())),
+            store,
+            policies: Arc::default(),
+            events: EventBus::default(),
+            metrics: Metrics::new(),
+            samples: Arc::default(),
+            update_lock: Arc::new(Mutex::new(())),
+            revisions: Arc::new(Mutex::new(revisions)),
         }
     }
 
+    /// Create a registry on top of an existing store
+    ///
+    /// Any clients already in the store are marked offline, since they have
+    /// not heartbeated to this server instance yet. They return to online
+    /// (or degraded) with their next heartbeat or registration. Clients that
+    /// departed cleanly or are in maintenance keep their status.
+    pub fn with_store(
+        store: Arc<dyn RegistryStore>,
+    ) -> Result<Self, RegistryError> {
+        let now = Utc::now();
+        let mut restored = store.list()?;
+        restored
+            .retain(|c| c.status.is_alive() || c.status == ClientStatus::Stale);
+        if !restored.is_empty() {
+            let history: Vec<_> = restored
+                .iter()
+                .map(|client| {
+                    let change = HistoryChange::StatusChanged {
+                        from: client.status,
+                        to: ClientStatus::Offline,
+                        expected: false,
+                    };
+                    (
+                        client.client_id,
+                        HistoryEntry {
+                            timestamp: now,
+                            change,
+                        },
+                    )
+                })
+                .collect();
+            for client in &mut restored {
+                client.status = ClientStatus::Offline;
+            }
+            store.put_many(&restored)?;
+            store.append_history(&history)?;
+        }
+
+        let revisions = Revisions::load(store.clone())?;
+        Ok(Self {
+            store,
+            policies: Arc::default(),
+            events: EventBus::default(),
+            metrics: Metrics::new(),
+            samples: Arc::default(),
+            update_lock: Arc::new(Mutex::new(())),
+            revisions: Arc::new(Mutex::new(revisions)),
+        })
+    }
+
+    /// Use different heartbeat policies than the default
+    pub fn with_heartbeat_policies(
+        mut self,
+        policies: HeartbeatPolicies,
+    ) -> Self {
+        self.policies = Arc::new(policies);
+        self
+    }
+
+    /// The bus client events are published on
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
+
+    /// The server's Prometheus metrics
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// The heartbeat policy for clients that match no rule
+    pub fn default_heartbeat_policy(&self) -> HeartbeatPolicy {
+        self.policies.default_policy()
+    }
+
+    /// The heartbeat policy a client is held to
+    pub fn heartbeat_policy(&self, info: &ClientInfo) -> HeartbeatPolicy {
+        self.policies.policy_for(info)
+    }
+
     /// Register a new client or update existing client
     ///
     /// If the client is already registered (based on deterministic client ID),
     /// this updates the client information but preserves the original
-    /// first_connected timestamp. The client is marked as online and the
-    /// last heartbeat time is updated to now.
-    pub fn register(&self, info: ClientInfo) -> ClientId {
+    /// first_connected timestamp and any quarantine or maintenance. The
+    /// client is marked as online, or stays degraded until a heartbeat
+    /// reports passing health checks, unless it is in maintenance, and the
+    /// last heartbeat time is updated to now. A new client
+    /// secret is issued, replacing any previous one, and the heartbeat
+    /// policy matching the client is returned with it. Returns an error if
+    /// the client ID or hostname is banned.
+    ///
+    /// Publishes [`ClientEventKind::Registered`] if the client was new,
+    /// offline or departed, [`ClientEventKind::CaughtUp`] if it was stale,
+    /// and [`ClientEventKind::InfoChanged`] if a known
+    /// client's information changed. Every registration is recorded in the
+    /// client's history, along with any status or information change.
+    pub fn register(
+        &self,
+        info: ClientInfo,
+    ) -> Result<Registration, RegistryError> {
         let client_id = info.client_id();
         let now = Utc::now();
 
-        let mut clients = self.clients.write().unwrap();
+        let _guard = self.update_lock.lock().unwrap();
 
-        let (first_connected, registered_at) = if let Some(existing) =
-            clients.get(&client_id)
-        {
-            // Preserve first_connected, update registered_at to now for reconnection
-            (existing.first_connected, now)
-        } else {
-            // New client - both timestamps are now
-            (now, now)
+        self.check_not_banned(client_id, &info.hostname)?;
+
+        let existing = self.store.get(client_id)?;
+        let (first_connected, registered_at, quarantine, maintenance) =
+            if let Some(existing) = &existing {
+                // Preserve first_connected, update registered_at to now for reconnection
+                (
+                    existing.first_connected,
+                    now,
+                    existing.quarantine.clone(),
+                    existing.maintenance.clone(),
+                )
+            } else {
+                // New client - both timestamps are now
+                (now, now, None, None)
+            };
+        let mut events = Vec::new();
+        let mut history = vec![HistoryChange::Registered {
+            first: existing.is_none(),
+        }];
+        // A degraded client stays degraded until its checks pass, and a
+        // client in maintenance stays there until an operator ends it
+        let (status, checks) = match &existing {
+            Some(existing) if existing.status == ClientStatus::Maintenance => {
+                (ClientStatus::Maintenance, existing.checks.clone())
+            }
+            Some(existing)
+                if existing.status.is_alive()
+                    || existing.status == ClientStatus::Stale =>
+            {
+                (existing.alive_status(), existing.checks.clone())
+            }
+            _ => (ClientStatus::Online, Vec::new()),
         };
+        match &existing {
+            Some(existing) => {
+                match existing.status {
+                    ClientStatus::Offline | ClientStatus::Departed => {
+                        events.push(ClientEventKind::Registered);
+                    }
+                    ClientStatus::Stale => {
+                        events.push(ClientEventKind::CaughtUp);
+                    }
+                    _ => {}
+                }
+                if existing.status != status {
+                    history.push(HistoryChange::StatusChanged {
+                        from: existing.status,
+                        to: status,
+                        expected: existing.maintenance_window.is_some(),
+                    });
+                }
+                if existing.info != info {
+                    events.push(ClientEventKind::InfoChanged);
+                    history.push(HistoryChange::InfoChanged {
+                        previous: Box::new(existing.info.clone()),
+                        current: Box::new(info.clone()),
+                    });
+                }
+            }
+            None => events.push(ClientEventKind::Registered),
+        }
 
+        let heartbeat_policy = self.policies.policy_for(&info);
         let registered_client = RegisteredClient {
             client_id,
             info,
-            status: ClientStatus::Online,
+            status,
             first_connected,
             registered_at,
             last_heartbeat: now,
+            quarantine,
+            maintenance,
+            maintenance_window: existing
+                .as_ref()
+                .and_then(|c| c.maintenance_window),
+            metrics: existing.as_ref().and_then(|c| c.metrics.clone()),
+            checks,
+            availability: Vec::new(),
         };
 
-        clients.insert(client_id, registered_client);
-        client_id
+        let client_secret = generate_secret();
+        let history: Vec<_> = history
+            .into_iter()
+            .map(|change| HistoryEntry {
+                timestamp: now,
+                change,
+            })
+            .collect();
+        self.store.put_registration(
+            &registered_client,
+            &hash_secret(&client_secret),
+            &history,
+        )?;
+        self.revisions
+            .lock()
+            .unwrap()
+            .touch(&registered_client, now)?;
+        let first = existing.is_none();
+        for kind in events {
+            if kind == ClientEventKind::Registered {
+                self.events.publish_registration(&registered_client, first);
+            } else {
+                self.events.publish(kind, &registered_client);
+            }
+        }
+        Ok(Registration {
+            client_id,
+            client_secret,
+            heartbeat_policy,
+            first,
+        })
     }
 
     /// Record a heartbeat from a client
     ///
-    /// Updates the last heartbeat timestamp and marks the client as online.
-    /// Returns an error if the client is not registered.
-    pub fn heartbeat(&self, client_id: ClientId) -> Result<(), RegistryError> {
-        let mut clients = self.clients.write().unwrap();
+    /// Updates the last heartbeat timestamp and marks the client as online,
+    /// or as degraded if any of its health checks failed. A client coming
+    /// back from offline or departed publishes
+    /// [`ClientEventKind::HeartbeatResumed`], and one that was stale
+    /// [`ClientEventKind::CaughtUp`]. Becoming degraded publishes
+    /// [`ClientEventKind::Degraded`], and going from degraded to online
+    /// [`ClientEventKind::Recovered`]. A client in maintenance keeps that
+    /// status, though its checks and heartbeat time are still updated. Any
+    /// system metrics
+    /// become the client's latest sample and are added to its recent
+    /// samples. Returns [`RegistryError::InvalidSecret`] if the client is
+    /// not registered or presents the wrong secret, and an error if it has
+    /// been banned since it registered.
+    pub fn heartbeat(
+        &self,
+        client_id: ClientId,
+        client_secret: &str,
+        metrics: Option<SystemMetrics>,
+        checks: Vec<CheckResult>,
+    ) -> Result<(), RegistryError> {
+        let _guard = self.update_lock.lock().unwrap();
 
-        let client = clients
-            .get_mut(&client_id)
-            .ok_or(RegistryError::ClientNotFound(client_id))?;
+        let mut client = self.authenticate(client_id, client_secret)?;
+        self.check_not_banned(client_id, &client.info.hostname)?;
 
+        let previous_status = client.status;
+        // Whether the checks were failing when the client was last heard from
+        let was_degraded = matches!(
+            previous_status,
+            ClientStatus::Degraded | ClientStatus::Stale
+        ) && client.alive_status() == ClientStatus::Degraded;
+        let checks_changed = client.checks != checks;
         client.last_heartbeat = Utc::now();
-        client.status = ClientStatus::Online;
+        client.checks = checks;
+        if previous_status != ClientStatus::Maintenance {
+            client.status = client.alive_status();
+        }
+        if let Some(metrics) = metrics {
+            let sample = MetricsSample {
+                timestamp: client.last_heartbeat,
+                metrics,
+            };
+            client.metrics = Some(sample.clone());
+            let mut samples = self.samples.lock().unwrap();
+            let client_samples = samples.entry(client_id).or_default();
+            if client_samples.len() == METRICS_SAMPLES {
+                client_samples.pop_front();
+            }
+            client_samples.push_back(sample);
+        }
+
+        // Most heartbeats change nothing but the heartbeat time and metrics
+        if checks_changed || client.status != previous_status {
+            self.store.put(&client)?;
+        } else {
+            self.store.put_heartbeat(&client)?;
+        }
+        if client.status != previous_status {
+            self.record_history(
+                client_id,
+                client.last_heartbeat,
+                [HistoryChange::StatusChanged {
+                    from: previous_status,
+                    to: client.status,
+                    expected: client.maintenance_window.is_some(),
+                }],
+            )?;
+        }
+        match previous_status {
+            ClientStatus::Offline | ClientStatus::Departed => {
+                self.events
+                    .publish(ClientEventKind::HeartbeatResumed, &client);
+            }
+            ClientStatus::Stale => {
+                self.events.publish(ClientEventKind::CaughtUp, &client);
+            }
+            _ => {}
+        }
+        match client.status {
+            ClientStatus::Online if was_degraded => {
+                self.events.publish(ClientEventKind::Recovered, &client);
+            }
+            ClientStatus::Degraded if !was_degraded => {
+                self.events.publish(ClientEventKind::Degraded, &client);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Record that a client is shutting down cleanly
+    ///
+    /// The client stays in the registry but is marked as departed, and the
+    /// periodic status update leaves it alone. A later registration or
+    /// heartbeat brings it back online. A client in maintenance stays in
+    /// maintenance. Returns [`RegistryError::InvalidSecret`] if the client
+    /// is not registered or presents the wrong secret.
+    pub fn deregister(
+        &self,
+        client_id: ClientId,
+        client_secret: &str,
+    ) -> Result<(), RegistryError> {
+        let _guard = self.update_lock.lock().unwrap();
+
+        let mut client = self.authenticate(client_id, client_secret)?;
+
+        let previous_status = client.status;
+        if previous_status != ClientStatus::Maintenance {
+            client.status = ClientStatus::Departed;
+        }
+
+        let now = Utc::now();
+        self.store.put(&client)?;
+        self.revisions.lock().unwrap().touch(&client, now)?;
+        if previous_status != client.status {
+            self.record_history(
+                client_id,
+                now,
+                [HistoryChange::StatusChanged {
+                    from: previous_status,
+                    to: client.status,
+                    expected: client.maintenance_window.is_some(),
+                }],
+            )?;
+        }
+        self.events.publish(ClientEventKind::Deregistered, &client);
+        Ok(())
+    }
+
+    /// Remove a client from the registry entirely
+    ///
+    /// Unlike [`Registry::deregister`], the client no longer appears in the
+    /// client list and its history is discarded. If it is still running it
+    /// will be re-added the next time it registers. Returns an error if the
+    /// client is not registered.
+    pub fn remove_client(
+        &self,
+        client_id: ClientId,
+    ) -> Result<(), RegistryError> {
+        let _guard = self.update_lock.lock().unwrap();
+
+        let client = self
+            .store
+            .remove(client_id)?
+            .ok_or(RegistryError::ClientNotFound(client_id))?;
+        self.samples.lock().unwrap().remove(&client_id);
+        self.revisions
+            .lock()
+            .unwrap()
+            .remove(client_id, Utc::now())?;
+        self.events.publish(ClientEventKind::Removed, &client);
+        Ok(())
+    }
+
+    /// Quarantine a client
+    ///
+    /// The client stays registered and keeps heartbeating, but is flagged in
+    /// the client list until the quarantine is lifted. Quarantining an
+    /// already quarantined client replaces the reason and start time.
+    pub fn quarantine(
+        &self,
+        client_id: ClientId,
+        reason: Option<String>,
+    ) -> Result<(), RegistryError> {
+        self.set_quarantine(
+            client_id,
+            Some(Quarantine {
+                reason,
+                since: Utc::now(),
+            }),
+        )
+    }
+
+    /// Lift a client's quarantine
+    pub fn release_quarantine(
+        &self,
+        client_id: ClientId,
+    ) -> Result<(), RegistryError> {
+        self.set_quarantine(client_id, None)
+    }
+
+    fn set_quarantine(
+        &self,
+        client_id: ClientId,
+        quarantine: Option<Quarantine>,
+    ) -> Result<(), RegistryError> {
+        let _guard = self.update_lock.lock().unwrap();
+
+        let mut client = self
+            .store
+            .get(client_id)?
+            .ok_or(RegistryError::ClientNotFound(client_id))?;
+
+        let change = match (&client.quarantine, &quarantine) {
+            (_, Some(new)) => Some(HistoryChange::Quarantined {
+                reason: new.reason.clone(),
+            }),
+            (Some(_), None) => Some(HistoryChange::QuarantineLifted),
+            (None, None) => None,
+        };
+        client.quarantine = quarantine;
+
+        let now = Utc::now();
+        self.store.put(&client)?;
+        self.revisions.lock().unwrap().touch(&client, now)?;
+        self.record_history(client_id, now, change)?;
+        Ok(())
+    }
+
+    /// Put a client in maintenance
+    ///
+    /// The client's status stays maintenance until the maintenance is
+    /// ended, whatever its heartbeats and registrations say, so taking it
+    /// down for work does not count against its availability. Putting a
+    /// client already in maintenance there again replaces the reason and
+    /// start time. Publishes [`ClientEventKind::MaintenanceStarted`].
+    pub fn start_maintenance(
+        &self,
+        client_id: ClientId,
+        reason: Option<String>,
+    ) -> Result<(), RegistryError> {
+        let now = Utc::now();
+        let _guard = self.update_lock.lock().unwrap();
+
+        let mut client = self
+            .store
+            .get(client_id)?
+            .ok_or(RegistryError::ClientNotFound(client_id))?;
+
+        let previous_status = client.status;
+        client.status = ClientStatus::Maintenance;
+        client.maintenance = Some(Maintenance { reason, since: now });
 
+        self.store.put(&client)?;
+        self.revisions.lock().unwrap().touch(&client, now)?;
+        if previous_status != ClientStatus::Maintenance {
+            self.record_history(
+                client_id,
+                now,
+                [HistoryChange::StatusChanged {
+                    from: previous_status,
+                    to: ClientStatus::Maintenance,
+                    expected: client.maintenance_window.is_some(),
+                }],
+            )?;
+        }
+        self.events
+            .publish(ClientEventKind::MaintenanceStarted, &client);
         Ok(())
     }
 
+    /// End a client's maintenance
+    ///
+    /// The client goes back to whatever status its last heartbeat and health
+    /// checks warrant. Publishes [`ClientEventKind::MaintenanceEnded`],
+    /// followed by the event for the status the client goes back to, so a
+    /// client that died during maintenance is reported as having gone
+    /// offline.
+    /// Returns an error if the client is not registered or not in
+    /// maintenance.
+    pub fn end_maintenance(
+        &self,
+        client_id: ClientId,
+    ) -> Result<(), RegistryError> {
+        let now = Utc::now();
+        let _guard = self.update_lock.lock().unwrap();
+
+        let mut client = self
+            .store
+            .get(client_id)?
+            .ok_or(RegistryError::ClientNotFound(client_id))?;
+        if client.status != ClientStatus::Maintenance {
+            return Err(RegistryError::NotInMaintenance(client_id));
+        }
+
+        // Heartbeats stamped in the future count as just received
+        let elapsed =
+            (now - client.last_heartbeat).to_std().unwrap_or_default();
+        client.status = self
+            .policies
+            .policy_for(&client.info)
+            .overdue_status(elapsed)
+            .unwrap_or_else(|| client.alive_status());
+        client.maintenance = None;
+
+        self.store.put(&client)?;
+        self.revisions.lock().unwrap().touch(&client, now)?;
+        self.record_history(
+            client_id,
+            now,
+            [HistoryChange::StatusChanged {
+                from: ClientStatus::Maintenance,
+                to: client.status,
+                expected: client.maintenance_window.is_some(),
+            }],
+        )?;
+        self.events
+            .publish(ClientEventKind::MaintenanceEnded, &client);
+        self.events.publish(
+            status_change_event(ClientStatus::Maintenance, client.status),
+            &client,
+        );
+        Ok(())
+    }
+
+    /// Schedule a maintenance window
+    ///
+    /// The window takes effect straight away: client statuses are updated
+    /// so that clients it covers are known to be in it. Returns an error if
+    /// the request is invalid or names a client that is not registered.
+    pub fn schedule_maintenance_window(
+        &self,
+        request: MaintenanceWindowRequest,
+    ) -> Result<MaintenanceWindow, RegistryError> {
+        let window = maintenance::new_window(request, Utc::now())
+            .map_err(RegistryError::InvalidMaintenanceWindow)?;
+        if let Some(client_id) = window.client_id {
+            self.get_client(client_id)?;
+        }
+
+        self.store.put_maintenance_window(&window)?;
+        self.revisions
+            .lock()
+            .unwrap()
+            .set(RevisionKey::MaintenanceWindows, Utc::now())?;
+        self.update_statuses()?;
+        Ok(window)
+    }
+
+    /// Cancel a maintenance window
+    ///
+    /// Clients it covered stop being in it straight away. Returns an error
+    /// if no such window exists.
+    pub fn cancel_maintenance_window(
+        &self,
+        id: Uuid,
+    ) -> Result<(), RegistryError> {
+        if !self.store.remove_maintenance_window(id)? {
+            return Err(RegistryError::MaintenanceWindowNotFound(id));
+        }
+        self.revisions
+            .lock()
+            .unwrap()
+            .set(RevisionKey::MaintenanceWindows, Utc::now())?;
+        self.update_statuses()
+    }
+
+    /// Get all maintenance windows, in order of when they start
+    pub fn list_maintenance_windows(
+        &self,
+    ) -> Result<Vec<MaintenanceWindow>, RegistryError> {
+        let mut windows = self.store.list_maintenance_windows()?;
+        windows.sort_by_key(|window| (window.starts_at, window.id));
+        Ok(windows)
+    }
+
+    /// Ban a client ID or hostname
+    ///
+    /// Matching clients are refused when they next register or heartbeat.
+    /// Client IDs must be valid UUIDs; hostnames are matched
+    /// case-insensitively. Banning the same target again replaces the
+    /// existing ban.
+    pub fn ban(
+        &self,
+        kind: BanKind,
+        value: &str,
+        reason: Option<String>,
+    ) -> Result<Ban, RegistryError> {
+        let ban = Ban {
+            kind,
+            value: normalize_ban_value(kind, value)?,
+            reason,
+            created_at: Utc::now(),
+        };
+
+        self.store.put_ban(&ban)?;
+        self.revisions
+            .lock()
+            .unwrap()
+            .set(RevisionKey::Bans, ban.created_at)?;
+        Ok(ban)
+    }
+
+    /// Remove a ban
+    ///
+    /// Returns an error if no such ban exists.
+    pub fn unban(
+        &self,
+        kind: BanKind,
+        value: &str,
+    ) -> Result<(), RegistryError> {
+        let value = normalize_ban_value(kind, value)?;
+        if self.store.remove_ban(kind, &value)? {
+            self.revisions
+                .lock()
+                .unwrap()
+                .set(RevisionKey::Bans, Utc::now())?;
+            Ok(())
+        } else {
+            Err(RegistryError::BanNotFound(value))
+        }
+    }
+
+    /// Get all bans
+    pub fn list_bans(&self) -> Result<Vec<Ban>, RegistryError> {
+        Ok(self.store.list_bans()?)
+    }
+
+    /// Append changes to a client's history, all stamped with `timestamp`
+    fn record_history(
+        &self,
+        client_id: ClientId,
+        timestamp: DateTime<Utc>,
+        changes: impl IntoIterator<Item = HistoryChange>,
+    ) -> Result<(), StoreError> {
+        let entries: Vec<_> = changes
+            .into_iter()
+            .map(|change| (client_id, HistoryEntry { timestamp, change }))
+            .collect();
+        if entries.is_empty() {
+            return Ok(());
+        }
+        self.store.append_history(&entries)
+    }
+
+    /// Look up a client on behalf of the client itself
+    ///
+    /// Returns [`RegistryError::InvalidSecret`] both when `client_secret` is
+    /// not the client's current secret and when the client is not
+    /// registered, so that callers cannot probe which client IDs exist.
+    pub fn authenticate(
+        &self,
+        client_id: ClientId,
+        client_secret: &str,
+    ) -> Result<RegisteredClient, RegistryError> {
+        // Hash the secret whether or not the client exists, and compare in
+        // constant time, so timing does not tell the cases apart either
+        let presented = hash_secret(client_secret);
+        match self.store.get_secret_hash(client_id)? {
+            Some(hash) if tokens_match(&presented, &hash) => self
+                .store
+                .get(client_id)?
+                .ok_or(RegistryError::InvalidSecret(client_id)),
+            _ => Err(RegistryError::InvalidSecret(client_id)),
+        }
+    }
+
+    /// Return an error if any ban matches the client
+    fn check_not_banned(
+        &self,
+        client_id: ClientId,
+        hostname: &str,
+    ) -> Result<(), RegistryError> {
+        match self
+            .store
+            .list_bans()?
+            .into_iter()
+            .find(|ban| ban.matches(client_id, hostname))
+        {
+            Some(ban) => Err(RegistryError::Banned(ban)),
+            None => Ok(()),
+        }
+    }
+
+    /// Look up a single registered client
+    pub fn get_client(
+        &self,
+        client_id: ClientId,
+    ) -> Result<RegisteredClient, RegistryError> {
+        self.store
+            .get(client_id)?
+            .ok_or(RegistryError::ClientNotFound(client_id))
+    }
+
+    /// Get a client's history, oldest first
+    ///
+    /// Returns an error if the client is not registered.
+    pub fn client_history(
+        &self,
+        client_id: ClientId,
+    ) -> Result<Vec<HistoryEntry>, RegistryError> {
+        self.get_client(client_id)?;
+        Ok(self.store.history(client_id)?)
+    }
+
+    /// Get a client's recent metrics samples, oldest first
+    ///
+    /// Samples are only kept in memory, so there are none from before the
+    /// server started. Returns an error if the client is not registered.
+    pub fn metrics_samples(
+        &self,
+        client_id: ClientId,
+    ) -> Result<Vec<MetricsSample>, RegistryError> {
+        self.get_client(client_id)?;
+        Ok(self
+            .samples
+            .lock()
+            .unwrap()
+            .get(&client_id)
+            .map(|samples| samples.iter().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    /// Compute a client's availability over each of `windows`
+    ///
+    /// Clients without history, including unknown clients, have no
+    /// observed time.
+    pub fn availability(
+        &self,
+        client_id: ClientId,
+        windows: &[AvailabilityWindow],
+    ) -> Result<Vec<AvailabilityStats>, RegistryError> {
+        let history = self.store.history(client_id)?;
+        let now = Utc::now();
+        Ok(windows
+            .iter()
+            .map(|window| availability::compute(*window, &history, now))
+            .collect())
+    }
+
     /// Get all registered clients
-    pub fn list_clients(&self) -> Vec<RegisteredClient> {
-        let clients = self.clients.read().unwrap();
-        clients.values().cloned().collect()
+    pub fn list_clients(&self) -> Result<Vec<RegisteredClient>, RegistryError> {
+        Ok(self.store.list()?)
     }
 
     /// Update client statuses based on last heartbeat time
     ///
     /// Iterates through all registered clients and updates their status
     /// based on how long ago their last heartbeat was:
-    /// - Online: last heartbeat < 15 seconds ago (< 1.5x heartbeat interval)
-    /// - Offline: last heartbeat >= 15 seconds ago (>= 1.5x heartbeat interval)
+    /// - Online or degraded: last heartbeat within the client's stale
+    ///   threshold, degraded if its latest health checks failed
+    /// - Stale: last heartbeat at least the stale threshold ago, but within
+    ///   the offline threshold
+    /// - Offline: last heartbeat at least the offline threshold ago
+    ///
+    /// Each client's thresholds come from the heartbeat policy that matches
+    /// it. Going stale publishes [`ClientEventKind::WentStale`], and going
+    /// offline [`ClientEventKind::WentOffline`].
     ///
-    /// This is called periodically by a background task.
-    pub fn update_statuses(&self) {
+    /// Departed clients keep their status, since they are known not to be
+    /// sending heartbeats, and so do clients in maintenance, whose status
+    /// only an operator changes.
+    ///
+    /// The update also notes the maintenance window, if any, that covers
+    /// each client, and records status changes made while a client is
+    /// covered as expected. One-off windows that are over are removed, and
+    /// client removals older than [`REMOVAL_RETENTION`] are forgotten.
+    ///
+    /// This is called periodically by a background task. Only
+    /// clients whose status or maintenance window changed are written back
+    /// to the store, and a history entry is recorded and an event published
+    /// for each status change.
+    /// The client counts in the registry's [`Metrics`] are refreshed too.
+    pub fn update_statuses(&self) -> Result<(), RegistryError> {
         let now = Utc::now();
-        let mut clients = self.clients.write().unwrap();
+        let _guard = self.update_lock.lock().unwrap();
+        let mut clients = self.store.list()?;
+        let mut changed = Vec::new();
+        let mut history = Vec::new();
+        let mut events = Vec::new();
+
+        // One-off windows that are over will never apply again
+        let mut windows = self.store.list_maintenance_windows()?;
+        for window in &windows {
+            if maintenance::has_ended(window, now) {
+                self.store.remove_maintenance_window(window.id)?;
+            }
+        }
+        windows.retain(|window| !maintenance::has_ended(window, now));
+        let active = ActiveWindows::new(&windows, now);
+        self.revisions.lock().unwrap().expire(now)?;
 
-        for client in clients.values_mut() {
-            let elapsed = now - client.last_heartbeat;
+        for client in &mut clients {
+            let window = active.covering(client);
+            let window_changed = client.maintenance_window != window;
+            client.maintenance_window = window;
 
-            client.status = if elapsed
-                < Duration::try_seconds(OFFLINE_THRESHOLD_SECS).unwrap()
-            {
-                ClientStatus::Online
-            } else {
-                ClientStatus::Offline
+            // Heartbeats stamped in the future count as just received
+            let elapsed =
+                (now - client.last_heartbeat).to_std().unwrap_or_default();
+
+            let status = match client.status {
+                ClientStatus::Departed | ClientStatus::Maintenance => {
+                    client.status
+                }
+                _ => self
+                    .policies
+                    .policy_for(&client.info)
+                    .overdue_status(elapsed)
+                    .unwrap_or_else(|| client.alive_status()),
             };
+
+            if client.status != status {
+                let kind = status_change_event(client.status, status);
+                let change = HistoryChange::StatusChanged {
+                    from: client.status,
+                    to: status,
+                    expected: window.is_some(),
+                };
+                history.push((
+                    client.client_id,
+                    HistoryEntry {
+                        timestamp: now,
+                        change,
+                    },
+                ));
+                client.status = status;
+                changed.push(client.clone());
+                events.push((kind, client.clone()));
+            } else if window_changed {
+                changed.push(client.clone());
+            }
+        }
+        self.metrics.set_clients(&clients);
+
+        if !changed.is_empty() {
+            self.store.put_many(&changed)?;
+            self.store.append_history(&history)?;
+        }
+        for (kind, client) in &events {
+            self.events.publish(*kind, client);
+        }
+
+        Ok(())
+    }
+
+    /// Write out any changes the store is holding back
+    ///
+    /// Called when the server shuts down, so that heartbeats the store has
+    /// not written yet are not lost.
+    pub fn flush(&self) -> Result<(), RegistryError> {
+        let _guard = self.update_lock.lock().unwrap();
+        Ok(self.store.flush()?)
+    }
+
+    /// Everything this node replicates to other cluster nodes
+    pub fn replication_state(&self) -> Result<RegistryState, RegistryError> {
+        let _guard = self.update_lock.lock().unwrap();
+        let revisions = self.revisions.lock().unwrap();
+
+        let clients = self
+            .store
+            .list()?
+            .into_iter()
+            .map(|client| {
+                Ok(ReplicatedClient {
+                    secret_hash: self
+                        .store
+                        .get_secret_hash(client.client_id)?,
+                    updated_at: revisions.client(&client),
+                    client,
+                })
+            })
+            .collect::<Result<_, RegistryError>>()?;
+        let removed = revisions.removals();
+
+        Ok(RegistryState {
+            clients,
+            removed,
+            bans: self.store.list_bans()?,
+            bans_updated_at: revisions.get(RevisionKey::Bans),
+            maintenance_windows: self.store.list_maintenance_windows()?,
+            maintenance_windows_updated_at: revisions
+                .get(RevisionKey::MaintenanceWindows),
+        })
+    }
+
+    /// Merge registry state replicated from another cluster node
+    ///
+    /// Client records that changed more recently on the other node replace
+    /// this node's, along with their secrets, and removals more recent than
+    /// a client's last change remove it here too. Bans and maintenance
+    /// windows are replaced as a whole if they changed more recently there.
+    ///
+    /// Whether a client is online, degraded, stale or offline is left to
+    /// this node's own status update, which runs once the state is merged
+    /// and publishes events as usual. Other statuses, such as departed and
+    /// maintenance, are taken over as they are and only recorded in the
+    /// client's history.
+    pub fn merge_replicated_state(
+        &self,
+        state: RegistryState,
+    ) -> Result<(), RegistryError> {
+        let swept = |status: ClientStatus| {
+            status.is_alive()
+                || matches!(status, ClientStatus::Stale | ClientStatus::Offline)
+        };
+
+        {
+            let _guard = self.update_lock.lock().unwrap();
+            let mut revisions = self.revisions.lock().unwrap();
+            let now = Utc::now();
+
+            for removed in state.removed {
+                let client_id = removed.client_id;
+                let known = revisions.get(RevisionKey::Removed(client_id));
+                if known.is_some_and(|at| at >= removed.removed_at)
+                    || removed.removed_at < now - REMOVAL_RETENTION
+                {
+                    continue;
+                }
+                if let Some(client) = self.store.get(client_id)? {
+                    if revisions.client(&client) >= removed.removed_at {
+                        continue;
+                    }
+                    self.store.remove(client_id)?;
+                    self.samples.lock().unwrap().remove(&client_id);
+                }
+                revisions.remove(client_id, removed.removed_at)?;
+            }
+
+            let mut changed = Vec::new();
+            let mut history = Vec::new();
+            for replicated in state.clients {
+                let mut client = replicated.client;
+                let client_id = client.client_id;
+                let removed = revisions.get(RevisionKey::Removed(client_id));
+                if removed.is_some_and(|at| at >= replicated.updated_at) {
+                    continue;
+                }
+                let existing = self.store.get(client_id)?;
+                if existing.as_ref().is_some_and(|existing| {
+                    revisions.client(existing) >= replicated.updated_at
+                }) {
+                    continue;
+                }
+
+                // Which window covers the client is worked out here
+                client.maintenance_window =
+                    existing.as_ref().and_then(|e| e.maintenance_window);
+                let mut changes = Vec::new();
+                match &existing {
+                    None => {
+                        changes.push(HistoryChange::Registered { first: true });
+                        if client.status != ClientStatus::Online {
+                            changes.push(HistoryChange::StatusChanged {
+                                from: ClientStatus::Online,
+                                to: client.status,
+                                expected: false,
+                            });
+                        }
+                    }
+                    Some(existing)
+                        if swept(existing.status) && swept(client.status) =>
+                    {
+                        client.status = existing.status;
+                    }
+                    Some(existing) if existing.status != client.status => {
+                        changes.push(HistoryChange::StatusChanged {
+                            from: existing.status,
+                            to: client.status,
+                            expected: client.maintenance_window.is_some(),
+                        });
+                    }
+                    Some(_) => {}
+                }
+                history.extend(changes.into_iter().map(|change| {
+                    (
+                        client_id,
+                        HistoryEntry {
+                            timestamp: now,
+                            change,
+                        },
+                    )
+                }));
+
+                if let Some(secret_hash) = &replicated.secret_hash {
+                    self.store.put_secret_hash(client_id, secret_hash)?;
+                }
+                revisions.touch(&client, replicated.updated_at)?;
+                changed.push(client);
+            }
+            if !changed.is_empty() {
+                self.store.put_many(&changed)?;
+                self.store.append_history(&history)?;
+            }
+
+            if let Some(updated_at) = state
+                .bans_updated_at
+                .filter(|at| Some(*at) > revisions.get(RevisionKey::Bans))
+            {
+                for ban in self.store.list_bans()? {
+                    if !state
+                        .bans
+                        .iter()
+                        .any(|b| b.kind == ban.kind && b.value == ban.value)
+                    {
+                        self.store.remove_ban(ban.kind, &ban.value)?;
+                    }
+                }
+                for ban in &state.bans {
+                    self.store.put_ban(ban)?;
+                }
+                revisions.set(RevisionKey::Bans, updated_at)?;
+            }
+
+            if let Some(updated_at) =
+                state.maintenance_windows_updated_at.filter(|at| {
+                    Some(*at) > revisions.get(RevisionKey::MaintenanceWindows)
+                })
+            {
+                for window in self.store.list_maintenance_windows()? {
+                    if !state.maintenance_windows.contains(&window) {
+                        self.store.remove_maintenance_window(window.id)?;
+                    }
+                }
+                for window in &state.maintenance_windows {
+                    self.store.put_maintenance_window(window)?;
+                }
+                revisions.set(RevisionKey::MaintenanceWindows, updated_at)?;
+            }
         }
+
+        self.update_statuses()
     }
 
     /// Set a client's last heartbeat time (for testing)
@@ -143,9 +1266,10 @@ impl Registry {
         client_id: ClientId,
         timestamp: chrono::DateTime<chrono::Utc>,
     ) {
-        let mut clients = self.clients.write().unwrap();
-        if let Some(client) = clients.get_mut(&client_id) {
+        let _guard = self.update_lock.lock().unwrap();
+        if let Some(mut client) = self.store.get(client_id).unwrap() {
             client.last_heartbeat = timestamp;
+            self.store.put(&client).unwrap();
         }
     }
 }
@@ -156,17 +1280,119 @@ impl Default for Registry {
     }
 }
 
+/// Outcome of a successful registration
+#[derive(Debug, Clone)]
+pub struct Registration {
+    /// The client's deterministic ID
+    pub client_id: ClientId,
+
+    /// Newly issued secret the client must present with every heartbeat
+    pub client_secret: String,
+
+    /// Heartbeat policy that applies to the client
+    pub heartbeat_policy: HeartbeatPolicy,
+
+    /// Whether the client had never registered before
+    pub first: bool,
+}
+
+/// Event published when a client's status changes from `from` to `to`
+fn status_change_event(
+    from: ClientStatus,
+    to: ClientStatus,
+) -> ClientEventKind {
+    match (from, to) {
+        (_, ClientStatus::Stale) => ClientEventKind::WentStale,
+        (_, ClientStatus::Offline) => ClientEventKind::WentOffline,
+        (ClientStatus::Stale, _) => ClientEventKind::CaughtUp,
+        _ => ClientEventKind::HeartbeatResumed,
+    }
+}
+
+/// Generate a new random client secret (256 bits, hex encoded)
+fn generate_secret() -> String {
+    to_hex(&rand::random::<[u8; 32]>())
+}
+
+/// Hash a client secret for storage
+///
+/// Secrets are random and long, so a plain SHA-256 is sufficient; only the
+/// hash is ever written to the store.
+fn hash_secret(secret: &str) -> String {
+    to_hex(&Sha256::digest(secret.as_bytes()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Validate a ban target and bring it into canonical form
+///
+/// Client IDs are parsed and re-formatted so that any UUID spelling matches,
+/// and hostnames are lowercased.
+fn normalize_ban_value(
+    kind: BanKind,
+    value: &str,
+) -> Result<String, RegistryError> {
+    let value = value.trim();
+    match kind {
+        BanKind::ClientId => uuid::Uuid::parse_str(value)
+            .map(|id| ClientId(id).to_string())
+            .map_err(|e| {
+                RegistryError::InvalidBan(format!(
+                    "invalid client ID {:?}: {}",
+                    value, e
+                ))
+            }),
+        BanKind::Hostname if value.is_empty() => Err(
+            RegistryError::InvalidBan("hostname must not be empty".to_string()),
+        ),
+        BanKind::Hostname => Ok(value.to_ascii_lowercase()),
+    }
+}
+
 /// Registry errors
 #[derive(Debug, thiserror::Error)]
 pub enum RegistryError {
     #[error("Client not found: {0}")]
     ClientNotFound(ClientId),
+
+    #[error("Invalid client secret for {0}")]
+    InvalidSecret(ClientId),
+
+    #[error("Client is banned ({} {})", .0.kind, .0.value)]
+    Banned(Ban),
+
+    #[error("Client is not in maintenance: {0}")]
+    NotInMaintenance(ClientId),
+
+    #[error("Maintenance window not found: {0}")]
+    MaintenanceWindowNotFound(Uuid),
+
+    #[error("Invalid maintenance window: {0}")]
+    InvalidMaintenanceWindow(String),
+
+    #[error("Ban not found: {0}")]
+    BanNotFound(String),
+
+    #[error("Invalid ban: {0}")]
+    InvalidBan(String),
+
+    #[error("Storage error: {0}")]
+    Store(#[from] StoreError),
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::store::{self, StoreKind};
+    use chrono::Duration;
     use std::collections::HashMap;
+    use std::panic::AssertUnwindSafe;
+    use std::path::Path;
+
+    const ALL_STORES: [StoreKind; 3] =
+        [StoreKind::Memory, StoreKind::Sqlite, StoreKind::Json];
 
     fn create_test_client_info(hostname: &str) -> ClientInfo {
         ClientInfo {
@@ -179,180 +1405,1527 @@ mod tests {
         }
     }
 
+    fn open_registry(kind: StoreKind, path: &Path) -> Registry {
+        Registry::with_store(store::open(kind, Some(path)).unwrap()).unwrap()
+    }
+
+    /// Run a test against a fresh registry for every store implementation
+    fn for_each_store(test: impl Fn(Registry)) {
+        let dir = tempfile::tempdir().unwrap();
+        for kind in ALL_STORES {
+            let path = dir.path().join(format!("{:?}", kind));
+            with_store_kind(kind, || test(open_registry(kind, &path)));
+        }
+    }
+
+    /// Run `test`, naming the store kind in the failure if it panics
+    fn with_store_kind(kind: StoreKind, test: impl FnOnce()) {
+        let Err(panic) = std::panic::catch_unwind(AssertUnwindSafe(test))
+        else {
+            return;
+        };
+        let message = panic
+            .downcast_ref::<String>()
+            .map(String::as_str)
+            .or_else(|| panic.downcast_ref::<&str>().copied())
+            .unwrap_or("test panicked");
+        panic!("{:?} store: {}", kind, message);
+    }
+
     #[test]
     fn test_registry_new_client_registration() {
-        let registry = Registry::new();
-        let info = create_test_client_info("testhost");
+        for_each_store(|registry| {
+            let info = create_test_client_info("testhost");
 
-        let client_id = registry.register(info.clone());
+            let client_id = registry.register(info.clone()).unwrap().client_id;
 
-        // Verify client is in registry
-        let clients = registry.list_clients();
-        assert_eq!(clients.len(), 1);
-        assert_eq!(clients[0].client_id, client_id);
-        assert_eq!(clients[0].info.hostname, "testhost");
-        assert_eq!(clients[0].status, ClientStatus::Online);
+            // Verify client is in registry
+            let clients = registry.list_clients().unwrap();
+            assert_eq!(clients.len(), 1);
+            assert_eq!(clients[0].client_id, client_id);
+            assert_eq!(clients[0].info.hostname, "testhost");
+            assert_eq!(clients[0].status, ClientStatus::Online);
+        });
     }
 
     #[test]
     fn test_registry_re_registration_preserves_first_connected() {
-        let registry = Registry::new();
-        let info = create_test_client_info("testhost");
+        for_each_store(|registry| {
+            let info = create_test_client_info("testhost");
 
-        // First registration
-        let client_id = registry.register(info.clone());
-        let clients = registry.list_clients();
-        let first_connected = clients[0].first_connected;
+            // First registration
+            let client_id = registry.register(info.clone()).unwrap().client_id;
+            let clients = registry.list_clients().unwrap();
+            let first_connected = clients[0].first_connected;
 
-        // Wait a bit then re-register
-        std::thread::sleep(std::time::Duration::from_millis(10));
-        registry.register(info.clone());
+            // Wait a bit then re-register
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            registry.register(info.clone()).unwrap();
 
-        // Verify first_connected timestamp is preserved, but registered_at updates
-        let clients = registry.list_clients();
-        assert_eq!(clients.len(), 1);
-        assert_eq!(clients[0].client_id, client_id);
-        assert_eq!(clients[0].first_connected, first_connected);
-        assert!(clients[0].registered_at > first_connected);
+            // Verify first_connected timestamp is preserved, but registered_at updates
+            let clients = registry.list_clients().unwrap();
+            assert_eq!(clients.len(), 1);
+            assert_eq!(clients[0].client_id, client_id);
+            assert_eq!(clients[0].first_connected, first_connected);
+            assert!(clients[0].registered_at > first_connected);
+        });
     }
 
     #[test]
     fn test_registry_heartbeat_updates_timestamp() {
-        let registry = Registry::new();
-        let info = create_test_client_info("testhost");
+        for_each_store(|registry| {
+            let info = create_test_client_info("testhost");
 
-        let client_id = registry.register(info);
-        let clients = registry.list_clients();
-        let first_heartbeat = clients[0].last_heartbeat;
+            let Registration {
+                client_id,
+                client_secret,
+                ..
+            } = registry.register(info).unwrap();
+            let clients = registry.list_clients().unwrap();
+            let first_heartbeat = clients[0].last_heartbeat;
 
-        // Wait a bit then send heartbeat
-        std::thread::sleep(std::time::Duration::from_millis(10));
-        registry.heartbeat(client_id).unwrap();
+            // Wait a bit then send heartbeat
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            registry
+                .heartbeat(client_id, &client_secret, None, Vec::new())
+                .unwrap();
 
-        // Verify heartbeat timestamp updated
-        let clients = registry.list_clients();
-        assert!(clients[0].last_heartbeat > first_heartbeat);
-        assert_eq!(clients[0].status, ClientStatus::Online);
+            // Verify heartbeat timestamp updated
+            let clients = registry.list_clients().unwrap();
+            assert!(clients[0].last_heartbeat > first_heartbeat);
+            assert_eq!(clients[0].status, ClientStatus::Online);
+        });
     }
 
     #[test]
     fn test_registry_heartbeat_unknown_client() {
-        let registry = Registry::new();
-        let unknown_id = ClientId::from_client_data("unknown", "linux", None);
+        for_each_store(|registry| {
+            let unknown_id =
+                ClientId::from_client_data("unknown", "linux", None);
+
+            // Unknown clients look the same as a wrong secret
+            let result =
+                registry.heartbeat(unknown_id, "secret", None, Vec::new());
+            assert!(result.is_err());
+            assert!(matches!(
+                result.unwrap_err(),
+                RegistryError::InvalidSecret(_)
+            ));
+        });
+    }
+
+    #[test]
+    fn test_registry_requires_current_secret() {
+        for_each_store(|registry| {
+            let info = create_test_client_info("testhost");
+            let Registration {
+                client_id,
+                client_secret,
+                ..
+            } = registry.register(info.clone()).unwrap();
+
+            assert!(matches!(
+                registry
+                    .heartbeat(client_id, "wrong", None, Vec::new())
+                    .unwrap_err(),
+                RegistryError::InvalidSecret(_)
+            ));
+            assert!(matches!(
+                registry.deregister(client_id, "wrong").unwrap_err(),
+                RegistryError::InvalidSecret(_)
+            ));
 
-        let result = registry.heartbeat(unknown_id);
-        assert!(result.is_err());
-        assert!(matches!(
-            result.unwrap_err(),
-            RegistryError::ClientNotFound(_)
-        ));
+            // Registering again rotates the secret
+            let new_secret = registry.register(info).unwrap().client_secret;
+            assert_ne!(new_secret, client_secret);
+            assert!(matches!(
+                registry
+                    .heartbeat(client_id, &client_secret, None, Vec::new())
+                    .unwrap_err(),
+                RegistryError::InvalidSecret(_)
+            ));
+            registry
+                .heartbeat(client_id, &new_secret, None, Vec::new())
+                .unwrap();
+
+            let clients = registry.list_clients().unwrap();
+            assert_eq!(clients[0].status, ClientStatus::Online);
+        });
     }
 
     #[test]
     fn test_registry_offline_threshold() {
-        let registry = Registry::new();
-        let info = create_test_client_info("testhost");
+        for_each_store(|registry| {
+            let info = create_test_client_info("testhost");
 
-        let client_id = registry.register(info);
+            let client_id = registry.register(info).unwrap().client_id;
 
-        // Set to 10 seconds ago (should be Online - < 15s)
-        {
-            let mut clients = registry.clients.write().unwrap();
-            let client = clients.get_mut(&client_id).unwrap();
-            client.last_heartbeat =
-                Utc::now() - Duration::try_seconds(10).unwrap();
-        }
+            // Set to 10 seconds ago (should be Online - < 15s)
+            registry.set_last_heartbeat(
+                client_id,
+                Utc::now() - Duration::try_seconds(10).unwrap(),
+            );
 
-        registry.update_statuses();
-        let clients = registry.list_clients();
-        assert_eq!(clients[0].status, ClientStatus::Online);
+            registry.update_statuses().unwrap();
+            let clients = registry.list_clients().unwrap();
+            assert_eq!(clients[0].status, ClientStatus::Online);
 
-        // Set to 20 seconds ago (should be Offline - >= 15s)
-        {
-            let mut clients = registry.clients.write().unwrap();
-            let client = clients.get_mut(&client_id).unwrap();
-            client.last_heartbeat =
-                Utc::now() - Duration::try_seconds(20).unwrap();
+            // Set to 20 seconds ago (should be Offline - >= 15s)
+            registry.set_last_heartbeat(
+                client_id,
+                Utc::now() - Duration::try_seconds(20).unwrap(),
+            );
+
+            registry.update_statuses().unwrap();
+            let clients = registry.list_clients().unwrap();
+            assert_eq!(clients[0].status, ClientStatus::Offline);
+        });
+    }
+
+    #[test]
+    fn test_registry_stale_threshold() {
+        for_each_store(|registry| {
+            let mut receiver =
+                registry.events().subscribe(None).unwrap().receiver;
+            let Registration {
+                client_id,
+                client_secret,
+                ..
+            } = registry
+                .register(create_test_client_info("testhost"))
+                .unwrap();
+            let status = || registry.get_client(client_id).unwrap().status;
+            let stale = || {
+                registry.set_last_heartbeat(
+                    client_id,
+                    Utc::now() - Duration::try_seconds(13).unwrap(),
+                );
+                registry.update_statuses().unwrap();
+            };
+
+            // Missing a heartbeat (>= 12s) makes the client stale, and the
+            // next heartbeat brings it back
+            stale();
+            assert_eq!(status(), ClientStatus::Stale);
+            registry
+                .heartbeat(client_id, &client_secret, None, Vec::new())
+                .unwrap();
+            assert_eq!(status(), ClientStatus::Online);
+
+            // So does registering again
+            stale();
+            let client_secret = registry
+                .register(create_test_client_info("testhost"))
+                .unwrap()
+                .client_secret;
+            assert_eq!(status(), ClientStatus::Online);
+
+            // A stale client that misses the offline threshold goes offline
+            stale();
+            registry.set_last_heartbeat(
+                client_id,
+                Utc::now() - Duration::try_seconds(20).unwrap(),
+            );
+            registry.update_statuses().unwrap();
+            assert_eq!(status(), ClientStatus::Offline);
+            registry
+                .heartbeat(client_id, &client_secret, None, Vec::new())
+                .unwrap();
+
+            let kinds: Vec<_> = std::iter::from_fn(|| receiver.try_recv().ok())
+                .map(|event| event.kind)
+                .collect();
+            assert_eq!(
+                kinds,
+                [
+                    ClientEventKind::Registered,
+                    ClientEventKind::WentStale,
+                    ClientEventKind::CaughtUp,
+                    ClientEventKind::WentStale,
+                    ClientEventKind::CaughtUp,
+                    ClientEventKind::WentStale,
+                    ClientEventKind::WentOffline,
+                    ClientEventKind::HeartbeatResumed,
+                ]
+            );
+
+            // Stale time counts as online
+            let stats = registry
+                .availability(client_id, &[AvailabilityWindow::Day])
+                .unwrap();
+            assert_eq!(stats[0].outages, 1);
+        });
+    }
+
+    #[test]
+    fn test_registry_stale_degraded_client_stays_degraded() {
+        for_each_store(|registry| {
+            let mut receiver =
+                registry.events().subscribe(None).unwrap().receiver;
+            let Registration {
+                client_id,
+                client_secret,
+                ..
+            } = registry
+                .register(create_test_client_info("testhost"))
+                .unwrap();
+            let failing = vec![CheckResult {
+                name: "web".to_string(),
+                passed: false,
+                message: None,
+            }];
+            registry
+                .heartbeat(client_id, &client_secret, None, failing.clone())
+                .unwrap();
+            registry.set_last_heartbeat(
+                client_id,
+                Utc::now() - Duration::try_seconds(13).unwrap(),
+            );
+            registry.update_statuses().unwrap();
+            registry
+                .heartbeat(client_id, &client_secret, None, failing)
+                .unwrap();
+            assert_eq!(
+                registry.get_client(client_id).unwrap().status,
+                ClientStatus::Degraded
+            );
+
+            // Catching up with the same failing checks is not newly degraded
+            let kinds: Vec<_> = std::iter::from_fn(|| receiver.try_recv().ok())
+                .map(|event| event.kind)
+                .collect();
+            assert_eq!(
+                kinds,
+                [
+                    ClientEventKind::Registered,
+                    ClientEventKind::Degraded,
+                    ClientEventKind::WentStale,
+                    ClientEventKind::CaughtUp,
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_registry_per_client_heartbeat_policy() {
+        use crate::policy::HeartbeatRuleSpec;
+
+        let policies = HeartbeatPolicies::new(
+            HeartbeatPolicy::default(),
+            &[HeartbeatRuleSpec {
+                tag: Some("role=laptop".to_string()),
+                hostname: None,
+                interval_secs: 60,
+                stale_threshold_secs: None,
+                offline_threshold_secs: Some(120),
+            }],
+        )
+        .unwrap();
+        let registry = Registry::new().with_heartbeat_policies(policies);
+
+        let mut laptop_info = create_test_client_info("laptop");
+        laptop_info
+            .tags
+            .insert("role".to_string(), "laptop".to_string());
+        let laptop = registry.register(laptop_info).unwrap();
+        assert_eq!(laptop.heartbeat_policy.interval.as_secs(), 60);
+        let server = registry
+            .register(create_test_client_info("server"))
+            .unwrap();
+        assert_eq!(server.heartbeat_policy, HeartbeatPolicy::default());
+
+        // 20 seconds is only too long for the client on the default policy
+        for client_id in [laptop.client_id, server.client_id] {
+            registry.set_last_heartbeat(
+                client_id,
+                Utc::now() - Duration::try_seconds(20).unwrap(),
+            );
         }
+        registry.update_statuses().unwrap();
+        assert_eq!(
+            registry.get_client(laptop.client_id).unwrap().status,
+            ClientStatus::Online
+        );
+        assert_eq!(
+            registry.get_client(server.client_id).unwrap().status,
+            ClientStatus::Offline
+        );
+
+        registry.set_last_heartbeat(
+            laptop.client_id,
+            Utc::now() - Duration::try_seconds(120).unwrap(),
+        );
+        registry.update_statuses().unwrap();
+        assert_eq!(
+            registry.get_client(laptop.client_id).unwrap().status,
+            ClientStatus::Offline
+        );
+    }
+
+    #[test]
+    fn test_registry_publishes_client_events() {
+        for_each_store(|registry| {
+            let mut receiver =
+                registry.events().subscribe(None).unwrap().receiver;
+            let mut next = || {
+                let event = receiver.try_recv().unwrap();
+                (event.sequence, event.kind, event.first)
+            };
+
+            let info = create_test_client_info("testhost");
+            let client_id = registry.register(info.clone()).unwrap().client_id;
+            assert_eq!(next(), (1, ClientEventKind::Registered, true));
+
+            // Re-registering while online with the same info is not news
+            let client_secret =
+                registry.register(info.clone()).unwrap().client_secret;
+
+            registry.set_last_heartbeat(
+                client_id,
+                Utc::now() - Duration::try_seconds(20).unwrap(),
+            );
+            registry.update_statuses().unwrap();
+            assert_eq!(next(), (2, ClientEventKind::WentOffline, false));
+
+            registry
+                .heartbeat(client_id, &client_secret, None, Vec::new())
+                .unwrap();
+            assert_eq!(next(), (3, ClientEventKind::HeartbeatResumed, false));
+            registry
+                .heartbeat(client_id, &client_secret, None, Vec::new())
+                .unwrap();
+
+            let mut changed = info.clone();
+            changed.version = "2.0.0".to_string();
+            let client_secret =
+                registry.register(changed).unwrap().client_secret;
+            assert_eq!(next(), (4, ClientEventKind::InfoChanged, false));
+
+            registry.deregister(client_id, &client_secret).unwrap();
+            assert_eq!(next(), (5, ClientEventKind::Deregistered, false));
+
+            // Coming back after departing with the old version is both, and
+            // is not a first registration
+            registry.register(info).unwrap();
+            assert_eq!(next(), (6, ClientEventKind::Registered, false));
+            assert_eq!(next(), (7, ClientEventKind::InfoChanged, false));
+
+            registry.remove_client(client_id).unwrap();
+            assert_eq!(next(), (8, ClientEventKind::Removed, false));
+            assert!(receiver.try_recv().is_err());
+        });
+    }
 
-        registry.update_statuses();
-        let clients = registry.list_clients();
-        assert_eq!(clients[0].status, ClientStatus::Offline);
+    #[test]
+    fn test_default_status_sweep_sees_clients_go_stale() {
+        let sweep =
+            crate::health::default_status_sweep(&HeartbeatPolicies::default());
+        let sweep = Duration::from_std(sweep).unwrap();
+        for_each_store(|registry| {
+            let mut receiver =
+                registry.events().subscribe(None).unwrap().receiver;
+
+            // Clients whose last heartbeats fall at different points
+            // between two sweeps
+            let phases = [0, 250, 500, 750].map(|millis| {
+                let info = create_test_client_info(&format!("host-{millis}"));
+                let client_id = registry.register(info).unwrap().client_id;
+                (client_id, Duration::try_milliseconds(millis).unwrap())
+            });
+            while receiver.try_recv().is_ok() {}
+
+            let mut seen: HashMap<ClientId, Vec<ClientEventKind>> =
+                HashMap::new();
+            for sweeps in 1..=20 {
+                for (client_id, phase) in phases {
+                    registry.set_last_heartbeat(
+                        client_id,
+                        Utc::now() - phase - sweep * sweeps,
+                    );
+                }
+                registry.update_statuses().unwrap();
+                while let Ok(event) = receiver.try_recv() {
+                    seen.entry(event.client.client_id)
+                        .or_default()
+                        .push(event.kind);
+                }
+            }
+
+            for (client_id, _) in phases {
+                assert_eq!(
+                    seen[&client_id],
+                    [ClientEventKind::WentStale, ClientEventKind::WentOffline]
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn test_registry_failing_checks_degrade_client() {
+        for_each_store(|registry| {
+            let mut receiver =
+                registry.events().subscribe(None).unwrap().receiver;
+            let Registration {
+                client_id,
+                client_secret,
+                ..
+            } = registry
+                .register(create_test_client_info("testhost"))
+                .unwrap();
+            assert_eq!(
+                receiver.try_recv().unwrap().kind,
+                ClientEventKind::Registered
+            );
+            let check = |passed| {
+                vec![CheckResult {
+                    name: "web".to_string(),
+                    passed,
+                    message: (!passed).then(|| "exit status: 1".to_string()),
+                }]
+            };
+            let status = || registry.get_client(client_id).unwrap().status;
+
+            registry
+                .heartbeat(client_id, &client_secret, None, check(false))
+                .unwrap();
+            assert_eq!(status(), ClientStatus::Degraded);
+            assert_eq!(
+                receiver.try_recv().unwrap().kind,
+                ClientEventKind::Degraded
+            );
+
+            // Staying degraded, or registering again, is not news, and the
+            // status update leaves a heartbeating degraded client alone
+            registry
+                .heartbeat(client_id, &client_secret, None, check(false))
+                .unwrap();
+            let client_secret = registry
+                .register(create_test_client_info("testhost"))
+                .unwrap()
+                .client_secret;
+            registry.update_statuses().unwrap();
+            assert_eq!(status(), ClientStatus::Degraded);
+            assert!(receiver.try_recv().is_err());
+
+            registry
+                .heartbeat(client_id, &client_secret, None, check(true))
+                .unwrap();
+            assert_eq!(status(), ClientStatus::Online);
+            assert_eq!(
+                receiver.try_recv().unwrap().kind,
+                ClientEventKind::Recovered
+            );
+
+            // A degraded client that times out goes offline, and comes back
+            // degraded if its checks still fail
+            registry
+                .heartbeat(client_id, &client_secret, None, check(false))
+                .unwrap();
+            registry.set_last_heartbeat(
+                client_id,
+                Utc::now() - Duration::try_seconds(20).unwrap(),
+            );
+            registry.update_statuses().unwrap();
+            assert_eq!(status(), ClientStatus::Offline);
+            registry
+                .heartbeat(client_id, &client_secret, None, check(false))
+                .unwrap();
+            assert_eq!(status(), ClientStatus::Degraded);
+            let kinds: Vec<_> = std::iter::from_fn(|| receiver.try_recv().ok())
+                .map(|event| event.kind)
+                .collect();
+            assert_eq!(
+                kinds,
+                [
+                    ClientEventKind::Degraded,
+                    ClientEventKind::WentOffline,
+                    ClientEventKind::HeartbeatResumed,
+                    ClientEventKind::Degraded,
+                ]
+            );
+
+            let statuses: Vec<_> = registry
+                .client_history(client_id)
+                .unwrap()
+                .iter()
+                .filter_map(|entry| entry.resulting_status())
+                .collect();
+            assert_eq!(
+                statuses,
+                [
+                    ClientStatus::Online,
+                    ClientStatus::Degraded,
+                    ClientStatus::Online,
+                    ClientStatus::Degraded,
+                    ClientStatus::Offline,
+                    ClientStatus::Degraded,
+                ]
+            );
+        });
     }
 
     #[test]
     fn test_registry_time_connected_zero_when_offline() {
-        let registry = Registry::new();
-        let info = create_test_client_info("testhost");
+        for_each_store(|registry| {
+            let info = create_test_client_info("testhost");
 
-        let client_id = registry.register(info);
+            let client_id = registry.register(info).unwrap().client_id;
 
-        // Client should be online with non-zero time connected
-        let clients = registry.list_clients();
-        assert_eq!(clients[0].status, ClientStatus::Online);
-        assert!(clients[0].time_connected().num_seconds() >= 0);
+            // Client should be online with non-zero time connected
+            let clients = registry.list_clients().unwrap();
+            assert_eq!(clients[0].status, ClientStatus::Online);
+            assert!(clients[0].time_connected().num_seconds() >= 0);
 
-        // Set heartbeat to old time to trigger offline
-        {
-            let mut clients = registry.clients.write().unwrap();
-            let client = clients.get_mut(&client_id).unwrap();
-            client.last_heartbeat =
-                Utc::now() - Duration::try_seconds(20).unwrap();
+            // Set heartbeat to old time to trigger offline
+            registry.set_last_heartbeat(
+                client_id,
+                Utc::now() - Duration::try_seconds(20).unwrap(),
+            );
+
+            // Update statuses - should transition to offline with zero time connected
+            registry.update_statuses().unwrap();
+            let clients = registry.list_clients().unwrap();
+            assert_eq!(clients[0].status, ClientStatus::Offline);
+            assert_eq!(
+                clients[0].time_connected().num_seconds(),
+                0,
+                "Time connected should be exactly zero for offline clients"
+            );
+        });
+    }
+
+    #[test]
+    fn test_registry_deregister_marks_departed() {
+        for_each_store(|registry| {
+            let info = create_test_client_info("testhost");
+            let Registration {
+                client_id,
+                client_secret,
+                ..
+            } = registry.register(info.clone()).unwrap();
+
+            registry.deregister(client_id, &client_secret).unwrap();
+            let clients = registry.list_clients().unwrap();
+            assert_eq!(clients.len(), 1);
+            assert_eq!(clients[0].status, ClientStatus::Departed);
+
+            // The status sweep must not turn a departure into an outage
+            registry.set_last_heartbeat(
+                client_id,
+                Utc::now() - Duration::try_seconds(20).unwrap(),
+            );
+            registry.update_statuses().unwrap();
+            let clients = registry.list_clients().unwrap();
+            assert_eq!(clients[0].status, ClientStatus::Departed);
+
+            // Registering again brings the client back online
+            registry.register(info).unwrap();
+            let clients = registry.list_clients().unwrap();
+            assert_eq!(clients[0].status, ClientStatus::Online);
+        });
+    }
+
+    #[test]
+    fn test_registry_deregister_unknown_client() {
+        for_each_store(|registry| {
+            let unknown_id =
+                ClientId::from_client_data("unknown", "linux", None);
+
+            assert!(matches!(
+                registry.deregister(unknown_id, "secret").unwrap_err(),
+                RegistryError::InvalidSecret(_)
+            ));
+        });
+    }
+
+    #[test]
+    fn test_registry_remove_client() {
+        for_each_store(|registry| {
+            let client_id = registry
+                .register(create_test_client_info("testhost"))
+                .unwrap()
+                .client_id;
+            registry
+                .register(create_test_client_info("otherhost"))
+                .unwrap();
+
+            registry.remove_client(client_id).unwrap();
+            let clients = registry.list_clients().unwrap();
+            assert_eq!(clients.len(), 1);
+            assert_eq!(clients[0].info.hostname, "otherhost");
+
+            // Removing again reports that the client is gone
+            assert!(matches!(
+                registry.remove_client(client_id).unwrap_err(),
+                RegistryError::ClientNotFound(_)
+            ));
+        });
+    }
+
+    #[test]
+    fn test_registry_ban_rejects_registration_and_heartbeat() {
+        for_each_store(|registry| {
+            let info = create_test_client_info("testhost");
+            let Registration {
+                client_id,
+                client_secret,
+                ..
+            } = registry.register(info.clone()).unwrap();
+
+            registry
+                .ban(BanKind::Hostname, "TestHost", Some("retired".into()))
+                .unwrap();
+            assert!(matches!(
+                registry.register(info.clone()).unwrap_err(),
+                RegistryError::Banned(_)
+            ));
+            assert!(matches!(
+                registry
+                    .heartbeat(client_id, &client_secret, None, Vec::new())
+                    .unwrap_err(),
+                RegistryError::Banned(_)
+            ));
+
+            // Other hosts are unaffected
+            registry
+                .register(create_test_client_info("otherhost"))
+                .unwrap();
+
+            registry.unban(BanKind::Hostname, "testhost").unwrap();
+            registry.register(info).unwrap();
+            assert!(registry.list_bans().unwrap().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_registry_ban_by_client_id() {
+        for_each_store(|registry| {
+            let info = create_test_client_info("testhost");
+            let client_id = info.client_id();
+
+            // Any UUID spelling matches the canonical form
+            let ban = registry
+                .ban(
+                    BanKind::ClientId,
+                    &client_id.to_string().to_uppercase(),
+                    None,
+                )
+                .unwrap();
+            assert_eq!(ban.value, client_id.to_string());
+            assert!(matches!(
+                registry.register(info).unwrap_err(),
+                RegistryError::Banned(_)
+            ));
+
+            assert!(matches!(
+                registry.ban(BanKind::ClientId, "not-a-uuid", None),
+                Err(RegistryError::InvalidBan(_))
+            ));
+            assert!(matches!(
+                registry.unban(BanKind::Hostname, "nobody"),
+                Err(RegistryError::BanNotFound(_))
+            ));
+        });
+    }
+
+    #[test]
+    fn test_registry_quarantine_survives_re_registration() {
+        for_each_store(|registry| {
+            let info = create_test_client_info("testhost");
+            let client_id = registry.register(info.clone()).unwrap().client_id;
+
+            registry
+                .quarantine(client_id, Some("suspicious".into()))
+                .unwrap();
+            let client_secret = registry.register(info).unwrap().client_secret;
+            registry
+                .heartbeat(client_id, &client_secret, None, Vec::new())
+                .unwrap();
+
+            let clients = registry.list_clients().unwrap();
+            let quarantine = clients[0].quarantine.as_ref().unwrap();
+            assert_eq!(quarantine.reason.as_deref(), Some("suspicious"));
+
+            registry.release_quarantine(client_id).unwrap();
+            let clients = registry.list_clients().unwrap();
+            assert!(clients[0].quarantine.is_none());
+        });
+    }
+
+    #[test]
+    fn test_registry_maintenance() {
+        for_each_store(|registry| {
+            let mut receiver =
+                registry.events().subscribe(None).unwrap().receiver;
+            let info = create_test_client_info("testhost");
+            let Registration {
+                client_id,
+                client_secret,
+                ..
+            } = registry.register(info.clone()).unwrap();
+            let status = || registry.get_client(client_id).unwrap().status;
+            assert!(matches!(
+                registry.end_maintenance(client_id),
+                Err(RegistryError::NotInMaintenance(_))
+            ));
+
+            registry
+                .start_maintenance(client_id, Some("kernel upgrade".into()))
+                .unwrap();
+            assert_eq!(status(), ClientStatus::Maintenance);
+            let client = registry.get_client(client_id).unwrap();
+            assert_eq!(
+                client.maintenance.unwrap().reason.as_deref(),
+                Some("kernel upgrade")
+            );
+
+            // Nothing the client does or fails to do changes its status
+            registry.set_last_heartbeat(
+                client_id,
+                Utc::now() - Duration::try_seconds(20).unwrap(),
+            );
+            registry.update_statuses().unwrap();
+            assert_eq!(status(), ClientStatus::Maintenance);
+            registry.deregister(client_id, &client_secret).unwrap();
+            assert_eq!(status(), ClientStatus::Maintenance);
+            let client_secret = registry.register(info).unwrap().client_secret;
+            registry
+                .heartbeat(client_id, &client_secret, None, Vec::new())
+                .unwrap();
+            assert_eq!(status(), ClientStatus::Maintenance);
+
+            // Ending it goes back to whatever the heartbeats warrant
+            registry.set_last_heartbeat(
+                client_id,
+                Utc::now() - Duration::try_seconds(20).unwrap(),
+            );
+            registry.end_maintenance(client_id).unwrap();
+            assert_eq!(status(), ClientStatus::Offline);
+            assert!(registry
+                .get_client(client_id)
+                .unwrap()
+                .maintenance
+                .is_none());
+
+            // and a client that died in maintenance is reported offline once
+            registry.update_statuses().unwrap();
+            let mut kinds = || {
+                std::iter::from_fn(|| receiver.try_recv().ok())
+                    .map(|event| event.kind)
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(
+                kinds(),
+                [
+                    ClientEventKind::Registered,
+                    ClientEventKind::MaintenanceStarted,
+                    ClientEventKind::Deregistered,
+                    ClientEventKind::MaintenanceEnded,
+                    ClientEventKind::WentOffline,
+                ]
+            );
+
+            // Maintenance time is neither available nor an outage
+            let stats = registry
+                .availability(client_id, &[AvailabilityWindow::Day])
+                .unwrap();
+            assert_eq!(stats[0].outages, 1);
+            let statuses: Vec<_> = registry
+                .client_history(client_id)
+                .unwrap()
+                .iter()
+                .filter_map(|entry| entry.resulting_status())
+                .collect();
+            assert_eq!(
+                statuses,
+                [
+                    ClientStatus::Online,
+                    ClientStatus::Maintenance,
+                    ClientStatus::Offline,
+                ]
+            );
+
+            // One that kept heartbeating is reported as back
+            registry.start_maintenance(client_id, None).unwrap();
+            registry
+                .heartbeat(client_id, &client_secret, None, Vec::new())
+                .unwrap();
+            registry.end_maintenance(client_id).unwrap();
+            assert_eq!(status(), ClientStatus::Online);
+            assert_eq!(
+                kinds(),
+                [
+                    ClientEventKind::MaintenanceStarted,
+                    ClientEventKind::MaintenanceEnded,
+                    ClientEventKind::HeartbeatResumed,
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_registry_maintenance_window() {
+        for_each_store(|registry| {
+            let mut info = create_test_client_info("rack-host");
+            info.tags.insert("rack".to_string(), "r12".to_string());
+            let patched = registry.register(info).unwrap().client_id;
+            let other = registry
+                .register(create_test_client_info("other-host"))
+                .unwrap()
+                .client_id;
+            let mut receiver =
+                registry.events().subscribe(None).unwrap().receiver;
+
+            let window = registry
+                .schedule_maintenance_window(MaintenanceWindowRequest {
+                    selector: Some("rack=r12".to_string()),
+                    reason: Some("patching".to_string()),
+                    duration_secs: 3600,
+                    ..Default::default()
+                })
+                .unwrap();
+            let window_of =
+                |id| registry.get_client(id).unwrap().maintenance_window;
+            assert_eq!(window_of(patched), Some(window.id));
+            assert_eq!(window_of(other), None);
+            assert_eq!(
+                registry.list_maintenance_windows().unwrap(),
+                std::slice::from_ref(&window)
+            );
+
+            // Both go offline, but only one of them was expected to
+            for client_id in [patched, other] {
+                registry.set_last_heartbeat(
+                    client_id,
+                    Utc::now() - Duration::try_seconds(20).unwrap(),
+                );
+            }
+            registry.update_statuses().unwrap();
+            let mut events: Vec<_> =
+                std::iter::from_fn(|| receiver.try_recv().ok())
+                    .map(|event| {
+                        (event.client.client_id, event.kind, event.expected)
+                    })
+                    .collect();
+            events.sort_by_key(|(client_id, ..)| *client_id != patched);
+            assert_eq!(
+                events,
+                [
+                    (patched, ClientEventKind::WentOffline, true),
+                    (other, ClientEventKind::WentOffline, false),
+                ]
+            );
+            let last_change =
+                |id| registry.client_history(id).unwrap().pop().unwrap().change;
+            assert_eq!(
+                last_change(patched),
+                HistoryChange::StatusChanged {
+                    from: ClientStatus::Online,
+                    to: ClientStatus::Offline,
+                    expected: true,
+                }
+            );
+
+            // Expected offline time is not an outage
+            let outages = |id| {
+                registry
+                    .availability(id, &[AvailabilityWindow::Day])
+                    .unwrap()[0]
+                    .outages
+            };
+            assert_eq!(outages(patched), 0);
+            assert_eq!(outages(other), 1);
+
+            // Cancelling the window takes the client out of it
+            registry.cancel_maintenance_window(window.id).unwrap();
+            assert_eq!(window_of(patched), None);
+            assert!(registry.list_maintenance_windows().unwrap().is_empty());
+            assert!(matches!(
+                registry.cancel_maintenance_window(window.id),
+                Err(RegistryError::MaintenanceWindowNotFound(_))
+            ));
+        });
+    }
+
+    #[test]
+    fn test_registry_maintenance_window_requests() {
+        for_each_store(|registry| {
+            let client_id = registry
+                .register(create_test_client_info("testhost"))
+                .unwrap()
+                .client_id;
+            let request = |client_id| MaintenanceWindowRequest {
+                client_id: Some(client_id),
+                duration_secs: 3600,
+                ..Default::default()
+            };
+
+            let unknown = ClientId::from_client_data("unknown", "linux", None);
+            assert!(matches!(
+                registry.schedule_maintenance_window(request(unknown)),
+                Err(RegistryError::ClientNotFound(_))
+            ));
+            assert!(matches!(
+                registry.schedule_maintenance_window(
+                    MaintenanceWindowRequest {
+                        duration_secs: 0,
+                        ..request(client_id)
+                    }
+                ),
+                Err(RegistryError::InvalidMaintenanceWindow(_))
+            ));
+
+            // A one-off window that is already over is dropped straight
+            // away, while a recurring one is kept
+            let past = Utc::now() - Duration::try_hours(2).unwrap();
+            registry
+                .schedule_maintenance_window(MaintenanceWindowRequest {
+                    starts_at: Some(past),
+                    ..request(client_id)
+                })
+                .unwrap();
+            assert!(registry.list_maintenance_windows().unwrap().is_empty());
+            let recurring = registry
+                .schedule_maintenance_window(MaintenanceWindowRequest {
+                    starts_at: Some(past),
+                    schedule: Some("0 0 1 1 *".to_string()),
+                    ..request(client_id)
+                })
+                .unwrap();
+            assert_eq!(
+                registry.list_maintenance_windows().unwrap(),
+                [recurring]
+            );
+        });
+    }
+
+    #[test]
+    fn test_registry_multiple_clients() {
+        for_each_store(|registry| {
+            let info1 = create_test_client_info("host1");
+            let info2 = create_test_client_info("host2");
+            let info3 = create_test_client_info("host3");
+
+            registry.register(info1).unwrap();
+            registry.register(info2).unwrap();
+            registry.register(info3).unwrap();
+
+            let clients = registry.list_clients().unwrap();
+            assert_eq!(clients.len(), 3);
+
+            // Verify all have unique IDs
+            let mut ids: Vec<_> = clients.iter().map(|c| c.client_id).collect();
+            ids.sort_by_key(|id| id.0.as_u128());
+            ids.dedup();
+            assert_eq!(ids.len(), 3);
+        });
+    }
+
+    #[test]
+    fn test_registry_clone_shares_data() {
+        for_each_store(|registry1| {
+            let info = create_test_client_info("testhost");
+
+            registry1.register(info).unwrap();
+
+            // Clone registry
+            let registry2 = registry1.clone();
+
+            // Both should see the same client
+            assert_eq!(registry1.list_clients().unwrap().len(), 1);
+            assert_eq!(registry2.list_clients().unwrap().len(), 1);
+
+            // Register via clone
+            let info2 = create_test_client_info("host2");
+            registry2.register(info2).unwrap();
+
+            // Both should see both clients
+            assert_eq!(registry1.list_clients().unwrap().len(), 2);
+            assert_eq!(registry2.list_clients().unwrap().len(), 2);
+        });
+    }
+
+    #[test]
+    fn test_registry_reopen_rehydrates_clients_offline() {
+        let dir = tempfile::tempdir().unwrap();
+
+        for kind in [StoreKind::Sqlite, StoreKind::Json] {
+            with_store_kind(kind, || {
+                let path = dir.path().join(format!("{:?}", kind));
+
+                let (client_id, client_secret, first_connected) = {
+                    let registry = open_registry(kind, &path);
+                    let Registration {
+                        client_id,
+                        client_secret,
+                        ..
+                    } = registry
+                        .register(create_test_client_info("testhost"))
+                        .unwrap();
+                    registry
+                        .heartbeat(client_id, &client_secret, None, Vec::new())
+                        .unwrap();
+                    let clients = registry.list_clients().unwrap();
+                    (client_id, client_secret, clients[0].first_connected)
+                };
+
+                // Reopen the same store, simulating a server restart
+                let registry = open_registry(kind, &path);
+                let clients = registry.list_clients().unwrap();
+                assert_eq!(clients.len(), 1);
+                assert_eq!(clients[0].client_id, client_id);
+                assert_eq!(clients[0].first_connected, first_connected);
+                assert_eq!(clients[0].status, ClientStatus::Offline);
+
+                // The client secret survives the restart, and a heartbeat
+                // brings the restored client back online
+                registry
+                    .heartbeat(client_id, &client_secret, None, Vec::new())
+                    .unwrap();
+                let clients = registry.list_clients().unwrap();
+                assert_eq!(clients[0].status, ClientStatus::Online);
+            });
         }
+    }
+
+    #[test]
+    fn test_json_store_holds_back_heartbeats() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("registry.json");
+        let registry = open_registry(StoreKind::Json, &path);
+        let Registration {
+            client_id,
+            client_secret,
+            ..
+        } = registry
+            .register(create_test_client_info("testhost"))
+            .unwrap();
+        let written = || {
+            let store = store::open(StoreKind::Json, Some(&path)).unwrap();
+            store.get(client_id).unwrap().unwrap()
+        };
+        let registered = written().last_heartbeat;
+
+        // A heartbeat that changes nothing else is not written straight away
+        registry
+            .heartbeat(client_id, &client_secret, None, Vec::new())
+            .unwrap();
+        assert_eq!(written().last_heartbeat, registered);
+
+        // Other changes are, along with the heartbeats held back
+        let failing = vec![CheckResult {
+            name: "web".to_string(),
+            passed: false,
+            message: None,
+        }];
+        registry
+            .heartbeat(client_id, &client_secret, None, failing.clone())
+            .unwrap();
+        assert_eq!(written().status, ClientStatus::Degraded);
+        let degraded = written().last_heartbeat;
+        assert!(degraded > registered);
+
+        // Flushing writes whatever is still held back
+        registry
+            .heartbeat(client_id, &client_secret, None, failing.clone())
+            .unwrap();
+        assert_eq!(written().last_heartbeat, degraded);
+        registry.flush().unwrap();
+        let flushed = written().last_heartbeat;
+        assert!(flushed > degraded);
+
+        // and so, as a last resort, does closing the store
+        registry
+            .heartbeat(client_id, &client_secret, None, failing)
+            .unwrap();
+        assert_eq!(written().last_heartbeat, flushed);
+        drop(registry);
+        assert!(written().last_heartbeat > flushed);
+    }
+
+    #[test]
+    fn test_json_store_temporary_file_keeps_snapshot_name() {
+        // A file that shares the snapshot's stem is left alone
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("registry.json");
+        let neighbour = dir.path().join("registry.tmp");
+        std::fs::write(&neighbour, "not a snapshot").unwrap();
 
-        // Update statuses - should transition to offline with zero time connected
-        registry.update_statuses();
-        let clients = registry.list_clients();
-        assert_eq!(clients[0].status, ClientStatus::Offline);
+        let registry = open_registry(StoreKind::Json, &path);
+        let client_id = registry
+            .register(create_test_client_info("testhost"))
+            .unwrap()
+            .client_id;
+        drop(registry);
         assert_eq!(
-            clients[0].time_connected().num_seconds(),
-            0,
-            "Time connected should be exactly zero for offline clients"
+            std::fs::read_to_string(&neighbour).unwrap(),
+            "not a snapshot"
         );
+        let store = store::open(StoreKind::Json, Some(&path)).unwrap();
+        assert!(store.get(client_id).unwrap().is_some());
+
+        let mut names: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["registry.json", "registry.tmp"]);
     }
 
     #[test]
-    fn test_registry_multiple_clients() {
-        let registry = Registry::new();
+    fn test_registry_reopen_keeps_bans() {
+        let dir = tempfile::tempdir().unwrap();
+
+        for kind in [StoreKind::Sqlite, StoreKind::Json] {
+            with_store_kind(kind, || {
+                let path = dir.path().join(format!("{:?}", kind));
+
+                open_registry(kind, &path)
+                    .ban(BanKind::Hostname, "testhost", None)
+                    .unwrap();
 
-        let info1 = create_test_client_info("host1");
-        let info2 = create_test_client_info("host2");
-        let info3 = create_test_client_info("host3");
+                let registry = open_registry(kind, &path);
+                assert_eq!(registry.list_bans().unwrap().len(), 1);
+                assert!(matches!(
+                    registry.register(create_test_client_info("testhost")),
+                    Err(RegistryError::Banned(_))
+                ));
+            });
+        }
+    }
+
+    #[test]
+    fn test_registry_reopen_keeps_revisions() {
+        let dir = tempfile::tempdir().unwrap();
 
-        registry.register(info1);
-        registry.register(info2);
-        registry.register(info3);
+        for kind in [StoreKind::Sqlite, StoreKind::Json] {
+            with_store_kind(kind, || {
+                let path = dir.path().join(format!("{:?}", kind));
 
-        let clients = registry.list_clients();
-        assert_eq!(clients.len(), 3);
+                let registry = open_registry(kind, &path);
+                let kept = registry
+                    .register(create_test_client_info("kept"))
+                    .unwrap()
+                    .client_id;
+                registry.quarantine(kept, None).unwrap();
+                let removed = registry
+                    .register(create_test_client_info("removed"))
+                    .unwrap()
+                    .client_id;
+                registry.remove_client(removed).unwrap();
+                registry.ban(BanKind::Hostname, "badhost", None).unwrap();
+                let before = registry.replication_state().unwrap();
+                drop(registry);
 
-        // Verify all have unique IDs
-        let mut ids: Vec<_> = clients.iter().map(|c| c.client_id).collect();
-        ids.sort_by_key(|id| id.0.as_u128());
-        ids.dedup();
-        assert_eq!(ids.len(), 3);
+                // Which changes are newest survives a restart, so an older
+                // copy from a peer cannot undo them
+                let registry = open_registry(kind, &path);
+                let after = registry.replication_state().unwrap();
+                assert_eq!(after.clients.len(), 1);
+                assert_eq!(
+                    after.clients[0].updated_at,
+                    before.clients[0].updated_at
+                );
+                assert_eq!(after.removed, before.removed);
+                assert_eq!(after.bans_updated_at, before.bans_updated_at);
+
+                let mut outdated = before;
+                outdated.clients[0].client.quarantine = None;
+                outdated.clients[0].updated_at -=
+                    Duration::try_minutes(1).unwrap();
+                registry.merge_replicated_state(outdated).unwrap();
+                assert!(registry
+                    .get_client(kept)
+                    .unwrap()
+                    .quarantine
+                    .is_some());
+            });
+        }
     }
 
     #[test]
-    fn test_registry_clone_shares_data() {
-        let registry1 = Registry::new();
-        let info = create_test_client_info("testhost");
+    fn test_registry_removals_expire() {
+        for_each_store(|registry| {
+            let now = Utc::now();
+            let removed = |client_id, days| RemovedClient {
+                client_id,
+                removed_at: now - Duration::try_days(days).unwrap(),
+            };
+            let recent = removed(ClientId(Uuid::new_v4()), 1);
+            let old = removed(ClientId(Uuid::new_v4()), 8);
+
+            // Removals too old to be replicated are not taken over
+            registry
+                .merge_replicated_state(RegistryState {
+                    removed: vec![recent.clone(), old],
+                    ..Default::default()
+                })
+                .unwrap();
+            let state = registry.replication_state().unwrap();
+            assert_eq!(state.removed, std::slice::from_ref(&recent));
+
+            // and remembered ones are forgotten once they are too old
+            registry
+                .revisions
+                .lock()
+                .unwrap()
+                .remove(recent.client_id, now - REMOVAL_RETENTION)
+                .unwrap();
+            registry.update_statuses().unwrap();
+            let state = registry.replication_state().unwrap();
+            assert!(state.removed.is_empty());
+            assert!(registry.store.list_revisions().unwrap().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_registry_reopen_keeps_status_changes() {
+        let dir = tempfile::tempdir().unwrap();
+
+        for kind in [StoreKind::Sqlite, StoreKind::Json] {
+            with_store_kind(kind, || {
+                let path = dir.path().join(format!("{:?}", kind));
+
+                let registry = open_registry(kind, &path);
+                let client_id = registry
+                    .register(create_test_client_info("testhost"))
+                    .unwrap()
+                    .client_id;
+                registry.set_last_heartbeat(
+                    client_id,
+                    Utc::now() - Duration::try_seconds(20).unwrap(),
+                );
+                registry.update_statuses().unwrap();
+                drop(registry);
+
+                let store = store::open(kind, Some(&path)).unwrap();
+                let clients = store.list().unwrap();
+                assert_eq!(clients.len(), 1);
+                assert_eq!(clients[0].status, ClientStatus::Offline);
+            });
+        }
+    }
+
+    #[test]
+    fn test_registry_records_client_history() {
+        for_each_store(|registry| {
+            let mut info = create_test_client_info("testhost");
+            let registration = registry.register(info.clone()).unwrap();
+            let client_id = registration.client_id;
+
+            // Heartbeats from an online client are not history
+            registry
+                .heartbeat(
+                    client_id,
+                    &registration.client_secret,
+                    None,
+                    Vec::new(),
+                )
+                .unwrap();
+
+            registry.set_last_heartbeat(
+                client_id,
+                Utc::now() - Duration::try_seconds(20).unwrap(),
+            );
+            registry.update_statuses().unwrap();
+
+            // Coming back with a new version records all three changes
+            let previous = info.clone();
+            info.version = "2.0.0".to_string();
+            let registration = registry.register(info.clone()).unwrap();
+
+            registry
+                .quarantine(client_id, Some("suspicious".to_string()))
+                .unwrap();
+            registry.release_quarantine(client_id).unwrap();
+            registry.release_quarantine(client_id).unwrap();
+            registry
+                .deregister(client_id, &registration.client_secret)
+                .unwrap();
+
+            let history = registry.client_history(client_id).unwrap();
+            let changes: Vec<_> =
+                history.iter().map(|e| e.change.clone()).collect();
+            assert_eq!(
+                changes,
+                vec![
+                    HistoryChange::Registered { first: true },
+                    HistoryChange::StatusChanged {
+                        from: ClientStatus::Online,
+                        to: ClientStatus::Offline,
+                        expected: false,
+                    },
+                    HistoryChange::Registered { first: false },
+                    HistoryChange::StatusChanged {
+                        from: ClientStatus::Offline,
+                        to: ClientStatus::Online,
+                        expected: false,
+                    },
+                    HistoryChange::InfoChanged {
+                        previous: Box::new(previous),
+                        current: Box::new(info),
+                    },
+                    HistoryChange::Quarantined {
+                        reason: Some("suspicious".to_string()),
+                    },
+                    HistoryChange::QuarantineLifted,
+                    HistoryChange::StatusChanged {
+                        from: ClientStatus::Online,
+                        to: ClientStatus::Departed,
+                        expected: false,
+                    },
+                ]
+            );
+            assert!(history
+                .windows(2)
+                .all(|w| w[0].timestamp <= w[1].timestamp));
+
+            // Removing the client discards its history
+            registry.remove_client(client_id).unwrap();
+            assert!(matches!(
+                registry.client_history(client_id),
+                Err(RegistryError::ClientNotFound(_))
+            ));
+            assert!(registry.store.history(client_id).unwrap().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_registry_history_expires() {
+        for_each_store(|registry| {
+            let client_id = ClientId(Uuid::new_v4());
+
+            let retention = store::history_retention();
+            let entries: Vec<_> = [50, 40, 31, 29, 1]
+                .iter()
+                .map(|days| {
+                    let change = HistoryChange::Quarantined {
+                        reason: Some(format!("{days} days ago")),
+                    };
+                    let timestamp =
+                        Utc::now() - Duration::try_days(*days).unwrap();
+                    (client_id, HistoryEntry { timestamp, change })
+                })
+                .collect();
+            assert!(Utc::now() - entries[2].1.timestamp > retention);
+            assert!(Utc::now() - entries[3].1.timestamp < retention);
+            registry.store.append_history(&entries).unwrap();
+
+            // Only the newest expired entry is kept, for the status the
+            // client was in when the retention period began
+            let history = registry.store.history(client_id).unwrap();
+            let changes: Vec<_> = history.iter().map(|e| &e.change).collect();
+            assert_eq!(
+                changes,
+                [
+                    &entries[2].1.change,
+                    &entries[3].1.change,
+                    &entries[4].1.change
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_registry_reopen_keeps_history() {
+        let dir = tempfile::tempdir().unwrap();
+
+        for kind in [StoreKind::Sqlite, StoreKind::Json] {
+            with_store_kind(kind, || {
+                let path = dir.path().join(format!("{:?}", kind));
+
+                let client_id = open_registry(kind, &path)
+                    .register(create_test_client_info("testhost"))
+                    .unwrap()
+                    .client_id;
+
+                // The restart itself takes the client offline
+                let registry = open_registry(kind, &path);
+                let changes: Vec<_> = registry
+                    .client_history(client_id)
+                    .unwrap()
+                    .into_iter()
+                    .map(|e| e.change)
+                    .collect();
+                assert_eq!(
+                    changes,
+                    vec![
+                        HistoryChange::Registered { first: true },
+                        HistoryChange::StatusChanged {
+                            from: ClientStatus::Online,
+                            to: ClientStatus::Offline,
+                            expected: false,
+                        },
+                    ]
+                );
+            });
+        }
+    }
+
+    #[test]
+    fn test_registry_replication() {
+        for_each_store(|registry| {
+            let peer = Registry::new();
+            let registration = registry
+                .register(create_test_client_info("testhost"))
+                .unwrap();
+            let client_id = registration.client_id;
+            registry.ban(BanKind::Hostname, "badhost", None).unwrap();
+            let original = registry.replication_state().unwrap();
 
-        registry1.register(info);
+            // The peer learns about the client, its secret and the ban
+            peer.merge_replicated_state(original.clone()).unwrap();
+            let replicated = peer.get_client(client_id).unwrap();
+            assert_eq!(replicated.status, ClientStatus::Online);
+            assert_eq!(
+                peer.client_history(client_id).unwrap()[0].change,
+                HistoryChange::Registered { first: true }
+            );
+            peer.heartbeat(
+                client_id,
+                &registration.client_secret,
+                None,
+                Vec::new(),
+            )
+            .unwrap();
+            assert_eq!(peer.list_bans().unwrap().len(), 1);
 
-        // Clone registry
-        let registry2 = registry1.clone();
+            // Changes made on the peer flow back
+            peer.quarantine(client_id, Some("suspicious".to_string()))
+                .unwrap();
+            peer.unban(BanKind::Hostname, "badhost").unwrap();
+            registry
+                .merge_replicated_state(peer.replication_state().unwrap())
+                .unwrap();
+            let client = registry.get_client(client_id).unwrap();
+            assert!(client.quarantine.is_some());
+            assert!(registry.list_bans().unwrap().is_empty());
 
-        // Both should see the same client
-        assert_eq!(registry1.list_clients().len(), 1);
-        assert_eq!(registry2.list_clients().len(), 1);
+            // An older copy does not undo newer changes
+            registry.merge_replicated_state(original.clone()).unwrap();
+            let client = registry.get_client(client_id).unwrap();
+            assert!(client.quarantine.is_some());
+            assert!(registry.list_bans().unwrap().is_empty());
 
-        // Register via clone
-        let info2 = create_test_client_info("host2");
-        registry2.register(info2);
+            // Removals are replicated, and outdated copies do not bring the
+            // client back
+            registry.remove_client(client_id).unwrap();
+            peer.merge_replicated_state(registry.replication_state().unwrap())
+                .unwrap();
+            assert!(matches!(
+                peer.get_client(client_id),
+                Err(RegistryError::ClientNotFound(_))
+            ));
+            registry.merge_replicated_state(original).unwrap();
+            assert!(registry.list_clients().unwrap().is_empty());
 
-        // Both should see both clients
-        assert_eq!(registry1.list_clients().len(), 2);
-        assert_eq!(registry2.list_clients().len(), 2);
+            // Registering again after the removal does
+            registry
+                .register(create_test_client_info("testhost"))
+                .unwrap();
+            peer.merge_replicated_state(registry.replication_state().unwrap())
+                .unwrap();
+            assert!(peer.get_client(client_id).is_ok());
+        });
     }
 }
@@ -0,0 +1,425 @@
+// Copyright 2025 Oxide Computer Company
+
+//! JSON snapshot registry store
+
+use super::{
+    expired_history, RegistryStore, Revision, RevisionKey, StoreError,
+};
+use chrono::{DateTime, Utc};
+use crs_common::{
+    Ban, BanKind, ClientId, HistoryEntry, MaintenanceWindow, RegisteredClient,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Longest a heartbeat is held back before being written to the snapshot
+const HEARTBEAT_WRITE_DELAY: Duration = Duration::from_secs(30);
+
+/// On-disk layout of the snapshot file
+#[derive(Default, Deserialize)]
+struct Snapshot {
+    #[serde(default)]
+    clients: Vec<RegisteredClient>,
+    #[serde(default)]
+    secret_hashes: BTreeMap<ClientId, String>,
+    #[serde(default)]
+    history: BTreeMap<ClientId, Vec<HistoryEntry>>,
+    #[serde(default)]
+    bans: Vec<Ban>,
+    #[serde(default)]
+    maintenance_windows: Vec<MaintenanceWindow>,
+    #[serde(default)]
+    revisions: Vec<Revision>,
+}
+
+/// The snapshot file's layout, borrowing its contents from a [`State`] so
+/// they need not be copied to be written out
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    clients: Vec<&'a RegisteredClient>,
+    secret_hashes: BTreeMap<&'a ClientId, &'a String>,
+    history: BTreeMap<&'a ClientId, &'a Vec<HistoryEntry>>,
+    bans: Vec<&'a Ban>,
+    maintenance_windows: Vec<&'a MaintenanceWindow>,
+    revisions: Vec<&'a Revision>,
+}
+
+/// In-memory copy of the snapshot contents
+#[derive(Default)]
+struct State {
+    clients: HashMap<ClientId, RegisteredClient>,
+    secret_hashes: HashMap<ClientId, String>,
+    history: HashMap<ClientId, Vec<HistoryEntry>>,
+    bans: HashMap<(BanKind, String), Ban>,
+    maintenance_windows: HashMap<Uuid, MaintenanceWindow>,
+    revisions: HashMap<RevisionKey, Revision>,
+
+    /// When the oldest change not yet in the snapshot file was made
+    unwritten_since: Option<Instant>,
+}
+
+/// Registry store backed by a JSON snapshot file
+///
+/// All clients, secrets, history, bans, maintenance windows and revisions
+/// are held in memory and the complete set is written to the snapshot file
+/// after every change. Heartbeats are the exception: they are written along
+/// with the next change, once they have waited [`HEARTBEAT_WRITE_DELAY`],
+/// or when the store is flushed, so a crash can lose the latest heartbeat
+/// times but nothing else. The snapshot is written to a temporary file,
+/// synced, and renamed into place, so a crash never leaves a half-written
+/// snapshot behind.
+pub struct JsonStore {
+    path: PathBuf,
+    state: RwLock<State>,
+}
+
+impl JsonStore {
+    /// Open the snapshot at `path`
+    ///
+    /// If the file does not exist the store starts out empty and the file
+    /// is created on the first change.
+    pub fn open(path: &Path) -> Result<Self, StoreError> {
+        let snapshot = match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                Snapshot::default()
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let state = State {
+            clients: snapshot
+                .clients
+                .into_iter()
+                .map(|c| (c.client_id, c))
+                .collect(),
+            secret_hashes: snapshot.secret_hashes.into_iter().collect(),
+            history: snapshot.history.into_iter().collect(),
+            bans: snapshot
+                .bans
+                .into_iter()
+                .map(|b| ((b.kind, b.value.clone()), b))
+                .collect(),
+            maintenance_windows: snapshot
+                .maintenance_windows
+                .into_iter()
+                .map(|w| (w.id, w))
+                .collect(),
+            revisions: snapshot
+                .revisions
+                .into_iter()
+                .map(|r| (r.key, r))
+                .collect(),
+            unwritten_since: None,
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            state: RwLock::new(state),
+        })
+    }
+
+    /// Apply a change and write the resulting snapshot to disk
+    ///
+    /// If the snapshot cannot be written, the change stays in memory and is
+    /// written along with the next one.
+    fn update<T>(
+        &self,
+        change: impl FnOnce(&mut State) -> T,
+    ) -> Result<T, StoreError> {
+        let mut state = self.state.write().unwrap();
+        let result = change(&mut state);
+        state.unwritten_since.get_or_insert_with(Instant::now);
+        self.write_snapshot(&mut state)?;
+        Ok(result)
+    }
+
+    /// Write the clients, secrets, history, bans, maintenance windows and
+    /// revisions in `state` to the snapshot file
+    fn write_snapshot(&self, state: &mut State) -> Result<(), StoreError> {
+        let mut snapshot = SnapshotRef {
+            clients: state.clients.values().collect(),
+            secret_hashes: state.secret_hashes.iter().collect(),
+            history: state.history.iter().collect(),
+            bans: state.bans.values().collect(),
+            maintenance_windows: state.maintenance_windows.values().collect(),
+            revisions: state.revisions.values().collect(),
+        };
+        snapshot.clients.sort_by_key(|c| c.client_id.0);
+        snapshot
+            .bans
+            .sort_by(|a, b| (a.kind, &a.value).cmp(&(b.kind, &b.value)));
+        snapshot.maintenance_windows.sort_by_key(|w| w.id);
+        snapshot.revisions.sort_by_key(|r| r.key);
+
+        // Append to the whole file name, so the temporary file never clashes
+        // with the snapshot itself or another file that shares its stem
+        let mut tmp_name = self.path.file_name().unwrap_or_default().to_owned();
+        tmp_name.push(".tmp");
+        let tmp_path = self.path.with_file_name(tmp_name);
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer_pretty(&mut writer, &snapshot)?;
+        writer
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+        std::fs::rename(&tmp_path, &self.path)?;
+
+        // The rename itself is only durable once the directory is synced
+        #[cfg(unix)]
+        {
+            let dir = match self.path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            File::open(dir)?.sync_all()?;
+        }
+
+        state.unwritten_since = None;
+        Ok(())
+    }
+}
+
+impl Drop for JsonStore {
+    // Write out any heartbeats still being held back. This is only a best
+    // effort for stores that were not flushed: a failure cannot be returned
+    // from here, so it is just reported. Callers that need to know whether
+    // everything was written call `flush` first.
+    fn drop(&mut self) {
+        let state = self.state.get_mut().unwrap();
+        if state.unwritten_since.is_some() {
+            let mut state = std::mem::take(state);
+            if let Err(e) = self.write_snapshot(&mut state) {
+                eprintln!("Failed to write {:?}: {}", self.path, e);
+            }
+        }
+    }
+}
+
+impl RegistryStore for JsonStore {
+    fn get(
+        &self,
+        client_id: ClientId,
+    ) -> Result<Option<RegisteredClient>, StoreError> {
+        Ok(self.state.read().unwrap().clients.get(&client_id).cloned())
+    }
+
+    fn list(&self) -> Result<Vec<RegisteredClient>, StoreError> {
+        Ok(self
+            .state
+            .read()
+            .unwrap()
+            .clients
+            .values()
+            .cloned()
+            .collect())
+    }
+
+    fn put_many(&self, clients: &[RegisteredClient]) -> Result<(), StoreError> {
+        self.update(|state| {
+            for client in clients {
+                state.clients.insert(client.client_id, client.clone());
+            }
+        })
+    }
+
+    fn remove(
+        &self,
+        client_id: ClientId,
+    ) -> Result<Option<RegisteredClient>, StoreError> {
+        self.update(|state| {
+            state.secret_hashes.remove(&client_id);
+            state.history.remove(&client_id);
+            state.clients.remove(&client_id)
+        })
+    }
+
+    fn get_secret_hash(
+        &self,
+        client_id: ClientId,
+    ) -> Result<Option<String>, StoreError> {
+        Ok(self
+            .state
+            .read()
+            .unwrap()
+            .secret_hashes
+            .get(&client_id)
+            .cloned())
+    }
+
+    fn put_secret_hash(
+        &self,
+        client_id: ClientId,
+        secret_hash: &str,
+    ) -> Result<(), StoreError> {
+        self.update(|state| {
+            state
+                .secret_hashes
+                .insert(client_id, secret_hash.to_string());
+        })
+    }
+
+    fn put_registration(
+        &self,
+        client: &RegisteredClient,
+        secret_hash: &str,
+        history: &[HistoryEntry],
+    ) -> Result<(), StoreError> {
+        let client_id = client.client_id;
+        let now = Utc::now();
+        self.update(|state| {
+            state.clients.insert(client_id, client.clone());
+            state
+                .secret_hashes
+                .insert(client_id, secret_hash.to_string());
+            append_entries(
+                state,
+                history.iter().map(|entry| (client_id, entry)),
+                now,
+            );
+        })
+    }
+
+    fn put_heartbeat(
+        &self,
+        client: &RegisteredClient,
+    ) -> Result<(), StoreError> {
+        let mut state = self.state.write().unwrap();
+        state.clients.insert(client.client_id, client.clone());
+        let since = *state.unwritten_since.get_or_insert_with(Instant::now);
+        if since.elapsed() >= HEARTBEAT_WRITE_DELAY {
+            self.write_snapshot(&mut state)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), StoreError> {
+        let mut state = self.state.write().unwrap();
+        if state.unwritten_since.is_some() {
+            self.write_snapshot(&mut state)?;
+        }
+        Ok(())
+    }
+
+    fn append_history(
+        &self,
+        entries: &[(ClientId, HistoryEntry)],
+    ) -> Result<(), StoreError> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let now = Utc::now();
+        self.update(|state| {
+            append_entries(
+                state,
+                entries.iter().map(|(client_id, entry)| (*client_id, entry)),
+                now,
+            );
+        })
+    }
+
+    fn history(
+        &self,
+        client_id: ClientId,
+    ) -> Result<Vec<HistoryEntry>, StoreError> {
+        Ok(self
+            .state
+            .read()
+            .unwrap()
+            .history
+            .get(&client_id)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn list_bans(&self) -> Result<Vec<Ban>, StoreError> {
+        Ok(self.state.read().unwrap().bans.values().cloned().collect())
+    }
+
+    fn put_ban(&self, ban: &Ban) -> Result<(), StoreError> {
+        self.update(|state| {
+            state
+                .bans
+                .insert((ban.kind, ban.value.clone()), ban.clone());
+        })
+    }
+
+    fn remove_ban(
+        &self,
+        kind: BanKind,
+        value: &str,
+    ) -> Result<bool, StoreError> {
+        self.update(|state| {
+            state.bans.remove(&(kind, value.to_string())).is_some()
+        })
+    }
+
+    fn list_maintenance_windows(
+        &self,
+    ) -> Result<Vec<MaintenanceWindow>, StoreError> {
+        Ok(self
+            .state
+            .read()
+            .unwrap()
+            .maintenance_windows
+            .values()
+            .cloned()
+            .collect())
+    }
+
+    fn put_maintenance_window(
+        &self,
+        window: &MaintenanceWindow,
+    ) -> Result<(), StoreError> {
+        self.update(|state| {
+            state.maintenance_windows.insert(window.id, window.clone());
+        })
+    }
+
+    fn remove_maintenance_window(&self, id: Uuid) -> Result<bool, StoreError> {
+        self.update(|state| state.maintenance_windows.remove(&id).is_some())
+    }
+
+    fn list_revisions(&self) -> Result<Vec<Revision>, StoreError> {
+        Ok(self
+            .state
+            .read()
+            .unwrap()
+            .revisions
+            .values()
+            .copied()
+            .collect())
+    }
+
+    fn put_revision(&self, revision: &Revision) -> Result<(), StoreError> {
+        self.update(|state| {
+            state.revisions.insert(revision.key, *revision);
+        })
+    }
+
+    fn remove_revision(&self, key: RevisionKey) -> Result<bool, StoreError> {
+        self.update(|state| state.revisions.remove(&key).is_some())
+    }
+}
+
+/// Append history entries and drop the ones that have expired as of `now`
+/// from the histories appended to
+fn append_entries<'a>(
+    state: &mut State,
+    entries: impl IntoIterator<Item = (ClientId, &'a HistoryEntry)>,
+    now: DateTime<Utc>,
+) {
+    for (client_id, entry) in entries {
+        let history = state.history.entry(client_id).or_default();
+        history.push(entry.clone());
+        let expired =
+            expired_history(history.iter().map(|entry| entry.timestamp), now);
+        history.drain(..expired);
+    }
+}
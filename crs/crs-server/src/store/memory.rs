@@ -0,0 +1,182 @@
+// Copyright 2025 Oxide Computer Company
+
+//! In-memory registry store
+
+use super::{
+    expired_history, RegistryStore, Revision, RevisionKey, StoreError,
+};
+use chrono::Utc;
+use crs_common::{
+    Ban, BanKind, ClientId, HistoryEntry, MaintenanceWindow, RegisteredClient,
+};
+use std::collections::{HashMap, VecDeque};
+use std::sync::RwLock;
+use uuid::Uuid;
+
+/// Registry store that keeps all clients in a `HashMap`
+///
+/// Nothing is persisted; all clients, secrets, history, bans, maintenance
+/// windows and revisions are lost when the server exits.
+#[derive(Default)]
+pub struct MemoryStore {
+    clients: RwLock<HashMap<ClientId, RegisteredClient>>,
+    secret_hashes: RwLock<HashMap<ClientId, String>>,
+    history: RwLock<HashMap<ClientId, VecDeque<HistoryEntry>>>,
+    bans: RwLock<HashMap<(BanKind, String), Ban>>,
+    maintenance_windows: RwLock<HashMap<Uuid, MaintenanceWindow>>,
+    revisions: RwLock<HashMap<RevisionKey, Revision>>,
+}
+
+impl MemoryStore {
+    /// Create a new empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl RegistryStore for MemoryStore {
+    fn get(
+        &self,
+        client_id: ClientId,
+    ) -> Result<Option<RegisteredClient>, StoreError> {
+        Ok(self.clients.read().unwrap().get(&client_id).cloned())
+    }
+
+    fn list(&self) -> Result<Vec<RegisteredClient>, StoreError> {
+        Ok(self.clients.read().unwrap().values().cloned().collect())
+    }
+
+    fn put_many(&self, clients: &[RegisteredClient]) -> Result<(), StoreError> {
+        let mut map = self.clients.write().unwrap();
+        for client in clients {
+            map.insert(client.client_id, client.clone());
+        }
+        Ok(())
+    }
+
+    fn remove(
+        &self,
+        client_id: ClientId,
+    ) -> Result<Option<RegisteredClient>, StoreError> {
+        self.secret_hashes.write().unwrap().remove(&client_id);
+        self.history.write().unwrap().remove(&client_id);
+        Ok(self.clients.write().unwrap().remove(&client_id))
+    }
+
+    fn get_secret_hash(
+        &self,
+        client_id: ClientId,
+    ) -> Result<Option<String>, StoreError> {
+        Ok(self.secret_hashes.read().unwrap().get(&client_id).cloned())
+    }
+
+    fn put_secret_hash(
+        &self,
+        client_id: ClientId,
+        secret_hash: &str,
+    ) -> Result<(), StoreError> {
+        self.secret_hashes
+            .write()
+            .unwrap()
+            .insert(client_id, secret_hash.to_string());
+        Ok(())
+    }
+
+    fn append_history(
+        &self,
+        entries: &[(ClientId, HistoryEntry)],
+    ) -> Result<(), StoreError> {
+        let now = Utc::now();
+        let mut history = self.history.write().unwrap();
+        for (client_id, entry) in entries {
+            let client_history = history.entry(*client_id).or_default();
+            client_history.push_back(entry.clone());
+            let expired = expired_history(
+                client_history.iter().map(|entry| entry.timestamp),
+                now,
+            );
+            client_history.drain(..expired);
+        }
+        Ok(())
+    }
+
+    fn history(
+        &self,
+        client_id: ClientId,
+    ) -> Result<Vec<HistoryEntry>, StoreError> {
+        Ok(self
+            .history
+            .read()
+            .unwrap()
+            .get(&client_id)
+            .map(|h| h.iter().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    fn list_bans(&self) -> Result<Vec<Ban>, StoreError> {
+        Ok(self.bans.read().unwrap().values().cloned().collect())
+    }
+
+    fn put_ban(&self, ban: &Ban) -> Result<(), StoreError> {
+        let key = (ban.kind, ban.value.clone());
+        self.bans.write().unwrap().insert(key, ban.clone());
+        Ok(())
+    }
+
+    fn remove_ban(
+        &self,
+        kind: BanKind,
+        value: &str,
+    ) -> Result<bool, StoreError> {
+        let key = (kind, value.to_string());
+        Ok(self.bans.write().unwrap().remove(&key).is_some())
+    }
+
+    fn list_maintenance_windows(
+        &self,
+    ) -> Result<Vec<MaintenanceWindow>, StoreError> {
+        Ok(self
+            .maintenance_windows
+            .read()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect())
+    }
+
+    fn put_maintenance_window(
+        &self,
+        window: &MaintenanceWindow,
+    ) -> Result<(), StoreError> {
+        self.maintenance_windows
+            .write()
+            .unwrap()
+            .insert(window.id, window.clone());
+        Ok(())
+    }
+
+    fn remove_maintenance_window(&self, id: Uuid) -> Result<bool, StoreError> {
+        Ok(self
+            .maintenance_windows
+            .write()
+            .unwrap()
+            .remove(&id)
+            .is_some())
+    }
+
+    fn list_revisions(&self) -> Result<Vec<Revision>, StoreError> {
+        Ok(self.revisions.read().unwrap().values().copied().collect())
+    }
+
+    fn put_revision(&self, revision: &Revision) -> Result<(), StoreError> {
+        self.revisions
+            .write()
+            .unwrap()
+            .insert(revision.key, *revision);
+        Ok(())
+    }
+
+    fn remove_revision(&self, key: RevisionKey) -> Result<bool, StoreError> {
+        Ok(self.revisions.write().unwrap().remove(&key).is_some())
+    }
+}
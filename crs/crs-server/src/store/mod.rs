@@ -0,0 +1,275 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Storage backends for the client registry
+//!
+//! The [`Registry`](crate::registry::Registry) keeps its clients in a
+//! [`RegistryStore`]. Three implementations are provided:
+//!
+//! - [`MemoryStore`] - in-memory only, all data is lost on restart
+//! - [`SqliteStore`] - one row per client in a SQLite database
+//! - [`JsonStore`] - a JSON snapshot file rewritten on every change, except
+//!   that heartbeats are only written every so often
+//!
+//! The backend is selected at startup with [`StoreKind`] and [`open`].
+
+mod json;
+mod memory;
+mod sqlite;
+
+pub use json::JsonStore;
+pub use memory::MemoryStore;
+pub use sqlite::SqliteStore;
+
+use chrono::{DateTime, Utc};
+use crs_common::{
+    AvailabilityWindow, Ban, BanKind, ClientId, HistoryEntry,
+    MaintenanceWindow, RegisteredClient,
+};
+use std::path::Path;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// How long history entries are kept
+///
+/// Long enough to replay a client's status over the longest
+/// [`AvailabilityWindow`]. The newest entry older than this is kept as well,
+/// since it gives the client's status at the start of the window.
+pub fn history_retention() -> chrono::Duration {
+    AvailabilityWindow::ALL
+        .iter()
+        .map(AvailabilityWindow::duration)
+        .max()
+        .unwrap_or_default()
+}
+
+/// How many entries at the start of a client's history, given oldest first
+/// by their timestamps, have expired as of `now`
+fn expired_history(
+    timestamps: impl IntoIterator<Item = DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> usize {
+    let cutoff = now - history_retention();
+    timestamps
+        .into_iter()
+        .take_while(|timestamp| *timestamp < cutoff)
+        .count()
+        .saturating_sub(1)
+}
+
+/// A record replicated to other cluster nodes, whose last change is
+/// remembered so that nodes can tell which copy of it is newest
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[serde(tag = "kind", content = "client_id", rename_all = "snake_case")]
+pub enum RevisionKey {
+    /// A client's record
+    Client(ClientId),
+    /// A client's removal from the registry
+    Removed(ClientId),
+    /// All bans, as a whole
+    Bans,
+    /// All maintenance windows, as a whole
+    MaintenanceWindows,
+}
+
+/// When a replicated record last changed
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize,
+)]
+pub struct Revision {
+    pub key: RevisionKey,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Storage for registered clients, their secrets and history, bans,
+/// maintenance windows and revisions
+///
+/// Implementations must be safe to share between threads. The registry
+/// serializes its read-modify-write operations, so a store only needs to
+/// make each individual call atomic.
+pub trait RegistryStore: Send + Sync {
+    /// Look up a single client
+    fn get(
+        &self,
+        client_id: ClientId,
+    ) -> Result<Option<RegisteredClient>, StoreError>;
+
+    /// Return every stored client
+    fn list(&self) -> Result<Vec<RegisteredClient>, StoreError>;
+
+    /// Insert or replace a client
+    fn put(&self, client: &RegisteredClient) -> Result<(), StoreError> {
+        self.put_many(std::slice::from_ref(client))
+    }
+
+    /// Insert or replace several clients at once
+    fn put_many(&self, clients: &[RegisteredClient]) -> Result<(), StoreError>;
+
+    /// Remove a client with its secret and history, returning the client if
+    /// it was present
+    fn remove(
+        &self,
+        client_id: ClientId,
+    ) -> Result<Option<RegisteredClient>, StoreError>;
+
+    /// Look up the hash of a client's secret
+    fn get_secret_hash(
+        &self,
+        client_id: ClientId,
+    ) -> Result<Option<String>, StoreError>;
+
+    /// Insert or replace the hash of a client's secret
+    fn put_secret_hash(
+        &self,
+        client_id: ClientId,
+        secret_hash: &str,
+    ) -> Result<(), StoreError>;
+
+    /// Store a client as it registers, along with the hash of its new
+    /// secret and the history entries the registration adds
+    ///
+    /// Stores that can should write all three at once.
+    fn put_registration(
+        &self,
+        client: &RegisteredClient,
+        secret_hash: &str,
+        history: &[HistoryEntry],
+    ) -> Result<(), StoreError> {
+        self.put(client)?;
+        self.put_secret_hash(client.client_id, secret_hash)?;
+        let entries: Vec<_> = history
+            .iter()
+            .map(|entry| (client.client_id, entry.clone()))
+            .collect();
+        self.append_history(&entries)
+    }
+
+    /// Replace a client after a heartbeat that changed nothing but its
+    /// heartbeat time and metrics
+    ///
+    /// Losing these to a crash costs little, since clients heartbeat again
+    /// soon after, so stores that write every change out in full may hold
+    /// them back for a while.
+    fn put_heartbeat(
+        &self,
+        client: &RegisteredClient,
+    ) -> Result<(), StoreError> {
+        self.put(client)
+    }
+
+    /// Write out any changes the store is holding back, such as
+    /// [`put_heartbeat`](Self::put_heartbeat) calls
+    ///
+    /// Stores that write every change straight away have nothing to do.
+    fn flush(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    /// Append entries to the histories of one or more clients
+    ///
+    /// Entries that have outlived [`history_retention`] are dropped from
+    /// the histories appended to.
+    fn append_history(
+        &self,
+        entries: &[(ClientId, HistoryEntry)],
+    ) -> Result<(), StoreError>;
+
+    /// Return a client's history, oldest first
+    fn history(
+        &self,
+        client_id: ClientId,
+    ) -> Result<Vec<HistoryEntry>, StoreError>;
+
+    /// Return every ban
+    fn list_bans(&self) -> Result<Vec<Ban>, StoreError>;
+
+    /// Insert or replace a ban (keyed by kind and value)
+    fn put_ban(&self, ban: &Ban) -> Result<(), StoreError>;
+
+    /// Remove a ban, returning whether it was present
+    fn remove_ban(
+        &self,
+        kind: BanKind,
+        value: &str,
+    ) -> Result<bool, StoreError>;
+
+    /// Return every maintenance window
+    fn list_maintenance_windows(
+        &self,
+    ) -> Result<Vec<MaintenanceWindow>, StoreError>;
+
+    /// Insert or replace a maintenance window (keyed by ID)
+    fn put_maintenance_window(
+        &self,
+        window: &MaintenanceWindow,
+    ) -> Result<(), StoreError>;
+
+    /// Remove a maintenance window, returning whether it was present
+    fn remove_maintenance_window(&self, id: Uuid) -> Result<bool, StoreError>;
+
+    /// Return every revision
+    fn list_revisions(&self) -> Result<Vec<Revision>, StoreError>;
+
+    /// Insert or replace a revision (keyed by its key)
+    fn put_revision(&self, revision: &Revision) -> Result<(), StoreError>;
+
+    /// Remove a revision, returning whether it was present
+    fn remove_revision(&self, key: RevisionKey) -> Result<bool, StoreError>;
+}
+
+/// Available storage backends
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum StoreKind {
+    /// Keep the registry in memory only
+    Memory,
+    /// Store the registry in a SQLite database
+    Sqlite,
+    /// Store the registry in a JSON snapshot file
+    Json,
+}
+
+/// Open a store of the given kind
+///
+/// `path` is required for the `sqlite` and `json` backends and ignored for
+/// the `memory` backend.
+pub fn open(
+    kind: StoreKind,
+    path: Option<&Path>,
+) -> Result<Arc<dyn RegistryStore>, StoreError> {
+    let store: Arc<dyn RegistryStore> = match (kind, path) {
+        (StoreKind::Memory, _) => Arc::new(MemoryStore::new()),
+        (StoreKind::Sqlite, Some(path)) => Arc::new(SqliteStore::open(path)?),
+        (StoreKind::Json, Some(path)) => Arc::new(JsonStore::open(path)?),
+        (kind, None) => return Err(StoreError::MissingPath(kind)),
+    };
+    Ok(store)
+}
+
+/// Storage errors
+#[derive(Debug, thiserror::Error)]
+pub enum StoreError {
+    #[error("A path is required for the {0:?} store")]
+    MissingPath(StoreKind),
+
+    #[error("SQLite error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Failed to encode client record: {0}")]
+    Serialization(#[from] serde_json::Error),
+}
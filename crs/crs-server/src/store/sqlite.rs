@@ -0,0 +1,363 @@
+// Copyright 2025 Oxide Computer Company
+
+//! SQLite registry store
+//!
+//! Each client is stored as a JSON document keyed by its client ID, which
+//! keeps the schema stable as client metadata evolves. Client secret hashes
+//! live in a separate table so they never end up in a client record. Bans
+//! are stored the same way as clients, keyed by their kind and value.
+//! History entries are JSON documents too, one row per entry, ordered by an
+//! autoincrementing row ID. Maintenance windows are keyed by their ID, and
+//! revisions by their key encoded as JSON.
+
+use super::{
+    history_retention, RegistryStore, Revision, RevisionKey, StoreError,
+};
+use chrono::Utc;
+use crs_common::{
+    Ban, BanKind, ClientId, HistoryEntry, MaintenanceWindow, RegisteredClient,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+/// Registry store backed by a SQLite database
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    /// Open (or create) the database at the given path
+    ///
+    /// Creates the `clients`, `client_secrets`, `client_history`, `bans`,
+    /// `maintenance_windows` and `revisions` tables if they do not exist
+    /// yet.
+    pub fn open(path: &Path) -> Result<Self, StoreError> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS clients (
+                client_id TEXT PRIMARY KEY NOT NULL,
+                record TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS client_secrets (
+                client_id TEXT PRIMARY KEY NOT NULL,
+                secret_hash TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS client_history (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                client_id TEXT NOT NULL,
+                record TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS client_history_client_id
+                ON client_history (client_id, id);
+            CREATE TABLE IF NOT EXISTS bans (
+                kind TEXT NOT NULL,
+                value TEXT NOT NULL,
+                record TEXT NOT NULL,
+                PRIMARY KEY (kind, value)
+            );
+            CREATE TABLE IF NOT EXISTS maintenance_windows (
+                id TEXT PRIMARY KEY NOT NULL,
+                record TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS revisions (
+                key TEXT PRIMARY KEY NOT NULL,
+                record TEXT NOT NULL
+            );",
+        )?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Run a query returning JSON records and decode every row
+    fn load_records<T: serde::de::DeserializeOwned>(
+        &self,
+        query: &str,
+    ) -> Result<Vec<T>, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(query)?;
+        let records = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        records
+            .iter()
+            .map(|record| Ok(serde_json::from_str(record)?))
+            .collect()
+    }
+}
+
+impl RegistryStore for SqliteStore {
+    fn get(
+        &self,
+        client_id: ClientId,
+    ) -> Result<Option<RegisteredClient>, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let record: Option<String> = conn
+            .query_row(
+                "SELECT record FROM clients WHERE client_id = ?1",
+                params![client_id.to_string()],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(record.map(|r| serde_json::from_str(&r)).transpose()?)
+    }
+
+    fn list(&self) -> Result<Vec<RegisteredClient>, StoreError> {
+        self.load_records("SELECT record FROM clients")
+    }
+
+    fn put_many(&self, clients: &[RegisteredClient]) -> Result<(), StoreError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        write_clients(&tx, clients)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn remove(
+        &self,
+        client_id: ClientId,
+    ) -> Result<Option<RegisteredClient>, StoreError> {
+        let existing = self.get(client_id)?;
+        if existing.is_some() {
+            let mut conn = self.conn.lock().unwrap();
+            let tx = conn.transaction()?;
+            tx.execute(
+                "DELETE FROM clients WHERE client_id = ?1",
+                params![client_id.to_string()],
+            )?;
+            tx.execute(
+                "DELETE FROM client_secrets WHERE client_id = ?1",
+                params![client_id.to_string()],
+            )?;
+            tx.execute(
+                "DELETE FROM client_history WHERE client_id = ?1",
+                params![client_id.to_string()],
+            )?;
+            tx.commit()?;
+        }
+        Ok(existing)
+    }
+
+    fn get_secret_hash(
+        &self,
+        client_id: ClientId,
+    ) -> Result<Option<String>, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn
+            .query_row(
+                "SELECT secret_hash FROM client_secrets WHERE client_id = ?1",
+                params![client_id.to_string()],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    fn put_secret_hash(
+        &self,
+        client_id: ClientId,
+        secret_hash: &str,
+    ) -> Result<(), StoreError> {
+        let conn = self.conn.lock().unwrap();
+        write_secret_hash(&conn, client_id, secret_hash)
+    }
+
+    fn put_registration(
+        &self,
+        client: &RegisteredClient,
+        secret_hash: &str,
+        history: &[HistoryEntry],
+    ) -> Result<(), StoreError> {
+        let entries: Vec<_> = history
+            .iter()
+            .map(|entry| (client.client_id, entry.clone()))
+            .collect();
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        write_clients(&tx, std::slice::from_ref(client))?;
+        write_secret_hash(&tx, client.client_id, secret_hash)?;
+        write_history(&tx, &entries)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn append_history(
+        &self,
+        entries: &[(ClientId, HistoryEntry)],
+    ) -> Result<(), StoreError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        write_history(&tx, entries)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn history(
+        &self,
+        client_id: ClientId,
+    ) -> Result<Vec<HistoryEntry>, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT record FROM client_history WHERE client_id = ?1
+             ORDER BY id",
+        )?;
+        let records = stmt
+            .query_map(params![client_id.to_string()], |row| {
+                row.get::<_, String>(0)
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        records
+            .iter()
+            .map(|record| Ok(serde_json::from_str(record)?))
+            .collect()
+    }
+
+    fn list_bans(&self) -> Result<Vec<Ban>, StoreError> {
+        self.load_records("SELECT record FROM bans")
+    }
+
+    fn put_ban(&self, ban: &Ban) -> Result<(), StoreError> {
+        let record = serde_json::to_string(ban)?;
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO bans (kind, value, record) VALUES (?1, ?2, ?3)
+             ON CONFLICT(kind, value) DO UPDATE SET record = excluded.record",
+            params![ban.kind.to_string(), ban.value, record],
+        )?;
+        Ok(())
+    }
+
+    fn remove_ban(
+        &self,
+        kind: BanKind,
+        value: &str,
+    ) -> Result<bool, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let removed = conn.execute(
+            "DELETE FROM bans WHERE kind = ?1 AND value = ?2",
+            params![kind.to_string(), value],
+        )?;
+        Ok(removed > 0)
+    }
+
+    fn list_maintenance_windows(
+        &self,
+    ) -> Result<Vec<MaintenanceWindow>, StoreError> {
+        self.load_records("SELECT record FROM maintenance_windows")
+    }
+
+    fn put_maintenance_window(
+        &self,
+        window: &MaintenanceWindow,
+    ) -> Result<(), StoreError> {
+        let record = serde_json::to_string(window)?;
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO maintenance_windows (id, record) VALUES (?1, ?2)
+             ON CONFLICT(id) DO UPDATE SET record = excluded.record",
+            params![window.id.to_string(), record],
+        )?;
+        Ok(())
+    }
+
+    fn remove_maintenance_window(&self, id: Uuid) -> Result<bool, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let removed = conn.execute(
+            "DELETE FROM maintenance_windows WHERE id = ?1",
+            params![id.to_string()],
+        )?;
+        Ok(removed > 0)
+    }
+
+    fn list_revisions(&self) -> Result<Vec<Revision>, StoreError> {
+        self.load_records("SELECT record FROM revisions")
+    }
+
+    fn put_revision(&self, revision: &Revision) -> Result<(), StoreError> {
+        let key = serde_json::to_string(&revision.key)?;
+        let record = serde_json::to_string(revision)?;
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO revisions (key, record) VALUES (?1, ?2)
+             ON CONFLICT(key) DO UPDATE SET record = excluded.record",
+            params![key, record],
+        )?;
+        Ok(())
+    }
+
+    fn remove_revision(&self, key: RevisionKey) -> Result<bool, StoreError> {
+        let key = serde_json::to_string(&key)?;
+        let conn = self.conn.lock().unwrap();
+        let removed =
+            conn.execute("DELETE FROM revisions WHERE key = ?1", params![key])?;
+        Ok(removed > 0)
+    }
+}
+
+/// Insert or replace client records
+fn write_clients(
+    conn: &Connection,
+    clients: &[RegisteredClient],
+) -> Result<(), StoreError> {
+    for client in clients {
+        let record = serde_json::to_string(client)?;
+        conn.execute(
+            "INSERT INTO clients (client_id, record) VALUES (?1, ?2)
+             ON CONFLICT(client_id) DO UPDATE SET record = excluded.record",
+            params![client.client_id.to_string(), record],
+        )?;
+    }
+    Ok(())
+}
+
+/// Insert or replace the hash of a client's secret
+fn write_secret_hash(
+    conn: &Connection,
+    client_id: ClientId,
+    secret_hash: &str,
+) -> Result<(), StoreError> {
+    conn.execute(
+        "INSERT INTO client_secrets (client_id, secret_hash) VALUES (?1, ?2)
+         ON CONFLICT(client_id) DO UPDATE
+         SET secret_hash = excluded.secret_hash",
+        params![client_id.to_string(), secret_hash],
+    )?;
+    Ok(())
+}
+
+/// Append history entries and drop the ones that have expired from the
+/// histories appended to
+fn write_history(
+    conn: &Connection,
+    entries: &[(ClientId, HistoryEntry)],
+) -> Result<(), StoreError> {
+    let cutoff = (Utc::now() - history_retention()).to_rfc3339();
+    for (client_id, entry) in entries {
+        let record = serde_json::to_string(entry)?;
+        conn.execute(
+            "INSERT INTO client_history (client_id, record) VALUES (?1, ?2)",
+            params![client_id.to_string(), record],
+        )?;
+    }
+
+    // Keep the newest of the expired entries, which gives the status the
+    // client was in when the retention period began
+    let client_ids: HashSet<_> = entries.iter().map(|(id, _)| id).collect();
+    for client_id in client_ids {
+        conn.execute(
+            "DELETE FROM client_history WHERE client_id = ?1 AND id < (
+                SELECT MAX(id) FROM client_history WHERE client_id = ?1
+                AND julianday(json_extract(record, '$.timestamp'))
+                    < julianday(?2)
+             )",
+            params![client_id.to_string(), cutoff],
+        )?;
+    }
+    Ok(())
+}
@@ -2,122 +2,339 @@
 
 //! Web dashboard
 //!
-//! This module provides the HTML web dashboard endpoint for viewing
-//! registered clients in a web browser.
+//! This module provides the HTML web dashboard for viewing registered
+//! clients in a web browser, and a detail page for each client showing its
+//! history.
 
 // Suppress warnings for Dropshot's macro-generated phantom types
 #![allow(dead_code)]
 
-use crate::api::ApiContext;
-use crs_common::ClientStatus;
-use dropshot::{endpoint, Body, HttpError, RequestContext};
+use crate::admin::ClientPath;
+use crate::api::{require_peer, ApiContext};
+use crate::health;
+use crate::listing;
+use crs_common::{
+    AvailabilityStats, AvailabilityWindow, ClientSortKey, ClientStatus,
+    ClusterStatus, HistoryChange, HistoryEntry, LoadAverage, MetricsSample,
+    RegisteredClient, Selector, Usage,
+};
+use dropshot::{
+    endpoint, Body, HttpError, PaginationOrder, Path, Query, RequestContext,
+};
 use http::{Response, StatusCode};
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+/// Stylesheet shared by every page
+const STYLE: &str = "
+        body {
+            font-family: monospace;
+            margin: 20px;
+            background-color: #f5f5f5;
+        }
+        h1 {
+            color: #333;
+        }
+        h2 {
+            color: #555;
+            margin-top: 30px;
+        }
+        table {
+            border-collapse: collapse;
+            width: 100%;
+            background-color: white;
+            box-shadow: 0 2px 4px rgba(0,0,0,0.1);
+            margin-bottom: 20px;
+        }
+        th, td {
+            border: 1px solid #ddd;
+            padding: 8px;
+            text-align: left;
+        }
+        th {
+            background-color: #4CAF50;
+            color: white;
+        }
+        tr:nth-child(even) {
+            background-color: #f9f9f9;
+        }
+        .info {
+            margin: 10px 0;
+            color: #666;
+        }
+        .server-info {
+            background-color: #e8f5e9;
+        }
+        tr.quarantined {
+            background-color: #fff3e0;
+        }
+";
+
+/// Format a duration as its two most significant units, e.g. `3h 12m`
+fn format_duration(duration: chrono::Duration) -> String {
+    if duration.num_days() > 0 {
+        format!("{}d {}h", duration.num_days(), duration.num_hours() % 24)
+    } else if duration.num_hours() > 0 {
+        format!("{}h {}m", duration.num_hours(), duration.num_minutes() % 60)
+    } else if duration.num_minutes() > 0 {
+        format!("{}m", duration.num_minutes())
+    } else {
+        format!("{}s", duration.num_seconds())
+    }
+}
+
+/// Format an availability percentage, or `-` if nothing was observed
+fn format_availability(stats: &AvailabilityStats) -> String {
+    match stats.availability_percent {
+        Some(percent) => format!("{:.2}%", percent),
+        None => "-".to_string(),
+    }
+}
+
+/// Format a size in bytes with a binary unit
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Format resource use as a percentage of the total
+fn format_usage(usage: Option<&Usage>) -> String {
+    match usage {
+        Some(usage) => format!(
+            "{:.0}% of {}",
+            usage.used_percent(),
+            format_bytes(usage.total_bytes)
+        ),
+        None => "-".to_string(),
+    }
+}
+
+/// Format the 1, 5 and 15 minute load averages
+fn format_load(load: Option<&LoadAverage>) -> String {
+    match load {
+        Some(load) => {
+            format!("{:.2} {:.2} {:.2}", load.one, load.five, load.fifteen)
+        }
+        None => "-".to_string(),
+    }
+}
+
+/// Summarize a client's latest metrics as load, memory and disk use
+fn format_metrics_summary(sample: Option<&MetricsSample>) -> String {
+    let Some(sample) = sample else {
+        return "-".to_string();
+    };
+    let metrics = &sample.metrics;
+    let percent = |usage: Option<&Usage>| {
+        usage
+            .map(|u| format!("{:.0}%", u.used_percent()))
+            .unwrap_or_else(|| "-".to_string())
+    };
+    format!(
+        "{} / {} / {}",
+        metrics
+            .load_average
+            .map(|load| format!("{:.2}", load.one))
+            .unwrap_or_else(|| "-".to_string()),
+        percent(metrics.memory.as_ref()),
+        percent(metrics.root_fs.as_ref()),
+    )
+}
+
+/// Dashboard color and label for a client status
+fn status_style(status: ClientStatus) -> (&'static str, &'static str) {
+    match status {
+        ClientStatus::Online => ("green", "online"),
+        ClientStatus::Degraded => ("darkorange", "degraded"),
+        ClientStatus::Stale => ("goldenrod", "stale"),
+        ClientStatus::Offline => ("red", "offline"),
+        ClientStatus::Departed => ("gray", "departed"),
+        ClientStatus::Maintenance => ("steelblue", "maintenance"),
+    }
+}
+
+/// Dashboard color and label for a client's current status
+///
+/// Like [`status_style`], except that a client missing heartbeats inside a
+/// maintenance window is shown as an expected outage rather than in red.
+fn client_status_style(client: &RegisteredClient) -> (&'static str, String) {
+    let (color, text) = status_style(client.status);
+    match client.status {
+        ClientStatus::Stale | ClientStatus::Offline
+            if client.maintenance_window.is_some() =>
+        {
+            ("steelblue", format!("{} (expected)", text))
+        }
+        _ => (color, text.to_string()),
+    }
+}
+
+/// Escape text reported by a client or an operator for inclusion in HTML
+fn html_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Wrap an HTML page in a response
+fn html_response(html: String) -> Result<Response<Body>, HttpError> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "text/html; charset=utf-8")
+        .body(html.into())
+        .map_err(|e| {
+            HttpError::for_internal_error(format!(
+                "failed to build response: {}",
+                e
+            ))
+        })
+}
 
 /// Serve the web dashboard
 ///
 /// Generates an HTML page displaying all registered clients in a table
 /// with their status, information, and last heartbeat time. The page
 /// auto-refreshes every 10 seconds. Status is color-coded:
-/// - Green: online (heartbeat within 15 seconds)
-/// - Red: offline (no heartbeat for 15+ seconds)
+/// - Green: online (heartbeat within the offline threshold)
+/// - Orange: degraded (heartbeating, but a health check fails)
+/// - Yellow: stale (missed a heartbeat, but within the offline threshold)
+/// - Red: offline (no heartbeat within the offline threshold)
+/// - Gray: departed (client deregistered during a clean shutdown)
+/// - Blue: maintenance (put in maintenance by an operator), or stale or
+///   offline inside a scheduled maintenance window
+///
+/// Quarantined clients are highlighted in orange. Each client's
+/// availability is shown over the window picked with `?window=` (24 hours
+/// by default), and `?selector=` narrows the list down to clients whose
+/// tags match a selector. Servers in a cluster also list their peers and
+/// whether the most recent sync with each succeeded.
 #[endpoint {
     method = GET,
     path = "/",
+    unpublished = true,
 }]
 pub async fn dashboard(
     ctx: RequestContext<ApiContext>,
+    query: Query<DashboardQuery>,
 ) -> Result<Response<Body>, HttpError> {
+    require_peer(&ctx)?;
     let api_context = ctx.context();
     let registry = &api_context.registry;
-    let mut clients = registry.list_clients();
-
-    // Sort clients by IP address
-    clients.sort_by(|a, b| a.info.ip_address.cmp(&b.info.ip_address));
-
-    // Get server information
-    let server_hostname = hostname::get()
-        .ok()
-        .and_then(|h| h.into_string().ok())
-        .unwrap_or_else(|| "unknown".to_string());
-    let server_os = std::env::consts::OS;
-    let server_version = env!("CARGO_PKG_VERSION");
-    let server_bind_addr = ctx.server.local_addr;
+    let query = query.into_inner();
+    let window = query.window.unwrap_or(AvailabilityWindow::Day);
+    let selector: Selector = query
+        .selector
+        .as_deref()
+        .unwrap_or_default()
+        .parse()
+        .map_err(|e| {
+            HttpError::for_bad_request(None, format!("Invalid selector: {}", e))
+        })?;
+    let mut clients = registry.list_clients()?;
+    clients.retain(|client| selector.matches(&client.info.tags));
+    listing::sort_clients(
+        &mut clients,
+        ClientSortKey::IpAddress,
+        PaginationOrder::Ascending,
+    );
 
-    // Calculate server uptime
-    let now = chrono::Utc::now();
-    let uptime_duration = now - api_context.start_time;
-    let uptime_str = if uptime_duration.num_days() > 0 {
-        format!(
-            "{}d {}h",
-            uptime_duration.num_days(),
-            uptime_duration.num_hours() % 24
-        )
-    } else if uptime_duration.num_hours() > 0 {
-        format!(
-            "{}h {}m",
-            uptime_duration.num_hours(),
-            uptime_duration.num_minutes() % 60
-        )
-    } else if uptime_duration.num_minutes() > 0 {
-        format!("{}m", uptime_duration.num_minutes())
-    } else {
-        format!("{}s", uptime_duration.num_seconds())
+    // Get server information, as served by `GET /api/server`
+    let server = health::server_info(&ctx);
+    let server_version = match &server.git_commit {
+        Some(commit) => format!("{} ({})", server.version, commit),
+        None => server.version.clone(),
     };
+    let uptime_str = format_duration(chrono::Utc::now() - server.start_time);
 
     let mut rows = String::new();
     for client in &clients {
-        let status_color = match client.status {
-            ClientStatus::Online => "green",
-            ClientStatus::Offline => "red",
-        };
-
-        let status_text = match client.status {
-            ClientStatus::Online => "online",
-            ClientStatus::Offline => "offline",
-        };
+        let (status_color, status_text) = client_status_style(client);
+        let connected_str = format_duration(client.time_connected());
+        let availability =
+            registry.availability(client.client_id, &[window])?;
+        let availability_str = format_availability(&availability[0]);
+        let metrics_str = format_metrics_summary(client.metrics.as_ref());
 
-        // Calculate time connected
-        let connected_duration = client.time_connected();
-        let connected_str = if connected_duration.num_days() > 0 {
-            format!(
-                "{}d {}h",
-                connected_duration.num_days(),
-                connected_duration.num_hours() % 24
-            )
-        } else if connected_duration.num_hours() > 0 {
-            format!(
-                "{}h {}m",
-                connected_duration.num_hours(),
-                connected_duration.num_minutes() % 60
-            )
-        } else if connected_duration.num_minutes() > 0 {
-            format!("{}m", connected_duration.num_minutes())
+        let (row_class, quarantine_str) = if client.quarantine.is_some() {
+            (r#" class="quarantined""#, " (quarantined)")
         } else {
-            format!("{}s", connected_duration.num_seconds())
+            ("", "")
         };
 
         rows.push_str(&format!(
             r#"
-        <tr>
+        <tr{}>
+            <td><a href="/clients/{}">{}</a></td>
             <td>{}</td>
             <td>{}</td>
             <td>{}</td>
+            <td style="color: {}; font-weight: bold;">{}{}</td>
+            <td>{}</td>
             <td>{}</td>
-            <td style="color: {}; font-weight: bold;">{}</td>
             <td>{}</td>
             <td>{}</td>
         </tr>"#,
-            client.info.hostname,
-            client.info.ip_address,
-            client.info.os,
+            row_class,
+            client.client_id,
+            html_escape(&client.info.hostname),
+            html_escape(&client.info.ip_address),
+            html_escape(&client.info.os),
             client.first_connected.format("%Y-%m-%d %H:%M:%S UTC"),
             status_color,
             status_text,
+            quarantine_str,
             client.last_heartbeat.format("%Y-%m-%d %H:%M:%S UTC"),
             connected_str,
+            availability_str,
+            metrics_str,
         ));
     }
 
+    // Cluster membership, if this server replicates with others
+    let cluster_section = match &api_context.cluster {
+        Some(cluster) => format_cluster(&cluster.status()),
+        None => String::new(),
+    };
+
+    // Links for switching the availability window, keeping the selector.
+    // A parsed selector only holds characters that are safe in HTML, so it
+    // can be shown as it is once it has been written back out.
+    let selector = selector.to_string();
+    let selector_param = if selector.is_empty() {
+        String::new()
+    } else {
+        format!("&amp;selector={}", query_escape(&selector))
+    };
+    let mut window_links = Vec::new();
+    for option in AvailabilityWindow::ALL {
+        if option == window {
+            window_links.push(format!("<b>{}</b>", option));
+        } else {
+            window_links.push(format!(
+                r#"<a href="/?window={0}{1}">{0}</a>"#,
+                option, selector_param
+            ));
+        }
+    }
+
     let html = format!(
         r#"<!DOCTYPE html>
 <html>
@@ -125,51 +342,13 @@ pub async fn dashboard(
     <meta charset="utf-8">
     <title>Central Registry Service</title>
     <meta http-equiv="refresh" content="10">
-    <style>
-        body {{
-            font-family: monospace;
-            margin: 20px;
-            background-color: #f5f5f5;
-        }}
-        h1 {{
-            color: #333;
-        }}
-        h2 {{
-            color: #555;
-            margin-top: 30px;
-        }}
-        table {{
-            border-collapse: collapse;
-            width: 100%;
-            background-color: white;
-            box-shadow: 0 2px 4px rgba(0,0,0,0.1);
-            margin-bottom: 20px;
-        }}
-        th, td {{
-            border: 1px solid #ddd;
-            padding: 8px;
-            text-align: left;
-        }}
-        th {{
-            background-color: #4CAF50;
-            color: white;
-        }}
-        tr:nth-child(even) {{
-            background-color: #f9f9f9;
-        }}
-        .info {{
-            margin: 10px 0;
-            color: #666;
-        }}
-        .server-info {{
-            background-color: #e8f5e9;
-        }}
-    </style>
+    <style>{}</style>
 </head>
 <body>
     <h1>Central Registry Service</h1>
     <div class="info">
-        Page auto-refreshes every 10 seconds
+        Page auto-refreshes every 10 seconds &middot;
+        Availability over {}
     </div>
 
     <h2>Server Information</h2>
@@ -189,8 +368,15 @@ pub async fn dashboard(
             <td>{}</td>
         </tr>
     </table>
-
+{}
     <h2>Registered Clients ({})</h2>
+    <form class="info" method="get" action="/">
+        <input type="hidden" name="window" value="{}">
+        Tag selector:
+        <input type="text" name="selector" size="60" value="{}"
+            placeholder="env=prod,region in (us-west,us-east),!decommissioned">
+        <input type="submit" value="Filter">
+    </form>
     <table>
         <tr>
             <th>Hostname</th>
@@ -200,28 +386,515 @@ pub async fn dashboard(
             <th>Status</th>
             <th>Last Heartbeat</th>
             <th>Time Connected</th>
+            <th>Availability ({})</th>
+            <th>Load / Mem / Disk</th>
         </tr>
         {}
     </table>
 </body>
 </html>"#,
-        server_hostname,
-        server_bind_addr,
-        server_os,
+        STYLE,
+        window_links.join(" | "),
+        server.hostname,
+        server.bind_address,
+        server.os,
         server_version,
         uptime_str,
+        cluster_section,
         clients.len(),
+        window,
+        selector,
+        window,
         rows
     );
 
-    Response::builder()
-        .status(StatusCode::OK)
-        .header("content-type", "text/html; charset=utf-8")
-        .body(html.into())
-        .map_err(|e| {
-            HttpError::for_internal_error(format!(
-                "failed to build response: {}",
-                e
-            ))
-        })
+    html_response(html)
+}
+
+/// Render the dashboard's cluster membership section
+fn format_cluster(status: &ClusterStatus) -> String {
+    let mut rows = String::new();
+    for member in &status.members {
+        let (color, text) = if member.reachable {
+            ("green", "reachable")
+        } else {
+            ("red", "unreachable")
+        };
+        let last_sync = match member.last_sync {
+            Some(at) => at.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+            None => "never".to_string(),
+        };
+        rows.push_str(&format!(
+            r#"
+        <tr>
+            <td>{}</td>
+            <td>{}</td>
+            <td style="color: {}; font-weight: bold;">{}</td>
+            <td>{}</td>
+            <td>{}</td>
+        </tr>"#,
+            html_escape(&member.url),
+            html_escape(member.node.as_deref().unwrap_or("-")),
+            color,
+            text,
+            last_sync,
+            html_escape(member.error.as_deref().unwrap_or("")),
+        ));
+    }
+
+    format!(
+        r#"
+    <h2>Cluster ({} peer(s))</h2>
+    <div class="info">This node: {}</div>
+    <table>
+        <tr>
+            <th>Peer</th>
+            <th>Node</th>
+            <th>Status</th>
+            <th>Last Sync</th>
+            <th>Error</th>
+        </tr>
+        {}
+    </table>
+"#,
+        status.members.len(),
+        html_escape(&status.node),
+        rows
+    )
+}
+
+/// Query parameters for the dashboard
+#[derive(Deserialize, JsonSchema)]
+pub struct DashboardQuery {
+    /// Window to show availability over (24h, 7d or 30d)
+    pub window: Option<AvailabilityWindow>,
+
+    /// Only show clients whose tags match this selector
+    pub selector: Option<String>,
+}
+
+/// Percent-encode a query parameter value
+fn query_escape(value: &str) -> String {
+    let mut escaped = String::new();
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-_.~".contains(&byte) {
+            escaped.push(byte as char);
+        } else {
+            escaped.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    escaped
+}
+
+/// Name and details of a history entry for the history table
+fn describe_change(change: &HistoryChange) -> (&'static str, String) {
+    match change {
+        HistoryChange::Registered { first: true } => {
+            ("registered", "first registration".to_string())
+        }
+        HistoryChange::Registered { first: false } => {
+            ("registered", "registered again".to_string())
+        }
+        HistoryChange::StatusChanged { from, to, expected } => (
+            "status changed",
+            format!(
+                "{} &rarr; {}{}",
+                status_style(*from).1,
+                status_style(*to).1,
+                if *expected { " (expected)" } else { "" }
+            ),
+        ),
+        HistoryChange::InfoChanged { previous, current } => {
+            let mut changes = Vec::new();
+            let fields = [
+                ("hostname", &previous.hostname, &current.hostname),
+                ("os", &previous.os, &current.os),
+                ("ip", &previous.ip_address, &current.ip_address),
+                ("version", &previous.version, &current.version),
+            ];
+            for (name, old, new) in fields {
+                if old != new {
+                    changes.push(format!(
+                        "{}: {} &rarr; {}",
+                        name,
+                        html_escape(old),
+                        html_escape(new)
+                    ));
+                }
+            }
+            if previous.host_id != current.host_id {
+                changes.push("host ID changed".to_string());
+            }
+            if previous.tags != current.tags {
+                changes.push(format!("tags: {}", format_tags(current)));
+            }
+            ("info changed", changes.join(", "))
+        }
+        HistoryChange::Quarantined { reason } => (
+            "quarantined",
+            reason
+                .as_deref()
+                .map(html_escape)
+                .unwrap_or_else(|| "no reason given".to_string()),
+        ),
+        HistoryChange::QuarantineLifted => ("quarantine lifted", String::new()),
+    }
+}
+
+/// Client tags as sorted `key=value` pairs, escaped for HTML
+fn format_tags(info: &crs_common::ClientInfo) -> String {
+    let mut tags: Vec<_> = info
+        .tags
+        .iter()
+        .map(|(k, v)| html_escape(&format!("{}={}", k, v)))
+        .collect();
+    tags.sort();
+    tags.join(", ")
+}
+
+/// How long each status in a history lasted
+///
+/// Returns one entry per history entry: for entries that set the client's
+/// status, the time until the next such entry (or until `now` for the
+/// current one), and `None` for the rest.
+fn status_durations(
+    entries: &[HistoryEntry],
+    now: chrono::DateTime<chrono::Utc>,
+) -> Vec<Option<chrono::Duration>> {
+    let mut durations = vec![None; entries.len()];
+    let mut end = now;
+    for (i, entry) in entries.iter().enumerate().rev() {
+        if entry.resulting_status().is_some() {
+            durations[i] = Some(end - entry.timestamp);
+            end = entry.timestamp;
+        }
+    }
+    durations
+}
+
+/// Serve the detail page for one client
+///
+/// Shows everything known about the client, its availability over every
+/// window, its latest health checks, its recent system metrics, and its
+/// history, newest first.
+/// Entries that changed the client's status also show how long that status
+/// lasted, so outages can be read straight off the page.
+#[endpoint {
+    method = GET,
+    path = "/clients/{client_id}",
+    unpublished = true,
+}]
+pub async fn client_detail(
+    ctx: RequestContext<ApiContext>,
+    path: Path<ClientPath>,
+) -> Result<Response<Body>, HttpError> {
+    require_peer(&ctx)?;
+    let registry = &ctx.context().registry;
+    let client_id = path.into_inner().client_id;
+    let client = registry.get_client(client_id)?;
+    let history = registry.client_history(client_id)?;
+    let now = chrono::Utc::now();
+
+    let (status_color, status_text) = client_status_style(&client);
+    let quarantine_str = match &client.quarantine {
+        Some(q) => format!(
+            "since {}{}",
+            q.since.format("%Y-%m-%d %H:%M:%S UTC"),
+            q.reason
+                .as_ref()
+                .map(|r| format!(": {}", html_escape(r)))
+                .unwrap_or_default()
+        ),
+        None => "no".to_string(),
+    };
+    let maintenance_str = match &client.maintenance {
+        Some(m) => format!(
+            "since {}{}",
+            m.since.format("%Y-%m-%d %H:%M:%S UTC"),
+            m.reason
+                .as_ref()
+                .map(|r| format!(": {}", html_escape(r)))
+                .unwrap_or_default()
+        ),
+        None => "no".to_string(),
+    };
+    let window = match client.maintenance_window {
+        Some(id) => registry
+            .list_maintenance_windows()?
+            .into_iter()
+            .find(|window| window.id == id),
+        None => None,
+    };
+    let window_str = match window {
+        Some(w) => format!(
+            "{}{}",
+            w.id,
+            w.reason
+                .as_ref()
+                .map(|r| format!(": {}", html_escape(r)))
+                .unwrap_or_default()
+        ),
+        None => "no".to_string(),
+    };
+
+    let details = [
+        ("Client ID", client.client_id.to_string()),
+        ("Hostname", html_escape(&client.info.hostname)),
+        ("IP Address", html_escape(&client.info.ip_address)),
+        ("OS", html_escape(&client.info.os)),
+        ("Version", html_escape(&client.info.version)),
+        (
+            "Host ID",
+            html_escape(client.info.host_id.as_deref().unwrap_or("-")),
+        ),
+        ("Tags", format_tags(&client.info)),
+        (
+            "Status",
+            format!(
+                r#"<span style="color: {}; font-weight: bold;">{}</span>"#,
+                status_color, status_text
+            ),
+        ),
+        (
+            "First Connected",
+            client
+                .first_connected
+                .format("%Y-%m-%d %H:%M:%S UTC")
+                .to_string(),
+        ),
+        (
+            "Registered At",
+            client
+                .registered_at
+                .format("%Y-%m-%d %H:%M:%S UTC")
+                .to_string(),
+        ),
+        (
+            "Last Heartbeat",
+            client
+                .last_heartbeat
+                .format("%Y-%m-%d %H:%M:%S UTC")
+                .to_string(),
+        ),
+        ("Time Connected", format_duration(client.time_connected())),
+        ("Quarantined", quarantine_str),
+        ("Maintenance", maintenance_str),
+        ("Maintenance Window", window_str),
+    ];
+    let mut detail_rows = String::new();
+    for (name, value) in details {
+        detail_rows.push_str(&format!(
+            r#"
+        <tr>
+            <th>{}</th>
+            <td>{}</td>
+        </tr>"#,
+            name, value
+        ));
+    }
+
+    // The most recent status entry is still in effect
+    let current = history.iter().rposition(|e| e.resulting_status().is_some());
+    let mut availability_rows = String::new();
+    for stats in registry.availability(client_id, &AvailabilityWindow::ALL)? {
+        let seconds = chrono::Duration::seconds;
+        availability_rows.push_str(&format!(
+            r#"
+        <tr>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+        </tr>"#,
+            stats.window,
+            format_availability(&stats),
+            stats.outages,
+            if stats.outages > 0 {
+                format_duration(seconds(stats.longest_outage_secs as i64))
+            } else {
+                "-".to_string()
+            },
+            stats
+                .mttr_secs
+                .map(|secs| format_duration(seconds(secs as i64)))
+                .unwrap_or_else(|| "-".to_string()),
+        ));
+    }
+
+    let mut check_rows = String::new();
+    for check in &client.checks {
+        let (color, result) = if check.passed {
+            ("green", "pass")
+        } else {
+            ("red", "fail")
+        };
+        check_rows.push_str(&format!(
+            r#"
+        <tr>
+            <td>{}</td>
+            <td style="color: {}; font-weight: bold;">{}</td>
+            <td>{}</td>
+        </tr>"#,
+            html_escape(&check.name),
+            color,
+            result,
+            html_escape(check.message.as_deref().unwrap_or("-")),
+        ));
+    }
+    if client.checks.is_empty() {
+        check_rows = r#"
+        <tr>
+            <td colspan="3">This client does not report health checks</td>
+        </tr>"#
+            .to_string();
+    }
+
+    let samples = registry.metrics_samples(client_id)?;
+    let mut metrics_rows = String::new();
+    for sample in samples.iter().rev() {
+        let metrics = &sample.metrics;
+        metrics_rows.push_str(&format!(
+            r#"
+        <tr>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+        </tr>"#,
+            sample.timestamp.format("%Y-%m-%d %H:%M:%S UTC"),
+            format_load(metrics.load_average.as_ref()),
+            format_usage(metrics.memory.as_ref()),
+            format_usage(metrics.root_fs.as_ref()),
+            metrics
+                .uptime_secs
+                .map(|secs| {
+                    format_duration(chrono::Duration::seconds(secs as i64))
+                })
+                .unwrap_or_else(|| "-".to_string()),
+        ));
+    }
+    if samples.is_empty() {
+        let message = if client.metrics.is_some() {
+            "No metrics received since the server started"
+        } else {
+            "This client does not report metrics"
+        };
+        metrics_rows = format!(
+            r#"
+        <tr>
+            <td colspan="5">{}</td>
+        </tr>"#,
+            message
+        );
+    }
+
+    let durations = status_durations(&history, now);
+    let mut history_rows = String::new();
+    for (i, (entry, duration)) in
+        history.iter().zip(durations).enumerate().rev()
+    {
+        let (event, details) = describe_change(&entry.change);
+        let lasted = match duration {
+            Some(duration) if Some(i) == current => {
+                format!("{} so far", format_duration(duration))
+            }
+            Some(duration) => format_duration(duration),
+            None => String::new(),
+        };
+        history_rows.push_str(&format!(
+            r#"
+        <tr>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+        </tr>"#,
+            entry.timestamp.format("%Y-%m-%d %H:%M:%S UTC"),
+            event,
+            details,
+            lasted,
+        ));
+    }
+
+    let html = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="utf-8">
+    <title>{} - Central Registry Service</title>
+    <meta http-equiv="refresh" content="10">
+    <style>{}</style>
+</head>
+<body>
+    <h1>{}</h1>
+    <div class="info">
+        <a href="/">All clients</a> &middot;
+        Page auto-refreshes every 10 seconds
+    </div>
+
+    <h2>Client</h2>
+    <table>
+        {}
+    </table>
+
+    <h2>Availability</h2>
+    <table>
+        <tr>
+            <th>Window</th>
+            <th>Availability</th>
+            <th>Outages</th>
+            <th>Longest Outage</th>
+            <th>MTTR</th>
+        </tr>
+        {}
+    </table>
+
+    <h2>Health Checks</h2>
+    <table>
+        <tr>
+            <th>Check</th>
+            <th>Result</th>
+            <th>Detail</th>
+        </tr>
+        {}
+    </table>
+
+    <h2>System Metrics</h2>
+    <table>
+        <tr>
+            <th>Time</th>
+            <th>Load (1m 5m 15m)</th>
+            <th>Memory</th>
+            <th>Root Filesystem</th>
+            <th>Uptime</th>
+        </tr>
+        {}
+    </table>
+
+    <h2>History ({})</h2>
+    <table>
+        <tr>
+            <th>Time</th>
+            <th>Event</th>
+            <th>Details</th>
+            <th>Lasted</th>
+        </tr>
+        {}
+    </table>
+</body>
+</html>"#,
+        html_escape(&client.info.hostname),
+        STYLE,
+        html_escape(&client.info.hostname),
+        detail_rows,
+        availability_rows,
+        check_rows,
+        metrics_rows,
+        history.len(),
+        history_rows,
+    );
+
+    html_response(html)
 }
@@ -0,0 +1,528 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Webhook notifications
+//!
+//! Webhooks are configured in the server config file. Each one receives a
+//! JSON [`WebhookPayload`] for the client transitions it is interested in:
+//! first registration, going offline, and coming back. Payloads are derived
+//! from the registry's [`EventBus`], so webhooks see the same transitions as
+//! `/api/events` subscribers, except for expected ones made inside a
+//! maintenance window.
+//!
+//! Every request carries these headers:
+//!
+//! - `X-CRS-Event` - the [`WebhookEvent`] name
+//! - `X-CRS-Delivery` - the event's sequence number, which stays the same
+//!   across retries
+//! - `X-CRS-Signature` - `sha256=` followed by the hex HMAC-SHA256 of the
+//!   body, keyed with the webhook's secret
+//!
+//! Each webhook has its own queue and worker, so a slow or unreachable
+//! receiver does not hold up the others. Deliveries to one webhook are made
+//! in order. Failed deliveries are retried with exponential backoff; once
+//! the attempts run out the payload is dropped and the worker moves on.
+
+use crate::events::{EventBus, Subscription};
+use crs_common::{
+    ClientEvent, Selector, SelectorError, WebhookEvent, WebhookPayload,
+};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+
+/// Payloads waiting for a webhook before new ones are dropped
+const QUEUE_CAPACITY: usize = 1024;
+
+/// Timeout for a single delivery attempt
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default number of delivery attempts
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+
+/// Default wait before the first retry
+const DEFAULT_INITIAL_BACKOFF_SECS: u64 = 1;
+
+/// Longest wait between retries
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// A webhook as written in the server config file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookSpec {
+    /// URL to POST payloads to
+    pub url: String,
+
+    /// Shared secret used to sign each payload
+    pub secret: String,
+
+    /// Transitions to send (defaults to all of them)
+    pub events: Option<Vec<WebhookEvent>>,
+
+    /// Only send transitions for clients whose tags match this selector,
+    /// such as `role=core`
+    pub tag: Option<String>,
+
+    /// Number of delivery attempts before giving up
+    pub max_attempts: Option<u32>,
+
+    /// Seconds to wait before the first retry, doubling after each failure
+    pub initial_backoff_secs: Option<u64>,
+}
+
+/// How failed deliveries are retried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Number of delivery attempts before giving up
+    pub max_attempts: u32,
+
+    /// Wait before the first retry, doubling after each failure
+    pub initial_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            initial_backoff: Duration::from_secs(DEFAULT_INITIAL_BACKOFF_SECS),
+        }
+    }
+}
+
+/// A validated webhook
+#[derive(Clone)]
+pub struct Webhook {
+    url: reqwest::Url,
+    secret: String,
+    events: Vec<WebhookEvent>,
+    tag: Option<Selector>,
+    retry: RetryPolicy,
+}
+
+impl Webhook {
+    /// Validate a webhook from the config file
+    pub fn from_spec(spec: &WebhookSpec) -> Result<Self, WebhookError> {
+        let url = reqwest::Url::parse(&spec.url).map_err(|e| {
+            WebhookError::InvalidUrl(spec.url.clone(), e.to_string())
+        })?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(WebhookError::UnsupportedScheme(spec.url.clone()));
+        }
+        if spec.secret.is_empty() {
+            return Err(WebhookError::EmptySecret(spec.url.clone()));
+        }
+
+        let tag = match &spec.tag {
+            Some(tag) => Some(
+                tag.parse()
+                    .map_err(|e| WebhookError::InvalidTag(tag.clone(), e))?,
+            ),
+            None => None,
+        };
+
+        let defaults = RetryPolicy::default();
+        let retry = RetryPolicy {
+            max_attempts: spec.max_attempts.unwrap_or(defaults.max_attempts),
+            initial_backoff: spec
+                .initial_backoff_secs
+                .map_or(defaults.initial_backoff, Duration::from_secs),
+        };
+        if retry.max_attempts == 0 {
+            return Err(WebhookError::ZeroAttempts(spec.url.clone()));
+        }
+
+        Ok(Self {
+            url,
+            secret: spec.secret.clone(),
+            events: spec.events.clone().unwrap_or_else(|| {
+                vec![
+                    WebhookEvent::Registered,
+                    WebhookEvent::WentOffline,
+                    WebhookEvent::CameBack,
+                    WebhookEvent::Degraded,
+                    WebhookEvent::Recovered,
+                ]
+            }),
+            tag,
+            retry,
+        })
+    }
+
+    /// Use a different retry policy
+    // Only the webhook integration tests shorten the retries, so the server
+    // binary, which builds this module itself, never calls this
+    #[allow(dead_code)]
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// The URL payloads are sent to
+    #[cfg(test)]
+    pub fn url(&self) -> &reqwest::Url {
+        &self.url
+    }
+
+    /// Whether this webhook should receive `payload`
+    fn wants(&self, payload: &WebhookPayload) -> bool {
+        let tag_matches = self
+            .tag
+            .as_ref()
+            .is_none_or(|selector| selector.matches(&payload.client.info.tags));
+        tag_matches && self.events.contains(&payload.event)
+    }
+}
+
+impl std::fmt::Debug for Webhook {
+    // Leaves out the secret
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Webhook")
+            .field("url", &self.url.as_str())
+            .field("events", &self.events)
+            .field("tag", &self.tag)
+            .field("retry", &self.retry)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Compute the `X-CRS-Signature` header value for a payload body
+pub fn signature(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(body);
+    let digest = mac.finalize().into_bytes();
+    let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256={}", hex)
+}
+
+/// Start sending client transitions from `events` to `webhooks`
+///
+/// Only transitions published after this call are sent. Returns
+/// immediately; delivery happens on background tasks.
+pub fn start(events: EventBus, webhooks: Vec<Webhook>) {
+    if webhooks.is_empty() {
+        return;
+    }
+
+    let http_client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .expect("failed to create webhook HTTP client");
+
+    let queues: Vec<_> = webhooks
+        .into_iter()
+        .map(|webhook| {
+            let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+            let url = webhook.url.clone();
+            tokio::spawn(run_worker(http_client.clone(), webhook.clone(), rx));
+            (webhook, url, tx)
+        })
+        .collect();
+
+    // Subscribe before returning so no event published afterwards is missed
+    let subscription = events
+        .subscribe(None)
+        .expect("subscribing from the latest event cannot fail");
+    tokio::spawn(async move {
+        dispatch(events, subscription, |payload| {
+            for (webhook, url, tx) in &queues {
+                if !webhook.wants(&payload) {
+                    continue;
+                }
+                if tx.try_send(payload.clone()).is_err() {
+                    eprintln!(
+                        "Webhook queue for {} is full, dropping {} event {}",
+                        url,
+                        payload.event.as_str(),
+                        payload.sequence
+                    );
+                }
+            }
+        })
+        .await
+    });
+}
+
+/// Turn client events into webhook payloads until the bus goes away
+async fn dispatch(
+    events: EventBus,
+    subscription: Subscription,
+    mut send: impl FnMut(WebhookPayload),
+) {
+    let mut last_sequence = subscription.since;
+    let mut receiver = subscription.receiver;
+
+    loop {
+        let event: ClientEvent = match receiver.recv().await {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(_)) => {
+                // Catch up from the history if it still has what we missed
+                let resumed = match events.subscribe(Some(last_sequence)) {
+                    Ok(resumed) => resumed,
+                    Err(e) => {
+                        eprintln!("Webhooks missed client events: {}", e);
+                        events.subscribe(None).expect(
+                            "subscribing from the latest event cannot fail",
+                        )
+                    }
+                };
+                for event in resumed.backlog {
+                    last_sequence = event.sequence;
+                    if let Some(payload) = to_payload(&event) {
+                        send(payload);
+                    }
+                }
+                last_sequence = last_sequence.max(resumed.since);
+                receiver = resumed.receiver;
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+
+        if event.sequence <= last_sequence {
+            continue;
+        }
+        last_sequence = event.sequence;
+        if let Some(payload) = to_payload(&event) {
+            send(payload);
+        }
+    }
+}
+
+fn to_payload(event: &ClientEvent) -> Option<WebhookPayload> {
+    Some(WebhookPayload {
+        event: WebhookEvent::from_client_event(event)?,
+        sequence: event.sequence,
+        timestamp: event.timestamp,
+        client: event.client.clone(),
+    })
+}
+
+/// Deliver queued payloads to one webhook, in order
+async fn run_worker(
+    http_client: reqwest::Client,
+    webhook: Webhook,
+    mut queue: mpsc::Receiver<WebhookPayload>,
+) {
+    while let Some(payload) = queue.recv().await {
+        deliver(&http_client, &webhook, &payload).await;
+    }
+}
+
+/// Send one payload, retrying with exponential backoff
+///
+/// Returns whether the receiver accepted it.
+async fn deliver(
+    http_client: &reqwest::Client,
+    webhook: &Webhook,
+    payload: &WebhookPayload,
+) -> bool {
+    let body = serde_json::to_vec(payload)
+        .expect("webhook payloads are always serializable");
+    let signature = signature(&webhook.secret, &body);
+    let mut backoff = webhook.retry.initial_backoff;
+
+    for attempt in 1..=webhook.retry.max_attempts {
+        let result = http_client
+            .post(webhook.url.clone())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header("X-CRS-Event", payload.event.as_str())
+            .header("X-CRS-Delivery", payload.sequence.to_string())
+            .header("X-CRS-Signature", &signature)
+            .body(body.clone())
+            .send()
+            .await;
+
+        match result {
+            Ok(response) if response.status().is_success() => return true,
+            Ok(response) if !is_retryable(response.status()) => {
+                eprintln!(
+                    "Webhook {} rejected event {} with {}, not retrying",
+                    webhook.url,
+                    payload.sequence,
+                    response.status()
+                );
+                return false;
+            }
+            Ok(response) => eprintln!(
+                "Webhook {} returned {} for event {} (attempt {}/{})",
+                webhook.url,
+                response.status(),
+                payload.sequence,
+                attempt,
+                webhook.retry.max_attempts
+            ),
+            Err(e) => eprintln!(
+                "Webhook {} failed for event {} (attempt {}/{}): {}",
+                webhook.url,
+                payload.sequence,
+                attempt,
+                webhook.retry.max_attempts,
+                e
+            ),
+        }
+
+        if attempt < webhook.retry.max_attempts {
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    eprintln!(
+        "Giving up on webhook {} for event {}",
+        webhook.url, payload.sequence
+    );
+    false
+}
+
+/// Whether a failed delivery is worth retrying
+///
+/// Server errors, timeouts and rate limiting are; other client errors mean
+/// the receiver will never accept the payload.
+fn is_retryable(status: reqwest::StatusCode) -> bool {
+    status.is_server_error()
+        || status == reqwest::StatusCode::REQUEST_TIMEOUT
+        || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Webhook configuration errors
+#[derive(Debug, thiserror::Error)]
+pub enum WebhookError {
+    #[error("Invalid webhook URL {0:?}: {1}")]
+    InvalidUrl(String, String),
+
+    #[error("Webhook URL {0:?} must use http or https")]
+    UnsupportedScheme(String),
+
+    #[error("Webhook {0:?} needs a non-empty secret")]
+    EmptySecret(String),
+
+    #[error("Invalid tag selector {0:?}: {1}")]
+    InvalidTag(String, SelectorError),
+
+    #[error("Webhook {0:?} needs at least one delivery attempt")]
+    ZeroAttempts(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use crs_common::{ClientInfo, ClientStatus, RegisteredClient};
+    use std::collections::HashMap;
+
+    fn spec(url: &str) -> WebhookSpec {
+        WebhookSpec {
+            url: url.to_string(),
+            secret: "s3cret".to_string(),
+            events: None,
+            tag: None,
+            max_attempts: None,
+            initial_backoff_secs: None,
+        }
+    }
+
+    fn payload(event: WebhookEvent, tags: &[(&str, &str)]) -> WebhookPayload {
+        let info = ClientInfo {
+            hostname: "testhost".to_string(),
+            os: "linux".to_string(),
+            ip_address: "192.168.1.100".to_string(),
+            version: "1.0.0".to_string(),
+            host_id: None,
+            tags: tags
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<HashMap<_, _>>(),
+        };
+        WebhookPayload {
+            event,
+            sequence: 1,
+            timestamp: Utc::now(),
+            client: RegisteredClient {
+                client_id: info.client_id(),
+                info,
+                status: ClientStatus::Online,
+                first_connected: Utc::now(),
+                registered_at: Utc::now(),
+                last_heartbeat: Utc::now(),
+                quarantine: None,
+                maintenance: None,
+                maintenance_window: None,
+                metrics: None,
+                checks: Vec::new(),
+                availability: Vec::new(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_signature() {
+        // RFC 4231 style check against a well-known HMAC-SHA256 vector
+        assert_eq!(
+            signature("key", b"The quick brown fox jumps over the lazy dog"),
+            "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+
+    #[test]
+    fn test_webhook_filters() {
+        let all =
+            Webhook::from_spec(&spec("https://hooks.example.com/crs")).unwrap();
+        assert!(all.wants(&payload(WebhookEvent::Registered, &[])));
+        assert!(all.wants(&payload(WebhookEvent::CameBack, &[])));
+
+        let filtered = Webhook::from_spec(&WebhookSpec {
+            events: Some(vec![WebhookEvent::WentOffline]),
+            tag: Some("role=core".to_string()),
+            ..spec("http://127.0.0.1:9000/hook")
+        })
+        .unwrap();
+        let core = [("role", "core")];
+        assert!(filtered.wants(&payload(WebhookEvent::WentOffline, &core)));
+        assert!(!filtered.wants(&payload(WebhookEvent::CameBack, &core)));
+        assert!(!filtered
+            .wants(&payload(WebhookEvent::WentOffline, &[("role", "laptop")])));
+
+        // Tags are matched with the same selectors as everywhere else
+        let selected = Webhook::from_spec(&WebhookSpec {
+            tag: Some("role in (core,edge),!decommissioned".to_string()),
+            ..spec("http://127.0.0.1:9000/hook")
+        })
+        .unwrap();
+        let edge = [("role", "edge")];
+        assert!(selected.wants(&payload(WebhookEvent::WentOffline, &edge)));
+        let retired = [("role", "core"), ("decommissioned", "yes")];
+        assert!(!selected.wants(&payload(WebhookEvent::WentOffline, &retired)));
+    }
+
+    #[test]
+    fn test_invalid_webhooks() {
+        let invalid = [
+            spec("not a url"),
+            spec("ftp://hooks.example.com/crs"),
+            WebhookSpec {
+                secret: String::new(),
+                ..spec("https://hooks.example.com/crs")
+            },
+            WebhookSpec {
+                tag: Some("role in core".to_string()),
+                ..spec("https://hooks.example.com/crs")
+            },
+            WebhookSpec {
+                max_attempts: Some(0),
+                ..spec("https://hooks.example.com/crs")
+            },
+        ];
+        for spec in invalid {
+            assert!(Webhook::from_spec(&spec).is_err(), "{:?}", spec);
+        }
+    }
+
+    #[test]
+    fn test_debug_hides_secret() {
+        let webhook =
+            Webhook::from_spec(&spec("https://hooks.example.com/crs")).unwrap();
+        assert!(!format!("{:?}", webhook).contains("s3cret"));
+    }
+}
@@ -29,11 +29,11 @@ async fn test_check_fetches_client_list() {
     let info2 = create_client_info("test-client-2");
     let info3 = create_client_info("test-client-3");
 
-    registry.register(info1);
-    registry.register(info2);
-    registry.register(info3);
+    registry.register(info1).unwrap();
+    registry.register(info2).unwrap();
+    registry.register(info3).unwrap();
 
-    let clients = registry.list_clients();
+    let clients = registry.list_clients().unwrap();
     assert_eq!(clients.len(), 3);
 }
 
@@ -43,10 +43,10 @@ async fn test_check_reflects_status_changes() {
 
     // Register a client
     let info = create_client_info("status-change-test");
-    let client_id = registry.register(info);
+    let client_id = registry.register(info).unwrap().client_id;
 
     // Initially online
-    let clients = registry.list_clients();
+    let clients = registry.list_clients().unwrap();
     assert_eq!(clients[0].status, ClientStatus::Online);
 
     // Make it offline (>= 15 seconds)
@@ -54,9 +54,9 @@ async fn test_check_reflects_status_changes() {
         client_id,
         Utc::now() - chrono::Duration::try_seconds(20).unwrap(),
     );
-    registry.update_statuses();
+    registry.update_statuses().unwrap();
 
-    let clients = registry.list_clients();
+    let clients = registry.list_clients().unwrap();
     assert_eq!(clients[0].status, ClientStatus::Offline);
 }
 
@@ -68,8 +68,8 @@ async fn test_check_shows_multiple_client_states() {
     let info1 = create_client_info("client-online");
     let info2 = create_client_info("client-offline");
 
-    let id1 = registry.register(info1);
-    let id2 = registry.register(info2);
+    let id1 = registry.register(info1).unwrap().client_id;
+    let id2 = registry.register(info2).unwrap().client_id;
 
     // Set different heartbeat times
     registry.set_last_heartbeat(
@@ -81,9 +81,9 @@ async fn test_check_shows_multiple_client_states() {
         Utc::now() - chrono::Duration::try_seconds(20).unwrap(),
     );
 
-    registry.update_statuses();
+    registry.update_statuses().unwrap();
 
-    let clients = registry.list_clients();
+    let clients = registry.list_clients().unwrap();
     assert_eq!(clients.len(), 2);
 
     // Verify each client has the correct status
@@ -105,22 +105,25 @@ async fn test_check_client_reconnection_updates() {
     let registry = Registry::new();
 
     let info = create_client_info("reconnection-test");
-    let client_id = registry.register(info.clone());
+    let registration = registry.register(info.clone()).unwrap();
+    let client_id = registration.client_id;
 
     // Make client offline
     registry.set_last_heartbeat(
         client_id,
         Utc::now() - chrono::Duration::try_seconds(20).unwrap(),
     );
-    registry.update_statuses();
+    registry.update_statuses().unwrap();
 
-    let clients = registry.list_clients();
+    let clients = registry.list_clients().unwrap();
     assert_eq!(clients[0].status, ClientStatus::Offline);
 
     // Client reconnects (send heartbeat)
-    registry.heartbeat(client_id).unwrap();
+    registry
+        .heartbeat(client_id, &registration.client_secret, None, Vec::new())
+        .unwrap();
 
-    let clients = registry.list_clients();
+    let clients = registry.list_clients().unwrap();
     assert_eq!(clients[0].status, ClientStatus::Online);
 }
 
@@ -129,16 +132,48 @@ async fn test_check_preserves_first_connected_time() {
     let registry = Registry::new();
 
     let info = create_client_info("time-preservation-test");
-    let _client_id = registry.register(info.clone());
+    registry.register(info.clone()).unwrap();
 
-    let clients = registry.list_clients();
+    let clients = registry.list_clients().unwrap();
     let original_first_connected = clients[0].first_connected;
 
     // Wait a bit and re-register
     tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
-    registry.register(info);
+    registry.register(info).unwrap();
 
-    let clients = registry.list_clients();
+    let clients = registry.list_clients().unwrap();
     assert_eq!(clients[0].first_connected, original_first_connected);
     assert!(clients[0].registered_at > original_first_connected);
 }
+
+#[tokio::test]
+async fn test_check_availability_counts_outages() {
+    use crs_common::AvailabilityWindow;
+
+    let registry = Registry::new();
+    let registration = registry
+        .register(create_client_info("availability-test"))
+        .unwrap();
+    let client_id = registration.client_id;
+
+    // One outage that has ended
+    registry.set_last_heartbeat(
+        client_id,
+        Utc::now() - chrono::Duration::try_seconds(20).unwrap(),
+    );
+    registry.update_statuses().unwrap();
+    registry
+        .heartbeat(client_id, &registration.client_secret, None, Vec::new())
+        .unwrap();
+
+    let stats = registry
+        .availability(client_id, &AvailabilityWindow::ALL)
+        .unwrap();
+    assert_eq!(stats.len(), 3);
+    for stats in stats {
+        assert_eq!(stats.outages, 1);
+        assert!(stats.mttr_secs.is_some());
+        let percent = stats.availability_percent.unwrap();
+        assert!((0.0..=100.0).contains(&percent));
+    }
+}
@@ -6,11 +6,37 @@
 //! by starting a real server and connecting real clients.
 
 use chrono::Utc;
-use crs_common::{ClientId, ClientInfo, ClientStatus, RegisterRequest};
-use crs_server::registry::Registry;
+use crs_common::{
+    AvailabilityWindow, Ban, BanKind, BanRequest, CheckResult, ClientEvent,
+    ClientEventKind, ClientHistoryResponse, ClientId, ClientInfo, ClientStatus,
+    ClusterSync, DeregisterRequest, HealthResponse, HeartbeatRequest,
+    HistoryChange, HistoryEntry, ListBansResponse, ListClientsResponse,
+    LoadAverage, MaintenanceRequest, MaintenanceWindow,
+    MaintenanceWindowRequest, QuarantineRequest, RegisterRequest,
+    RegisterResponse, RegisteredClient, ServerInfo, SystemMetrics, Usage,
+    ADMIN_TOKEN_HEADER,
+};
+use crs_server::api::{self, ApiContext};
+use crs_server::cluster::{self, Cluster, ClusterSpec};
+use crs_server::health::{Health, ServerSettings};
+use crs_server::mtls::{self, PeerTable};
+use crs_server::policy::{
+    HeartbeatPolicies, HeartbeatPolicy, HeartbeatRuleSpec,
+};
+use crs_server::registry::{Registration, Registry};
+use crs_server::store::{RegistryStore, Revision, RevisionKey, StoreError};
+use dropshot::{
+    ConfigDropshot, ConfigLogging, ConfigLoggingLevel, ConfigTls, HttpServer,
+    HttpServerStarter,
+};
 use std::collections::HashMap;
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::time::sleep;
+use uuid::Uuid;
+
+/// Admin token the test servers are started with
+const ADMIN_TOKEN: &str = "test-admin-token";
 
 /// Helper to create test client info
 fn create_client_info(hostname: &str) -> ClientInfo {
@@ -24,6 +50,1706 @@ fn create_client_info(hostname: &str) -> ClientInfo {
     }
 }
 
+/// Start a CRS server on an ephemeral localhost port
+///
+/// Returns the running server and its base URL.
+fn start_server(registry: Registry) -> (HttpServer<ApiContext>, String) {
+    serve(ApiContext {
+        registry,
+        start_time: Utc::now(),
+        enrollment_token: None,
+        admin_token: Some(ADMIN_TOKEN.to_string()),
+        peers: None,
+        health: Health::default(),
+        settings: ServerSettings::default(),
+        cluster: None,
+    })
+}
+
+/// Start a CRS server with the given context on an ephemeral localhost port
+fn serve(context: ApiContext) -> (HttpServer<ApiContext>, String) {
+    serve_with_tls(context, None)
+}
+
+/// Start a CRS server, optionally serving HTTPS, on an ephemeral port
+fn serve_with_tls(
+    context: ApiContext,
+    tls: Option<ConfigTls>,
+) -> (HttpServer<ApiContext>, String) {
+    serve_at(context, tls, "127.0.0.1:0".parse().unwrap())
+}
+
+/// Start a CRS server, optionally serving HTTPS, on the given address
+fn serve_at(
+    context: ApiContext,
+    tls: Option<ConfigTls>,
+    bind_address: std::net::SocketAddr,
+) -> (HttpServer<ApiContext>, String) {
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    let config = ConfigDropshot {
+        bind_address,
+        ..Default::default()
+    };
+    let log = ConfigLogging::StderrTerminal {
+        level: ConfigLoggingLevel::Critical,
+    }
+    .to_logger("crs-test")
+    .unwrap();
+
+    let server = HttpServerStarter::new_with_tls(
+        &config,
+        api::api_description(),
+        context,
+        &log,
+        tls,
+    )
+    .unwrap()
+    .start();
+    let url = format!("{}://{}", scheme, server.local_addr());
+    (server, url)
+}
+
+/// A throwaway CA and a server certificate for 127.0.0.1 signed by it
+struct TestPki {
+    ca_pem: String,
+    ca_cert: rcgen::Certificate,
+    ca_key: rcgen::KeyPair,
+    server_cert_pem: String,
+    server_key_pem: String,
+}
+
+impl TestPki {
+    fn generate() -> Self {
+        use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+
+        let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        ca_params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "CRS test CA");
+        let ca_key = KeyPair::generate().unwrap();
+        let ca_cert = ca_params.self_signed(&ca_key).unwrap();
+
+        let server_params =
+            CertificateParams::new(vec!["127.0.0.1".to_string()]).unwrap();
+        let server_key = KeyPair::generate().unwrap();
+        let server_cert = server_params
+            .signed_by(&server_key, &ca_cert, &ca_key)
+            .unwrap();
+
+        Self {
+            ca_pem: ca_cert.pem(),
+            ca_cert,
+            ca_key,
+            server_cert_pem: server_cert.pem(),
+            server_key_pem: server_key.serialize_pem(),
+        }
+    }
+
+    /// Dropshot TLS configuration for the server certificate
+    fn server_tls(&self) -> ConfigTls {
+        ConfigTls::AsBytes {
+            certs: self.server_cert_pem.clone().into_bytes(),
+            key: self.server_key_pem.clone().into_bytes(),
+        }
+    }
+
+    /// Issue a client certificate for `hostname`
+    ///
+    /// Returns the PEM certificate and PKCS#8 key.
+    fn client_cert(&self, hostname: &str) -> (String, String) {
+        use rcgen::{CertificateParams, DnType, KeyPair};
+
+        let mut params =
+            CertificateParams::new(vec![hostname.to_string()]).unwrap();
+        params.distinguished_name.push(DnType::CommonName, hostname);
+        let key = KeyPair::generate().unwrap();
+        let cert = params.signed_by(&key, &self.ca_cert, &self.ca_key).unwrap();
+        (cert.pem(), key.serialize_pem())
+    }
+
+    /// An HTTPS client that trusts the CA and presents a certificate for
+    /// `hostname`
+    fn https_client(&self, hostname: Option<&str>) -> reqwest::Client {
+        let ca =
+            reqwest::Certificate::from_pem(self.ca_pem.as_bytes()).unwrap();
+        let mut builder = reqwest::Client::builder()
+            .tls_built_in_root_certs(false)
+            .add_root_certificate(ca);
+        if let Some(hostname) = hostname {
+            let (cert, key) = self.client_cert(hostname);
+            let identity = reqwest::Identity::from_pkcs8_pem(
+                cert.as_bytes(),
+                key.as_bytes(),
+            )
+            .unwrap();
+            builder = builder.identity(identity);
+        }
+        builder.build().unwrap()
+    }
+}
+
+/// A CRS server behind the mutual TLS front end
+struct MtlsServer {
+    server: HttpServer<ApiContext>,
+    /// HTTPS URL of the front end
+    url: String,
+    /// Plain HTTP URL of the Dropshot server behind the front end
+    direct_url: String,
+    _dir: tempfile::TempDir,
+}
+
+/// Start a CRS server that requires client certificates signed by the
+/// test CA
+async fn start_mtls_server(pki: &TestPki, registry: Registry) -> MtlsServer {
+    let peers = PeerTable::new();
+    let (server, direct_url) = serve(ApiContext {
+        registry,
+        start_time: Utc::now(),
+        enrollment_token: None,
+        admin_token: Some(ADMIN_TOKEN.to_string()),
+        peers: Some(peers.clone()),
+        health: Health::default(),
+        settings: ServerSettings::default(),
+        cluster: None,
+    });
+
+    let dir = tempfile::tempdir().unwrap();
+    let cert_path = dir.path().join("server.pem");
+    let key_path = dir.path().join("server.key");
+    let ca_path = dir.path().join("ca.pem");
+    std::fs::write(&cert_path, &pki.server_cert_pem).unwrap();
+    std::fs::write(&key_path, &pki.server_key_pem).unwrap();
+    std::fs::write(&ca_path, &pki.ca_pem).unwrap();
+    let tls =
+        mtls::load_server_config(&cert_path, &key_path, &ca_path).unwrap();
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("https://{}", listener.local_addr().unwrap());
+    tokio::spawn(mtls::serve(listener, tls, server.local_addr(), peers));
+
+    MtlsServer {
+        server,
+        url,
+        direct_url,
+        _dir: dir,
+    }
+}
+
+/// Read Server-Sent Events from a response until `count` have arrived
+///
+/// Checks that each message's `id` and `event` fields agree with its data.
+async fn read_events(
+    response: &mut reqwest::Response,
+    count: usize,
+) -> Vec<ClientEvent> {
+    let mut buffer = String::new();
+    let mut events = Vec::new();
+    while events.len() < count {
+        let chunk =
+            tokio::time::timeout(Duration::from_secs(5), response.chunk())
+                .await
+                .expect("timed out waiting for an event")
+                .unwrap()
+                .expect("event stream ended");
+        buffer.push_str(std::str::from_utf8(&chunk).unwrap());
+
+        while let Some(end) = buffer.find("\n\n") {
+            let message: String = buffer.drain(..end + 2).collect();
+            let field = |name: &str| {
+                message.lines().find_map(|line| {
+                    line.strip_prefix(name)?.strip_prefix(": ")
+                })
+            };
+            let Some(data) = field("data") else {
+                continue;
+            };
+            let event: ClientEvent = serde_json::from_str(data).unwrap();
+            assert_eq!(field("id"), Some(event.sequence.to_string().as_str()));
+            assert_eq!(field("event"), Some(event.kind.as_str()));
+            events.push(event);
+        }
+    }
+    events
+}
+
+/// Fake store that can be read but rejects every write
+struct ReadOnlyStore;
+
+impl RegistryStore for ReadOnlyStore {
+    fn get(
+        &self,
+        _client_id: ClientId,
+    ) -> Result<Option<RegisteredClient>, StoreError> {
+        Ok(None)
+    }
+
+    fn list(&self) -> Result<Vec<RegisteredClient>, StoreError> {
+        Ok(Vec::new())
+    }
+
+    fn put_many(
+        &self,
+        _clients: &[RegisteredClient],
+    ) -> Result<(), StoreError> {
+        Err(read_only())
+    }
+
+    fn remove(
+        &self,
+        _client_id: ClientId,
+    ) -> Result<Option<RegisteredClient>, StoreError> {
+        Err(read_only())
+    }
+
+    fn get_secret_hash(
+        &self,
+        _client_id: ClientId,
+    ) -> Result<Option<String>, StoreError> {
+        Ok(None)
+    }
+
+    fn put_secret_hash(
+        &self,
+        _client_id: ClientId,
+        _secret_hash: &str,
+    ) -> Result<(), StoreError> {
+        Err(read_only())
+    }
+
+    fn append_history(
+        &self,
+        _entries: &[(ClientId, HistoryEntry)],
+    ) -> Result<(), StoreError> {
+        Err(read_only())
+    }
+
+    fn history(
+        &self,
+        _client_id: ClientId,
+    ) -> Result<Vec<HistoryEntry>, StoreError> {
+        Ok(Vec::new())
+    }
+
+    fn list_bans(&self) -> Result<Vec<Ban>, StoreError> {
+        Ok(Vec::new())
+    }
+
+    fn put_ban(&self, _ban: &Ban) -> Result<(), StoreError> {
+        Err(read_only())
+    }
+
+    fn remove_ban(
+        &self,
+        _kind: BanKind,
+        _value: &str,
+    ) -> Result<bool, StoreError> {
+        Err(read_only())
+    }
+
+    fn list_maintenance_windows(
+        &self,
+    ) -> Result<Vec<MaintenanceWindow>, StoreError> {
+        Ok(Vec::new())
+    }
+
+    fn put_maintenance_window(
+        &self,
+        _window: &MaintenanceWindow,
+    ) -> Result<(), StoreError> {
+        Err(read_only())
+    }
+
+    fn remove_maintenance_window(
+        &self,
+        _id: uuid::Uuid,
+    ) -> Result<bool, StoreError> {
+        Err(read_only())
+    }
+
+    fn list_revisions(&self) -> Result<Vec<Revision>, StoreError> {
+        Ok(Vec::new())
+    }
+
+    fn put_revision(&self, _revision: &Revision) -> Result<(), StoreError> {
+        Err(read_only())
+    }
+
+    fn remove_revision(&self, _key: RevisionKey) -> Result<bool, StoreError> {
+        Err(read_only())
+    }
+}
+
+fn read_only() -> StoreError {
+    StoreError::Io(std::io::Error::other("store is read-only"))
+}
+
+#[tokio::test]
+async fn test_api_register_heartbeat_and_list() {
+    let (server, url) = start_server(Registry::new());
+    let client = reqwest::Client::new();
+    let info = create_client_info("api-test-host");
+
+    let response = client
+        .post(format!("{}/api/register", url))
+        .json(&RegisterRequest {
+            client_info: info.clone(),
+            enrollment_token: None,
+        })
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    let registered: RegisterResponse = response.json().await.unwrap();
+    assert_eq!(registered.client_id, info.client_id());
+
+    let response = client
+        .post(format!("{}/api/heartbeat", url))
+        .json(&HeartbeatRequest {
+            client_id: registered.client_id,
+            client_secret: registered.client_secret,
+            metrics: None,
+            checks: Vec::new(),
+        })
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    let list: ListClientsResponse = client
+        .get(format!("{}/api/clients", url))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(list.clients.len(), 1);
+    assert_eq!(list.clients[0].info.hostname, "api-test-host");
+    // The server records the address it actually sees
+    assert_eq!(list.clients[0].info.ip_address, "127.0.0.1");
+
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_api_heartbeat_unknown_client_is_unauthorized() {
+    let registry = Registry::new();
+    let (server, url) = start_server(registry.clone());
+    let client_id = registry
+        .register(create_client_info("known"))
+        .unwrap()
+        .client_id;
+
+    // An unknown client and a wrong secret get the same answer, so client
+    // IDs cannot be probed
+    let mut answers = Vec::new();
+    for client_id in [
+        client_id,
+        ClientId::from_client_data("unknown", "linux", None),
+    ] {
+        let response = reqwest::Client::new()
+            .post(format!("{}/api/heartbeat", url))
+            .json(&HeartbeatRequest {
+                client_id,
+                client_secret: "secret".to_string(),
+                metrics: None,
+                checks: Vec::new(),
+            })
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+        let body: serde_json::Value = response.json().await.unwrap();
+        answers.push(body["error_code"].clone());
+    }
+    assert_eq!(answers[0], answers[1]);
+
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_api_register_returns_client_heartbeat_interval() {
+    let policies = HeartbeatPolicies::new(
+        HeartbeatPolicy::default(),
+        &[HeartbeatRuleSpec {
+            tag: Some("role=laptop".to_string()),
+            hostname: None,
+            interval_secs: 60,
+            stale_threshold_secs: None,
+            offline_threshold_secs: None,
+        }],
+    )
+    .unwrap();
+    let (server, url) =
+        start_server(Registry::new().with_heartbeat_policies(policies));
+    let client = reqwest::Client::new();
+
+    let mut laptop = create_client_info("laptop-host");
+    laptop.tags.insert("role".to_string(), "laptop".to_string());
+    for (info, expected_secs) in
+        [(laptop, 60), (create_client_info("server-host"), 10)]
+    {
+        let registered: RegisterResponse = client
+            .post(format!("{}/api/register", url))
+            .json(&RegisterRequest {
+                client_info: info,
+                enrollment_token: None,
+            })
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(registered.heartbeat_interval_secs, expected_secs);
+    }
+
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_api_enrollment_token_and_client_secret() {
+    let (server, url) = serve(ApiContext {
+        registry: Registry::new(),
+        start_time: Utc::now(),
+        enrollment_token: Some("enroll-me".to_string()),
+        admin_token: None,
+        peers: None,
+        health: Health::default(),
+        settings: ServerSettings::default(),
+        cluster: None,
+    });
+    let client = reqwest::Client::new();
+    let info = create_client_info("enrolling-host");
+
+    // Registrations without the right token are refused
+    for token in [None, Some("wrong-token".to_string())] {
+        let response = client
+            .post(format!("{}/api/register", url))
+            .json(&RegisterRequest {
+                client_info: info.clone(),
+                enrollment_token: token,
+            })
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+    }
+
+    let registered: RegisterResponse = client
+        .post(format!("{}/api/register", url))
+        .json(&RegisterRequest {
+            client_info: info.clone(),
+            enrollment_token: Some("enroll-me".to_string()),
+        })
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(!registered.client_secret.is_empty());
+
+    // Knowing the client ID is not enough to heartbeat for the client
+    let response = client
+        .post(format!("{}/api/heartbeat", url))
+        .json(&HeartbeatRequest {
+            client_id: registered.client_id,
+            client_secret: "guessed".to_string(),
+            metrics: None,
+            checks: Vec::new(),
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    let response = client
+        .post(format!("{}/api/heartbeat", url))
+        .json(&HeartbeatRequest {
+            client_id: registered.client_id,
+            client_secret: registered.client_secret,
+            metrics: None,
+            checks: Vec::new(),
+        })
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_api_over_tls() {
+    let pki = TestPki::generate();
+    let (server, url) = serve_with_tls(
+        ApiContext {
+            registry: Registry::new(),
+            start_time: Utc::now(),
+            enrollment_token: None,
+            admin_token: None,
+            peers: None,
+            health: Health::default(),
+            settings: ServerSettings::default(),
+            cluster: None,
+        },
+        Some(pki.server_tls()),
+    );
+    assert!(url.starts_with("https://"));
+
+    // A client that trusts the test CA can talk to the server
+    let ca = reqwest::Certificate::from_pem(pki.ca_pem.as_bytes()).unwrap();
+    let client = reqwest::Client::builder()
+        .tls_built_in_root_certs(false)
+        .add_root_certificate(ca)
+        .build()
+        .unwrap();
+    let response = client
+        .post(format!("{}/api/register", url))
+        .json(&RegisterRequest {
+            client_info: create_client_info("tls-host"),
+            enrollment_token: None,
+        })
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    // A client that does not trust the CA refuses the connection
+    let untrusting = reqwest::Client::builder()
+        .tls_built_in_root_certs(false)
+        .build()
+        .unwrap();
+    let result = untrusting.get(format!("{}/api/clients", url)).send().await;
+    assert!(result.is_err());
+
+    // Plain HTTP is not served
+    let result = reqwest::Client::new()
+        .get(format!("{}/api/clients", url.replacen("https", "http", 1)))
+        .send()
+        .await;
+    assert!(!result.is_ok_and(|r| r.status().is_success()));
+
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_crs_client_over_tls() {
+    let pki = TestPki::generate();
+    let registry = Registry::new();
+    let (server, url) = serve_with_tls(
+        ApiContext {
+            registry: registry.clone(),
+            start_time: Utc::now(),
+            enrollment_token: Some("enroll-me".to_string()),
+            admin_token: None,
+            peers: None,
+            health: Health::default(),
+            settings: ServerSettings::default(),
+            cluster: None,
+        },
+        Some(pki.server_tls()),
+    );
+    let dir = tempfile::tempdir().unwrap();
+    let ca_path = dir.path().join("ca.pem");
+    std::fs::write(&ca_path, &pki.ca_pem).unwrap();
+
+    let client = crs_client::CrsClient::new(url, "1.0.0".to_string())
+        .await
+        .unwrap()
+        .with_enrollment_token("enroll-me".to_string())
+        .with_ca_bundle(&ca_path)
+        .unwrap();
+    let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+    let run = tokio::spawn(client.run_until(async {
+        let _ = stop_rx.await;
+    }));
+
+    // Wait for the client to register over TLS
+    let mut registered = false;
+    for _ in 0..50 {
+        if !registry.list_clients().unwrap().is_empty() {
+            registered = true;
+            break;
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+    assert!(registered, "client did not register over TLS");
+
+    // Shutting down deregisters over TLS as well
+    stop_tx.send(()).unwrap();
+    run.await.unwrap().unwrap();
+    let clients = registry.list_clients().unwrap();
+    assert_eq!(clients[0].status, ClientStatus::Departed);
+
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_crs_client_fails_over_and_back() {
+    // Both servers tell clients to heartbeat every second, and both are
+    // ready as soon as they start
+    let context = |registry: Registry| {
+        let policies = HeartbeatPolicies::new(
+            HeartbeatPolicy::with_interval(Duration::from_secs(1)),
+            &[],
+        )
+        .unwrap();
+        let health = Health::default();
+        health.set_registry_loaded();
+        health.record_sweep();
+        ApiContext {
+            registry: registry.with_heartbeat_policies(policies),
+            start_time: Utc::now(),
+            enrollment_token: None,
+            admin_token: None,
+            peers: None,
+            health,
+            settings: ServerSettings::default(),
+            cluster: None,
+        }
+    };
+    let wait_for = |what: &'static str, registry: Registry, since| async move {
+        for _ in 0..100 {
+            let clients = registry.list_clients().unwrap();
+            if clients.iter().any(|c| c.registered_at > since) {
+                return;
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+        panic!("client did not {}", what);
+    };
+
+    // The primary is down when the client starts
+    let primary_addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let primary_registry = Registry::new();
+    let secondary_registry = Registry::new();
+    let (secondary, secondary_url) = serve(context(secondary_registry.clone()));
+
+    let client = crs_client::CrsClient::new(
+        format!("http://{}", primary_addr),
+        "1.0.0".to_string(),
+    )
+    .await
+    .unwrap()
+    .with_failover_servers(vec![secondary_url])
+    .with_failback_interval(Duration::from_secs(1));
+    let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+    let run = tokio::spawn(client.run_until(async {
+        let _ = stop_rx.await;
+    }));
+    let start = Utc::now();
+    wait_for("fail over", secondary_registry.clone(), start).await;
+
+    // Once the primary is up, the client goes back to it
+    let (primary, _) =
+        serve_at(context(primary_registry.clone()), None, primary_addr);
+    wait_for("go back to the primary", primary_registry.clone(), start).await;
+
+    // When the primary goes away again, the client registers anew with the
+    // secondary
+    let failed = Utc::now();
+    primary.close().await.unwrap();
+    wait_for("fail over again", secondary_registry.clone(), failed).await;
+
+    stop_tx.send(()).unwrap();
+    run.await.unwrap().unwrap();
+    secondary.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_cluster_replicates_registry() {
+    // Three nodes on reserved ports, each syncing with the other two every
+    // second
+    let addrs: Vec<_> = (0..3)
+        .map(|_| {
+            std::net::TcpListener::bind("127.0.0.1:0")
+                .unwrap()
+                .local_addr()
+                .unwrap()
+        })
+        .collect();
+    let urls: Vec<_> = addrs.iter().map(|a| format!("http://{}", a)).collect();
+    let mut nodes = Vec::new();
+    for (i, addr) in addrs.iter().enumerate() {
+        let spec = ClusterSpec {
+            node_name: Some(format!("node-{}", i + 1)),
+            peers: urls
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(_, url)| url.clone())
+                .collect(),
+            secret: "cluster-secret".to_string(),
+            sync_interval_secs: Some(1),
+            ca_bundle: None,
+        };
+        let cluster = Cluster::from_spec(&spec, String::new()).unwrap();
+        let registry = Registry::new();
+        cluster::start(cluster.clone(), registry.clone());
+        let (server, _) = serve_at(
+            ApiContext {
+                registry,
+                start_time: Utc::now(),
+                enrollment_token: None,
+                admin_token: Some(ADMIN_TOKEN.to_string()),
+                peers: None,
+                health: Health::default(),
+                settings: ServerSettings::default(),
+                cluster: Some(cluster),
+            },
+            None,
+            *addr,
+        );
+        nodes.push(server);
+    }
+    let apis: Vec<_> = urls
+        .iter()
+        .map(|url| {
+            crs_api_client::Client::new(url)
+                .with_admin_token(ADMIN_TOKEN.to_string())
+        })
+        .collect();
+    async fn wait_for(
+        what: &str,
+        api: &crs_api_client::Client,
+        check: impl Fn(&RegisteredClient) -> bool,
+    ) {
+        for _ in 0..100 {
+            let clients =
+                api.list_all_clients(&Default::default()).await.unwrap();
+            if clients.clients.iter().any(&check) {
+                return;
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+        panic!("{} did not reach {}", what, api.baseurl());
+    }
+
+    // A client registered with one node shows up on the others
+    let registered = apis[0]
+        .register(&RegisterRequest {
+            client_info: create_client_info("clustered-host"),
+            enrollment_token: None,
+        })
+        .await
+        .unwrap();
+    let client_id = registered.client_id;
+    for api in &apis[1..] {
+        let is_registered = |c: &RegisteredClient| c.client_id == client_id;
+        wait_for("the registration", api, is_registered).await;
+    }
+
+    // It can heartbeat with any node using the secret it got from the first
+    apis[2]
+        .heartbeat(&HeartbeatRequest {
+            client_id,
+            client_secret: registered.client_secret.clone(),
+            metrics: None,
+            checks: Vec::new(),
+        })
+        .await
+        .unwrap();
+
+    // Changes made on any node reach the others
+    apis[1]
+        .quarantine_client(
+            client_id,
+            &QuarantineRequest {
+                reason: Some("replicated".to_string()),
+            },
+        )
+        .await
+        .unwrap();
+    let is_quarantined = |c: &RegisteredClient| c.quarantine.is_some();
+    wait_for("the quarantine", &apis[0], is_quarantined).await;
+    wait_for("the quarantine", &apis[2], is_quarantined).await;
+
+    // Every node lists its peers, once it has synced with them, and the
+    // dashboard shows them
+    let mut status = apis[0].get_cluster_status().await.unwrap();
+    for _ in 0..100 {
+        if status.members.iter().all(|m| m.reachable) {
+            break;
+        }
+        sleep(Duration::from_millis(100)).await;
+        status = apis[0].get_cluster_status().await.unwrap();
+    }
+    assert!(status.enabled);
+    assert_eq!(status.node, "node-1");
+    let peers: Vec<_> = status.members.iter().map(|m| &m.url).collect();
+    assert_eq!(peers, [&urls[1], &urls[2]]);
+    assert!(status.members.iter().all(|m| m.reachable));
+    assert_eq!(status.members[0].node.as_deref(), Some("node-2"));
+    let dashboard = reqwest::get(&urls[0]).await.unwrap().text().await.unwrap();
+    assert!(dashboard.contains("Cluster (2 peer(s))"));
+    assert!(dashboard.contains("node-3"));
+
+    // Syncs must carry the cluster secret
+    let sync = ClusterSync {
+        node: "intruder".to_string(),
+        state: Default::default(),
+    };
+    let intruder = crs_api_client::Client::new(&urls[0])
+        .with_cluster_secret("wrong".to_string());
+    let error = intruder.sync_cluster(&sync).await.unwrap_err();
+    assert_eq!(error.status(), Some(reqwest::StatusCode::UNAUTHORIZED));
+
+    // Servers outside a cluster say so
+    let (standalone, standalone_url) = start_server(Registry::new());
+    let standalone_api = crs_api_client::Client::new(&standalone_url);
+    let status = standalone_api.get_cluster_status().await.unwrap();
+    assert!(!status.enabled);
+    assert!(status.members.is_empty());
+    let error = standalone_api
+        .with_cluster_secret("cluster-secret".to_string())
+        .sync_cluster(&sync)
+        .await
+        .unwrap_err();
+    assert_eq!(error.status(), Some(reqwest::StatusCode::NOT_FOUND));
+
+    standalone.close().await.unwrap();
+    for node in nodes {
+        node.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_cluster_peer_ca_bundle() {
+    let pki = TestPki::generate();
+    let spec = |peers: Vec<String>, ca_bundle| ClusterSpec {
+        node_name: None,
+        peers,
+        secret: "cluster-secret".to_string(),
+        sync_interval_secs: None,
+        ca_bundle,
+    };
+
+    // A node served over HTTPS with a certificate from the test CA
+    let peer_registry = Registry::new();
+    let peer = Cluster::from_spec(&spec(Vec::new(), None), "peer".to_string());
+    let (server, url) = serve_with_tls(
+        ApiContext {
+            registry: peer_registry.clone(),
+            start_time: Utc::now(),
+            enrollment_token: None,
+            admin_token: None,
+            peers: None,
+            health: Health::default(),
+            settings: ServerSettings::default(),
+            cluster: Some(peer.unwrap()),
+        },
+        Some(pki.server_tls()),
+    );
+    let registered = peer_registry
+        .register(create_client_info("tls-peer-host"))
+        .unwrap();
+
+    // Without the CA bundle the peer's certificate is not trusted
+    let registry = Registry::new();
+    let untrusting = spec(vec![url.clone()], None);
+    let untrusting =
+        Cluster::from_spec(&untrusting, "untrusting".to_string()).unwrap();
+    untrusting.sync_once(&registry).await;
+    assert!(!untrusting.status().members[0].reachable);
+    assert!(registry.get_client(registered.client_id).is_err());
+
+    let dir = tempfile::tempdir().unwrap();
+    let ca_path = dir.path().join("ca.pem");
+    std::fs::write(&ca_path, &pki.ca_pem).unwrap();
+    let trusting = spec(vec![url], Some(ca_path));
+    let trusting =
+        Cluster::from_spec(&trusting, "trusting".to_string()).unwrap();
+    trusting.sync_once(&registry).await;
+    assert!(trusting.status().members[0].reachable);
+    registry.get_client(registered.client_id).unwrap();
+
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_api_client_certificates() {
+    let pki = TestPki::generate();
+    let registry = Registry::new();
+    let mtls = start_mtls_server(&pki, registry.clone()).await;
+    let register = |hostname: &str| RegisterRequest {
+        client_info: create_client_info(hostname),
+        enrollment_token: None,
+    };
+
+    // A certificate for the client's hostname lets it register
+    let client = pki.https_client(Some("mtls-host"));
+    let registered: RegisterResponse = client
+        .post(format!("{}/api/register", mtls.url))
+        .json(&register("mtls-host"))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let stored = registry.get_client(registered.client_id).unwrap();
+    assert_eq!(stored.info.ip_address, "127.0.0.1");
+
+    // The same certificate cannot be used to register another hostname
+    let response = client
+        .post(format!("{}/api/register", mtls.url))
+        .json(&register("other-host"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+
+    // Another host's certificate cannot heartbeat for this client, even
+    // with the right secret
+    let other = pki.https_client(Some("other-host"));
+    let response = other
+        .post(format!("{}/api/heartbeat", mtls.url))
+        .json(&HeartbeatRequest {
+            client_id: registered.client_id,
+            client_secret: registered.client_secret.clone(),
+            metrics: None,
+            checks: Vec::new(),
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+
+    let response = client
+        .post(format!("{}/api/heartbeat", mtls.url))
+        .json(&HeartbeatRequest {
+            client_id: registered.client_id,
+            client_secret: registered.client_secret,
+            metrics: None,
+            checks: Vec::new(),
+        })
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    // Connections without a client certificate are refused
+    let result = pki
+        .https_client(None)
+        .post(format!("{}/api/register", mtls.url))
+        .json(&register("mtls-host"))
+        .send()
+        .await;
+    assert!(!result.is_ok_and(|r| r.status().is_success()));
+
+    // Requests that bypass the front end carry no certificate at all
+    let response = reqwest::Client::new()
+        .post(format!("{}/api/register", mtls.direct_url))
+        .json(&register("mtls-host"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    // Nor can anything be read without a certificate
+    let id = registered.client_id;
+    for path in [
+        "/api/clients".to_string(),
+        format!("/api/clients/{}/history", id),
+        format!("/api/clients/{}/metrics", id),
+        "/api/events".to_string(),
+        "/".to_string(),
+        format!("/clients/{}", id),
+        "/metrics".to_string(),
+        "/healthz".to_string(),
+        "/readyz".to_string(),
+        "/api/server".to_string(),
+        "/api/cluster".to_string(),
+        "/api/openapi.json".to_string(),
+    ] {
+        let response = reqwest::Client::new()
+            .get(format!("{}{}", mtls.direct_url, path))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            response.status(),
+            reqwest::StatusCode::UNAUTHORIZED,
+            "{}",
+            path
+        );
+        let response = client
+            .get(format!("{}{}", mtls.url, path))
+            .send()
+            .await
+            .unwrap();
+        assert_ne!(
+            response.status(),
+            reqwest::StatusCode::UNAUTHORIZED,
+            "{}",
+            path
+        );
+    }
+    let response = reqwest::Client::new()
+        .post(format!("{}/api/cluster/sync", mtls.direct_url))
+        .json(&ClusterSync {
+            node: "local".to_string(),
+            state: Default::default(),
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    // Nor can operators change the registry without a certificate
+    let response = reqwest::Client::new()
+        .delete(format!(
+            "{}/api/clients/{}",
+            mtls.direct_url, registered.client_id
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+    let response = client
+        .delete(format!("{}/api/clients/{}", mtls.url, registered.client_id))
+        .header(ADMIN_TOKEN_HEADER, ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::NO_CONTENT);
+
+    mtls.server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_crs_client_adopts_new_interval_on_reregistration() {
+    let context = |registry: Registry, interval| {
+        let policies = HeartbeatPolicies::new(
+            HeartbeatPolicy::with_interval(Duration::from_secs(interval)),
+            &[],
+        )
+        .unwrap();
+        ApiContext {
+            registry: registry.with_heartbeat_policies(policies),
+            start_time: Utc::now(),
+            enrollment_token: None,
+            admin_token: None,
+            peers: None,
+            health: Health::default(),
+            settings: ServerSettings::default(),
+            cluster: None,
+        }
+    };
+    let registered = |registry: Registry| async move {
+        for _ in 0..100 {
+            if let Some(client) = registry.list_clients().unwrap().pop() {
+                return client;
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+        panic!("client did not register");
+    };
+
+    // The first server tells the client to heartbeat every second
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let first_registry = Registry::new();
+    let (first, url) = serve_at(context(first_registry.clone(), 1), None, addr);
+    let client = crs_client::CrsClient::new(url, "1.0.0".to_string())
+        .await
+        .unwrap();
+    let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+    let run = tokio::spawn(client.run_until(async {
+        let _ = stop_rx.await;
+    }));
+    registered(first_registry).await;
+
+    // Its replacement does not know the client, and asks for a heartbeat
+    // every minute when the client registers again
+    first.close().await.unwrap();
+    let second_registry = Registry::new();
+    let (second, _) =
+        serve_at(context(second_registry.clone(), 60), None, addr);
+    let client = registered(second_registry.clone()).await;
+    sleep(Duration::from_millis(2500)).await;
+    let clients = second_registry.list_clients().unwrap();
+    assert_eq!(clients[0].last_heartbeat, client.last_heartbeat);
+
+    stop_tx.send(()).unwrap();
+    run.await.unwrap().unwrap();
+    second.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_crs_client_with_client_certificate() {
+    let pki = TestPki::generate();
+    let registry = Registry::new();
+    let mtls = start_mtls_server(&pki, registry.clone()).await;
+
+    // crs-client registers under the machine's hostname
+    let hostname = hostname::get().unwrap().to_string_lossy().to_string();
+    let (cert, key) = pki.client_cert(&hostname);
+    let dir = tempfile::tempdir().unwrap();
+    let ca_path = dir.path().join("ca.pem");
+    let cert_path = dir.path().join("client.pem");
+    let key_path = dir.path().join("client.key");
+    std::fs::write(&ca_path, &pki.ca_pem).unwrap();
+    std::fs::write(&cert_path, cert).unwrap();
+    std::fs::write(&key_path, key).unwrap();
+
+    let client = crs_client::CrsClient::new(mtls.url, "1.0.0".to_string())
+        .await
+        .unwrap()
+        .with_ca_bundle(&ca_path)
+        .unwrap()
+        .with_client_certificate(&cert_path, &key_path)
+        .unwrap();
+    let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+    let run = tokio::spawn(client.run_until(async {
+        let _ = stop_rx.await;
+    }));
+
+    let mut registered = false;
+    for _ in 0..50 {
+        if !registry.list_clients().unwrap().is_empty() {
+            registered = true;
+            break;
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+    assert!(registered, "client did not register with its certificate");
+
+    stop_tx.send(()).unwrap();
+    run.await.unwrap().unwrap();
+    let clients = registry.list_clients().unwrap();
+    assert_eq!(clients[0].info.hostname, hostname);
+    assert_eq!(clients[0].status, ClientStatus::Departed);
+
+    mtls.server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_api_event_stream() {
+    let registry = Registry::new();
+    let (server, url) = start_server(registry.clone());
+    let client = reqwest::Client::new();
+
+    let mut stream = client
+        .get(format!("{}/api/events", url))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(stream.status(), reqwest::StatusCode::OK);
+    assert_eq!(
+        stream.headers()[reqwest::header::CONTENT_TYPE],
+        "text/event-stream"
+    );
+
+    let registered: RegisterResponse = client
+        .post(format!("{}/api/register", url))
+        .json(&RegisterRequest {
+            client_info: create_client_info("streamed-host"),
+            enrollment_token: None,
+        })
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let events = read_events(&mut stream, 1).await;
+    assert_eq!(events[0].sequence, 1);
+    assert_eq!(events[0].kind, ClientEventKind::Registered);
+    assert_eq!(events[0].client.client_id, registered.client_id);
+    drop(stream);
+
+    // Events published while disconnected are replayed on reconnect
+    registry.set_last_heartbeat(
+        registered.client_id,
+        Utc::now() - chrono::Duration::try_seconds(60).unwrap(),
+    );
+    registry.update_statuses().unwrap();
+    registry
+        .deregister(registered.client_id, &registered.client_secret)
+        .unwrap();
+
+    let mut stream = client
+        .get(format!("{}/api/events", url))
+        .header("Last-Event-ID", "1")
+        .send()
+        .await
+        .unwrap();
+    let kinds: Vec<_> = read_events(&mut stream, 2)
+        .await
+        .into_iter()
+        .map(|event| (event.sequence, event.kind))
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            (2, ClientEventKind::WentOffline),
+            (3, ClientEventKind::Deregistered)
+        ]
+    );
+    drop(stream);
+
+    // A sequence number the server has never issued, e.g. from before a
+    // restart, cannot be resumed from
+    let response = client
+        .get(format!("{}/api/events?since=100", url))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::GONE);
+
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_api_deregister_marks_client_departed() {
+    let registry = Registry::new();
+    let (server, url) = start_server(registry.clone());
+    let client = reqwest::Client::new();
+    let Registration {
+        client_id,
+        client_secret,
+        ..
+    } = registry
+        .register(create_client_info("departing-host"))
+        .unwrap();
+
+    let response = client
+        .post(format!("{}/api/deregister", url))
+        .json(&DeregisterRequest {
+            client_id,
+            client_secret,
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::NO_CONTENT);
+
+    let clients = registry.list_clients().unwrap();
+    assert_eq!(clients[0].status, ClientStatus::Departed);
+
+    // Deregistering an unknown client is an error
+    let response = client
+        .post(format!("{}/api/deregister", url))
+        .json(&DeregisterRequest {
+            client_id: ClientId::from_client_data("unknown", "linux", None),
+            client_secret: "secret".to_string(),
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_api_client_history_and_detail_page() {
+    let registry = Registry::new();
+    let (server, url) = start_server(registry.clone());
+    let client = reqwest::Client::new();
+    let Registration {
+        client_id,
+        client_secret,
+        ..
+    } = registry
+        .register(create_client_info("history-host"))
+        .unwrap();
+
+    // Go offline, then come back
+    registry.set_last_heartbeat(
+        client_id,
+        Utc::now() - chrono::Duration::try_minutes(5).unwrap(),
+    );
+    registry.update_statuses().unwrap();
+    registry
+        .heartbeat(client_id, &client_secret, None, Vec::new())
+        .unwrap();
+
+    let history: ClientHistoryResponse = client
+        .get(format!("{}/api/clients/{}/history", url, client_id))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(history.client_id, client_id);
+    let changes: Vec<_> =
+        history.entries.into_iter().map(|e| e.change).collect();
+    assert_eq!(
+        changes,
+        vec![
+            HistoryChange::Registered { first: true },
+            HistoryChange::StatusChanged {
+                from: ClientStatus::Online,
+                to: ClientStatus::Offline,
+                expected: false,
+            },
+            HistoryChange::StatusChanged {
+                from: ClientStatus::Offline,
+                to: ClientStatus::Online,
+                expected: false,
+            },
+        ]
+    );
+
+    // The client listing carries availability over every window, or just
+    // the one asked for
+    let list: ListClientsResponse = client
+        .get(format!("{}/api/clients", url))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let windows: Vec<_> = list.clients[0]
+        .availability
+        .iter()
+        .map(|a| a.window)
+        .collect();
+    assert_eq!(windows, AvailabilityWindow::ALL);
+    assert!(list.clients[0].availability.iter().all(|a| a.outages == 1));
+    let list: ListClientsResponse = client
+        .get(format!("{}/api/clients?window=7d", url))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(list.clients[0].availability.len(), 1);
+    assert_eq!(
+        list.clients[0].availability[0].window,
+        AvailabilityWindow::Week
+    );
+    let response = client
+        .get(format!("{}/api/clients?window=1y", url))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    // The detail page shows the client and its history, and the dashboard
+    // links to it
+    let page = client
+        .get(format!("{}/clients/{}", url, client_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(page.status(), reqwest::StatusCode::OK);
+    let page = page.text().await.unwrap();
+    assert!(page.contains("history-host"));
+    assert!(page.contains("History (3)"));
+    assert!(page.contains("offline &rarr; online"));
+    assert!(page.contains("MTTR"));
+    let dashboard = client.get(&url).send().await.unwrap().text().await;
+    assert!(dashboard
+        .unwrap()
+        .contains(&format!("href=\"/clients/{}\"", client_id)));
+
+    // Unknown clients have neither
+    let unknown = ClientId::from_client_data("unknown", "linux", None);
+    for path in [
+        format!("/api/clients/{}/history", unknown),
+        format!("/clients/{}", unknown),
+    ] {
+        let response =
+            client.get(format!("{}{}", url, path)).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+    }
+
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_web_pages_escape_client_supplied_text() {
+    let registry = Registry::new();
+    let (server, url) = start_server(registry.clone());
+    let client = reqwest::Client::new();
+    let script = "<script>alert(1)</script>";
+    let mut info = create_client_info(script);
+    info.os = script.to_string();
+    info.ip_address = script.to_string();
+    info.version = script.to_string();
+    info.host_id = Some(script.to_string());
+    info.tags.insert("role".to_string(), script.to_string());
+    let client_id = registry.register(info.clone()).unwrap().client_id;
+
+    // A changed version lands in the history with its old and new values
+    info.version = "<b>2.0</b>".to_string();
+    registry.register(info).unwrap();
+    registry
+        .quarantine(client_id, Some(script.to_string()))
+        .unwrap();
+
+    let escaped = "&lt;script&gt;alert(1)&lt;/script&gt;";
+    let page = client
+        .get(format!("{}/clients/{}", url, client_id))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(!page.contains("<script>"));
+    assert!(!page.contains("<b>2.0</b>"));
+    assert!(page.contains(&format!("<title>{} - ", escaped)));
+    assert!(page.contains(&format!("role={}", escaped)));
+    assert!(page.contains("&lt;b&gt;2.0&lt;/b&gt;"));
+
+    let dashboard =
+        client.get(&url).send().await.unwrap().text().await.unwrap();
+    assert!(!dashboard.contains("<script>"));
+    assert!(dashboard.contains(escaped));
+
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_api_admin_remove_ban_and_quarantine() {
+    let registry = Registry::new();
+    let (server, url) = start_server(registry.clone());
+    let client = reqwest::Client::new();
+    let info = create_client_info("admin-test-host");
+    let client_id = registry.register(info.clone()).unwrap().client_id;
+
+    // Quarantine keeps the client listed but flags it
+    let response = client
+        .put(format!("{}/api/clients/{}/quarantine", url, client_id))
+        .header(ADMIN_TOKEN_HEADER, ADMIN_TOKEN)
+        .json(&QuarantineRequest {
+            reason: Some("investigating".to_string()),
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::NO_CONTENT);
+    let list: ListClientsResponse = client
+        .get(format!("{}/api/clients", url))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(list.clients.len(), 1);
+    assert!(list.clients[0].quarantine.is_some());
+
+    // Ban the hostname, then remove the client
+    let response = client
+        .post(format!("{}/api/bans", url))
+        .header(ADMIN_TOKEN_HEADER, ADMIN_TOKEN)
+        .json(&BanRequest {
+            kind: BanKind::Hostname,
+            value: "admin-test-host".to_string(),
+            reason: None,
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::CREATED);
+    let response = client
+        .delete(format!("{}/api/clients/{}", url, client_id))
+        .header(ADMIN_TOKEN_HEADER, ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::NO_CONTENT);
+    assert!(registry.list_clients().unwrap().is_empty());
+
+    // The banned host can no longer register
+    let response = client
+        .post(format!("{}/api/register", url))
+        .json(&RegisterRequest {
+            client_info: info.clone(),
+            enrollment_token: None,
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+
+    let bans: ListBansResponse = client
+        .get(format!("{}/api/bans", url))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(bans.bans.len(), 1);
+
+    // Lifting the ban lets it back in
+    let response = client
+        .delete(format!("{}/api/bans/hostname/admin-test-host", url))
+        .header(ADMIN_TOKEN_HEADER, ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::NO_CONTENT);
+    let response = client
+        .post(format!("{}/api/register", url))
+        .json(&RegisterRequest {
+            client_info: info,
+            enrollment_token: None,
+        })
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_api_admin_token() {
+    let registry = Registry::new();
+    let (server, url) = serve(ApiContext {
+        registry: registry.clone(),
+        start_time: Utc::now(),
+        enrollment_token: None,
+        admin_token: Some("adm1n".to_string()),
+        peers: None,
+        health: Health::default(),
+        settings: ServerSettings::default(),
+        cluster: None,
+    });
+    let client_id = registry
+        .register(create_client_info("guarded-host"))
+        .unwrap()
+        .client_id;
+
+    fn refused<T>(result: Result<T, crs_api_client::Error>) -> bool {
+        result.is_err_and(|e| {
+            e.status() == Some(reqwest::StatusCode::UNAUTHORIZED)
+        })
+    }
+
+    // Every change needs the admin token
+    let window = MaintenanceWindowRequest {
+        client_id: Some(client_id),
+        duration_secs: 3600,
+        ..Default::default()
+    };
+    let ban = BanRequest {
+        kind: BanKind::Hostname,
+        value: "guarded-host".to_string(),
+        reason: None,
+    };
+    for api in [
+        crs_api_client::Client::new(&url),
+        crs_api_client::Client::new(&url).with_admin_token("admin".to_string()),
+    ] {
+        assert!(refused(api.remove_client(client_id).await));
+        let quarantine = QuarantineRequest::default();
+        assert!(refused(api.quarantine_client(client_id, &quarantine).await));
+        assert!(refused(api.release_client(client_id).await));
+        let maintenance = MaintenanceRequest::default();
+        assert!(refused(
+            api.start_maintenance(client_id, &maintenance).await
+        ));
+        assert!(refused(api.end_maintenance(client_id).await));
+        assert!(refused(api.create_maintenance_window(&window).await));
+        assert!(refused(api.delete_maintenance_window(Uuid::new_v4()).await));
+        assert!(refused(api.create_ban(&ban).await));
+        assert!(refused(
+            api.delete_ban(BanKind::Hostname, "guarded-host").await
+        ));
+
+        // Looking is still open to anyone
+        assert!(api.list_bans().await.is_ok());
+        assert!(api.list_maintenance_windows().await.is_ok());
+    }
+    let client = registry.get_client(client_id).unwrap();
+    assert!(client.quarantine.is_none());
+    assert_eq!(client.status, ClientStatus::Online);
+    assert!(registry.list_bans().unwrap().is_empty());
+
+    // With the token the changes go through
+    let admin =
+        crs_api_client::Client::new(&url).with_admin_token("adm1n".to_string());
+    admin
+        .quarantine_client(client_id, &QuarantineRequest::default())
+        .await
+        .unwrap();
+    assert!(registry.get_client(client_id).unwrap().quarantine.is_some());
+    admin.create_ban(&ban).await.unwrap();
+    let error = admin
+        .delete_maintenance_window(Uuid::new_v4())
+        .await
+        .unwrap_err();
+    assert_eq!(error.status(), Some(reqwest::StatusCode::NOT_FOUND));
+    admin.remove_client(client_id).await.unwrap();
+    assert!(registry.list_clients().unwrap().is_empty());
+    server.close().await.unwrap();
+
+    // A server without an admin token refuses every change, whatever token
+    // is presented
+    let registry = Registry::new();
+    let (server, url) = serve(ApiContext {
+        registry: registry.clone(),
+        start_time: Utc::now(),
+        enrollment_token: None,
+        admin_token: None,
+        peers: None,
+        health: Health::default(),
+        settings: ServerSettings::default(),
+        cluster: None,
+    });
+    let client_id = registry
+        .register(create_client_info("guarded-host"))
+        .unwrap()
+        .client_id;
+    for api in [
+        crs_api_client::Client::new(&url),
+        crs_api_client::Client::new(&url).with_admin_token(String::new()),
+        crs_api_client::Client::new(&url).with_admin_token("adm1n".to_string()),
+    ] {
+        let error = api.remove_client(client_id).await.unwrap_err();
+        assert_eq!(error.status(), Some(reqwest::StatusCode::FORBIDDEN));
+        let error = api.create_ban(&ban).await.unwrap_err();
+        assert_eq!(error.status(), Some(reqwest::StatusCode::FORBIDDEN));
+        assert!(api.list_bans().await.is_ok());
+    }
+    assert_eq!(registry.list_clients().unwrap().len(), 1);
+
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_api_store_failure_is_internal_error() {
+    let registry = Registry::with_store(Arc::new(ReadOnlyStore)).unwrap();
+    let (server, url) = start_server(registry);
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/api/register", url))
+        .json(&RegisterRequest {
+            client_info: create_client_info("read-only-host"),
+            enrollment_token: None,
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        response.status(),
+        reqwest::StatusCode::INTERNAL_SERVER_ERROR
+    );
+
+    server.close().await.unwrap();
+}
+
 #[tokio::test]
 async fn test_registration_flow() {
     let client = reqwest::Client::new();
@@ -32,6 +1758,7 @@ async fn test_registration_flow() {
     let info = create_client_info("integration-test-host");
     let request = RegisterRequest {
         client_info: info.clone(),
+        enrollment_token: None,
     };
 
     // Note: This test requires a running server
@@ -60,14 +1787,19 @@ async fn test_heartbeat_flow() {
     let info = create_client_info("heartbeat-test");
 
     // Register client
-    let client_id = registry.register(info);
+    let registration = registry.register(info).unwrap();
 
     // Send heartbeat
-    let result = registry.heartbeat(client_id);
+    let result = registry.heartbeat(
+        registration.client_id,
+        &registration.client_secret,
+        None,
+        Vec::new(),
+    );
     assert!(result.is_ok());
 
     // Verify client is online
-    let clients = registry.list_clients();
+    let clients = registry.list_clients().unwrap();
     assert_eq!(clients.len(), 1);
     assert_eq!(clients[0].status, ClientStatus::Online);
 }
@@ -77,7 +1809,7 @@ async fn test_heartbeat_unknown_client() {
     let registry = Registry::new();
     let unknown_id = ClientId::from_client_data("unknown", "linux", None);
 
-    let result = registry.heartbeat(unknown_id);
+    let result = registry.heartbeat(unknown_id, "secret", None, Vec::new());
     assert!(result.is_err());
 }
 
@@ -86,10 +1818,10 @@ async fn test_client_status_online_to_offline() {
     let registry = Registry::new();
     let info = create_client_info("status-test");
 
-    let client_id = registry.register(info);
+    let client_id = registry.register(info).unwrap().client_id;
 
     // Client should be online initially
-    let clients = registry.list_clients();
+    let clients = registry.list_clients().unwrap();
     assert_eq!(clients[0].status, ClientStatus::Online);
 
     // Manually set heartbeat to 20 seconds ago (offline - >= 15s)
@@ -99,10 +1831,10 @@ async fn test_client_status_online_to_offline() {
     );
 
     // Update statuses
-    registry.update_statuses();
+    registry.update_statuses().unwrap();
 
     // Client should now be offline
-    let clients = registry.list_clients();
+    let clients = registry.list_clients().unwrap();
     assert_eq!(clients[0].status, ClientStatus::Offline);
 }
 
@@ -112,21 +1844,21 @@ async fn test_client_reconnection_preserves_id() {
     let info = create_client_info("reconnect-test");
 
     // First registration
-    let client_id1 = registry.register(info.clone());
-    let clients = registry.list_clients();
+    let client_id1 = registry.register(info.clone()).unwrap().client_id;
+    let clients = registry.list_clients().unwrap();
     let first_connected1 = clients[0].first_connected;
 
     // Wait a bit
     sleep(Duration::from_millis(10)).await;
 
     // Re-register (simulating reconnection)
-    let client_id2 = registry.register(info.clone());
+    let client_id2 = registry.register(info.clone()).unwrap().client_id;
 
     // IDs should be the same
     assert_eq!(client_id1, client_id2);
 
     // First connected time should be preserved, but registered_at updates
-    let clients = registry.list_clients();
+    let clients = registry.list_clients().unwrap();
     assert_eq!(clients[0].first_connected, first_connected1);
     assert!(clients[0].registered_at > first_connected1);
 }
@@ -140,12 +1872,12 @@ async fn test_multiple_clients_tracking() {
     let info2 = create_client_info("client2");
     let info3 = create_client_info("client3");
 
-    registry.register(info1);
-    registry.register(info2);
-    registry.register(info3);
+    registry.register(info1).unwrap();
+    registry.register(info2).unwrap();
+    registry.register(info3).unwrap();
 
     // Should have 3 clients
-    let clients = registry.list_clients();
+    let clients = registry.list_clients().unwrap();
     assert_eq!(clients.len(), 3);
 
     // All should be online
@@ -159,20 +1891,26 @@ async fn test_heartbeat_returns_to_online() {
     let registry = Registry::new();
     let info = create_client_info("recovery-test");
 
-    let client_id = registry.register(info);
+    let Registration {
+        client_id,
+        client_secret,
+        ..
+    } = registry.register(info).unwrap();
 
     // Set client to offline
     registry.set_last_heartbeat(
         client_id,
         Utc::now() - chrono::Duration::try_seconds(20).unwrap(),
     );
-    registry.update_statuses();
+    registry.update_statuses().unwrap();
 
     // Send heartbeat
-    registry.heartbeat(client_id).unwrap();
+    registry
+        .heartbeat(client_id, &client_secret, None, Vec::new())
+        .unwrap();
 
     // Should be online again
-    let clients = registry.list_clients();
+    let clients = registry.list_clients().unwrap();
     assert_eq!(clients[0].status, ClientStatus::Online);
 }
 
@@ -183,10 +1921,10 @@ async fn test_list_clients_returns_all() {
     // Register 5 clients
     for i in 1..=5 {
         let info = create_client_info(&format!("client{}", i));
-        registry.register(info);
+        registry.register(info).unwrap();
     }
 
-    let clients = registry.list_clients();
+    let clients = registry.list_clients().unwrap();
     assert_eq!(clients.len(), 5);
 
     // Verify all hostnames are present
@@ -209,7 +1947,7 @@ async fn test_concurrent_registrations() {
         let reg = registry.clone();
         let handle = tokio::spawn(async move {
             let info = create_client_info(&format!("concurrent{}", i));
-            reg.register(info)
+            reg.register(info).unwrap()
         });
         handles.push(handle);
     }
@@ -220,7 +1958,7 @@ async fn test_concurrent_registrations() {
     }
 
     // Should have 10 clients
-    let clients = registry.list_clients();
+    let clients = registry.list_clients().unwrap();
     assert_eq!(clients.len(), 10);
 }
 
@@ -229,19 +1967,24 @@ async fn test_concurrent_heartbeats() {
     let registry = Registry::new();
 
     // Register clients first
-    let mut client_ids = vec![];
+    let mut registrations = vec![];
     for i in 0..10 {
         let info = create_client_info(&format!("heartbeat{}", i));
-        let id = registry.register(info);
-        client_ids.push(id);
+        registrations.push(registry.register(info).unwrap());
     }
 
     // Send concurrent heartbeats
     let mut handles = vec![];
-    for id in client_ids {
+    for registration in registrations {
         let reg = registry.clone();
         let handle = tokio::spawn(async move {
-            reg.heartbeat(id).unwrap();
+            reg.heartbeat(
+                registration.client_id,
+                &registration.client_secret,
+                None,
+                Vec::new(),
+            )
+            .unwrap();
         });
         handles.push(handle);
     }
@@ -252,9 +1995,662 @@ async fn test_concurrent_heartbeats() {
     }
 
     // All should still be online
-    let clients = registry.list_clients();
+    let clients = registry.list_clients().unwrap();
     assert_eq!(clients.len(), 10);
     for client in clients {
         assert_eq!(client.status, ClientStatus::Online);
     }
 }
+
+#[tokio::test]
+async fn test_api_list_clients_filters_sorts_and_pages() {
+    let registry = Registry::new();
+    let (server, url) = start_server(registry.clone());
+    let client = reqwest::Client::new();
+
+    for i in 0..5 {
+        let mut info = create_client_info(&format!("web-{}", i));
+        info.ip_address = format!("10.0.0.{}", 10 - i);
+        info.tags.insert("role".to_string(), "web".to_string());
+        registry.register(info).unwrap();
+    }
+    let mut info = create_client_info("db-1");
+    info.os = "illumos".to_string();
+    info.tags.insert("role".to_string(), "db".to_string());
+    let db = registry.register(info).unwrap();
+    registry.set_last_heartbeat(
+        db.client_id,
+        Utc::now() - chrono::Duration::try_minutes(5).unwrap(),
+    );
+    registry.update_statuses().unwrap();
+
+    let list = |query: String| {
+        let client = client.clone();
+        let url = url.clone();
+        async move {
+            let response = client
+                .get(format!("{}/api/clients?{}", url, query))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), reqwest::StatusCode::OK);
+            response.json::<ListClientsResponse>().await.unwrap()
+        }
+    };
+    let hostnames = |list: &ListClientsResponse| -> Vec<String> {
+        list.clients
+            .iter()
+            .map(|c| c.info.hostname.clone())
+            .collect()
+    };
+
+    // Sorted by IP address by default, numerically
+    let all = list(String::new()).await;
+    assert_eq!(
+        hostnames(&all),
+        ["web-4", "web-3", "web-2", "web-1", "web-0", "db-1"]
+    );
+    assert!(all.next_page.is_none());
+
+    // Filters
+    let offline = list("status=offline".to_string()).await;
+    assert_eq!(hostnames(&offline), ["db-1"]);
+    let illumos = list("os=Illumos".to_string()).await;
+    assert_eq!(hostnames(&illumos), ["db-1"]);
+    let web = list("hostname=WEB-*&tag=role%3Dweb".to_string()).await;
+    assert_eq!(web.clients.len(), 5);
+    let none = list("tag=role%3Dweb&status=offline".to_string()).await;
+    assert!(none.clients.is_empty());
+    let db = list("selector=role%20in%20(db%2Ccache)%2C!gpu".to_string()).await;
+    assert_eq!(hostnames(&db), ["db-1"]);
+
+    // The dashboard takes the same selector
+    let dashboard = client
+        .get(format!("{}/?selector=role%3Ddb", url))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(dashboard.contains("db-1"));
+    assert!(!dashboard.contains("web-1"));
+    assert!(dashboard.contains(r#"value="role=db""#));
+
+    // Paging through hostnames in descending order keeps the scan going
+    let mut page =
+        list("sort=hostname&order=descending&limit=2".to_string()).await;
+    let mut seen = hostnames(&page);
+    while let Some(token) = page.next_page.take() {
+        page = list(format!("page_token={}&limit=2", token)).await;
+        assert!(page.clients.len() <= 2);
+        seen.extend(hostnames(&page));
+    }
+    assert_eq!(seen, ["web-4", "web-3", "web-2", "web-1", "web-0", "db-1"]);
+
+    // Bad filters are rejected
+    for query in [
+        "tag=%3Dweb",
+        "hostname=%5B",
+        "sort=color",
+        "status=gone",
+        "selector=role%20in%20db",
+    ] {
+        let response = client
+            .get(format!("{}/api/clients?{}", url, query))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            response.status(),
+            reqwest::StatusCode::BAD_REQUEST,
+            "{}",
+            query
+        );
+    }
+
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_metrics_endpoint() {
+    let registry = Registry::new();
+    let (server, url) = start_server(registry.clone());
+    let client = reqwest::Client::new();
+
+    // Register twice, heartbeat once, and heartbeat as an unknown client
+    let mut registered = None;
+    for _ in 0..2 {
+        let response: RegisterResponse = client
+            .post(format!("{}/api/register", url))
+            .json(&RegisterRequest {
+                client_info: create_client_info("metrics-host"),
+                enrollment_token: None,
+            })
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        registered = Some(response);
+    }
+    let registered = registered.unwrap();
+    let response = client
+        .post(format!("{}/api/heartbeat", url))
+        .json(&HeartbeatRequest {
+            client_id: registered.client_id,
+            client_secret: registered.client_secret,
+            metrics: None,
+            checks: Vec::new(),
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let response = client
+        .post(format!("{}/api/heartbeat", url))
+        .json(&HeartbeatRequest {
+            client_id: ClientId(uuid::Uuid::nil()),
+            client_secret: "nope".to_string(),
+            metrics: None,
+            checks: Vec::new(),
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+    registry.update_statuses().unwrap();
+
+    let response = client.get(format!("{}/metrics", url)).send().await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    assert!(response.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain"));
+    let text = response.text().await.unwrap();
+    for line in [
+        "crs_registrations_total 2",
+        "crs_reregistrations_total 1",
+        "crs_heartbeats_total 1",
+        "crs_heartbeats_rejected_total 1",
+        r#"crs_clients{os="linux",status="online",version="1.0.0"} 1"#,
+        r#"crs_handler_duration_seconds_count{handler="register"} 2"#,
+        r#"crs_handler_duration_seconds_count{handler="heartbeat"} 2"#,
+    ] {
+        assert!(text.contains(line), "missing {:?} in:\n{}", line, text);
+    }
+
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_health_and_server_info() {
+    let health = Health::default();
+    let (server, url) = serve(ApiContext {
+        registry: Registry::new(),
+        start_time: Utc::now(),
+        enrollment_token: None,
+        admin_token: None,
+        peers: None,
+        health: health.clone(),
+        settings: ServerSettings {
+            bind_address: Some("192.0.2.1:8081".parse().unwrap()),
+            status_sweep: Duration::from_secs(5),
+        },
+        cluster: None,
+    });
+    let client = reqwest::Client::new();
+
+    let response = client.get(format!("{}/healthz", url)).send().await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let body: HealthResponse = response.json().await.unwrap();
+    assert_eq!(body.status, "ok");
+
+    // Not ready until the registry is loaded and the sweeper has run
+    let response = client.get(format!("{}/readyz", url)).send().await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+    health.set_registry_loaded();
+    let response = client.get(format!("{}/readyz", url)).send().await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+    health.record_sweep();
+    let response = client.get(format!("{}/readyz", url)).send().await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    let info: ServerInfo = client
+        .get(format!("{}/api/server", url))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+    assert_eq!(info.bind_address, "192.0.2.1:8081");
+    assert_eq!(info.heartbeat_interval_secs, 10);
+    assert_eq!(info.offline_threshold_secs, 15);
+    assert_eq!(info.status_sweep_secs, 5);
+
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_openapi_endpoint() {
+    let (server, url) = start_server(Registry::new());
+
+    let response = reqwest::get(format!("{}/api/openapi.json", url))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let document: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(document, crs_server::openapi::openapi_document());
+    assert_eq!(document["info"]["version"], env!("CARGO_PKG_VERSION"));
+
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_api_client() {
+    let (server, url) = start_server(Registry::new());
+    let api = crs_api_client::Client::new(&url)
+        .with_admin_token(ADMIN_TOKEN.to_string());
+
+    let registered = api
+        .register(&RegisterRequest {
+            client_info: create_client_info("typed-host"),
+            enrollment_token: None,
+        })
+        .await
+        .unwrap();
+    api.heartbeat(&HeartbeatRequest {
+        client_id: registered.client_id,
+        client_secret: registered.client_secret.clone(),
+        metrics: None,
+        checks: Vec::new(),
+    })
+    .await
+    .unwrap();
+
+    // Errors carry the status and the server's message
+    let error = api
+        .heartbeat(&HeartbeatRequest {
+            client_id: ClientId(uuid::Uuid::nil()),
+            client_secret: "nope".to_string(),
+            metrics: None,
+            checks: Vec::new(),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(error.status(), Some(reqwest::StatusCode::UNAUTHORIZED));
+    assert!(matches!(
+        error,
+        crs_api_client::Error::Response {
+            message: Some(_),
+            ..
+        }
+    ));
+
+    let clients = api.list_all_clients(&Default::default()).await.unwrap();
+    assert_eq!(clients.clients.len(), 1);
+    let history = api.client_history(registered.client_id).await.unwrap();
+    assert_eq!(history.client_id, registered.client_id);
+
+    api.quarantine_client(
+        registered.client_id,
+        &QuarantineRequest {
+            reason: Some("testing".to_string()),
+        },
+    )
+    .await
+    .unwrap();
+    api.release_client(registered.client_id).await.unwrap();
+
+    // Hostnames are escaped in ban paths
+    api.create_ban(&BanRequest {
+        kind: BanKind::Hostname,
+        value: "odd host/name".to_string(),
+        reason: None,
+    })
+    .await
+    .unwrap();
+    assert_eq!(api.list_bans().await.unwrap().bans.len(), 1);
+    api.delete_ban(BanKind::Hostname, "odd host/name")
+        .await
+        .unwrap();
+    assert!(api.list_bans().await.unwrap().bans.is_empty());
+
+    api.deregister(&DeregisterRequest {
+        client_id: registered.client_id,
+        client_secret: registered.client_secret,
+    })
+    .await
+    .unwrap();
+    api.remove_client(registered.client_id).await.unwrap();
+
+    assert_eq!(api.healthz().await.unwrap().status, "ok");
+    assert_eq!(
+        api.readyz().await.unwrap_err().status(),
+        Some(reqwest::StatusCode::SERVICE_UNAVAILABLE)
+    );
+    assert_eq!(
+        api.get_server_info().await.unwrap().version,
+        env!("CARGO_PKG_VERSION")
+    );
+    let metrics = api.serve_metrics().await.unwrap().text().await.unwrap();
+    assert!(metrics.contains("crs_heartbeats_total 1"));
+    assert!(api.get_openapi().await.unwrap()["paths"].is_object());
+
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_api_client_metrics() {
+    let (server, url) = start_server(Registry::new());
+    let api = crs_api_client::Client::new(&url);
+
+    let registered = api
+        .register(&RegisterRequest {
+            client_info: create_client_info("metrics-host"),
+            enrollment_token: None,
+        })
+        .await
+        .unwrap();
+
+    // Heartbeats without metrics keep working and record no samples
+    let heartbeat = |metrics| HeartbeatRequest {
+        client_id: registered.client_id,
+        client_secret: registered.client_secret.clone(),
+        metrics,
+        checks: Vec::new(),
+    };
+    api.heartbeat(&heartbeat(None)).await.unwrap();
+    let response = api.client_metrics(registered.client_id).await.unwrap();
+    assert!(response.samples.is_empty());
+
+    let metrics = SystemMetrics {
+        load_average: Some(LoadAverage {
+            one: 0.5,
+            five: 0.25,
+            fifteen: 0.1,
+        }),
+        memory: Some(Usage {
+            used_bytes: 1 << 30,
+            total_bytes: 4 << 30,
+        }),
+        root_fs: None,
+        uptime_secs: Some(3600),
+    };
+    for _ in 0..3 {
+        api.heartbeat(&heartbeat(Some(metrics.clone())))
+            .await
+            .unwrap();
+    }
+    // Empty reports are treated as no report
+    api.heartbeat(&heartbeat(Some(SystemMetrics::default())))
+        .await
+        .unwrap();
+
+    let response = api.client_metrics(registered.client_id).await.unwrap();
+    assert_eq!(response.client_id, registered.client_id);
+    assert_eq!(response.samples.len(), 3);
+    assert_eq!(response.samples[2].metrics, metrics);
+
+    let clients = api.list_all_clients(&Default::default()).await.unwrap();
+    assert_eq!(
+        clients.clients[0].metrics.as_ref().unwrap().metrics,
+        metrics
+    );
+
+    // The detail page shows the samples
+    let page =
+        reqwest::get(format!("{}/clients/{}", url, registered.client_id))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+    assert!(page.contains("System Metrics"));
+    assert!(page.contains("0.50 0.25 0.10"));
+    assert!(page.contains("25% of 4.0 GiB"));
+
+    let error = api
+        .client_metrics(ClientId(uuid::Uuid::nil()))
+        .await
+        .unwrap_err();
+    assert_eq!(error.status(), Some(reqwest::StatusCode::NOT_FOUND));
+
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_api_health_checks_degrade_client() {
+    let (server, url) = start_server(Registry::new());
+    let api = crs_api_client::Client::new(&url);
+
+    let registered = api
+        .register(&RegisterRequest {
+            client_info: create_client_info("checked-host"),
+            enrollment_token: None,
+        })
+        .await
+        .unwrap();
+    let heartbeat = |checks| HeartbeatRequest {
+        client_id: registered.client_id,
+        client_secret: registered.client_secret.clone(),
+        metrics: None,
+        checks,
+    };
+
+    api.heartbeat(&heartbeat(vec![
+        CheckResult {
+            name: "sshd".to_string(),
+            passed: true,
+            message: None,
+        },
+        CheckResult {
+            name: "nginx".to_string(),
+            passed: false,
+            message: Some("exit status: 3: <inactive>".to_string()),
+        },
+    ]))
+    .await
+    .unwrap();
+
+    let client = &api
+        .list_all_clients(&Default::default())
+        .await
+        .unwrap()
+        .clients[0];
+    assert_eq!(client.status, ClientStatus::Degraded);
+    assert_eq!(client.checks.len(), 2);
+
+    // Degraded clients can be picked out by status
+    let list: ListClientsResponse =
+        reqwest::get(format!("{}/api/clients?status=degraded", url))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+    assert_eq!(list.clients.len(), 1);
+
+    // The detail page escapes what the check reported
+    let page =
+        reqwest::get(format!("{}/clients/{}", url, registered.client_id))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+    assert!(page.contains("Health Checks"));
+    assert!(page.contains("exit status: 3: &lt;inactive&gt;"));
+
+    // Heartbeats without checks leave nothing failing
+    api.heartbeat(&heartbeat(Vec::new())).await.unwrap();
+    let client = &api
+        .list_all_clients(&Default::default())
+        .await
+        .unwrap()
+        .clients[0];
+    assert_eq!(client.status, ClientStatus::Online);
+    assert!(client.checks.is_empty());
+
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_api_stale_and_maintenance_statuses() {
+    let registry = Registry::new();
+    let (server, url) = start_server(registry.clone());
+    let api = crs_api_client::Client::new(&url)
+        .with_admin_token(ADMIN_TOKEN.to_string());
+
+    let registered = api
+        .register(&RegisterRequest {
+            client_info: create_client_info("maint-host"),
+            enrollment_token: None,
+        })
+        .await
+        .unwrap();
+    let client_id = registered.client_id;
+    let status = || async {
+        api.list_all_clients(&Default::default())
+            .await
+            .unwrap()
+            .clients[0]
+            .status
+    };
+
+    // A client that misses a heartbeat is stale before it is offline
+    registry.set_last_heartbeat(
+        client_id,
+        Utc::now() - chrono::Duration::try_seconds(13).unwrap(),
+    );
+    registry.update_statuses().unwrap();
+    assert_eq!(status().await, ClientStatus::Stale);
+    assert_eq!(
+        api.get_server_info().await.unwrap().stale_threshold_secs,
+        12
+    );
+
+    api.start_maintenance(
+        client_id,
+        &MaintenanceRequest {
+            reason: Some("disk <swap>".to_string()),
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(status().await, ClientStatus::Maintenance);
+    let list: ListClientsResponse =
+        reqwest::get(format!("{}/api/clients?status=maintenance", url))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+    assert_eq!(list.clients.len(), 1);
+    let page = reqwest::get(format!("{}/clients/{}", url, client_id))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(page.contains("disk &lt;swap&gt;"));
+
+    // Heartbeats are recorded without ending the maintenance
+    api.heartbeat(&HeartbeatRequest {
+        client_id,
+        client_secret: registered.client_secret.clone(),
+        metrics: None,
+        checks: Vec::new(),
+    })
+    .await
+    .unwrap();
+    assert_eq!(status().await, ClientStatus::Maintenance);
+
+    api.end_maintenance(client_id).await.unwrap();
+    assert_eq!(status().await, ClientStatus::Online);
+    let error = api.end_maintenance(client_id).await.unwrap_err();
+    assert_eq!(error.status(), Some(reqwest::StatusCode::CONFLICT));
+
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_api_maintenance_windows() {
+    let registry = Registry::new();
+    let (server, url) = start_server(registry.clone());
+    let api = crs_api_client::Client::new(&url)
+        .with_admin_token(ADMIN_TOKEN.to_string());
+
+    let mut info = create_client_info("rack-host");
+    info.tags.insert("rack".to_string(), "r12".to_string());
+    let client_id = api
+        .register(&RegisterRequest {
+            client_info: info,
+            enrollment_token: None,
+        })
+        .await
+        .unwrap()
+        .client_id;
+
+    let window: MaintenanceWindow = api
+        .create_maintenance_window(&MaintenanceWindowRequest {
+            selector: Some("rack=r12".to_string()),
+            reason: Some("patching <r12>".to_string()),
+            duration_secs: 3600,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    let windows = api.list_maintenance_windows().await.unwrap().windows;
+    assert_eq!(windows, std::slice::from_ref(&window));
+
+    // An outage inside the window is shown as expected
+    registry.set_last_heartbeat(
+        client_id,
+        Utc::now() - chrono::Duration::try_seconds(20).unwrap(),
+    );
+    registry.update_statuses().unwrap();
+    let client = api
+        .list_all_clients(&Default::default())
+        .await
+        .unwrap()
+        .clients
+        .remove(0);
+    assert_eq!(client.status, ClientStatus::Offline);
+    assert_eq!(client.maintenance_window, Some(window.id));
+    let page = reqwest::get(format!("{}/clients/{}", url, client_id))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(page.contains("offline (expected)"));
+    assert!(page.contains("patching &lt;r12&gt;"));
+
+    // Invalid requests are rejected
+    let error = api
+        .create_maintenance_window(&MaintenanceWindowRequest {
+            selector: Some("rack=r12".to_string()),
+            duration_secs: 3600,
+            schedule: Some("whenever".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(error.status(), Some(reqwest::StatusCode::BAD_REQUEST));
+
+    api.delete_maintenance_window(window.id).await.unwrap();
+    assert!(api
+        .list_maintenance_windows()
+        .await
+        .unwrap()
+        .windows
+        .is_empty());
+    let error = api.delete_maintenance_window(window.id).await.unwrap_err();
+    assert_eq!(error.status(), Some(reqwest::StatusCode::NOT_FOUND));
+
+    server.close().await.unwrap();
+}
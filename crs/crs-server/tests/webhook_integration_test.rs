@@ -0,0 +1,298 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Integration tests for webhook notifications
+//!
+//! These tests drive a real registry and deliver webhooks to a local
+//! stand-in receiver, which records every request and can be told to fail.
+
+#![allow(dead_code)]
+
+use chrono::Utc;
+use crs_common::{ClientInfo, WebhookEvent, WebhookPayload};
+use crs_server::registry::Registry;
+use crs_server::webhooks::{self, RetryPolicy, Webhook, WebhookSpec};
+use dropshot::{
+    endpoint, ApiDescription, ConfigDropshot, ConfigLogging,
+    ConfigLoggingLevel, HttpError, HttpResponseUpdatedNoContent, HttpServer,
+    HttpServerStarter, Path, RequestContext, UntypedBody,
+};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const SECRET: &str = "s3cret";
+
+/// A request received by the stand-in
+#[derive(Debug, Clone)]
+struct Delivery {
+    hook: String,
+    event: String,
+    delivery: String,
+    signature: String,
+    body: Vec<u8>,
+    succeeded: bool,
+}
+
+impl Delivery {
+    fn payload(&self) -> WebhookPayload {
+        serde_json::from_slice(&self.body).unwrap()
+    }
+}
+
+/// State of the stand-in webhook receiver
+struct Receiver {
+    deliveries: Mutex<Vec<Delivery>>,
+
+    /// Number of requests still to answer with 500
+    failures_left: AtomicUsize,
+}
+
+impl Receiver {
+    fn deliveries(&self, hook: &str) -> Vec<Delivery> {
+        self.deliveries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|d| d.hook == hook)
+            .cloned()
+            .collect()
+    }
+
+    /// Wait until `hook` has accepted `count` deliveries
+    async fn wait_for(&self, hook: &str, count: usize) -> Vec<WebhookPayload> {
+        for _ in 0..100 {
+            let accepted: Vec<_> = self
+                .deliveries(hook)
+                .into_iter()
+                .filter(|d| d.succeeded)
+                .map(|d| d.payload())
+                .collect();
+            if accepted.len() >= count {
+                return accepted;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("timed out waiting for {} deliveries to {}", count, hook);
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct HookPath {
+    hook: String,
+}
+
+#[endpoint {
+    method = POST,
+    path = "/hooks/{hook}",
+}]
+async fn receive(
+    rqctx: RequestContext<Receiver>,
+    path: Path<HookPath>,
+    body: UntypedBody,
+) -> Result<HttpResponseUpdatedNoContent, HttpError> {
+    let receiver = rqctx.context();
+    let header = |name: &str| {
+        rqctx
+            .request
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string()
+    };
+    let succeeded = receiver
+        .failures_left
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+        .is_err();
+
+    receiver.deliveries.lock().unwrap().push(Delivery {
+        hook: path.into_inner().hook,
+        event: header("x-crs-event"),
+        delivery: header("x-crs-delivery"),
+        signature: header("x-crs-signature"),
+        body: body.as_bytes().to_vec(),
+        succeeded,
+    });
+
+    if succeeded {
+        Ok(HttpResponseUpdatedNoContent())
+    } else {
+        Err(HttpError::for_internal_error(
+            "failing on purpose".to_string(),
+        ))
+    }
+}
+
+/// Start the stand-in receiver, failing the first `failures` requests
+fn start_receiver(failures: usize) -> (HttpServer<Receiver>, String) {
+    let mut api = ApiDescription::new();
+    api.register(receive).unwrap();
+
+    let config = ConfigDropshot {
+        bind_address: "127.0.0.1:0".parse().unwrap(),
+        ..Default::default()
+    };
+    let log = ConfigLogging::StderrTerminal {
+        level: ConfigLoggingLevel::Critical,
+    }
+    .to_logger("crs-webhook-test")
+    .unwrap();
+
+    let server = HttpServerStarter::new(
+        &config,
+        api,
+        Receiver {
+            deliveries: Mutex::new(Vec::new()),
+            failures_left: AtomicUsize::new(failures),
+        },
+        &log,
+    )
+    .unwrap()
+    .start();
+    let url = format!("http://{}", server.local_addr());
+    (server, url)
+}
+
+fn webhook(
+    url: String,
+    events: Option<Vec<WebhookEvent>>,
+    tag: Option<&str>,
+) -> Webhook {
+    Webhook::from_spec(&WebhookSpec {
+        url,
+        secret: SECRET.to_string(),
+        events,
+        tag: tag.map(str::to_string),
+        max_attempts: None,
+        initial_backoff_secs: None,
+    })
+    .unwrap()
+    .with_retry(RetryPolicy {
+        max_attempts: 3,
+        initial_backoff: Duration::from_millis(10),
+    })
+}
+
+fn client_info(hostname: &str, tags: &[(&str, &str)]) -> ClientInfo {
+    ClientInfo {
+        hostname: hostname.to_string(),
+        os: "linux".to_string(),
+        ip_address: "192.168.1.100".to_string(),
+        version: "1.0.0".to_string(),
+        host_id: None,
+        tags: tags
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<HashMap<_, _>>(),
+    }
+}
+
+#[tokio::test]
+async fn test_webhooks_follow_client_transitions() {
+    // The first two requests fail, so the first delivery needs a retry
+    let (server, url) = start_receiver(2);
+    let receiver = server.app_private();
+
+    let registry = Registry::new();
+    webhooks::start(
+        registry.events().clone(),
+        vec![
+            webhook(format!("{}/hooks/all", url), None, None),
+            webhook(
+                format!("{}/hooks/core", url),
+                Some(vec![WebhookEvent::WentOffline, WebhookEvent::CameBack]),
+                Some("role=core"),
+            ),
+        ],
+    );
+
+    let laptop = registry.register(client_info("laptop-1", &[])).unwrap();
+    let core = registry
+        .register(client_info("core-1", &[("role", "core")]))
+        .unwrap();
+
+    // Re-registering an online client is not a transition
+    registry.register(client_info("laptop-1", &[])).unwrap();
+
+    let payloads = receiver.wait_for("all", 2).await;
+    assert_eq!(payloads[0].event, WebhookEvent::Registered);
+    assert_eq!(payloads[0].client.client_id, laptop.client_id);
+    assert_eq!(payloads[1].event, WebhookEvent::Registered);
+    assert_eq!(payloads[1].client.client_id, core.client_id);
+
+    // Both clients go offline, then the core client comes back
+    let long_ago = Utc::now() - chrono::Duration::try_minutes(5).unwrap();
+    registry.set_last_heartbeat(laptop.client_id, long_ago);
+    registry.set_last_heartbeat(core.client_id, long_ago);
+    registry.update_statuses().unwrap();
+    registry
+        .heartbeat(core.client_id, &core.client_secret, None, Vec::new())
+        .unwrap();
+
+    let payloads = receiver.wait_for("all", 5).await;
+    let events: Vec<_> = payloads[2..].iter().map(|p| p.event).collect();
+    assert_eq!(
+        events
+            .iter()
+            .filter(|e| **e == WebhookEvent::WentOffline)
+            .count(),
+        2
+    );
+    assert_eq!(events[2], WebhookEvent::CameBack);
+
+    // The filtered webhook only hears about the tagged client
+    let payloads = receiver.wait_for("core", 2).await;
+    assert_eq!(payloads.len(), 2);
+    assert_eq!(payloads[0].event, WebhookEvent::WentOffline);
+    assert_eq!(payloads[1].event, WebhookEvent::CameBack);
+    assert!(payloads
+        .iter()
+        .all(|p| p.client.client_id == core.client_id));
+
+    // The failed attempts were retried with the same payload
+    let deliveries = receiver.deliveries("all");
+    assert!(!deliveries[0].succeeded);
+    assert!(!deliveries[1].succeeded);
+    assert!(deliveries[2].succeeded);
+    assert_eq!(deliveries[0].body, deliveries[2].body);
+    assert_eq!(deliveries[0].delivery, deliveries[2].delivery);
+
+    // Every request is signed and labeled
+    for delivery in receiver.deliveries("all") {
+        assert_eq!(
+            delivery.signature,
+            webhooks::signature(SECRET, &delivery.body)
+        );
+        let payload = delivery.payload();
+        assert_eq!(delivery.event, payload.event.as_str());
+        assert_eq!(delivery.delivery, payload.sequence.to_string());
+    }
+
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_webhook_gives_up_after_max_attempts() {
+    let (server, url) = start_receiver(3);
+    let receiver = server.app_private();
+
+    let registry = Registry::new();
+    webhooks::start(
+        registry.events().clone(),
+        vec![webhook(format!("{}/hooks/all", url), None, None)],
+    );
+
+    registry.register(client_info("gone-1", &[])).unwrap();
+    let second = registry.register(client_info("next-1", &[])).unwrap();
+
+    // The first payload is dropped after three attempts and the next one
+    // goes through
+    let payloads = receiver.wait_for("all", 1).await;
+    assert_eq!(payloads[0].client.client_id, second.client_id);
+    assert_eq!(receiver.deliveries("all").len(), 4);
+
+    server.close().await.unwrap();
+}